@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::coordination::WorkerState;
+use crate::database::SQLiteDatabase;
+use crate::error::WorkerError;
+use crate::events::QueryEvent;
+use crate::query_result::SqlValue;
+use crate::time::TimeProvider;
+
+// `ANALYZE` only creates `sqlite_stat1` when there is something to record
+const STAT1_EXISTS_SQL: &str =
+    "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sqlite_stat1'";
+const ANALYZED_TABLES_SQL: &str = "SELECT DISTINCT tbl FROM sqlite_stat1 ORDER BY tbl";
+
+/// What a run of `ANALYZE` recorded statistics for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnalysisResult {
+    // Tables with rows in `sqlite_stat1`, by name
+    #[serde(rename = "tablesAnalyzed")]
+    pub tables_analyzed: Vec<String>,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: f64,
+}
+
+// Counts the leader's writes so `ANALYZE` runs after every `threshold` of them
+#[derive(Debug)]
+pub(crate) struct AutoAnalyze {
+    threshold: u64,
+    writes: Cell<u64>,
+}
+
+impl AutoAnalyze {
+    // `None` for a threshold of 0, which never analyzes
+    pub(crate) fn new(threshold: u64) -> Option<Rc<Self>> {
+        (threshold > 0).then(|| {
+            Rc::new(AutoAnalyze {
+                threshold,
+                writes: Cell::new(0),
+            })
+        })
+    }
+
+    // Count a write, returning true and starting over when it is the
+    // `threshold`th since the last analysis
+    pub(crate) fn record_write(&self) -> bool {
+        let writes = self.writes.get() + 1;
+        let due = writes >= self.threshold;
+        self.writes.set(if due { 0 } else { writes });
+        due
+    }
+}
+
+// The tables in a query's single text column
+fn table_names(rows: Vec<Vec<SqlValue>>) -> Vec<String> {
+    rows.into_iter()
+        .filter_map(|row| match row.into_iter().next() {
+            Some(SqlValue::Text(table)) => Some(table),
+            _ => None,
+        })
+        .collect()
+}
+
+// Run `ANALYZE` on the leader's own connection
+pub(crate) async fn analyze(
+    database: &SQLiteDatabase,
+    time: &dyn TimeProvider,
+) -> Result<AnalysisResult, WorkerError> {
+    let started_at = time.now_ms();
+    database.exec("ANALYZE").await?;
+    let tables_analyzed = if database.query(STAT1_EXISTS_SQL).await?.rows.is_empty() {
+        Vec::new()
+    } else {
+        table_names(database.query(ANALYZED_TABLES_SQL).await?.rows)
+    };
+    Ok(AnalysisResult {
+        tables_analyzed,
+        duration_ms: time.now_ms() - started_at,
+    })
+}
+
+impl WorkerState {
+    /// Run `ANALYZE` so the query planner has up-to-date statistics about the
+    /// tables and indexes, and report which tables it analyzed. Emits
+    /// `AnalysisComplete` on this worker when it succeeds.
+    pub async fn run_analysis(&self) -> Result<AnalysisResult, WorkerError> {
+        let started_at = self.time.now_ms();
+        self.execute_parameterized("ANALYZE".to_string(), Vec::new())
+            .await?;
+        let stat1 = self
+            .execute_parameterized(STAT1_EXISTS_SQL.to_string(), Vec::new())
+            .await?;
+        let tables_analyzed = if stat1.rows.is_empty() {
+            Vec::new()
+        } else {
+            table_names(
+                self.execute_parameterized(ANALYZED_TABLES_SQL.to_string(), Vec::new())
+                    .await?
+                    .rows,
+            )
+        };
+
+        let result = AnalysisResult {
+            tables_analyzed,
+            duration_ms: self.time.now_ms() - started_at,
+        };
+        self.events
+            .emit(&QueryEvent::AnalysisComplete(result.clone()));
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, leader_with_db, sleep, WorkerStateBuilder};
+    use std::cell::RefCell;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    async fn seed(state: &WorkerState) {
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS analysis_items".to_string(),
+                "CREATE TABLE analysis_items (id INTEGER PRIMARY KEY, category TEXT)".to_string(),
+                "CREATE INDEX analysis_items_category ON analysis_items (category)".to_string(),
+                "INSERT INTO analysis_items (category) VALUES ('a'), ('b'), ('a')".to_string(),
+            ])
+            .await
+            .expect("Seed failed");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_auto_analyze_counts_writes() {
+        assert!(AutoAnalyze::new(0).is_none());
+        let auto = AutoAnalyze::new(3).unwrap();
+        assert!(!auto.record_write());
+        assert!(!auto.record_write());
+        assert!(auto.record_write());
+        // The count starts over after each analysis
+        assert!(!auto.record_write());
+        assert!(!auto.record_write());
+        assert!(auto.record_write());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_run_analysis_populates_stat1() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        seed(&state).await;
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&events);
+        state
+            .events
+            .subscribe(move |event| recorded.borrow_mut().push(event.clone()));
+
+        let result = state.run_analysis().await.expect("Analysis failed");
+        assert!(result
+            .tables_analyzed
+            .contains(&"analysis_items".to_string()));
+        assert!(result.duration_ms >= 0.0);
+
+        let (stats,): (i64,) = state
+            .execute_parameterized(
+                "SELECT count(*) FROM sqlite_stat1 WHERE tbl = 'analysis_items'".to_string(),
+                Vec::new(),
+            )
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert!(stats > 0);
+        assert_eq!(*events.borrow(), vec![QueryEvent::AnalysisComplete(result)]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_auto_analyze_after_n_writes() {
+        let Ok(state) = WorkerStateBuilder::new()
+            .auto_analyze_after_n_writes(2)
+            .build()
+        else {
+            return;
+        };
+        let Some(state) = lead_with_db(state).await else {
+            return;
+        };
+        seed(&state).await;
+        let analyses = Rc::new(Cell::new(0));
+        let counted = Rc::clone(&analyses);
+        state.events.subscribe(move |event| {
+            if matches!(event, QueryEvent::AnalysisComplete(_)) {
+                counted.set(counted.get() + 1);
+            }
+        });
+
+        for category in ["c", "d", "e"] {
+            state
+                .execute_parameterized(
+                    "INSERT INTO analysis_items (category) VALUES (?)".to_string(),
+                    vec![SqlValue::from(category)],
+                )
+                .await
+                .expect("Insert failed");
+        }
+        sleep(50.0).await;
+        assert_eq!(analyses.get(), 1);
+    }
+}
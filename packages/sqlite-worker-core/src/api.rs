@@ -0,0 +1,619 @@
+use js_sys::Promise;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+use crate::authorizer::AuthResult;
+use crate::coordination::WorkerState;
+use crate::priority::PriorityCounts;
+use crate::watch::WatchHandle;
+use crate::worker;
+
+/// JavaScript-facing handle onto a worker's state
+#[wasm_bindgen]
+pub struct SQLiteWorkerApi {
+    state: Rc<WorkerState>,
+}
+
+impl SQLiteWorkerApi {
+    pub fn new(state: Rc<WorkerState>) -> Self {
+        SQLiteWorkerApi { state }
+    }
+
+    pub fn state(&self) -> &WorkerState {
+        &self.state
+    }
+}
+
+#[wasm_bindgen]
+impl SQLiteWorkerApi {
+    /// Handle onto the state started by `worker_main`, if the worker is running
+    pub fn current() -> Option<SQLiteWorkerApi> {
+        worker::current_state().map(SQLiteWorkerApi::new)
+    }
+
+    /// The worker's message log as a JSON array, oldest first
+    #[wasm_bindgen(js_name = "dumpMessageLog")]
+    pub fn dump_message_log(&self) -> String {
+        serde_json::to_string(&self.state.message_log()).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Pending queries by priority as a `{ high, normal, low }` object
+    #[wasm_bindgen(js_name = "queueDepth")]
+    pub fn queue_depth(&self) -> JsValue {
+        let counts = PriorityCounts::from(&self.state.count_pending_by_priority());
+        serde_wasm_bindgen::to_value(&counts).unwrap_or(JsValue::UNDEFINED)
+    }
+
+    /// Call `cb` with each error that has no caller to return it to
+    #[wasm_bindgen(js_name = "onError")]
+    pub fn on_error(&self, cb: js_sys::Function) {
+        self.state.on_error(move |err| {
+            let _ = cb.call1(&JsValue::NULL, &JsValue::from(err));
+        });
+    }
+
+    /// Call `cb` once the database is ready, straight away if it already is
+    #[wasm_bindgen(js_name = "onReady")]
+    pub fn on_ready(&self, cb: js_sys::Function) {
+        self.state.on_database_ready(Box::new(move || {
+            let _ = cb.call0(&JsValue::NULL);
+        }));
+    }
+
+    /// Call `cb` with `{ actionCode, arg1, arg2, dbName, trigger }` for each
+    /// action of each statement prepared while this worker leads. Returning
+    /// `"deny"` fails the statement and `"ignore"` reads NULL instead; anything
+    /// else allows it. A callback that throws denies the action.
+    #[wasm_bindgen(js_name = "setAuthorizer")]
+    pub fn set_authorizer(&self, cb: js_sys::Function) {
+        self.state.set_authorizer(Box::new(move |action| {
+            let Ok(action) = serde_wasm_bindgen::to_value(&action) else {
+                return AuthResult::Deny;
+            };
+            match cb.call1(&JsValue::NULL, &action) {
+                Ok(verdict) => match verdict.as_string().as_deref() {
+                    Some("deny") => AuthResult::Deny,
+                    Some("ignore") => AuthResult::Ignore,
+                    _ => AuthResult::Ok,
+                },
+                Err(_) => AuthResult::Deny,
+            }
+        }));
+    }
+
+    #[wasm_bindgen(js_name = "clearAuthorizer")]
+    pub fn clear_authorizer(&self) {
+        self.state.clear_authorizer();
+    }
+
+    /// Iterator over `{ queryId, sql, priority, sentAtMs }` objects for each
+    /// pending query, oldest first. The objects are copies.
+    #[wasm_bindgen(js_name = "pendingQueriesIter")]
+    pub fn pending_queries_iter(&self) -> js_sys::Iterator {
+        let array = js_sys::Array::new();
+        for info in self.state.pending_query_info() {
+            if let Ok(value) = serde_wasm_bindgen::to_value(&info) {
+                array.push(&value);
+            }
+        }
+        array.values()
+    }
+
+    /// The query that has waited longest for a response, as in
+    /// `pendingQueriesIter`
+    #[wasm_bindgen(js_name = "oldestPendingQuery")]
+    pub fn oldest_pending_query(&self) -> Option<js_sys::Object> {
+        let oldest = self
+            .state
+            .pending_query_info()
+            .into_iter()
+            .min_by(|a, b| a.sent_at_ms.total_cmp(&b.sent_at_ms))?;
+        serde_wasm_bindgen::to_value(&oldest)
+            .ok()
+            .map(JsCast::unchecked_into)
+    }
+
+    /// Hold back queries until `resume` is called
+    pub fn pause(&self) {
+        self.state.pause_dispatch();
+    }
+
+    pub fn resume(&self) {
+        self.state.resume_dispatch();
+    }
+
+    /// Resolves with the `EXPLAIN QUERY PLAN` steps for `sql` as an array of
+    /// `{ id, parentId, detail }` objects
+    pub fn explain(&self, sql: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            let plan = state.explain_query_plan(sql).await?;
+            serde_wasm_bindgen::to_value(&plan).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Resolves if `sql` is complete and compiles, without running it, and
+    /// rejects with the syntax error otherwise
+    #[wasm_bindgen(js_name = "validateSql")]
+    pub fn validate_sql(&self, sql: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            state.validate_sql(sql).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Resolve with the result of running `sql` on the leader, calling `cb`
+    /// with the instructions run so far about every `progress_interval` of
+    /// them. Returning `true` from `cb` interrupts the query.
+    #[wasm_bindgen(js_name = "queryWithProgress")]
+    pub fn query_with_progress(
+        &self,
+        sql: String,
+        progress_interval: u32,
+        cb: js_sys::Function,
+    ) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            let result = state
+                .query_with_progress(sql, progress_interval, cb)
+                .await?;
+            serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Back up the leader's database into the IndexedDB database `name`,
+    /// calling `on_progress` with `(pagesCopied, pageCount)` as it goes
+    #[wasm_bindgen(js_name = "copyToIndexedDb")]
+    pub fn copy_to_indexeddb(
+        &self,
+        name: String,
+        on_progress: Option<js_sys::Function>,
+    ) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            state
+                .copy_to_indexeddb(&name, |copied, total| {
+                    if let Some(cb) = &on_progress {
+                        let _ = cb.call2(&JsValue::NULL, &copied.into(), &total.into());
+                    }
+                })
+                .await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Resolve with the indexes on `table` as an array of
+    /// `{ name, columns, unique, partial }` objects
+    #[wasm_bindgen(js_name = "listIndexes")]
+    pub fn list_indexes(&self, table: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            let indexes = state.list_indexes(&table).await?;
+            serde_wasm_bindgen::to_value(&indexes).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Resolve with the triggers as an array of
+    /// `{ name, table, event, timing, sql }` objects, only those on `table`
+    /// if given
+    #[wasm_bindgen(js_name = "listTriggers")]
+    pub fn list_triggers(&self, table: Option<String>) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            let triggers = state.list_triggers(table.as_deref()).await?;
+            serde_wasm_bindgen::to_value(&triggers).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Resolve with every row of `table` as a CSV string, starting with a
+    /// header record of column names if `headers` is true
+    #[wasm_bindgen(js_name = "exportCsv")]
+    pub fn export_csv(&self, table: String, headers: bool) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.export_to_csv(table, headers).await?.into()) })
+    }
+
+    /// Resolve with the `CREATE TABLE` statement for `table`
+    #[wasm_bindgen(js_name = "getTableDdl")]
+    pub fn get_table_ddl(&self, table: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.get_table_ddl(&table).await?.into()) })
+    }
+
+    /// Resolve with the `CREATE INDEX` statement for `index`
+    #[wasm_bindgen(js_name = "getIndexDdl")]
+    pub fn get_index_ddl(&self, index: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.get_index_ddl(&index).await?.into()) })
+    }
+
+    /// Resolve with the `CREATE TRIGGER` statement for `trigger`
+    #[wasm_bindgen(js_name = "getTriggerDdl")]
+    pub fn get_trigger_ddl(&self, trigger: String) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.get_trigger_ddl(&trigger).await?.into()) })
+    }
+
+    /// Rebuild `index_name`, or every index if it is omitted, then check the
+    /// database's integrity
+    pub fn reindex(&self, index_name: Option<String>) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            state.reindex(index_name.as_deref()).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Poll `sql` every `interval_ms`, calling `callback` with the result
+    /// whenever it changes. Returns an id to pass to `unwatch`.
+    pub fn watch(
+        &self,
+        sql: String,
+        interval_ms: u32,
+        callback: js_sys::Function,
+    ) -> Result<String, JsValue> {
+        let watcher_id = self
+            .state
+            .watch_query(sql, u64::from(interval_ms), move |result| {
+                if let Ok(value) = serde_wasm_bindgen::to_value(&result) {
+                    let _ = callback.call1(&JsValue::NULL, &value);
+                }
+            })?;
+        Ok(watcher_id)
+    }
+
+    /// Call `callback` with the rows inserted into `table`, checking every
+    /// `poll_ms`. Returns a handle whose `stop` ends the watch.
+    #[wasm_bindgen(js_name = "watchTable")]
+    pub fn watch_table(
+        &self,
+        table: String,
+        poll_ms: u32,
+        callback: js_sys::Function,
+    ) -> Result<WatchHandle, JsValue> {
+        let handle = self
+            .state
+            .watch_table(&table, u64::from(poll_ms), move |rows| {
+                if let Ok(value) = serde_wasm_bindgen::to_value(&rows) {
+                    let _ = callback.call1(&JsValue::NULL, &value);
+                }
+            })?;
+        Ok(handle)
+    }
+
+    /// Stop the watcher with id `id`
+    pub fn unwatch(&self, id: String) {
+        self.state.unwatch_query(&id);
+    }
+
+    /// Resolves with the database's `PRAGMA application_id`
+    #[wasm_bindgen(js_name = "getApplicationId")]
+    pub fn get_application_id(&self) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.get_application_id().await?.into()) })
+    }
+
+    #[wasm_bindgen(js_name = "setApplicationId")]
+    pub fn set_application_id(&self, id: i32) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            state.set_application_id(id).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Resolves with the database's `PRAGMA user_version`
+    #[wasm_bindgen(js_name = "getUserVersion")]
+    pub fn get_user_version(&self) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move { Ok(state.get_user_version().await?.into()) })
+    }
+
+    #[wasm_bindgen(js_name = "setUserVersion")]
+    pub fn set_user_version(&self, version: i32) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            state.set_user_version(version).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Resolve with `{ usageBytes, quotaBytes }` from `navigator.storage.estimate()`.
+    /// Queries that run out of space reject with an error named
+    /// `QuotaExceededError`.
+    #[wasm_bindgen(js_name = "estimatedStorageQuota")]
+    pub fn estimated_storage_quota(&self) -> Promise {
+        let state = Rc::clone(&self.state);
+        future_to_promise(async move {
+            let quota = state.estimated_storage_quota().await?;
+            serde_wasm_bindgen::to_value(&quota).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Call `cb` with `{ usageBytes, quotaBytes }` whenever a check, once a
+    /// minute, finds more than `threshold` of the storage quota in use.
+    /// Returns a function that stops the checks; the worker runs one such
+    /// monitor, so calling this again replaces the previous one.
+    #[wasm_bindgen(js_name = "onStorageWarning")]
+    pub fn on_storage_warning(
+        &self,
+        threshold: f64,
+        cb: js_sys::Function,
+    ) -> Result<js_sys::Function, JsValue> {
+        self.state.start_storage_monitor(threshold, move |quota| {
+            if let Ok(value) = serde_wasm_bindgen::to_value(&quota) {
+                let _ = cb.call1(&JsValue::NULL, &value);
+            }
+        })?;
+        let state = Rc::clone(&self.state);
+        let cancel = Closure::<dyn FnMut()>::new(move || state.stop_storage_monitor());
+        Ok(cancel.into_js_value().unchecked_into())
+    }
+
+    /// SQLite memory and cache counters from the leader's latest response to a
+    /// query from this worker
+    #[wasm_bindgen(js_name = "lastQueryStats")]
+    pub fn last_query_stats(&self) -> Option<js_sys::Object> {
+        let stats = self.state.last_query_stats()?;
+        serde_wasm_bindgen::to_value(&stats)
+            .ok()
+            .map(JsCast::unchecked_into)
+    }
+
+    /// Dispatch `sqlite-query-complete`, `sqlite-leader-changed` and
+    /// `sqlite-error` `CustomEvent`s on the global object
+    #[wasm_bindgen(js_name = "enableDomEvents")]
+    pub fn enable_dom_events(&self, enabled: bool) {
+        self.state.enable_dom_events(enabled);
+    }
+
+    /// Milliseconds this worker waited for the leadership lock, or `undefined`
+    /// if it has not become leader
+    #[wasm_bindgen(js_name = "leadershipAcquisitionTime")]
+    pub fn leadership_acquisition_time(&self) -> Option<f64> {
+        self.state.leadership_acquisition_ms.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, sleep};
+    use crate::messages::{ChannelMessage, MessageDirection};
+    use js_sys::{Function, Reflect};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_dump_message_log_empty() {
+        if let Ok(state) = WorkerState::new() {
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            assert_eq!(api.dump_message_log(), "[]");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_queue_depth() {
+        if let Ok(state) = WorkerState::new() {
+            let resolve = Function::new_no_args("return 'resolved';");
+            let reject = Function::new_no_args("return 'rejected';");
+            state.pending_queries.borrow_mut().insert(
+                "query-1".to_string(),
+                crate::messages::PendingQuery::new(resolve, reject)
+                    .with_priority(crate::priority::QueryPriority::Low),
+            );
+
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            let depth: PriorityCounts =
+                serde_wasm_bindgen::from_value(api.queue_depth()).expect("Should be an object");
+            assert_eq!(
+                depth,
+                PriorityCounts {
+                    high: 0,
+                    normal: 0,
+                    low: 1
+                }
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_explain_resolves_with_plan() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let api = SQLiteWorkerApi::new(Rc::new(state));
+        let plan = wasm_bindgen_futures::JsFuture::from(api.explain("SELECT 1".to_string()))
+            .await
+            .expect("Explain should resolve");
+        let plan: Vec<crate::explain::ExplainRow> =
+            serde_wasm_bindgen::from_value(plan).expect("Should be an array of plan rows");
+        assert!(!plan.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_list_indexes_rejects_invalid_table() {
+        if let Ok(state) = WorkerState::new() {
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            let result =
+                wasm_bindgen_futures::JsFuture::from(api.list_indexes("bad name".to_string()))
+                    .await;
+            assert!(result.is_err());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_watch_calls_back_until_unwatched() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let api = SQLiteWorkerApi::new(Rc::new(state));
+        let seen = js_sys::Array::new();
+        let push = Function::new_with_args("result", "this.push(result);").bind(&seen);
+        let id = api
+            .watch("SELECT 1 AS value".to_string(), 20, push)
+            .expect("Should start watching");
+        sleep(60.0).await;
+        assert_eq!(seen.length(), 1, "An unchanging result is delivered once");
+
+        api.unwatch(id);
+        assert!(api.state.watchers.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_user_version_roundtrip() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let api = SQLiteWorkerApi::new(Rc::new(state));
+        wasm_bindgen_futures::JsFuture::from(api.set_user_version(7))
+            .await
+            .expect("Set should resolve");
+        let version = wasm_bindgen_futures::JsFuture::from(api.get_user_version())
+            .await
+            .expect("Get should resolve");
+        assert_eq!(version.as_f64(), Some(7.0));
+        wasm_bindgen_futures::JsFuture::from(api.set_user_version(0))
+            .await
+            .expect("Reset should resolve");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pending_queries_iter() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let api = SQLiteWorkerApi::new(Rc::new(state));
+        assert!(api.oldest_pending_query().is_none());
+
+        let noop = Function::new_no_args("");
+        for (query_id, sent_at_ms) in [("query-new", 200.0), ("query-old", 100.0)] {
+            let mut pending = crate::messages::PendingQuery::new(noop.clone(), noop.clone())
+                .with_request(ChannelMessage::QueryRequest {
+                    query_id: query_id.to_string(),
+                    sql: format!("SELECT '{query_id}'"),
+                    origin_worker_id: String::new(),
+                    metadata: Default::default(),
+                    source_location: None,
+                    read_concern: None,
+                    progress_interval: None,
+                });
+            pending.sent_at_ms = sent_at_ms;
+            api.state()
+                .pending_queries
+                .borrow_mut()
+                .insert(query_id.to_string(), pending);
+        }
+
+        let entries: Vec<JsValue> = js_sys::try_iter(&api.pending_queries_iter())
+            .unwrap()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(entries.len(), 2);
+        let id = |entry: &JsValue| Reflect::get(entry, &JsValue::from_str("queryId")).unwrap();
+        assert_eq!(id(&entries[0]).as_string().as_deref(), Some("query-new"));
+        assert_eq!(
+            Reflect::get(&entries[0], &JsValue::from_str("sql"))
+                .unwrap()
+                .as_string()
+                .as_deref(),
+            Some("SELECT 'query-new'")
+        );
+
+        // Mutating a returned object leaves the Rust side untouched
+        Reflect::set(
+            &entries[0],
+            &JsValue::from_str("queryId"),
+            &JsValue::from_str("x"),
+        )
+        .unwrap();
+        let oldest = api
+            .oldest_pending_query()
+            .expect("Should have an oldest query");
+        assert_eq!(id(&oldest).as_string().as_deref(), Some("query-old"));
+        assert_eq!(
+            api.state().dump_pending_queries(),
+            ["query-new", "query-old"]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_on_error_forwards_to_js() {
+        if let Ok(state) = WorkerState::new() {
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            let seen = js_sys::Array::new();
+            let push = Function::new_with_args("err", "this.push(err);").bind(&seen);
+            api.on_error(push);
+
+            api.state()
+                .report_error(crate::error::WorkerError::NotLeader);
+            assert_eq!(seen.length(), 1);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pause_and_resume() {
+        if let Ok(state) = WorkerState::new() {
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            api.pause();
+            assert!(api.state().is_dispatch_paused());
+            api.resume();
+            assert!(!api.state().is_dispatch_paused());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_leadership_acquisition_time() {
+        if let Ok(state) = WorkerState::new() {
+            let api = SQLiteWorkerApi::new(Rc::new(state));
+            assert_eq!(api.leadership_acquisition_time(), None);
+
+            api.state().leadership_acquisition_ms.set(Some(12.5));
+            assert_eq!(api.leadership_acquisition_time(), Some(12.5));
+            assert_eq!(
+                api.state().health_report().leadership_acquisition_ms,
+                Some(12.5)
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_dump_message_log_records_both_directions() {
+        let (Ok(receiver), Ok(sender)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        receiver.setup_channel_listener();
+        receiver
+            .broadcast(ChannelMessage::NewLeader {
+                leader_id: receiver.worker_id.clone(),
+            })
+            .unwrap();
+        sender
+            .broadcast(ChannelMessage::NewLeader {
+                leader_id: sender.worker_id.clone(),
+            })
+            .unwrap();
+        sleep(50.0).await;
+
+        let api = SQLiteWorkerApi::new(Rc::new(receiver));
+        let log = api.state().message_log();
+        assert_eq!(log[0].direction, MessageDirection::Sent);
+        assert!(log
+            .iter()
+            .any(|entry| entry.direction == MessageDirection::Received));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&api.dump_message_log()).expect("Should be valid JSON");
+        let entries = json.as_array().expect("Should be a JSON array");
+        assert_eq!(entries.len(), log.len());
+        assert_eq!(entries[0]["direction"], "sent");
+        assert_eq!(entries[0]["message"]["type"], "new-leader");
+    }
+}
@@ -0,0 +1,225 @@
+use serde::Serialize;
+use sqlite_wasm_rs::export::{SQLITE_DENY, SQLITE_IGNORE, SQLITE_OK};
+use std::cell::RefCell;
+use std::ffi::{c_int, c_void, CStr};
+use std::os::raw::c_char;
+use std::rc::Rc;
+
+use crate::coordination::WorkerState;
+
+/// One action SQLite asks permission for while preparing a statement, as
+/// passed to `sqlite3_set_authorizer`. The meaning of `arg1` and `arg2`
+/// depends on `action_code`: for `SQLITE_READ` they are the table and column.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuthAction {
+    #[serde(rename = "actionCode")]
+    pub action_code: i32,
+    pub arg1: Option<String>,
+    pub arg2: Option<String>,
+    #[serde(rename = "dbName")]
+    pub db_name: Option<String>,
+    // Innermost trigger or view behind the access, if any
+    pub trigger: Option<String>,
+}
+
+/// An authorizer's verdict on an `AuthAction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthResult {
+    Ok,
+    // Fail the statement with `SQLITE_AUTH`
+    Deny,
+    // Let the statement run, reading NULL for a denied column or skipping
+    // the action where SQLite allows it
+    Ignore,
+}
+
+impl AuthResult {
+    fn code(self) -> c_int {
+        match self {
+            AuthResult::Ok => SQLITE_OK,
+            AuthResult::Deny => SQLITE_DENY,
+            AuthResult::Ignore => SQLITE_IGNORE,
+        }
+    }
+}
+
+pub type Authorizer = Rc<dyn Fn(AuthAction) -> AuthResult>;
+
+// Authorizer a worker installs on its connection whenever it leads
+pub type SharedAuthorizer = Rc<RefCell<Option<Authorizer>>>;
+
+unsafe fn optional_str(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+}
+
+pub(crate) unsafe extern "C" fn authorizer_callback(
+    user_data: *mut c_void,
+    action_code: c_int,
+    arg1: *const c_char,
+    arg2: *const c_char,
+    db_name: *const c_char,
+    trigger: *const c_char,
+) -> c_int {
+    let authorizer = &*(user_data as *const Authorizer);
+    authorizer(AuthAction {
+        action_code,
+        arg1: optional_str(arg1),
+        arg2: optional_str(arg2),
+        db_name: optional_str(db_name),
+        trigger: optional_str(trigger),
+    })
+    .code()
+}
+
+impl WorkerState {
+    /// Check every action of every statement prepared on this worker's
+    /// connection with `auth`, replacing any earlier authorizer. It applies
+    /// while this worker leads, so it sees queries sent by other workers too
+    /// and should return `Ok` for anything it does not restrict, including
+    /// the library's own PRAGMA and schema statements.
+    pub fn set_authorizer(&self, auth: Box<dyn Fn(AuthAction) -> AuthResult + 'static>) {
+        let auth: Authorizer = Rc::from(auth);
+        *self.authorizer.borrow_mut() = Some(Rc::clone(&auth));
+        if let Some(database) = self.db.borrow().as_ref() {
+            database.set_authorizer(Some(auth));
+        }
+    }
+
+    /// Remove the authorizer, allowing every action again
+    pub fn clear_authorizer(&self) {
+        *self.authorizer.borrow_mut() = None;
+        if let Some(database) = self.db.borrow().as_ref() {
+            database.set_authorizer(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, WorkerStateBuilder};
+    use sqlite_wasm_rs::export::{SQLITE_AUTH, SQLITE_READ};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // Deny reading `staff.salary`, allowing everything else
+    fn hide_salaries(action: AuthAction) -> AuthResult {
+        let is_salary = action.arg1.as_deref() == Some("auth_staff")
+            && action.arg2.as_deref() == Some("salary");
+        if action.action_code == SQLITE_READ && is_salary {
+            AuthResult::Deny
+        } else {
+            AuthResult::Ok
+        }
+    }
+
+    async fn leader_with_staff(state: WorkerState) -> Option<WorkerState> {
+        let state = lead_with_db(state).await?;
+        for sql in [
+            "DROP TABLE IF EXISTS auth_staff",
+            "CREATE TABLE auth_staff (name TEXT, salary INTEGER)",
+            "INSERT INTO auth_staff VALUES ('Ada', 100)",
+        ] {
+            state
+                .execute_parameterized(sql.to_string(), Vec::new())
+                .await
+                .expect("Setup failed");
+        }
+        Some(state)
+    }
+
+    #[wasm_bindgen_test]
+    fn test_authorizer_callback_forwards_action() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_clone = Rc::clone(&seen);
+        let authorizer: Authorizer = Rc::new(move |action| {
+            seen_clone.borrow_mut().push(action);
+            AuthResult::Ignore
+        });
+        let ret = unsafe {
+            authorizer_callback(
+                &authorizer as *const Authorizer as *mut c_void,
+                SQLITE_READ,
+                c"auth_staff".as_ptr(),
+                c"salary".as_ptr(),
+                c"main".as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(ret, SQLITE_IGNORE);
+        assert_eq!(
+            *seen.borrow(),
+            vec![AuthAction {
+                action_code: SQLITE_READ,
+                arg1: Some("auth_staff".to_string()),
+                arg2: Some("salary".to_string()),
+                db_name: Some("main".to_string()),
+                trigger: None,
+            }]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_select_on_restricted_column_is_denied() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let Some(state) = leader_with_staff(state).await else {
+            return;
+        };
+        state.set_authorizer(Box::new(hide_salaries));
+
+        let denied = state
+            .execute_parameterized("SELECT salary FROM auth_staff".to_string(), Vec::new())
+            .await
+            .expect_err("Salary should be hidden");
+        assert_eq!(denied.sqlite_code(), Some(SQLITE_AUTH));
+        assert!(state
+            .execute_parameterized("SELECT name FROM auth_staff".to_string(), Vec::new())
+            .await
+            .is_ok());
+
+        state.clear_authorizer();
+        assert!(state
+            .execute_parameterized("SELECT salary FROM auth_staff".to_string(), Vec::new())
+            .await
+            .is_ok());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_ignored_column_reads_null() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let Some(state) = leader_with_staff(state).await else {
+            return;
+        };
+        state.set_authorizer(Box::new(|action| match hide_salaries(action) {
+            AuthResult::Deny => AuthResult::Ignore,
+            verdict => verdict,
+        }));
+
+        let (name, salary): (String, Option<i64>) = state
+            .execute_parameterized(
+                "SELECT name, salary FROM auth_staff".to_string(),
+                Vec::new(),
+            )
+            .await
+            .and_then(|result| result.get_single())
+            .expect("Query failed");
+        assert_eq!((name.as_str(), salary), ("Ada", None));
+        state.clear_authorizer();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_builder_authorizer_is_kept_for_leadership() {
+        let Ok(state) = WorkerStateBuilder::new()
+            .authorizer(Box::new(hide_salaries))
+            .build()
+        else {
+            return;
+        };
+        assert!(state.authorizer.borrow().is_some());
+    }
+}
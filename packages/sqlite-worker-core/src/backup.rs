@@ -0,0 +1,137 @@
+use crate::database::{DatabaseBackup, DB_FILE_NAME};
+use crate::error::WorkerError;
+
+/// Progress of a `WorkerState::copy_database_to` backup. Dropping the handle
+/// abandons the copy, leaving the target partially written.
+pub struct BackupHandle {
+    // None once the leader has run the whole backup on another worker's behalf
+    backup: Option<DatabaseBackup>,
+}
+
+impl BackupHandle {
+    pub(crate) fn new(backup: DatabaseBackup) -> Self {
+        BackupHandle {
+            backup: Some(backup),
+        }
+    }
+
+    pub(crate) fn completed() -> Self {
+        BackupHandle { backup: None }
+    }
+
+    /// Copy up to `pages` pages, or all of them if negative. Returns true
+    /// once the whole database has been copied.
+    pub fn step(&mut self, pages: i32) -> Result<bool, WorkerError> {
+        match &self.backup {
+            Some(backup) => Ok(backup.step(pages)?),
+            None => Ok(true),
+        }
+    }
+
+    /// Pages still to copy, or 0 if the copy ran elsewhere
+    pub fn remaining(&self) -> i32 {
+        self.backup.as_ref().map_or(0, DatabaseBackup::remaining)
+    }
+
+    pub fn finish(self) -> Result<(), WorkerError> {
+        match self.backup {
+            Some(backup) => Ok(backup.finish()?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A backup target must be a plain file name in the OPFS pool other than the
+/// live database
+pub fn validate_backup_target(target_name: &str) -> Result<(), WorkerError> {
+    let valid = !target_name.is_empty()
+        && target_name != DB_FILE_NAME
+        && target_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(WorkerError::InvalidBackupTarget(target_name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, WorkerState};
+    use crate::database::SQLiteDatabase;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_validate_backup_target() {
+        for name in ["archive.db", "backup-2024_01.sqlite"] {
+            assert_eq!(validate_backup_target(name), Ok(()));
+        }
+        for name in ["", "worker.db", "../escape.db", "opfs-sahpool:x.db"] {
+            assert_eq!(
+                validate_backup_target(name),
+                Err(WorkerError::InvalidBackupTarget(name.to_string()))
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_copy_database_to_in_steps() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS backup_items".to_string(),
+                "CREATE TABLE backup_items (id INTEGER, payload TEXT)".to_string(),
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50) \
+                 INSERT INTO backup_items SELECT i, hex(randomblob(512)) FROM n"
+                    .to_string(),
+            ])
+            .await
+            .expect("Seed failed");
+
+        let mut handle = state
+            .copy_database_to("backup-test.db")
+            .await
+            .expect("Backup should start");
+        let mut steps = 0;
+        while !handle.step(1).expect("Step failed") {
+            steps += 1;
+        }
+        assert!(steps > 0, "Copy should take several single-page steps");
+        assert_eq!(handle.remaining(), 0);
+        handle.finish().expect("Finish failed");
+
+        let copy = SQLiteDatabase::open_file("backup-test.db").expect("Open copy failed");
+        let result = copy
+            .query("SELECT count(*) FROM backup_items")
+            .await
+            .expect("Copy should contain the table");
+        assert_eq!(result.rows, vec![vec![50i64.into()]]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_copy_database_to_from_follower() {
+        let (Some(leader), Ok(follower)) = (leader_with_db().await, WorkerState::new()) else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let mut handle = follower
+            .copy_database_to("backup-follower.db")
+            .await
+            .expect("Leader should run the backup");
+        assert_eq!(handle.step(1), Ok(true));
+        assert_eq!(handle.finish(), Ok(()));
+
+        assert_eq!(
+            follower.copy_database_to("worker.db").await.err(),
+            Some(WorkerError::InvalidBackupTarget("worker.db".to_string()))
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use sqlite_wasm_rs::export::{SQLITE_BUSY, SQLITE_LOCKED};
+use std::future::Future;
+
+use crate::coordination::{console_debug, sleep};
+use crate::error::WorkerError;
+
+pub const DEFAULT_BUSY_MAX_RETRIES: usize = 3;
+pub const DEFAULT_BUSY_BACKOFF_MS: u64 = 50;
+
+// Whether `error` means another connection held a lock, so the statement may
+// succeed if run again
+fn is_busy(error: &WorkerError) -> bool {
+    error
+        .sqlite_code()
+        .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+}
+
+// Reruns the leader's statements that fail with `SQLITE_BUSY` or
+// `SQLITE_LOCKED`, such as while a checkpoint holds the lock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BusyRetry {
+    pub(crate) max_retries: usize,
+    pub(crate) backoff_ms: u64,
+}
+
+impl BusyRetry {
+    // Run `attempt`, waiting `backoff_ms` and running it again after each busy
+    // failure, up to `max_retries` times
+    pub(crate) async fn run<T, F, Fut>(&self, mut attempt: F) -> Result<T, WorkerError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, WorkerError>>,
+    {
+        let mut retries = 0;
+        loop {
+            match attempt().await {
+                Err(err) if retries < self.max_retries && is_busy(&err) => {
+                    retries += 1;
+                    console_debug(&format!(
+                        "Database busy, retry {retries} of {} in {}ms: {err}",
+                        self.max_retries, self.backoff_ms
+                    ));
+                    sleep(self.backoff_ms as f64).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    const RETRY: BusyRetry = BusyRetry {
+        max_retries: 3,
+        backoff_ms: 1,
+    };
+
+    fn busy() -> WorkerError {
+        WorkerError::Busy {
+            message: "database is locked".to_string(),
+        }
+    }
+
+    // A database call that fails with `error` the first `failures` times
+    async fn run_failing(failures: usize, error: WorkerError) -> (Result<u32, WorkerError>, usize) {
+        let attempts = Cell::new(0);
+        let result = RETRY
+            .run(|| {
+                attempts.set(attempts.get() + 1);
+                let result = if attempts.get() <= failures {
+                    Err(error.clone())
+                } else {
+                    Ok(7)
+                };
+                async move { result }
+            })
+            .await;
+        (result, attempts.get())
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_busy_statements_are_retried() {
+        assert_eq!(run_failing(0, busy()).await, (Ok(7), 1));
+        assert_eq!(run_failing(2, busy()).await, (Ok(7), 3));
+        assert_eq!(run_failing(3, busy()).await, (Ok(7), 4));
+
+        let locked = WorkerError::from_sqlite_code(SQLITE_LOCKED, "table is locked".to_string());
+        assert_eq!(run_failing(1, locked).await, (Ok(7), 2));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_busy_retries_give_up() {
+        assert_eq!(run_failing(4, busy()).await, (Err(busy()), 4));
+
+        let no_retries = BusyRetry {
+            max_retries: 0,
+            backoff_ms: 1,
+        };
+        let attempts = Cell::new(0);
+        let result: Result<(), _> = no_retries
+            .run(|| {
+                attempts.set(attempts.get() + 1);
+                async { Err(busy()) }
+            })
+            .await;
+        assert_eq!((result, attempts.get()), (Err(busy()), 1));
+
+        // Other failures are returned straight away
+        let failed = WorkerError::sqlite("no such table: missing".to_string());
+        assert_eq!(run_failing(1, failed.clone()).await, (Err(failed), 1));
+    }
+}
@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::SqlParam;
+use crate::row::FromRow;
+use crate::triggers::TriggerEvent;
+
+// Milliseconds since the Unix epoch, evaluated by the triggers
+const NOW_MS_SQL: &str = "(julianday('now') - 2440587.5) * 86400000.0";
+const TRACKED_EVENTS: [TriggerEvent; 3] = [
+    TriggerEvent::Insert,
+    TriggerEvent::Update,
+    TriggerEvent::Delete,
+];
+
+/// A change to a tracked table, recorded by the triggers
+/// `enable_change_tracking` creates
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChangeRecord {
+    // The rowid of the changed row
+    #[serde(rename = "rowId")]
+    pub row_id: i64,
+    pub operation: TriggerEvent,
+    #[serde(rename = "changedAtMs")]
+    pub changed_at_ms: f64,
+    // The row as a JSON object keyed by column before the change, for an
+    // update or delete
+    #[serde(rename = "oldData")]
+    pub old_data: Option<serde_json::Value>,
+    // The row after the change, for an insert or update
+    #[serde(rename = "newData")]
+    pub new_data: Option<serde_json::Value>,
+}
+
+// The table `table`'s changes are recorded in
+fn changes_table(table: &str) -> String {
+    format!("__changes_{table}")
+}
+
+fn event_name(event: TriggerEvent) -> &'static str {
+    match event {
+        TriggerEvent::Insert => "insert",
+        TriggerEvent::Update => "update",
+        TriggerEvent::Delete => "delete",
+    }
+}
+
+fn parse_event(name: &str) -> Result<TriggerEvent, WorkerError> {
+    TRACKED_EVENTS
+        .into_iter()
+        .find(|&event| event_name(event) == name)
+        .ok_or_else(|| WorkerError::RowDecode(format!("unknown change operation {name}")))
+}
+
+fn trigger_name(table: &str, event: TriggerEvent) -> String {
+    format!("{}_{}", changes_table(table), event_name(event))
+}
+
+// `json_object` of every column of the `OLD` or `NEW` row. `json_object`
+// rejects BLOBs, so they are recorded as hex strings.
+fn row_json(row: &str, columns: &[String]) -> String {
+    let pairs: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let value = format!("{row}.\"{}\"", column.replace('"', "\"\""));
+            format!(
+                "'{}', CASE typeof({value}) WHEN 'blob' THEN hex({value}) ELSE {value} END",
+                column.replace('\'', "''"),
+            )
+        })
+        .collect();
+    format!("json_object({})", pairs.join(", "))
+}
+
+fn create_trigger(table: &str, event: TriggerEvent, columns: &[String]) -> String {
+    let (row, old_data, new_data) = match event {
+        TriggerEvent::Insert => ("NEW", "NULL".to_string(), row_json("NEW", columns)),
+        TriggerEvent::Update => ("NEW", row_json("OLD", columns), row_json("NEW", columns)),
+        TriggerEvent::Delete => ("OLD", row_json("OLD", columns), "NULL".to_string()),
+    };
+    format!(
+        "CREATE TRIGGER {trigger} AFTER {operation} ON {table} BEGIN \
+         INSERT INTO {changes} (rowid, operation, changed_at_ms, old_data_json, new_data_json) \
+         VALUES ({row}.rowid, '{name}', {NOW_MS_SQL}, {old_data}, {new_data}); END",
+        trigger = trigger_name(table, event),
+        operation = event_name(event).to_ascii_uppercase(),
+        changes = changes_table(table),
+        name = event_name(event),
+    )
+}
+
+fn parse_json(json: Option<String>) -> Result<Option<serde_json::Value>, WorkerError> {
+    json.map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|e| WorkerError::RowDecode(e.to_string()))
+}
+
+impl WorkerState {
+    /// Record every insert, update and delete on `table` in a shadow table
+    /// named `__changes_{table}`, filled by `AFTER` triggers with the rowid,
+    /// the operation, the time and the old and new rows as JSON, with BLOBs as
+    /// hex strings. Calling it again keeps the recorded changes and recreates
+    /// the triggers, picking up columns added since. Tables without a rowid
+    /// cannot be tracked.
+    pub async fn enable_change_tracking(&self, table: &str) -> Result<(), WorkerError> {
+        validate_identifier(table)?;
+        let columns: Vec<String> = self
+            .execute_parameterized(
+                "SELECT name FROM pragma_table_info(?) ORDER BY cid".to_string(),
+                vec![SqlParam::from(table)],
+            )
+            .await?
+            .into_iter()
+            .map(|row| FromRow::from_row(&row).map(|(name,): (String,)| name))
+            .collect::<Result<_, _>>()?;
+        if columns.is_empty() {
+            return Err(WorkerError::TableNotFound(table.to_string()));
+        }
+
+        let mut ddls = vec![format!(
+            "CREATE TABLE IF NOT EXISTS {} (rowid INTEGER, operation TEXT, \
+             changed_at_ms REAL, old_data_json TEXT, new_data_json TEXT)",
+            changes_table(table)
+        )];
+        for event in TRACKED_EVENTS {
+            ddls.push(format!(
+                "DROP TRIGGER IF EXISTS {}",
+                trigger_name(table, event)
+            ));
+            ddls.push(create_trigger(table, event, &columns));
+        }
+        self.execute_ddl_transaction(ddls).await
+    }
+
+    /// The changes recorded on `table` after `since_ms` milliseconds since the
+    /// Unix epoch, oldest first
+    pub async fn get_changes_since(
+        &self,
+        table: &str,
+        since_ms: f64,
+    ) -> Result<Vec<ChangeRecord>, WorkerError> {
+        validate_identifier(table)?;
+        let sql = format!(
+            "SELECT rowid, operation, changed_at_ms, old_data_json, new_data_json FROM {} \
+             WHERE changed_at_ms > ? ORDER BY changed_at_ms, _rowid_",
+            changes_table(table)
+        );
+        self.execute_parameterized(sql, vec![SqlParam::Real(since_ms)])
+            .await?
+            .into_iter()
+            .map(|row| {
+                let (row_id, operation, changed_at_ms, old_data, new_data): (
+                    i64,
+                    String,
+                    f64,
+                    Option<String>,
+                    Option<String>,
+                ) = FromRow::from_row(&row)?;
+                Ok(ChangeRecord {
+                    row_id,
+                    operation: parse_event(&operation)?,
+                    changed_at_ms,
+                    old_data: parse_json(old_data)?,
+                    new_data: parse_json(new_data)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Stop tracking `table`, dropping its triggers and the recorded changes
+    pub async fn disable_change_tracking(&self, table: &str) -> Result<(), WorkerError> {
+        validate_identifier(table)?;
+        let mut ddls: Vec<String> = TRACKED_EVENTS
+            .into_iter()
+            .map(|event| format!("DROP TRIGGER IF EXISTS {}", trigger_name(table, event)))
+            .collect();
+        ddls.push(format!("DROP TABLE IF EXISTS {}", changes_table(table)));
+        self.execute_ddl_transaction(ddls).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use serde_json::json;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    async fn run(state: &WorkerState, sql: &str) {
+        state
+            .execute_parameterized(sql.to_string(), Vec::new())
+            .await
+            .unwrap_or_else(|e| panic!("{sql} failed: {e:?}"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_change_tracking_records_rows() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let _ = state.disable_change_tracking("tracked_items").await;
+        run(&state, "DROP TABLE IF EXISTS tracked_items").await;
+        run(
+            &state,
+            "CREATE TABLE tracked_items (id INTEGER PRIMARY KEY, name TEXT)",
+        )
+        .await;
+        state
+            .enable_change_tracking("tracked_items")
+            .await
+            .expect("Enable failed");
+
+        run(&state, "INSERT INTO tracked_items VALUES (1, 'apple')").await;
+        run(
+            &state,
+            "UPDATE tracked_items SET name = 'pear' WHERE id = 1",
+        )
+        .await;
+        run(&state, "DELETE FROM tracked_items WHERE id = 1").await;
+
+        let changes = state
+            .get_changes_since("tracked_items", 0.0)
+            .await
+            .expect("Reading changes failed");
+        let operations: Vec<TriggerEvent> = changes.iter().map(|change| change.operation).collect();
+        assert_eq!(
+            operations,
+            vec![
+                TriggerEvent::Insert,
+                TriggerEvent::Update,
+                TriggerEvent::Delete
+            ]
+        );
+        assert!(changes.iter().all(|change| change.row_id == 1));
+        assert!(changes[0].changed_at_ms > 0.0);
+
+        let update = &changes[1];
+        assert_eq!(update.old_data, Some(json!({"id": 1, "name": "apple"})));
+        assert_eq!(update.new_data, Some(json!({"id": 1, "name": "pear"})));
+        let delete = &changes[2];
+        assert_eq!(delete.old_data, Some(json!({"id": 1, "name": "pear"})));
+        assert_eq!(delete.new_data, None);
+
+        let later = changes[2].changed_at_ms + 1.0;
+        assert!(state
+            .get_changes_since("tracked_items", later)
+            .await
+            .unwrap()
+            .is_empty());
+
+        state
+            .disable_change_tracking("tracked_items")
+            .await
+            .expect("Disable failed");
+        assert!(state
+            .list_triggers(Some("tracked_items"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(state.get_changes_since("tracked_items", 0.0).await.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_change_tracking_records_blobs_as_hex() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let _ = state.disable_change_tracking("tracked_files").await;
+        run(&state, "DROP TABLE IF EXISTS tracked_files").await;
+        run(
+            &state,
+            "CREATE TABLE tracked_files (id INTEGER PRIMARY KEY, data BLOB)",
+        )
+        .await;
+        state
+            .enable_change_tracking("tracked_files")
+            .await
+            .expect("Enable failed");
+
+        run(&state, "INSERT INTO tracked_files VALUES (1, x'00ff10')").await;
+        let changes = state
+            .get_changes_since("tracked_files", 0.0)
+            .await
+            .expect("Reading changes failed");
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].new_data,
+            Some(json!({"id": 1, "data": "00FF10"}))
+        );
+
+        state
+            .disable_change_tracking("tracked_files")
+            .await
+            .expect("Disable failed");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_change_tracking_needs_a_table() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        assert_eq!(
+            state.enable_change_tracking("tracked_missing").await,
+            Err(WorkerError::TableNotFound("tracked_missing".to_string()))
+        );
+        assert!(matches!(
+            state.enable_change_tracking("items; --").await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use std::cell::Cell;
+
+use sqlite_wasm_rs::export::{
+    SQLITE_CANTOPEN, SQLITE_CORRUPT, SQLITE_FULL, SQLITE_IOERR, SQLITE_NOTADB,
+};
+
+use crate::error::WorkerError;
+
+pub const DEFAULT_CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_CIRCUIT_RESET_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    // Queries run normally
+    Closed,
+    // Queries are rejected without touching the database
+    Open,
+    // One probe query is let through to test whether the database recovered
+    HalfOpen,
+}
+
+/// Stops the leader running queries after repeated consecutive failures, so a
+/// corrupt or unavailable database fails fast instead of on every request
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout_ms: u64,
+    state: Cell<CircuitState>,
+    consecutive_failures: Cell<u32>,
+    opened_at_ms: Cell<f64>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout_ms: u64) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            reset_timeout_ms,
+            state: Cell::new(CircuitState::Closed),
+            consecutive_failures: Cell::new(0),
+            opened_at_ms: Cell::new(0.0),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.get()
+    }
+
+    /// Whether a query may run at `now_ms`. Once the reset timeout has passed
+    /// an open circuit lets a single probe through and rejects the rest until
+    /// the probe's outcome is recorded.
+    pub fn allow(&self, now_ms: f64) -> Result<(), WorkerError> {
+        match self.state.get() {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open
+                if now_ms - self.opened_at_ms.get() >= self.reset_timeout_ms as f64 =>
+            {
+                self.state.set(CircuitState::HalfOpen);
+                Ok(())
+            }
+            CircuitState::Open | CircuitState::HalfOpen => Err(WorkerError::CircuitOpen),
+        }
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.set(0);
+        self.state.set(CircuitState::Closed);
+    }
+
+    pub fn record_failure(&self, now_ms: f64) {
+        let failures = self.consecutive_failures.get().saturating_add(1);
+        self.consecutive_failures.set(failures);
+        if self.state.get() == CircuitState::HalfOpen || failures >= self.failure_threshold {
+            self.state.set(CircuitState::Open);
+            self.opened_at_ms.set(now_ms);
+        }
+    }
+}
+
+/// Whether `err` means the database itself is failing, as opposed to a
+/// problem with one query such as a syntax error or a constraint violation.
+/// Only these count towards opening the circuit.
+pub(crate) fn is_infrastructure_failure(err: &WorkerError) -> bool {
+    matches!(
+        err.sqlite_code().map(|code| code & 0xff),
+        Some(SQLITE_IOERR | SQLITE_CORRUPT | SQLITE_NOTADB | SQLITE_CANTOPEN | SQLITE_FULL)
+    )
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(
+            DEFAULT_CIRCUIT_FAILURE_THRESHOLD,
+            DEFAULT_CIRCUIT_RESET_TIMEOUT_MS,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_opens_after_threshold_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, 1000);
+        breaker.record_failure(0.0);
+        breaker.record_failure(0.0);
+        breaker.record_success();
+        breaker.record_failure(0.0);
+        breaker.record_failure(0.0);
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed,
+            "Success resets the count"
+        );
+        assert_eq!(breaker.allow(0.0), Ok(()));
+
+        breaker.record_failure(10.0);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.allow(500.0), Err(WorkerError::CircuitOpen));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_only_database_failures_are_infrastructure_failures() {
+        let sqlite = |extended_code| WorkerError::from_sqlite_code(extended_code, String::new());
+        // SQLITE_IOERR_READ, SQLITE_CORRUPT, SQLITE_NOTADB, SQLITE_CANTOPEN
+        // and SQLITE_FULL
+        for code in [266, 11, 26, 14, 13] {
+            assert!(is_infrastructure_failure(&sqlite(code)), "code {code}");
+        }
+        // SQLITE_ERROR, SQLITE_CONSTRAINT_UNIQUE and SQLITE_BUSY
+        for code in [1, 2067, 5] {
+            assert!(!is_infrastructure_failure(&sqlite(code)), "code {code}");
+        }
+        assert!(!is_infrastructure_failure(&WorkerError::QueryFailed(
+            "no such table".to_string()
+        )));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_half_open_probe_closes_on_success() {
+        let breaker = CircuitBreaker::new(1, 1000);
+        breaker.record_failure(0.0);
+
+        assert_eq!(breaker.allow(1000.0), Ok(()));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(
+            breaker.allow(1000.0),
+            Err(WorkerError::CircuitOpen),
+            "Only one probe is let through"
+        );
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.allow(1000.0), Ok(()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_half_open_probe_reopens_on_failure() {
+        let breaker = CircuitBreaker::new(2, 1000);
+        breaker.record_failure(0.0);
+        breaker.record_failure(0.0);
+        assert_eq!(breaker.allow(1500.0), Ok(()));
+
+        breaker.record_failure(1500.0);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.allow(2000.0), Err(WorkerError::CircuitOpen));
+        assert_eq!(
+            breaker.allow(2500.0),
+            Ok(()),
+            "The timeout restarts on reopening"
+        );
+    }
+}
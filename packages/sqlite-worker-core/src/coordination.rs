@@ -1,431 +1,6301 @@
+use indexmap::IndexMap;
 use js_sys::{Function, Object, Promise, Reflect};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use sqlite_wasm_rs::export::SQLITE_ERROR;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
 use std::rc::Rc;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::BroadcastChannel;
 
+use crate::analysis::{analyze, AutoAnalyze};
+use crate::authorizer::{AuthAction, AuthResult, Authorizer, SharedAuthorizer};
+use crate::backup::{validate_backup_target, BackupHandle};
+use crate::busy_retry::{BusyRetry, DEFAULT_BUSY_BACKOFF_MS, DEFAULT_BUSY_MAX_RETRIES};
+use crate::circuit_breaker::{
+    is_infrastructure_failure, CircuitBreaker, DEFAULT_CIRCUIT_FAILURE_THRESHOLD,
+    DEFAULT_CIRCUIT_RESET_TIMEOUT_MS,
+};
 use crate::database::SQLiteDatabase;
-use crate::messages::{ChannelMessage, PendingQuery};
+use crate::dispatcher::{MessageDispatcher, MessageKind};
+use crate::error::WorkerError;
+use crate::events::{EventEmitter, QueryEvent};
+use crate::fallback_queue::{
+    FallbackQueue, DEFAULT_FALLBACK_QUEUE_SIZE, DEFAULT_MAX_PENDING_QUERIES,
+};
+use crate::gossip::register_gossip_handler;
+use crate::health::LeaderState;
+use crate::identifier::validate_identifier;
+use crate::locks::{acquire_named_lock, LockGuard, LockMode};
+use crate::messages::{
+    coded_query_response, query_response, BatchResult, BatchedMessage, ChannelEnvelope,
+    ChannelMessage, HandledRequests, LoggedMessage, MessageDirection, MessageReorderBuffer,
+    PendingQuery, PendingQueryInfo, SavepointAction, WorkerGossip, REORDER_GAP_TIMEOUT_MS,
+};
+use crate::micro_batch::{MicroBatch, PendingDispatch};
+use crate::observer::ConnectionObserver;
+use crate::prepared::PreparedStatement;
+use crate::priority::QueryPriority;
+use crate::query_builder::{
+    bulk_insert_chunk_rows, bulk_insert_rows, bulk_insert_sql, sql_literal,
+};
+use crate::query_cache::{QueryCache, DEFAULT_QUERY_CACHE_SIZE};
+use crate::query_context::{QueryContext, DEFAULT_QUERY_TIMEOUT_MS, PROGRESS_REPORT_INTERVAL};
+use crate::query_result::{QueryResult, SqlParam};
+use crate::query_stats::QueryStats;
+use crate::read_concern::{apply_read_concern, ReadConcern, WalCheckpoint};
+use crate::read_transaction::ReadTransaction;
+use crate::remote::open_with_remote_import;
+use crate::row::FromRow;
+use crate::shared_state::{store_leader_flag, SharedStateBuffer};
+use crate::sql_classifier::SqlClassifier;
+use crate::storage::request_persistence;
+use crate::time::{RealTimeProvider, TimeProvider};
 
-// Worker state
-pub struct WorkerState {
-    pub worker_id: String,
-    pub is_leader: Rc<RefCell<bool>>,
-    pub db: Rc<RefCell<Option<Rc<SQLiteDatabase>>>>,
-    pub channel: BroadcastChannel,
-    pub pending_queries: Rc<RefCell<HashMap<String, PendingQuery>>>,
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 4;
+pub const DEFAULT_MAX_SNAPSHOT_BYTES: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MESSAGE_LOG_SIZE: usize = 100;
+// SQLite's default SQLITE_MAX_SQL_LENGTH
+pub const DEFAULT_MAX_SQL_LENGTH: usize = 1_000_000;
+// How long `execute_query` waits for a leader to be announced before dispatching
+pub const LEADER_WAIT_MS: u64 = 1000;
+const LEADER_POLL_INITIAL_MS: u64 = 10;
+// SQLite fails immediately on a locked database unless a busy timeout is set
+pub const DEFAULT_BUSY_TIMEOUT_MS: u64 = 0;
+// How often a paused query checks whether dispatch has resumed
+const PAUSE_POLL_MS: f64 = 50.0;
+// How long `start` waits for the database to be ready before checking the
+// schema version
+pub const DATABASE_READY_TIMEOUT_MS: f64 = 10_000.0;
+// Leadership lock waits longer than this are logged as contention
+pub const SLOW_LEADERSHIP_ACQUISITION_MS: f64 = 3000.0;
+// How long `request_leader_state` waits for the leader to answer
+pub const LEADER_STATE_TIMEOUT_MS: f64 = 1000.0;
+// Requests the leader takes longer than this to execute are logged
+pub const SLOW_QUERY_MS: f64 = 1000.0;
+// Further attempts at a broadcast the channel refused, each after a short wait
+const SEND_RETRIES: u32 = 3;
+const SEND_RETRY_DELAY_MS: f64 = 10.0;
+// The only pragmas `set_pragma`, and so a `PragmaSet` from another worker, may
+// change. Anything else, like `journal_mode` or `writable_schema`, could break
+// the connection every worker shares.
+const SETTABLE_PRAGMAS: &[&str] = &[
+    "application_id",
+    "busy_timeout",
+    "foreign_keys",
+    "user_version",
+];
+
+// Callbacks registered with `WorkerState::on_error`
+pub type ErrorHandlers = Rc<RefCell<Vec<Box<dyn Fn(WorkerError)>>>>;
+
+// Handlers registered with `WorkerState::register_custom_handler`, by type name
+pub type CustomHandlers = Rc<RefCell<HashMap<String, Rc<dyn Fn(serde_json::Value)>>>>;
+
+// Callbacks registered with `WorkerState::on_database_ready`, called once
+pub type ReadyCallbacks = Rc<RefCell<Vec<Box<dyn Fn()>>>>;
+
+fn notify_ready(callbacks: &ReadyCallbacks) {
+    // Taken out first so a callback can register another
+    let callbacks = std::mem::take(&mut *callbacks.borrow_mut());
+    for callback in callbacks {
+        callback();
+    }
 }
 
-impl WorkerState {
-    pub fn new() -> Result<Self, JsValue> {
+// Reject the first statement that would change data rather than the schema
+fn check_ddl(statements: &[String]) -> Result<(), WorkerError> {
+    match statements
+        .iter()
+        .position(|sql| !SqlClassifier::is_ddl(sql))
+    {
+        Some(index) => Err(WorkerError::NotDdl {
+            index,
+            sql: statements[index].clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Settings from the config applied to each connection the leader opens
+pub(crate) fn connection_pragmas(
+    busy_timeout_ms: u64,
+    enforce_foreign_keys: Option<bool>,
+) -> Vec<String> {
+    let mut pragmas = Vec::new();
+    if busy_timeout_ms != DEFAULT_BUSY_TIMEOUT_MS {
+        pragmas.push(format!("PRAGMA busy_timeout = {busy_timeout_ms}"));
+    }
+    if let Some(enabled) = enforce_foreign_keys {
+        pragmas.push(format!("PRAGMA foreign_keys = {}", enabled as i32));
+    }
+    pragmas
+}
+
+fn report_error(handlers: &ErrorHandlers, error: WorkerError) {
+    for handler in handlers.borrow().iter() {
+        handler(error.clone());
+    }
+}
+
+// Tunable settings applied when the worker state is built
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerConfig {
+    pub max_snapshots: usize,
+    // Total size of the database copies open snapshots and read transactions hold
+    pub max_snapshot_bytes: usize,
+    pub message_log_size: usize,
+    pub max_sql_length: usize,
+    pub busy_timeout_ms: u64,
+    // Times the leader reruns a statement that fails with `SQLITE_BUSY` or
+    // `SQLITE_LOCKED`, waiting `busy_backoff_ms` before each
+    pub busy_max_retries: usize,
+    pub busy_backoff_ms: u64,
+    // Consecutive failures before the leader's circuit breaker opens
+    pub circuit_failure_threshold: u32,
+    // How long an open circuit waits before letting a probe query through
+    pub circuit_reset_timeout_ms: u64,
+    // Give up leadership while the page is hidden; see `setup_visibility_listener`
+    pub resign_on_hide: bool,
+    // Most results `execute_query_cached` keeps at once
+    pub query_cache_size: usize,
+    // Coalesce a follower's query requests made in one microtask; see `MicroBatch`
+    pub batch_dispatch: bool,
+    // `user_version` that `start` checks for before letting queries run
+    pub expected_schema_version: Option<i32>,
+    // Requests awaiting the leader's response before new ones wait their turn
+    pub max_pending_queries: usize,
+    // Requests that can wait for a pending slot before new ones are rejected
+    pub fallback_queue_size: usize,
+    // Run writes at `High` priority unless the caller chose a priority
+    pub auto_prioritize_writes: bool,
+    // `PRAGMA foreign_keys` applied when the leader opens the database
+    pub enforce_foreign_keys: Option<bool>,
+    // Declare tables created through `create_table` and
+    // `execute_ddl_transaction` as `STRICT`
+    pub strict_tables: bool,
+    // Let `reset_database` delete the database file
+    pub allow_reset: bool,
+    // Ask for persistent storage on becoming leader
+    pub request_persistence: bool,
+    // Leader writes between automatic `ANALYZE` runs, or 0 for none
+    pub auto_analyze_after_writes: u64,
+    // SQLite file the leader fills an empty database from
+    pub remote_database_url: Option<String>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+            max_snapshot_bytes: DEFAULT_MAX_SNAPSHOT_BYTES,
+            message_log_size: DEFAULT_MESSAGE_LOG_SIZE,
+            max_sql_length: DEFAULT_MAX_SQL_LENGTH,
+            busy_timeout_ms: DEFAULT_BUSY_TIMEOUT_MS,
+            busy_max_retries: DEFAULT_BUSY_MAX_RETRIES,
+            busy_backoff_ms: DEFAULT_BUSY_BACKOFF_MS,
+            circuit_failure_threshold: DEFAULT_CIRCUIT_FAILURE_THRESHOLD,
+            circuit_reset_timeout_ms: DEFAULT_CIRCUIT_RESET_TIMEOUT_MS,
+            resign_on_hide: false,
+            query_cache_size: DEFAULT_QUERY_CACHE_SIZE,
+            batch_dispatch: false,
+            expected_schema_version: None,
+            max_pending_queries: DEFAULT_MAX_PENDING_QUERIES,
+            fallback_queue_size: DEFAULT_FALLBACK_QUEUE_SIZE,
+            auto_prioritize_writes: false,
+            enforce_foreign_keys: None,
+            strict_tables: false,
+            allow_reset: false,
+            request_persistence: false,
+            auto_analyze_after_writes: 0,
+            remote_database_url: None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct WorkerStateBuilder {
+    config: WorkerConfig,
+    observer: Option<Rc<dyn ConnectionObserver>>,
+    time: Option<Rc<dyn TimeProvider>>,
+    authorizer: Option<Authorizer>,
+}
+
+impl WorkerStateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of read snapshots the leader keeps open at once
+    pub fn max_snapshots(mut self, n: usize) -> Self {
+        self.config.max_snapshots = n;
+        self
+    }
+
+    /// Most memory, in bytes, the leader spends on open snapshots and read
+    /// transactions together. Each one holds its own in-memory copy of the
+    /// whole database, so `max_snapshots` of them can cost that many times
+    /// the database's size; one that would go over this fails with
+    /// `SnapshotMemoryExceeded` before anything is copied. Defaults to 64MiB.
+    pub fn max_snapshot_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_snapshot_bytes = bytes;
+        self
+    }
+
+    /// Number of recent channel messages kept in the message log
+    pub fn message_log_size(mut self, n: usize) -> Self {
+        self.config.message_log_size = n;
+        self
+    }
+
+    /// Longest SQL string, in bytes, accepted before dispatching a query
+    pub fn max_sql_length(mut self, n: usize) -> Self {
+        self.config.max_sql_length = n;
+        self
+    }
+
+    /// Busy timeout applied to the leader's connection once it opens. The
+    /// default of 0 fails immediately when the database is locked; 5000ms is a
+    /// reasonable value for OPFS.
+    pub fn busy_timeout_ms(mut self, ms: u64) -> Self {
+        self.config.busy_timeout_ms = ms;
+        self
+    }
+
+    /// Times the leader runs a statement again when it fails with
+    /// `SQLITE_BUSY` or `SQLITE_LOCKED`, for instance during a checkpoint,
+    /// before returning the error. Defaults to 3; 0 returns it immediately.
+    pub fn busy_retry_count(mut self, n: usize) -> Self {
+        self.config.busy_max_retries = n;
+        self
+    }
+
+    /// How long the leader waits before each `busy_retry_count` retry,
+    /// 50ms by default
+    pub fn busy_retry_delay_ms(mut self, ms: u64) -> Self {
+        self.config.busy_backoff_ms = ms;
+        self
+    }
+
+    /// Reject queries on the leader for `reset_timeout_ms` after
+    /// `failure_threshold` consecutive failures
+    pub fn circuit_breaker(mut self, failure_threshold: u32, reset_timeout_ms: u64) -> Self {
+        self.config.circuit_failure_threshold = failure_threshold;
+        self.config.circuit_reset_timeout_ms = reset_timeout_ms;
+        self
+    }
+
+    /// Resign leadership when the page is hidden and request it again when
+    /// visible, once `setup_visibility_listener` is called. Off by default.
+    pub fn resign_on_hide(mut self, enabled: bool) -> Self {
+        self.config.resign_on_hide = enabled;
+        self
+    }
+
+    /// Most results `execute_query_cached` keeps at once; 0 disables caching
+    pub fn query_cache_size(mut self, n: usize) -> Self {
+        self.config.query_cache_size = n;
+        self
+    }
+
+    /// Send a follower's `execute_query` calls made within one microtask to
+    /// the leader as a single message. The leader still runs and answers each
+    /// query on its own, so the results are the same as without batching. Off
+    /// by default.
+    pub fn batch_dispatch(mut self, enabled: bool) -> Self {
+        self.config.batch_dispatch = enabled;
+        self
+    }
+
+    /// Have `start` hold back queries until `PRAGMA user_version` is checked
+    /// against `version`, failing every query if it differs
+    pub fn expected_schema_version(mut self, version: i32) -> Self {
+        self.config.expected_schema_version = Some(version);
+        self
+    }
+
+    /// Most requests awaiting the leader's response at once. Further requests
+    /// wait in the fallback queue until a response frees a slot.
+    pub fn max_pending_queries(mut self, n: usize) -> Self {
+        self.config.max_pending_queries = n;
+        self
+    }
+
+    /// Most requests waiting for a pending slot; beyond that requests fail
+    /// with `QueueFull`
+    pub fn fallback_queue_size(mut self, n: usize) -> Self {
+        self.config.fallback_queue_size = n;
+        self
+    }
+
+    /// Give `execute_query` calls that write rows `High` priority when their
+    /// context leaves it at `Normal`, so they leave the fallback queue ahead
+    /// of reads. Off by default.
+    pub fn auto_prioritize_writes(mut self, enabled: bool) -> Self {
+        self.config.auto_prioritize_writes = enabled;
+        self
+    }
+
+    /// Turn foreign key enforcement on or off as soon as the leader opens the
+    /// database, as `enable_foreign_keys` does. SQLite's default of off is
+    /// kept otherwise.
+    pub fn enforce_foreign_keys(mut self, enabled: bool) -> Self {
+        self.config.enforce_foreign_keys = Some(enabled);
+        self
+    }
+
+    /// Start in strict mode, as after `enable_strict_mode`. Off by default.
+    pub fn strict_tables(mut self, enabled: bool) -> Self {
+        self.config.strict_tables = enabled;
+        self
+    }
+
+    /// Let `reset_database` delete and recreate the database file. Off by
+    /// default so a stray call cannot wipe a user's data.
+    pub fn allow_reset(mut self, enabled: bool) -> Self {
+        self.config.allow_reset = enabled;
+        self
+    }
+
+    /// Call `request_storage_persistence` on becoming leader, emitting
+    /// `PersistenceNotGranted` if the browser refuses. Off by default.
+    pub fn request_persistence(mut self, enabled: bool) -> Self {
+        self.config.request_persistence = enabled;
+        self
+    }
+
+    /// Have the leader run `ANALYZE` after every `n` successful INSERT,
+    /// UPDATE, DELETE or REPLACE statements it executes on their own, emitting
+    /// `AnalysisComplete` each time, so the query planner's statistics keep up
+    /// with the data. Statements in a batch or run through a prepared
+    /// statement are not counted. 0, the default, turns it off.
+    pub fn auto_analyze_after_n_writes(mut self, n: u64) -> Self {
+        self.config.auto_analyze_after_writes = n;
+        self
+    }
+
+    /// Initialise the database from the SQLite file at `url`, such as a
+    /// pre-populated read-only dataset. Whichever worker becomes leader
+    /// fetches it and replaces the OPFS file before opening it, emitting
+    /// `DatabaseImported`, but only while that file is still empty, so the
+    /// imported data and any changes to it survive later leaders. A failed
+    /// fetch, or a body that is not a SQLite file, is reported to `on_error`
+    /// handlers and leaves the leader without a database.
+    pub fn remote_database_url(mut self, url: String) -> Self {
+        self.config.remote_database_url = Some(url);
+        self
+    }
+
+    /// Observer notified of query and leadership lifecycle events
+    pub fn observer(mut self, observer: Box<dyn ConnectionObserver + 'static>) -> Self {
+        self.observer = Some(Rc::from(observer));
+        self
+    }
+
+    /// Authorizer installed on the connection whenever this worker leads; see
+    /// `WorkerState::set_authorizer`
+    pub fn authorizer(mut self, auth: Box<dyn Fn(AuthAction) -> AuthResult + 'static>) -> Self {
+        self.authorizer = Some(Rc::from(auth));
+        self
+    }
+
+    /// Clock used for query timeouts, replaced in tests to make them deterministic
+    #[cfg(test)]
+    pub fn time_provider(mut self, time: Rc<dyn TimeProvider>) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn build(self) -> Result<WorkerState, WorkerError> {
+        if !WorkerState::is_broadcast_channel_supported() {
+            return Err(WorkerError::BroadcastChannelUnsupported);
+        }
+        if !WorkerState::is_navigator_locks_supported() {
+            return Err(WorkerError::NavigatorLocksUnsupported);
+        }
+
         let worker_id = Uuid::new_v4().to_string();
-        let channel = BroadcastChannel::new("sqlite-queries")?;
+        let channel = BroadcastChannel::new("sqlite-queries")
+            .map_err(|e| WorkerError::ChannelError(format!("{e:?}")))?;
+        let max_sql_length = self.config.max_sql_length;
+        let strict_tables = self.config.strict_tables;
+        let auto_analyze = AutoAnalyze::new(self.config.auto_analyze_after_writes);
+        let query_cache = QueryCache::new(self.config.query_cache_size);
+        let pending_queries = Rc::new(RefCell::new(IndexMap::new()));
+        let circuit_breaker = CircuitBreaker::new(
+            self.config.circuit_failure_threshold,
+            self.config.circuit_reset_timeout_ms,
+        );
+        let known_workers = Rc::new(RefCell::new(HashMap::new()));
+        let dispatcher = MessageDispatcher::new();
+        register_gossip_handler(&dispatcher, worker_id.clone(), Rc::clone(&known_workers));
 
         Ok(WorkerState {
             worker_id,
             is_leader: Rc::new(RefCell::new(false)),
             db: Rc::new(RefCell::new(None)),
             channel,
-            pending_queries: Rc::new(RefCell::new(HashMap::new())),
+            micro_batch: Rc::new(MicroBatch::new()),
+            pending_queries,
+            active_transaction: Rc::new(RefCell::new(None)),
+            savepoints: Rc::new(RefCell::new(Vec::new())),
+            snapshots: Rc::new(RefCell::new(HashMap::new())),
+            config: self.config,
+            observer: self.observer,
+            time: self.time.unwrap_or_else(|| Rc::new(RealTimeProvider)),
+            send_sequence: Rc::new(Cell::new(0)),
+            messages_failed_to_send: Rc::new(Cell::new(0)),
+            known_leader_id: Rc::new(RefCell::new(None)),
+            message_log: Rc::new(RefCell::new(VecDeque::new())),
+            events: EventEmitter::new(),
+            trace_callback: Rc::new(RefCell::new(None)),
+            max_sql_length: Cell::new(max_sql_length),
+            circuit_breaker: Rc::new(circuit_breaker),
+            leadership_acquisition_ms: Rc::new(Cell::new(None)),
+            known_workers,
+            gossip_interval: RefCell::new(None),
+            dispatch_paused: Rc::new(Cell::new(false)),
+            paused_requests: Rc::new(RefCell::new(VecDeque::new())),
+            error_handlers: Rc::new(RefCell::new(Vec::new())),
+            watchers: RefCell::new(HashMap::new()),
+            custom_handlers: Rc::new(RefCell::new(HashMap::new())),
+            leadership_requested: Rc::new(Cell::new(false)),
+            leadership_release: Rc::new(RefCell::new(None)),
+            query_cache: Rc::new(RefCell::new(query_cache)),
+            shared_state: Rc::new(RefCell::new(None)),
+            ready_callbacks: Rc::new(RefCell::new(Vec::new())),
+            channel_listener: RefCell::new(None),
+            visibility_listener: RefCell::new(None),
+            created_at_ms: js_sys::Date::now(),
+            last_leader_state: Rc::new(RefCell::new(None)),
+            state_requests: Rc::new(RefCell::new(Vec::new())),
+            schema_error: RefCell::new(None),
+            fallback_queue: Rc::new(RefCell::new(VecDeque::new())),
+            promoted_queries: Rc::new(Cell::new(0)),
+            authorizer: Rc::new(RefCell::new(self.authorizer)),
+            dispatcher: Rc::new(dispatcher),
+            last_query_stats: Rc::new(RefCell::new(None)),
+            dom_events: Rc::new(Cell::new(false)),
+            dom_events_installed: Cell::new(false),
+            cross_db_schemas: RefCell::new(Vec::new()),
+            strict_tables: Cell::new(strict_tables),
+            progress_channel: Rc::new(RefCell::new(HashMap::new())),
+            storage_monitor: RefCell::new(None),
+            auto_analyze,
         })
     }
+}
 
-    pub fn setup_channel_listener(&self) {
-        let is_leader = Rc::clone(&self.is_leader);
-        let db = Rc::clone(&self.db);
-        let pending_queries = Rc::clone(&self.pending_queries);
-        let channel = self.channel.clone();
+// Worker state
+pub struct WorkerState {
+    pub worker_id: String,
+    pub is_leader: Rc<RefCell<bool>>,
+    pub db: Rc<RefCell<Option<Rc<SQLiteDatabase>>>>,
+    pub channel: BroadcastChannel,
+    pub pending_queries: Rc<RefCell<IndexMap<String, PendingQuery>>>,
+    pub micro_batch: Rc<MicroBatch>,
+    pub active_transaction: Rc<RefCell<Option<String>>>,
+    // Savepoints this worker has open through `execute_with_savepoint`,
+    // outermost first
+    pub savepoints: Rc<RefCell<Vec<String>>>,
+    pub snapshots: Rc<RefCell<HashMap<String, Rc<SQLiteDatabase>>>>,
+    pub config: WorkerConfig,
+    pub observer: Option<Rc<dyn ConnectionObserver>>,
+    // Clock used for query timeouts
+    pub time: Rc<dyn TimeProvider>,
+    pub send_sequence: Rc<Cell<u64>>,
+    // Messages given up on after the channel refused every attempt
+    pub messages_failed_to_send: Rc<Cell<u64>>,
+    pub known_leader_id: Rc<RefCell<Option<String>>>,
+    pub message_log: Rc<RefCell<VecDeque<LoggedMessage>>>,
+    pub events: EventEmitter,
+    pub trace_callback: Rc<RefCell<Option<Rc<dyn Fn(&str, f64)>>>>,
+    pub max_sql_length: Cell<usize>,
+    // Guards query execution while this worker leads
+    pub circuit_breaker: Rc<CircuitBreaker>,
+    // Time between requesting the leadership lock and being granted it
+    pub leadership_acquisition_ms: Rc<Cell<Option<f64>>>,
+    // Other workers learned about through gossip, keyed by worker id
+    pub known_workers: Rc<RefCell<HashMap<String, WorkerGossip>>>,
+    pub gossip_interval: RefCell<Option<Interval>>,
+    pub dispatch_paused: Rc<Cell<bool>>,
+    // Requests the leader received while paused, with their trace ids
+    pub paused_requests: Rc<RefCell<VecDeque<(ChannelMessage, Option<String>)>>>,
+    pub error_handlers: ErrorHandlers,
+    // Polling timers started by `watch_query`, keyed by watcher id
+    pub watchers: RefCell<HashMap<String, Interval>>,
+    pub custom_handlers: CustomHandlers,
+    // Set from requesting the leadership lock until resigning it
+    pub leadership_requested: Rc<Cell<bool>>,
+    // Releases the leadership lock while this worker holds it
+    pub leadership_release: Rc<RefCell<Option<Function>>>,
+    pub query_cache: Rc<RefCell<QueryCache>>,
+    // Mirrors `is_leader` for other threads once `shared_state_buffer` is called
+    pub shared_state: SharedStateBuffer,
+    pub ready_callbacks: ReadyCallbacks,
+    // Owned here so they are released with the state rather than leaked
+    pub channel_listener: RefCell<Option<Closure<dyn FnMut(web_sys::MessageEvent)>>>,
+    pub visibility_listener: RefCell<Option<EventListener>>,
+    pub created_at_ms: f64,
+    // Cached from the leader's most recent `StateResponse` to this worker
+    pub last_leader_state: Rc<RefCell<Option<LeaderState>>>,
+    // Resolve functions of `request_leader_state` calls awaiting a response
+    pub state_requests: Rc<RefCell<Vec<Function>>>,
+    // Set by `start` when the schema version check fails; rejects all queries
+    pub schema_error: RefCell<Option<WorkerError>>,
+    // Requests waiting for a slot in `pending_queries`, oldest first
+    pub fallback_queue: FallbackQueue,
+    // Requests woken from the fallback queue that have yet to claim their slot
+    pub promoted_queries: Rc<Cell<usize>>,
+    pub authorizer: SharedAuthorizer,
+    // Handlers other modules register for received messages
+    pub dispatcher: Rc<MessageDispatcher>,
+    // Latest stats attached to a `QueryResponse` for this worker
+    pub last_query_stats: Rc<RefCell<Option<QueryStats>>>,
+    // Whether `enable_dom_events` is on, and whether it has subscribed yet
+    pub dom_events: Rc<Cell<bool>>,
+    pub dom_events_installed: Cell<bool>,
+    // Schemas attached by `begin_cross_db_transaction`, while one is open
+    pub cross_db_schemas: RefCell<Vec<String>>,
+    // Whether new tables are declared `STRICT`; see `enable_strict_mode`
+    pub strict_tables: Cell<bool>,
+    // Progress callbacks of queries sent to the leader, by query id
+    pub progress_channel: Rc<RefCell<HashMap<String, Function>>>,
+    // Polls the storage estimate; see `start_storage_monitor`
+    pub storage_monitor: RefCell<Option<Interval>>,
+    // Counts the leader's writes towards the next automatic `ANALYZE`
+    pub(crate) auto_analyze: Option<Rc<AutoAnalyze>>,
+}
 
-        let onmessage = Closure::wrap(Box::new(move |event: web_sys::MessageEvent| {
-            let data = event.data();
+impl Drop for WorkerState {
+    fn drop(&mut self) {
+        // Detach before the closure is freed so the channel never calls into it
+        if self.channel_listener.get_mut().take().is_some() {
+            self.channel.set_onmessage(None);
+        }
+    }
+}
 
-            if let Ok(msg) = serde_wasm_bindgen::from_value::<ChannelMessage>(data) {
-                match msg {
-                    ChannelMessage::QueryRequest { query_id, sql } => {
-                        if *is_leader.borrow() {
-                            let db = Rc::clone(&db);
-                            let channel = channel.clone();
-
-                            spawn_local(async move {
-                                let database = db.borrow().clone();
-                                let result = if let Some(database) = database {
-                                    database.exec(&sql).await
-                                } else {
-                                    Err("Database not initialized".to_string())
-                                };
-
-                                let response = match result {
-                                    Ok(res) => ChannelMessage::QueryResponse {
-                                        query_id,
-                                        result: Some(res),
-                                        error: None,
-                                    },
-                                    Err(err) => ChannelMessage::QueryResponse {
-                                        query_id,
-                                        result: None,
-                                        error: Some(err),
-                                    },
-                                };
-
-                                let msg_js = serde_wasm_bindgen::to_value(&response).unwrap();
-                                let _ = channel.post_message(&msg_js);
-                            });
-                        }
-                    }
-                    ChannelMessage::QueryResponse {
-                        query_id,
-                        result,
-                        error,
-                    } => {
-                        if let Some(pending) = pending_queries.borrow_mut().remove(&query_id) {
-                            if let Some(err) = error {
-                                let _ = pending
-                                    .reject
-                                    .call1(&JsValue::NULL, &JsValue::from_str(&err));
-                            } else if let Some(res) = result {
-                                let _ = pending
-                                    .resolve
-                                    .call1(&JsValue::NULL, &JsValue::from_str(&res));
-                            }
-                        }
-                    }
-                    ChannelMessage::NewLeader { leader_id: _ } => {}
+impl std::fmt::Debug for WorkerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerState")
+            .field("worker_id", &self.worker_id)
+            .field("is_leader", &*self.is_leader.borrow())
+            .field("db_initialized", &self.db.borrow().is_some())
+            .field("pending_query_count", &self.pending_queries.borrow().len())
+            .field("fallback_queue_len", &self.fallback_queue.borrow().len())
+            .field("channel_name", &self.channel.name())
+            .field("known_leader_id", &*self.known_leader_id.borrow())
+            .field("known_worker_count", &self.known_workers.borrow().len())
+            .field("leadership_requested", &self.leadership_requested.get())
+            .field("dispatch_paused", &self.dispatch_paused.get())
+            .field("error_handler_count", &self.error_handlers.borrow().len())
+            .field(
+                "cached_query_count",
+                &self.query_cache.borrow().entries.len(),
+            )
+            .field("watcher_count", &self.watchers.borrow().len())
+            .field(
+                "custom_handler_types",
+                &self.custom_handlers.borrow().keys().collect::<Vec<_>>(),
+            )
+            .field("active_transaction", &*self.active_transaction.borrow())
+            .field("savepoints", &*self.savepoints.borrow())
+            .field("snapshot_count", &self.snapshots.borrow().len())
+            .field("config", &self.config)
+            .field("max_sql_length", &self.max_sql_length.get())
+            .field("circuit_state", &self.circuit_breaker.state())
+            .field(
+                "leadership_acquisition_ms",
+                &self.leadership_acquisition_ms.get(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+// Ring buffer of the most recent channel traffic, shared by the sender and listener
+#[derive(Clone)]
+struct MessageLog {
+    entries: Rc<RefCell<VecDeque<LoggedMessage>>>,
+    max_entries: usize,
+}
+
+impl MessageLog {
+    fn record(&self, direction: MessageDirection, message: &ChannelMessage) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.borrow_mut();
+        while entries.len() >= self.max_entries {
+            entries.pop_front();
+        }
+        entries.push_back(LoggedMessage {
+            direction,
+            message: message.clone(),
+            timestamp_ms: js_sys::Date::now(),
+        });
+    }
+}
+
+// Broadcasts messages wrapped in an envelope carrying this worker's send sequence
+#[derive(Clone)]
+pub(crate) struct ChannelSender {
+    channel: BroadcastChannel,
+    worker_id: String,
+    send_sequence: Rc<Cell<u64>>,
+    log: MessageLog,
+    error_handlers: ErrorHandlers,
+    failed_sends: Rc<Cell<u64>>,
+}
+
+impl ChannelSender {
+    // Send where there is no caller to return a failure to. If the channel
+    // refuses the message it is retried in the background and reported to the
+    // error handlers if that fails too.
+    pub(crate) fn post(&self, message: ChannelMessage, trace_id: Option<String>) {
+        let (msg_js, sequence) = match self.envelope(message, trace_id) {
+            Ok(envelope) => envelope,
+            Err(err) => return self.give_up(err),
+        };
+        if let Err(err) = self.post_envelope(&msg_js) {
+            let sender = self.clone();
+            spawn_local(async move {
+                if sender.retry_post(&msg_js, err).await.is_err() {
+                    sender.release_sequence(sequence);
                 }
+            });
+        }
+    }
+
+    // A single attempt, leaving the failure to the caller
+    pub(crate) fn send(&self, message: ChannelMessage) -> Result<(), WorkerError> {
+        let (msg_js, sequence) = self.envelope(message, None)?;
+        self.post_envelope(&msg_js).inspect_err(|_| {
+            self.failed_sends.set(self.failed_sends.get() + 1);
+            self.release_sequence(sequence);
+        })
+    }
+
+    pub(crate) async fn send_with_retry(&self, message: ChannelMessage) -> Result<(), WorkerError> {
+        let (msg_js, sequence) = self
+            .envelope(message, None)
+            .inspect_err(|err| self.give_up(err.clone()))?;
+        self.post_with_retry(&msg_js)
+            .await
+            .inspect_err(|_| self.release_sequence(sequence))
+    }
+
+    pub(crate) async fn post_with_retry(&self, msg_js: &JsValue) -> Result<(), WorkerError> {
+        match self.post_envelope(msg_js) {
+            Ok(()) => Ok(()),
+            Err(err) => self.retry_post(msg_js, err).await,
+        }
+    }
+
+    // The envelope keeps its sequence number across attempts, so receivers
+    // still deliver it in order if a retry succeeds
+    async fn retry_post(
+        &self,
+        msg_js: &JsValue,
+        mut error: WorkerError,
+    ) -> Result<(), WorkerError> {
+        for _ in 0..SEND_RETRIES {
+            sleep(SEND_RETRY_DELAY_MS).await;
+            match self.post_envelope(msg_js) {
+                Ok(()) => return Ok(()),
+                Err(err) => error = err,
             }
-        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+        }
+        self.give_up(error.clone());
+        Err(error)
+    }
 
+    fn give_up(&self, error: WorkerError) {
+        self.failed_sends.set(self.failed_sends.get() + 1);
+        report_error(&self.error_handlers, error);
+    }
+
+    fn post_envelope(&self, msg_js: &JsValue) -> Result<(), WorkerError> {
         self.channel
-            .set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-        onmessage.forget();
+            .post_message(msg_js)
+            .map_err(|e| WorkerError::ChannelError(format!("{e:?}")))
     }
 
-    pub async fn attempt_leadership(&self) {
-        let worker_id = self.worker_id.clone();
-        let is_leader = Rc::clone(&self.is_leader);
-        let db = Rc::clone(&self.db);
-        let channel = self.channel.clone();
+    // Wrap `message` for posting with the next send sequence, which is only
+    // taken once the envelope has been built
+    fn envelope(
+        &self,
+        message: ChannelMessage,
+        trace_id: Option<String>,
+    ) -> Result<(JsValue, u64), WorkerError> {
+        let sequence = self.send_sequence.get();
+        let envelope = ChannelEnvelope {
+            sender_id: self.worker_id.clone(),
+            sender_sequence: sequence,
+            trace_id,
+            message,
+        };
+        let msg_js = serde_wasm_bindgen::to_value(&envelope)
+            .map_err(|e| WorkerError::ChannelError(e.to_string()))?;
+        self.send_sequence.set(sequence + 1);
+        self.log.record(MessageDirection::Sent, &envelope.message);
+        Ok((msg_js, sequence))
+    }
 
-        // Get navigator.locks from WorkerGlobalScope
-        let global = js_sys::global();
-        let navigator = Reflect::get(&global, &JsValue::from_str("navigator")).unwrap();
-        let locks = Reflect::get(&navigator, &JsValue::from_str("locks")).unwrap();
+    // Hand back the sequence of an envelope that was never delivered, so
+    // receivers do not wait on the gap. Once a later message has taken the
+    // next one it has to stay a gap.
+    fn release_sequence(&self, sequence: u64) {
+        if self.send_sequence.get() == sequence + 1 {
+            self.send_sequence.set(sequence);
+        }
+    }
+}
 
-        let options = Object::new();
-        Reflect::set(
-            &options,
-            &JsValue::from_str("mode"),
-            &JsValue::from_str("exclusive"),
+// Make `state` the leader with the OPFS database open, skipping the election,
+// for tests that run queries on the leader. The tests run in a dedicated
+// worker, which has OPFS, so failing to open the database fails the test
+// rather than skipping it.
+#[cfg(test)]
+pub(crate) async fn lead_with_db(state: WorkerState) -> Option<WorkerState> {
+    let database = SQLiteDatabase::initialize_opfs()
+        .await
+        .expect("Failed to open the OPFS database");
+    *state.is_leader.borrow_mut() = true;
+    *state.db.borrow_mut() = Some(Rc::new(database));
+    Some(state)
+}
+
+// A new worker made the leader by `lead_with_db`
+#[cfg(test)]
+pub(crate) async fn leader_with_db() -> Option<WorkerState> {
+    lead_with_db(WorkerState::new().ok()?).await
+}
+
+// performance.now() from the current global scope, falling back to wall-clock time
+fn performance_now() -> f64 {
+    let global = js_sys::global();
+    Reflect::get(&global, &JsValue::from_str("performance"))
+        .ok()
+        .and_then(|performance| {
+            let now = Reflect::get(&performance, &JsValue::from_str("now")).ok()?;
+            now.dyn_ref::<Function>()?
+                .call0(&performance)
+                .ok()?
+                .as_f64()
+        })
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+fn console_warn(message: &str) {
+    console_call("warn", message);
+}
+
+pub(crate) fn console_debug(message: &str) {
+    console_call("debug", message);
+}
+
+fn console_call(method: &str, message: &str) {
+    let global = js_sys::global();
+    let Ok(console) = Reflect::get(&global, &JsValue::from_str("console")) else {
+        return;
+    };
+    if let Ok(log) = Reflect::get(&console, &JsValue::from_str(method)) {
+        if let Some(log) = log.dyn_ref::<Function>() {
+            let _ = log.call1(&console, &JsValue::from_str(message));
+        }
+    }
+}
+
+// The `EmptyResponse` a leader sends instead of `result` when it has no rows,
+// changed nothing and left no insert rowid, so its columns are all a
+// follower needs to rebuild it
+fn empty_response(
+    query_id: &str,
+    result: &Result<QueryResult, WorkerError>,
+) -> Option<ChannelMessage> {
+    match result {
+        Ok(result)
+            if result.rows.is_empty()
+                && result.rows_affected == 0
+                && result.last_insert_rowid == 0 =>
+        {
+            Some(ChannelMessage::EmptyResponse {
+                query_id: query_id.to_string(),
+                columns: result.columns.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// What a follower resolves an `EmptyResponse` with: the empty JSON array
+// `execute_query` returns, or an empty `QueryResult` with `columns` for other
+// requests
+fn empty_response_json(request: Option<&ChannelMessage>, columns: Vec<String>) -> String {
+    match request {
+        Some(ChannelMessage::QueryRequest { .. }) => "[]".to_string(),
+        _ => serde_json::to_string(&QueryResult {
+            columns,
+            ..QueryResult::default()
+        })
+        .unwrap_or_default(),
+    }
+}
+
+// Handle a request on the leader and broadcast the response
+fn respond_to_request(
+    leader: LeaderContext,
+    sender: ChannelSender,
+    request: ChannelMessage,
+    trace_id: Option<String>,
+) {
+    spawn_local(async move {
+        if let Some(response) = leader.handle_request(request).await {
+            sender.post(response, trace_id);
+        }
+    });
+}
+
+/// A `setInterval` timer that owns its callback and is cleared when dropped
+pub struct Interval {
+    handle: i32,
+    _tick: Closure<dyn FnMut()>,
+}
+
+impl Interval {
+    pub(crate) fn start(tick: Closure<dyn FnMut()>, interval_ms: u64) -> Result<Self, WorkerError> {
+        let handle = set_interval(&tick, interval_ms)?;
+        Ok(Interval {
+            handle,
+            _tick: tick,
+        })
+    }
+
+    pub fn handle(&self) -> i32 {
+        self.handle
+    }
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        clear_interval(self.handle);
+    }
+}
+
+/// An event listener that owns its callback and is removed when dropped
+pub struct EventListener {
+    target: JsValue,
+    event_type: &'static str,
+    callback: Closure<dyn FnMut()>,
+}
+
+impl EventListener {
+    pub(crate) fn add(
+        target: &JsValue,
+        event_type: &'static str,
+        callback: Closure<dyn FnMut()>,
+    ) -> Result<Self, WorkerError> {
+        let add_event_listener = Reflect::get(target, &JsValue::from_str("addEventListener"))
+            .ok()
+            .and_then(|add| add.dyn_into::<Function>().ok())
+            .ok_or_else(|| {
+                WorkerError::QueryFailed("addEventListener is unavailable".to_string())
+            })?;
+        add_event_listener
+            .call2(
+                target,
+                &JsValue::from_str(event_type),
+                callback.as_ref().unchecked_ref(),
+            )
+            .map_err(|e| WorkerError::QueryFailed(format!("{e:?}")))?;
+        Ok(EventListener {
+            target: target.clone(),
+            event_type,
+            callback,
+        })
+    }
+}
+
+impl Drop for EventListener {
+    fn drop(&mut self) {
+        let remove = Reflect::get(&self.target, &JsValue::from_str("removeEventListener"))
+            .ok()
+            .and_then(|remove| remove.dyn_into::<Function>().ok());
+        if let Some(remove) = remove {
+            let _ = remove.call2(
+                &self.target,
+                &JsValue::from_str(self.event_type),
+                self.callback.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
+
+// Call `callback` every `interval_ms` and return the timer's handle
+fn set_interval(callback: &Closure<dyn FnMut()>, interval_ms: u64) -> Result<i32, WorkerError> {
+    let global = js_sys::global();
+    let handle = Reflect::get(&global, &JsValue::from_str("setInterval"))
+        .ok()
+        .and_then(|set_interval| set_interval.dyn_into::<Function>().ok())
+        .ok_or_else(|| WorkerError::QueryFailed("setInterval is unavailable".to_string()))?
+        .call2(
+            &JsValue::NULL,
+            callback.as_ref().unchecked_ref(),
+            &JsValue::from_f64(interval_ms as f64),
         )
-        .unwrap();
+        .map_err(|e| WorkerError::QueryFailed(format!("{e:?}")))?;
+    handle
+        .as_f64()
+        .map(|handle| handle as i32)
+        .ok_or_else(|| WorkerError::QueryFailed("setInterval returned no handle".to_string()))
+}
 
-        let handler = Closure::once(move |_lock: JsValue| -> Promise {
-            *is_leader.borrow_mut() = true;
+fn clear_interval(handle: i32) {
+    let global = js_sys::global();
+    if let Ok(clear_interval) = Reflect::get(&global, &JsValue::from_str("clearInterval")) {
+        if let Some(clear_interval) = clear_interval.dyn_ref::<Function>() {
+            let _ = clear_interval.call1(&JsValue::NULL, &JsValue::from(handle));
+        }
+    }
+}
 
-            let db = Rc::clone(&db);
-            let channel = channel.clone();
-            let worker_id = worker_id.clone();
+// What a pending query is rejected with: the message, or for an error with a
+// SQLite result code `{ message, errorCode }`
+fn rejection_value(message: &str, error_code: Option<i32>) -> JsValue {
+    let Some(code) = error_code else {
+        return JsValue::from_str(message);
+    };
+    let value = Object::new();
+    let _ = Reflect::set(
+        &value,
+        &JsValue::from_str("message"),
+        &JsValue::from_str(message),
+    );
+    let _ = Reflect::set(
+        &value,
+        &JsValue::from_str("errorCode"),
+        &JsValue::from(code),
+    );
+    value.into()
+}
 
-            spawn_local(async move {
-                match SQLiteDatabase::initialize_opfs().await {
-                    Ok(database) => {
-                        *db.borrow_mut() = Some(Rc::new(database));
+// Inverse of `rejection_value`; other rejections, such as timeouts, become
+// `QueryFailed`
+fn rejection_error(value: JsValue) -> WorkerError {
+    let field = |name: &str| Reflect::get(&value, &JsValue::from_str(name)).ok();
+    let message = field("message").and_then(|message| message.as_string());
+    let code = field("errorCode").and_then(|code| code.as_f64());
+    match (value.is_object(), message, code) {
+        (true, Some(message), Some(code)) => WorkerError::from_sqlite_code(code as i32, message),
+        _ => WorkerError::QueryFailed(format!("{value:?}")),
+    }
+}
+
+// Rebuild a function sent as its `.toString()` source
+fn function_from_source(source: &str) -> Result<Function, WorkerError> {
+    let global = js_sys::global();
+    let invalid = |e: JsValue| WorkerError::QueryFailed(format!("Invalid function source: {e:?}"));
+    let constructor: Function = Reflect::get(&global, &JsValue::from_str("Function"))
+        .map_err(invalid)?
+        .unchecked_into();
+    let factory: Function = Reflect::construct(
+        &constructor,
+        &js_sys::Array::of1(&JsValue::from_str(&format!("return ({source});"))),
+    )
+    .map_err(invalid)?
+    .unchecked_into();
+    factory
+        .call0(&JsValue::NULL)
+        .map_err(invalid)?
+        .dyn_into::<Function>()
+        .map_err(|_| WorkerError::QueryFailed("Source is not a function".to_string()))
+}
+
+// Call site of the outermost `#[track_caller]` function, captured in debug builds only
+#[track_caller]
+fn caller_location() -> Option<String> {
+    #[cfg(debug_assertions)]
+    {
+        Some(std::panic::Location::caller().to_string())
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        None
+    }
+}
+
+// Where a request on the leader came from, for its logs
+#[derive(Default)]
+struct RequestOrigin<'a> {
+    worker_id: Option<&'a str>,
+    source_location: Option<&'a str>,
+    metadata: Option<&'a HashMap<String, String>>,
+}
+
+fn warn_if_slow(sql: &str, duration_ms: f64, origin: RequestOrigin) {
+    if duration_ms <= SLOW_QUERY_MS {
+        return;
+    }
+    let worker_id = origin.worker_id.unwrap_or("unknown worker");
+    let location = origin.source_location.unwrap_or("unknown location");
+    let mut message = format!(
+        "Slow query from {worker_id} took {duration_ms:.0}ms (sent from {location}): {sql}"
+    );
+    if let Some(metadata) = origin.metadata.filter(|metadata| !metadata.is_empty()) {
+        message.push_str(&format!(" {metadata:?}"));
+    }
+    console_warn(&message);
+}
+
+// Deliver messages held back behind a gap once it times out, for as long as
+// any buffer is still waiting on one
+async fn release_reordered(
+    buffers: Rc<RefCell<HashMap<String, MessageReorderBuffer<ChannelEnvelope>>>>,
+    running: Rc<Cell<bool>>,
+    deliver: Rc<dyn Fn(ChannelEnvelope)>,
+) {
+    loop {
+        sleep(REORDER_GAP_TIMEOUT_MS).await;
+        let now_ms = js_sys::Date::now();
+        let (ready, waiting) = {
+            let mut buffers = buffers.borrow_mut();
+            let ready: Vec<ChannelEnvelope> = buffers
+                .values_mut()
+                .flat_map(|buffer| buffer.release_expired(now_ms))
+                .collect();
+            let waiting = buffers.values().any(|buffer| !buffer.pending.is_empty());
+            (ready, waiting)
+        };
+        for envelope in ready {
+            deliver(envelope);
+        }
+        if !waiting {
+            running.set(false);
+            return;
+        }
+    }
+}
+
+// Point a worker at `new_leader` and re-send every pending request, returning
+// how many were re-issued
+fn redirect_pending(
+    known_leader_id: &RefCell<Option<String>>,
+    pending_queries: &RefCell<IndexMap<String, PendingQuery>>,
+    sender: &ChannelSender,
+    new_leader: &str,
+) -> usize {
+    *known_leader_id.borrow_mut() = Some(new_leader.to_string());
+
+    let requests: Vec<ChannelMessage> = pending_queries
+        .borrow()
+        .values()
+        .filter_map(|pending| pending.request.clone())
+        .collect();
+    requests
+        .into_iter()
+        .filter(|request| sender.send(request.clone()).is_ok())
+        .count()
+}
+
+// Bytes `database` takes as a serialized copy, without making one
+async fn database_size(database: &SQLiteDatabase) -> Result<usize, WorkerError> {
+    let (size,): (i64,) = database
+        .query_with_params(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            &[],
+        )
+        .await
+        .map_err(WorkerError::from)?
+        .get_single()?;
+    Ok(usize::try_from(size).unwrap_or(usize::MAX))
+}
+
+// Roll back what `execute_batch` ran, in its own transaction or savepoint
+async fn undo_batch(database: &SQLiteDatabase, owns_transaction: bool) {
+    if owns_transaction {
+        let _ = database.exec("ROLLBACK").await;
+    } else {
+        let _ = database.exec("ROLLBACK TO execute_batch").await;
+        let _ = database.exec("RELEASE execute_batch").await;
+    }
+}
+
+// Leader-side handles shared with the channel listener
+#[derive(Clone)]
+struct LeaderContext {
+    db: Rc<RefCell<Option<Rc<SQLiteDatabase>>>>,
+    snapshots: Rc<RefCell<HashMap<String, Rc<SQLiteDatabase>>>>,
+    max_snapshots: usize,
+    max_snapshot_bytes: usize,
+    events: EventEmitter,
+    circuit_breaker: Rc<CircuitBreaker>,
+    time: Rc<dyn TimeProvider>,
+    sender: ChannelSender,
+    auto_analyze: Option<Rc<AutoAnalyze>>,
+    busy_retry: BusyRetry,
+}
+
+impl LeaderContext {
+    fn database(&self) -> Result<Rc<SQLiteDatabase>, WorkerError> {
+        self.db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)
+    }
+
+    async fn exec(&self, sql: &str) -> Result<String, WorkerError> {
+        self.circuit_breaker.allow(self.time.now_ms())?;
+        let result = match self.database() {
+            Ok(database) => {
+                let database = &database;
+                self.busy_retry
+                    .run(move || async move { database.exec(sql).await.map_err(WorkerError::from) })
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        self.record_outcome(&result);
+        self.record_write(sql, result.is_ok());
+        result
+    }
+
+    // Count a successful write towards `auto_analyze_after_n_writes`, running
+    // `ANALYZE` in the background once enough have accumulated
+    fn record_write(&self, sql: &str, success: bool) {
+        let Some(auto_analyze) = &self.auto_analyze else {
+            return;
+        };
+        if !success || !SqlClassifier::is_write_statement(sql) || !auto_analyze.record_write() {
+            return;
+        }
+        let Ok(database) = self.database() else {
+            return;
+        };
+        let events = self.events.clone();
+        let time = Rc::clone(&self.time);
+        spawn_local(async move {
+            if let Ok(result) = analyze(&database, &*time).await {
+                events.emit(&QueryEvent::AnalysisComplete(result));
+            }
+        });
+    }
+
+    // Call `report` with the VM instructions run so far about every
+    // `interval` of them, until `stop_progress`
+    fn report_progress(&self, interval: u32, report: impl Fn(u64) + 'static) {
+        let Ok(database) = self.database() else {
+            return;
+        };
+        let interval = interval.max(1);
+        let calls = Cell::new(0u64);
+        database.set_progress_handler(
+            interval,
+            Some(Box::new(move || {
+                calls.set(calls.get() + 1);
+                report(calls.get() * u64::from(interval));
+                false
+            })),
+        );
+    }
+
+    fn stop_progress(&self) {
+        if let Ok(database) = self.database() {
+            database.set_progress_handler(0, None);
+        }
+    }
+
+    // Checkpoint the WAL as `read_concern` asks before a query reads
+    async fn checkpoint_for(
+        &self,
+        read_concern: Option<ReadConcern>,
+    ) -> Result<Option<WalCheckpoint>, WorkerError> {
+        match read_concern {
+            Some(read_concern) => apply_read_concern(&*self.database()?, read_concern).await,
+            None => Ok(None),
+        }
+    }
+
+    fn stats_with_checkpoint(&self, checkpoint: Option<WalCheckpoint>) -> Option<QueryStats> {
+        self.database().ok().map(|database| QueryStats {
+            wal_checkpoint: checkpoint,
+            ..database.query_stats()
+        })
+    }
+
+    // A query that fails on its own, say with a syntax error, still shows the
+    // database is answering, so only infrastructure failures count against it
+    fn record_outcome<T>(&self, result: &Result<T, WorkerError>) {
+        match result {
+            Err(err) if is_infrastructure_failure(err) => {
+                self.circuit_breaker.record_failure(self.time.now_ms())
+            }
+            _ => self.circuit_breaker.record_success(),
+        }
+    }
+
+    async fn set_pragma(&self, name: &str, value: &SqlParam) -> Result<(), WorkerError> {
+        if !SETTABLE_PRAGMAS
+            .iter()
+            .any(|pragma| pragma.eq_ignore_ascii_case(name))
+        {
+            return Err(WorkerError::PragmaNotAllowed(name.to_string()));
+        }
+        self.database()?
+            .exec(&format!("PRAGMA {name} = {}", sql_literal(value)))
+            .await
+            .map(|_| ())
+            .map_err(WorkerError::from)
+    }
+
+    fn register_function(&self, name: &str, arity: i32, func: Function) -> Result<(), WorkerError> {
+        validate_identifier(name)?;
+        self.database()?
+            .register_scalar_function(name, arity, func)
+            .map_err(WorkerError::sqlite)
+    }
+
+    async fn begin_snapshot(&self, snapshot_id: String) -> Result<String, WorkerError> {
+        let database = self.database()?;
+        if self.snapshots.borrow().len() >= self.max_snapshots {
+            return Err(WorkerError::SnapshotLimitReached(self.max_snapshots));
+        }
+        let size = database_size(&database).await?;
+        let open: Vec<_> = self.snapshots.borrow().values().cloned().collect();
+        let mut held = 0;
+        for connection in open {
+            held += database_size(&connection).await?;
+        }
+        if held + size > self.max_snapshot_bytes {
+            return Err(WorkerError::SnapshotMemoryExceeded {
+                size,
+                held,
+                limit: self.max_snapshot_bytes,
+            });
+        }
+
+        // The opfs-sahpool VFS has no WAL and its locks do nothing, so a
+        // second connection would see the leader's later writes. Each snapshot
+        // is instead an in-memory copy of the database taken now.
+        let bytes = database.serialize().map_err(WorkerError::from)?;
+        let connection = SQLiteDatabase::from_bytes(&bytes).map_err(WorkerError::from)?;
+        connection
+            .exec("PRAGMA query_only = 1")
+            .await
+            .map_err(WorkerError::from)?;
+
+        self.snapshots
+            .borrow_mut()
+            .insert(snapshot_id.clone(), Rc::new(connection));
+        Ok(snapshot_id)
+    }
+
+    async fn query_snapshot(&self, snapshot_id: &str, sql: &str) -> Result<String, WorkerError> {
+        let connection = self
+            .snapshots
+            .borrow()
+            .get(snapshot_id)
+            .cloned()
+            .ok_or_else(|| WorkerError::SnapshotNotFound(snapshot_id.to_string()))?;
+        connection.exec(sql).await.map_err(WorkerError::from)
+    }
+
+    async fn end_snapshot(&self, snapshot_id: &str) -> Result<(), WorkerError> {
+        self.close_snapshot(snapshot_id)
+    }
+
+    // Run a parameterized query in a read transaction opened by
+    // `begin_snapshot`
+    async fn query_read_transaction(
+        &self,
+        tx_id: &str,
+        sql: &str,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, WorkerError> {
+        let connection = self
+            .snapshots
+            .borrow()
+            .get(tx_id)
+            .cloned()
+            .ok_or_else(|| WorkerError::SnapshotNotFound(tx_id.to_string()))?;
+        connection
+            .query_with_params(sql, params)
+            .await
+            .map_err(WorkerError::from)
+    }
+
+    async fn end_read_transaction(&self, tx_id: &str) -> Result<(), WorkerError> {
+        self.close_snapshot(tx_id)
+    }
+
+    // Drop a snapshot's copy of the database, freeing its memory
+    fn close_snapshot(&self, snapshot_id: &str) -> Result<(), WorkerError> {
+        self.snapshots
+            .borrow_mut()
+            .remove(snapshot_id)
+            .map(|_| ())
+            .ok_or_else(|| WorkerError::SnapshotNotFound(snapshot_id.to_string()))
+    }
+
+    async fn execute_parameterized(
+        &self,
+        sql: &str,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, WorkerError> {
+        self.circuit_breaker.allow(self.time.now_ms())?;
+        let result = match self.database() {
+            Ok(database) => {
+                let database = &database;
+                self.busy_retry
+                    .run(move || async move {
+                        database
+                            .query_with_params(sql, params)
+                            .await
+                            .map_err(WorkerError::from)
+                    })
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        self.record_outcome(&result);
+        self.record_write(sql, result.is_ok());
+        result
+    }
+
+    // Prepare `sql` under a new id to be run by `execute_prepared`
+    fn prepare_statement(&self, sql: &str) -> Result<String, WorkerError> {
+        let stmt_id = Uuid::new_v4().to_string();
+        self.database()?.prepare_statement(&stmt_id, sql)?;
+        Ok(stmt_id)
+    }
+
+    fn execute_prepared(
+        &self,
+        stmt_id: &str,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, WorkerError> {
+        self.circuit_breaker.allow(self.time.now_ms())?;
+        let result = self.database().and_then(|database| {
+            database
+                .query_prepared(stmt_id, params)
+                .ok_or_else(|| WorkerError::StatementNotFound(stmt_id.to_string()))?
+                .map_err(WorkerError::from)
+        });
+        self.record_outcome(&result);
+        result
+    }
+
+    fn finalize_statement(&self, stmt_id: &str) {
+        if let Ok(database) = self.database() {
+            database.finalize_statement(stmt_id);
+        }
+    }
+
+    async fn copy_table(
+        &self,
+        src: &str,
+        dst: &str,
+        filter: Option<&str>,
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(src)?;
+        validate_identifier(dst)?;
+        let database = self.database()?;
+
+        let owns_transaction = database.is_autocommit();
+        if owns_transaction {
+            database
+                .exec("BEGIN IMMEDIATE")
+                .await
+                .map_err(WorkerError::from)?;
+        }
+
+        let copied: Result<u64, WorkerError> = async {
+            let existing = database
+                .query_with_params(
+                    "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+                    &[SqlParam::from(dst)],
+                )
+                .await
+                .map_err(WorkerError::from)?;
+            if existing.rows.is_empty() {
+                database
+                    .query(&format!(
+                        "CREATE TABLE {dst} AS SELECT * FROM {src} LIMIT 0"
+                    ))
+                    .await
+                    .map_err(WorkerError::from)?;
+            }
+
+            let sql = match filter {
+                Some(filter) => format!("INSERT INTO {dst} SELECT * FROM {src} WHERE {filter}"),
+                None => format!("INSERT INTO {dst} SELECT * FROM {src}"),
+            };
+            let inserted = database.query(&sql).await.map_err(WorkerError::from)?;
+            Ok(inserted.rows_affected)
+        }
+        .await;
+
+        if owns_transaction {
+            let end = if copied.is_ok() { "COMMIT" } else { "ROLLBACK" };
+            database.exec(end).await.map_err(WorkerError::from)?;
+        }
+        copied
+    }
+
+    // Insert `values` in multi-row statements of up to `BULK_INSERT_CHUNK_ROWS`
+    // rows, all in one transaction unless one is already open
+    async fn bulk_insert(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: Vec<Vec<SqlParam>>,
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(table)?;
+        for column in columns {
+            validate_identifier(column)?;
+        }
+        if let Some(row) = values.iter().position(|row| row.len() != columns.len()) {
+            return Err(WorkerError::QueryFailed(format!(
+                "Row {row} has {} values for {} columns",
+                values[row].len(),
+                columns.len()
+            )));
+        }
+        let database = self.database()?;
+
+        let owns_transaction = database.is_autocommit();
+        if owns_transaction {
+            database
+                .exec("BEGIN IMMEDIATE")
+                .await
+                .map_err(WorkerError::from)?;
+        }
+
+        let inserted: Result<u64, WorkerError> = async {
+            let mut inserted = 0;
+            for chunk in values.chunks(bulk_insert_chunk_rows(columns.len())) {
+                let sql = bulk_insert_sql(table, columns, chunk.len());
+                let params: Vec<SqlParam> = chunk.iter().flatten().cloned().collect();
+                let result = database
+                    .query_with_params(&sql, &params)
+                    .await
+                    .map_err(WorkerError::from)?;
+                inserted += result.rows_affected;
+            }
+            Ok(inserted)
+        }
+        .await;
+
+        if owns_transaction {
+            let end = if inserted.is_ok() {
+                "COMMIT"
+            } else {
+                "ROLLBACK"
+            };
+            database.exec(end).await.map_err(WorkerError::from)?;
+        }
+        inserted
+    }
+
+    // Run `statements` in one transaction, rolling back at the first failure.
+    // Refuses to start inside an open transaction, which it could not undo.
+    async fn execute_ddl_transaction(&self, statements: &[String]) -> Result<(), WorkerError> {
+        check_ddl(statements)?;
+        let database = self.database()?;
+        if !database.is_autocommit() {
+            return Err(WorkerError::TransactionActive);
+        }
+
+        database.exec("BEGIN IMMEDIATE").await?;
+        for (index, sql) in statements.iter().enumerate() {
+            if let Err(err) = database.exec(sql).await {
+                let _ = database.exec("ROLLBACK").await;
+                return Err(WorkerError::DdlStatementFailed {
+                    index,
+                    error: Box::new(err.into()),
+                });
+            }
+        }
+        if let Err(err) = database.exec("COMMIT").await {
+            let _ = database.exec("ROLLBACK").await;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    async fn drop_table(&self, table: &str, if_exists: bool) -> Result<(), WorkerError> {
+        validate_identifier(table)?;
+        let if_exists = if if_exists { "IF EXISTS " } else { "" };
+        self.database()?
+            .exec(&format!("DROP TABLE {if_exists}{table}"))
+            .await
+            .map(|_| ())
+            .map_err(WorkerError::from)
+    }
+
+    async fn rename_table(&self, old_name: &str, new_name: &str) -> Result<(), WorkerError> {
+        validate_identifier(old_name)?;
+        validate_identifier(new_name)?;
+        self.database()?
+            .exec(&format!("ALTER TABLE {old_name} RENAME TO {new_name}"))
+            .await
+            .map(|_| ())
+            .map_err(WorkerError::from)
+    }
+
+    // Compile `sql` under `EXPLAIN`, which lists its bytecode instead of
+    // running it. Bypasses the circuit breaker, as user-typed SQL is expected
+    // to fail.
+    async fn validate_sql(&self, sql: &str) -> Result<(), WorkerError> {
+        self.database()?
+            .query(&format!("EXPLAIN {sql}"))
+            .await
+            .map(drop)
+            .map_err(WorkerError::from)
+    }
+
+    // Rebuild the indexes in an exclusive transaction so nothing writes
+    // meanwhile, then check the database is sound
+    async fn reindex(&self, index_name: Option<&str>) -> Result<(), WorkerError> {
+        if let Some(name) = index_name {
+            validate_identifier(name)?;
+        }
+        let database = self.database()?;
+        if !database.is_autocommit() {
+            return Err(WorkerError::TransactionActive);
+        }
+        let sql = match index_name {
+            Some(name) => format!("REINDEX {name}"),
+            None => "REINDEX".to_string(),
+        };
+
+        database.exec("BEGIN EXCLUSIVE").await?;
+        if let Err(err) = database.exec(&sql).await {
+            let _ = database.exec("ROLLBACK").await;
+            return Err(err.into());
+        }
+        database.exec("COMMIT").await?;
+
+        // integrity_check reports a single "ok" row when there is nothing wrong
+        let problems: Vec<String> = database
+            .query("PRAGMA integrity_check")
+            .await?
+            .into_iter()
+            .map(|row| <(String,)>::from_row(&row).map(|(problem,)| problem))
+            .collect::<Result<_, _>>()?;
+        if problems.iter().all(|problem| problem == "ok") {
+            Ok(())
+        } else {
+            Err(WorkerError::Corrupt {
+                message: problems.join("; "),
+            })
+        }
+    }
+
+    async fn savepoint(&self, name: &str, action: SavepointAction) -> Result<(), WorkerError> {
+        validate_identifier(name)?;
+        let database = self.database()?;
+        match action {
+            SavepointAction::Begin => database.exec(&format!("SAVEPOINT {name}")).await?,
+            SavepointAction::Release => database.exec(&format!("RELEASE SAVEPOINT {name}")).await?,
+            // Rolling back keeps the savepoint open, so release it as well
+            SavepointAction::RollbackTo => {
+                database
+                    .exec(&format!("ROLLBACK TO SAVEPOINT {name}"))
+                    .await?;
+                database.exec(&format!("RELEASE SAVEPOINT {name}")).await?
+            }
+        };
+        Ok(())
+    }
+
+    // Copy the whole database into `target_name` in one step
+    fn backup_database(&self, target_name: &str) -> Result<(), WorkerError> {
+        let backup = self.database()?.backup_to(target_name)?;
+        backup.step(-1)?;
+        backup.finish()?;
+        Ok(())
+    }
+
+    async fn execute_batch(&self, statements: &[String]) -> Vec<Result<QueryResult, WorkerError>> {
+        let database = match self.database() {
+            Ok(database) => database,
+            Err(err) => return statements.iter().map(|_| Err(err.clone())).collect(),
+        };
+
+        // Group the batch into one transaction, or into a savepoint if the
+        // caller already opened one, so it can be undone as a whole
+        let owns_transaction = database.is_autocommit();
+        let (begin, end) = if owns_transaction {
+            ("BEGIN DEFERRED", "COMMIT")
+        } else {
+            ("SAVEPOINT execute_batch", "RELEASE execute_batch")
+        };
+        if let Err(err) = database.exec(begin).await {
+            let err = WorkerError::from(err);
+            return statements.iter().map(|_| Err(err.clone())).collect();
+        }
+
+        let mut results = Vec::with_capacity(statements.len());
+        for sql in statements {
+            results.push(database.query(sql).await.map_err(WorkerError::from));
+        }
+
+        // One failing statement undoes the others, leaving the caller's
+        // transaction as it was before the batch
+        if results.iter().any(Result::is_err) {
+            undo_batch(&database, owns_transaction).await;
+            return results;
+        }
+        if let Err(err) = database.exec(end).await {
+            // Nothing in the batch was kept, so none of it succeeded
+            undo_batch(&database, owns_transaction).await;
+            let err = WorkerError::from(err);
+            return statements.iter().map(|_| Err(err.clone())).collect();
+        }
+        results
+    }
+
+    // Execute a request received over the channel and build the response to broadcast
+    async fn handle_request(&self, msg: ChannelMessage) -> Option<ChannelMessage> {
+        let (query_id, result) = match msg {
+            ChannelMessage::CopyTable {
+                query_id,
+                src,
+                dst,
+                filter,
+            } => (
+                query_id,
+                self.copy_table(&src, &dst, filter.as_deref())
+                    .await
+                    .map(|count| count.to_string())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::PrepareStatement { query_id, sql } => (
+                query_id,
+                self.prepare_statement(&sql).map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::ExecutePrepared {
+                query_id,
+                stmt_id,
+                params,
+            } => {
+                let result = self.execute_prepared(&stmt_id, &params);
+                if let Some(empty) = empty_response(&query_id, &result) {
+                    return Some(empty);
+                }
+                let result = result.and_then(|result| {
+                    serde_json::to_string(&result)
+                        .map_err(|e| WorkerError::QueryFailed(e.to_string()))
+                });
+                return Some(coded_query_response(query_id, result));
+            }
+            ChannelMessage::FinalizeStatement { stmt_id } => {
+                self.finalize_statement(&stmt_id);
+                return None;
+            }
+            ChannelMessage::BulkInsertRequest {
+                query_id,
+                table,
+                columns,
+                values,
+            } => (
+                query_id,
+                self.bulk_insert(&table, &columns, values)
+                    .await
+                    .map(|count| count.to_string())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::BatchQueryRequest {
+                query_id,
+                statements,
+                requests,
+            } => {
+                let results = if statements.is_empty() {
+                    Vec::new()
+                } else {
+                    self.execute_batch(&statements).await
+                };
+                // Each batched request runs on its own, exactly as if it were sent alone
+                let mut responses = Vec::with_capacity(requests.len());
+                for BatchedMessage { trace_id, message } in requests {
+                    if let Some(response) = Box::pin(self.handle_request(message)).await {
+                        responses.push(BatchedMessage {
+                            trace_id,
+                            message: response,
+                        });
+                    }
+                }
+                return Some(ChannelMessage::BatchQueryResponse {
+                    query_id,
+                    results: results.into_iter().map(BatchResult::from).collect(),
+                    responses,
+                });
+            }
+            ChannelMessage::PragmaSet {
+                query_id,
+                name,
+                value,
+            } => (
+                query_id,
+                self.set_pragma(&name, &value)
+                    .await
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::DdlTransaction {
+                query_id,
+                statements,
+            } => {
+                // A failing statement is reported at its index, as in a batch
+                let results = match self.execute_ddl_transaction(&statements).await {
+                    Ok(()) => Vec::new(),
+                    Err(WorkerError::DdlStatementFailed { index, error }) => {
+                        let mut results =
+                            vec![BatchResult::from(Ok(QueryResult::default())); index];
+                        results.push(BatchResult::from(Err(*error)));
+                        results
+                    }
+                    Err(err) => return Some(coded_query_response(query_id, Err(err))),
+                };
+                return Some(ChannelMessage::BatchQueryResponse {
+                    query_id,
+                    results,
+                    responses: Vec::new(),
+                });
+            }
+            ChannelMessage::DropTable {
+                query_id,
+                table,
+                if_exists,
+            } => {
+                let result = self.drop_table(&table, if_exists).await;
+                return Some(coded_query_response(
+                    query_id,
+                    result.map(|_| String::new()),
+                ));
+            }
+            ChannelMessage::RenameTable {
+                query_id,
+                old_name,
+                new_name,
+            } => {
+                let result = self.rename_table(&old_name, &new_name).await;
+                return Some(coded_query_response(
+                    query_id,
+                    result.map(|_| String::new()),
+                ));
+            }
+            ChannelMessage::ReindexRequest {
+                query_id,
+                index_name,
+            } => {
+                let result = self.reindex(index_name.as_deref()).await;
+                return Some(coded_query_response(
+                    query_id,
+                    result.map(|_| String::new()),
+                ));
+            }
+            ChannelMessage::ValidateSqlRequest { query_id, sql } => {
+                let result = self.validate_sql(&sql).await;
+                return Some(coded_query_response(
+                    query_id,
+                    result.map(|_| String::new()),
+                ));
+            }
+            ChannelMessage::Savepoint {
+                query_id,
+                name,
+                action,
+            } => {
+                let result = self.savepoint(&name, action).await;
+                return Some(coded_query_response(
+                    query_id,
+                    result.map(|_| String::new()),
+                ));
+            }
+            ChannelMessage::BackupRequest {
+                query_id,
+                target_name,
+            } => (
+                query_id,
+                self.backup_database(&target_name)
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::RegisterFunction {
+                query_id,
+                name,
+                arity,
+                func_source,
+            } => (
+                query_id,
+                function_from_source(&func_source)
+                    .and_then(|func| self.register_function(&name, arity, func))
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::QueryRequest {
+                query_id,
+                sql,
+                origin_worker_id,
+                metadata,
+                source_location,
+                read_concern,
+                progress_interval,
+            } => {
+                let started_at = js_sys::Date::now();
+                let checkpoint = match self.checkpoint_for(read_concern).await {
+                    Ok(checkpoint) => checkpoint,
+                    Err(err) => return Some(coded_query_response(query_id, Err(err))),
+                };
+                if let Some(interval) = progress_interval {
+                    let sender = self.sender.clone();
+                    let query_id = query_id.clone();
+                    self.report_progress(interval, move |vm_steps_completed| {
+                        let progress = ChannelMessage::QueryProgress {
+                            query_id: query_id.clone(),
+                            vm_steps_completed,
+                        };
+                        sender.post(progress, None);
+                    });
+                }
+                let result = self.exec(&sql).await;
+                if progress_interval.is_some() {
+                    self.stop_progress();
+                }
+                let duration_ms = js_sys::Date::now() - started_at;
+                warn_if_slow(
+                    &sql,
+                    duration_ms,
+                    RequestOrigin {
+                        worker_id: Some(&origin_worker_id),
+                        source_location: source_location.as_deref(),
+                        metadata: Some(&metadata),
+                    },
+                );
+                self.events.emit(&QueryEvent::QueryExecuted {
+                    query_id: query_id.clone(),
+                    origin_worker_id,
+                    duration_ms,
+                });
+                if result.as_deref() == Ok("[]") && checkpoint.is_none() {
+                    return Some(ChannelMessage::EmptyResponse {
+                        query_id,
+                        columns: Vec::new(),
+                    });
+                }
+                return Some(match result {
+                    Ok(res) => ChannelMessage::QueryResponse {
+                        query_id,
+                        result: Some(res),
+                        error: None,
+                        error_code: None,
+                        stats: self.stats_with_checkpoint(checkpoint),
+                    },
+                    Err(err) => coded_query_response(query_id, Err(err)),
+                });
+            }
+            ChannelMessage::ParameterizedQueryRequest {
+                query_id,
+                sql,
+                params,
+                source_location,
+            } => {
+                let started_at = js_sys::Date::now();
+                let result = self.execute_parameterized(&sql, &params).await;
+                warn_if_slow(
+                    &sql,
+                    js_sys::Date::now() - started_at,
+                    RequestOrigin {
+                        source_location: source_location.as_deref(),
+                        ..RequestOrigin::default()
+                    },
+                );
+                if let Some(empty) = empty_response(&query_id, &result) {
+                    return Some(empty);
+                }
+                let result = result.and_then(|result| {
+                    serde_json::to_string(&result)
+                        .map_err(|e| WorkerError::QueryFailed(e.to_string()))
+                });
+                return Some(coded_query_response(query_id, result));
+            }
+            ChannelMessage::Snapshot {
+                query_id,
+                snapshot_id,
+            } => (
+                query_id,
+                self.begin_snapshot(snapshot_id)
+                    .await
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::QuerySnapshot {
+                query_id,
+                snapshot_id,
+                sql,
+            } => (
+                query_id,
+                self.query_snapshot(&snapshot_id, &sql)
+                    .await
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::EndSnapshot {
+                query_id,
+                snapshot_id,
+            } => (
+                query_id,
+                self.end_snapshot(&snapshot_id)
+                    .await
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::BeginReadTransaction { query_id, tx_id } => (
+                query_id,
+                self.begin_snapshot(tx_id).await.map_err(|e| e.to_string()),
+            ),
+            ChannelMessage::ReadTransactionQuery {
+                query_id,
+                tx_id,
+                sql,
+                params,
+            } => {
+                let result = self
+                    .query_read_transaction(&tx_id, &sql, &params)
+                    .await
+                    .and_then(|result| {
+                        serde_json::to_string(&result)
+                            .map_err(|e| WorkerError::QueryFailed(e.to_string()))
+                    });
+                return Some(coded_query_response(query_id, result));
+            }
+            ChannelMessage::EndReadTransaction { query_id, tx_id } => (
+                query_id,
+                self.end_read_transaction(&tx_id)
+                    .await
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string()),
+            ),
+            _ => return None,
+        };
+        Some(query_response(query_id, result))
+    }
+}
+
+// Rolls back the open transaction if `run_in_transaction` is dropped before finishing
+struct TransactionGuard {
+    database: Rc<SQLiteDatabase>,
+    active_transaction: Rc<RefCell<Option<String>>>,
+    savepoints: Rc<RefCell<Vec<String>>>,
+    // Hands the requests queued during the transaction to the leader
+    flush_queued: Rc<dyn Fn()>,
+    completed: bool,
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        // Ending the transaction either way closes any savepoints left in it
+        self.savepoints.borrow_mut().clear();
+        let active_transaction = Rc::clone(&self.active_transaction);
+        let flush_queued = Rc::clone(&self.flush_queued);
+        if self.completed {
+            active_transaction.borrow_mut().take();
+            flush_queued();
+            return;
+        }
+        // Still marked as open until the rollback has run, so no other
+        // transaction starts and no queued request runs inside this one
+        let database = Rc::clone(&self.database);
+        spawn_local(async move {
+            let _ = database.exec("ROLLBACK").await;
+            active_transaction.borrow_mut().take();
+            flush_queued();
+        });
+    }
+}
+
+// Resolve after `ms` milliseconds, using the global scope's setTimeout
+pub(crate) async fn sleep(ms: f64) {
+    let promise = Promise::new(&mut |resolve, _| {
+        let global = js_sys::global();
+        let set_timeout = Reflect::get(&global, &JsValue::from_str("setTimeout")).unwrap();
+        let set_timeout = set_timeout.dyn_ref::<Function>().unwrap();
+        let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(ms));
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+impl WorkerState {
+    pub fn new() -> Result<Self, WorkerError> {
+        WorkerStateBuilder::new().build()
+    }
+
+    /// A second state with this worker's id and channel but fresh pending
+    /// queries, no leadership and no database, standing in for another tab
+    /// that shares the worker id. Intended for tests.
+    pub fn fork_for_test(&self) -> WorkerState {
+        let pending_queries = Rc::new(RefCell::new(IndexMap::new()));
+        let known_workers = Rc::new(RefCell::new(HashMap::new()));
+        let dispatcher = MessageDispatcher::new();
+        register_gossip_handler(
+            &dispatcher,
+            self.worker_id.clone(),
+            Rc::clone(&known_workers),
+        );
+        WorkerState {
+            worker_id: self.worker_id.clone(),
+            is_leader: Rc::new(RefCell::new(false)),
+            db: Rc::new(RefCell::new(None)),
+            channel: self.channel.clone(),
+            micro_batch: Rc::new(MicroBatch::new()),
+            pending_queries,
+            active_transaction: Rc::new(RefCell::new(None)),
+            savepoints: Rc::new(RefCell::new(Vec::new())),
+            snapshots: Rc::new(RefCell::new(HashMap::new())),
+            config: self.config.clone(),
+            observer: self.observer.clone(),
+            time: Rc::clone(&self.time),
+            // Both states send on one channel under one id, so they share a sequence
+            // for receivers to keep accepting their messages
+            send_sequence: Rc::clone(&self.send_sequence),
+            messages_failed_to_send: Rc::new(Cell::new(0)),
+            known_leader_id: Rc::new(RefCell::new(None)),
+            message_log: Rc::new(RefCell::new(VecDeque::new())),
+            events: EventEmitter::new(),
+            trace_callback: Rc::new(RefCell::new(None)),
+            max_sql_length: Cell::new(self.max_sql_length.get()),
+            circuit_breaker: Rc::new(CircuitBreaker::new(
+                self.config.circuit_failure_threshold,
+                self.config.circuit_reset_timeout_ms,
+            )),
+            leadership_acquisition_ms: Rc::new(Cell::new(None)),
+            known_workers,
+            gossip_interval: RefCell::new(None),
+            dispatch_paused: Rc::new(Cell::new(false)),
+            paused_requests: Rc::new(RefCell::new(VecDeque::new())),
+            error_handlers: Rc::new(RefCell::new(Vec::new())),
+            watchers: RefCell::new(HashMap::new()),
+            custom_handlers: Rc::new(RefCell::new(HashMap::new())),
+            leadership_requested: Rc::new(Cell::new(false)),
+            leadership_release: Rc::new(RefCell::new(None)),
+            query_cache: Rc::new(RefCell::new(QueryCache::new(self.config.query_cache_size))),
+            shared_state: Rc::new(RefCell::new(None)),
+            ready_callbacks: Rc::new(RefCell::new(Vec::new())),
+            channel_listener: RefCell::new(None),
+            visibility_listener: RefCell::new(None),
+            created_at_ms: self.created_at_ms,
+            last_leader_state: Rc::new(RefCell::new(None)),
+            state_requests: Rc::new(RefCell::new(Vec::new())),
+            schema_error: RefCell::new(None),
+            fallback_queue: Rc::new(RefCell::new(VecDeque::new())),
+            promoted_queries: Rc::new(Cell::new(0)),
+            authorizer: Rc::new(RefCell::new(self.authorizer.borrow().clone())),
+            dispatcher: Rc::new(dispatcher),
+            last_query_stats: Rc::new(RefCell::new(None)),
+            dom_events: Rc::new(Cell::new(false)),
+            dom_events_installed: Cell::new(false),
+            cross_db_schemas: RefCell::new(Vec::new()),
+            strict_tables: Cell::new(self.strict_tables.get()),
+            progress_channel: Rc::new(RefCell::new(HashMap::new())),
+            storage_monitor: RefCell::new(None),
+            auto_analyze: AutoAnalyze::new(self.config.auto_analyze_after_writes),
+        }
+    }
+
+    /// Whether a `BroadcastChannel` can be constructed here. Some environments
+    /// (Node.js, old Safari) lack the global or throw from its constructor.
+    pub fn is_broadcast_channel_supported() -> bool {
+        let global = js_sys::global();
+        let constructor = Reflect::get(&global, &JsValue::from_str("BroadcastChannel"));
+        if !constructor.is_ok_and(|constructor| constructor.is_function()) {
+            return false;
+        }
+        match BroadcastChannel::new("sqlite-queries-probe") {
+            Ok(probe) => {
+                probe.close();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Whether `navigator.locks`, used for leader election, is available
+    pub fn is_navigator_locks_supported() -> bool {
+        let global = js_sys::global();
+        Reflect::get(&global, &JsValue::from_str("navigator"))
+            .ok()
+            .filter(|navigator| navigator.is_object())
+            .and_then(|navigator| Reflect::get(&navigator, &JsValue::from_str("locks")).ok())
+            .is_some_and(|locks| locks.is_object())
+    }
+
+    pub fn builder() -> WorkerStateBuilder {
+        WorkerStateBuilder::new()
+    }
+
+    pub(crate) fn sender(&self) -> ChannelSender {
+        ChannelSender {
+            channel: self.channel.clone(),
+            worker_id: self.worker_id.clone(),
+            send_sequence: Rc::clone(&self.send_sequence),
+            log: self.log(),
+            error_handlers: Rc::clone(&self.error_handlers),
+            failed_sends: Rc::clone(&self.messages_failed_to_send),
+        }
+    }
+
+    /// Register a handler for errors that have no caller to return them to,
+    /// such as failed broadcasts and undecodable channel messages. Handlers
+    /// must not register further handlers while being called.
+    pub fn on_error(&self, handler: impl Fn(WorkerError) + 'static) {
+        self.error_handlers.borrow_mut().push(Box::new(handler));
+    }
+
+    /// Whether queries can run: this worker leads with an open database, or
+    /// another worker has announced itself as leader
+    pub fn is_database_ready(&self) -> bool {
+        if *self.is_leader.borrow() {
+            self.db.borrow().is_some()
+        } else {
+            self.known_leader_id.borrow().is_some()
+        }
+    }
+
+    /// Call `cb` once the database is ready: when this worker opens it as
+    /// leader or hears a leader announce itself. If it is already ready `cb`
+    /// runs before this returns.
+    pub fn on_database_ready(&self, cb: Box<dyn Fn()>) {
+        if self.is_database_ready() {
+            cb();
+        } else {
+            self.ready_callbacks.borrow_mut().push(cb);
+        }
+    }
+
+    pub(crate) fn report_error(&self, error: WorkerError) {
+        report_error(&self.error_handlers, error);
+    }
+
+    /// Call `handler` with each received message of `kind`, after this worker's
+    /// own handling of it and after any handlers registered earlier
+    pub fn register_message_handler(
+        &self,
+        kind: MessageKind,
+        handler: Box<dyn Fn(ChannelMessage) + 'static>,
+    ) {
+        self.dispatcher.register(kind, handler);
+    }
+
+    /// Call `handler` with the payload of each `Custom` message of type
+    /// `type_name` received from other workers, replacing any earlier handler
+    /// for that type. Custom messages with no handler are ignored.
+    pub fn register_custom_handler(
+        &self,
+        type_name: &str,
+        handler: Box<dyn Fn(serde_json::Value) + 'static>,
+    ) {
+        self.custom_handlers
+            .borrow_mut()
+            .insert(type_name.to_string(), Rc::from(handler));
+    }
+
+    /// Broadcast an application-defined message to the other workers
+    pub fn send_custom(
+        &self,
+        type_name: String,
+        payload: serde_json::Value,
+    ) -> Result<(), WorkerError> {
+        self.broadcast(ChannelMessage::Custom { type_name, payload })
+    }
+
+    fn log(&self) -> MessageLog {
+        MessageLog {
+            entries: Rc::clone(&self.message_log),
+            max_entries: self.config.message_log_size,
+        }
+    }
+
+    /// Recent channel messages sent and received by this worker, oldest first
+    pub fn message_log(&self) -> Vec<LoggedMessage> {
+        self.message_log.borrow().iter().cloned().collect()
+    }
+
+    pub fn clear_message_log(&self) {
+        self.message_log.borrow_mut().clear();
+    }
+
+    /// Broadcast a message to the other workers, tagged with the next send sequence
+    pub fn broadcast(&self, message: ChannelMessage) -> Result<(), WorkerError> {
+        self.sender().send(message)
+    }
+
+    /// Broadcast `msg`, trying again up to 3 times 10ms apart if the channel
+    /// refuses it. A message that still fails is counted in
+    /// `messages_failed_to_send` and passed to the error handlers as well as
+    /// returned.
+    pub async fn send_message(&self, msg: &ChannelMessage) -> Result<(), WorkerError> {
+        self.sender().send_with_retry(msg.clone()).await
+    }
+
+    pub fn messages_failed_to_send(&self) -> u64 {
+        self.messages_failed_to_send.get()
+    }
+
+    fn leader_context(&self) -> LeaderContext {
+        LeaderContext {
+            db: Rc::clone(&self.db),
+            snapshots: Rc::clone(&self.snapshots),
+            max_snapshots: self.config.max_snapshots,
+            max_snapshot_bytes: self.config.max_snapshot_bytes,
+            events: self.events.clone(),
+            circuit_breaker: Rc::clone(&self.circuit_breaker),
+            time: Rc::clone(&self.time),
+            sender: self.sender(),
+            auto_analyze: self.auto_analyze.clone(),
+            busy_retry: BusyRetry {
+                max_retries: self.config.busy_max_retries,
+                backoff_ms: self.config.busy_backoff_ms,
+            },
+        }
+    }
+
+    fn database(&self) -> Result<Rc<SQLiteDatabase>, WorkerError> {
+        self.db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)
+    }
+
+    pub fn setup_channel_listener(&self) {
+        let is_leader = Rc::clone(&self.is_leader);
+        let leader = self.leader_context();
+        let pending_queries = Rc::clone(&self.pending_queries);
+        let observer = self.observer.clone();
+        let sender = self.sender();
+        let known_leader_id = Rc::clone(&self.known_leader_id);
+        let trace_callback = Rc::clone(&self.trace_callback);
+        let dispatch_paused = Rc::clone(&self.dispatch_paused);
+        let active_transaction = Rc::clone(&self.active_transaction);
+        let paused_requests = Rc::clone(&self.paused_requests);
+        let custom_handlers = Rc::clone(&self.custom_handlers);
+        let ready_callbacks = Rc::clone(&self.ready_callbacks);
+        let db = Rc::clone(&self.db);
+        let created_at_ms = self.created_at_ms;
+        let last_leader_state = Rc::clone(&self.last_leader_state);
+        let state_requests = Rc::clone(&self.state_requests);
+        let last_query_stats = Rc::clone(&self.last_query_stats);
+        let events = self.events.clone();
+        let query_cache = Rc::clone(&self.query_cache);
+        let progress_channel = Rc::clone(&self.progress_channel);
+        let handled_requests = RefCell::new(HandledRequests::default());
+
+        let handle_message = move |msg: ChannelMessage, trace_id: Option<String>| match msg {
+            ChannelMessage::QueryResponse {
+                query_id,
+                result,
+                error,
+                error_code,
+                stats,
+            } => {
+                progress_channel.borrow_mut().remove(&query_id);
+                let pending = pending_queries.borrow_mut().shift_remove(&query_id);
+                if let Some(pending) = pending {
+                    if stats.is_some() {
+                        *last_query_stats.borrow_mut() = stats;
+                    }
+                    if let Some(trace_id) = &trace_id {
+                        let callback = trace_callback.borrow().clone();
+                        if let Some(callback) = callback {
+                            callback(trace_id, js_sys::Date::now() - pending.sent_at_ms);
+                        }
+                    }
+                    if let Some(err) = error {
+                        let _ = pending
+                            .reject
+                            .call1(&JsValue::NULL, &rejection_value(&err, error_code));
+                    } else if let Some(res) = result {
+                        let _ = pending
+                            .resolve
+                            .call1(&JsValue::NULL, &JsValue::from_str(&res));
+                    }
+                }
+            }
+            ChannelMessage::EmptyResponse { query_id, columns } => {
+                progress_channel.borrow_mut().remove(&query_id);
+                let pending = pending_queries.borrow_mut().shift_remove(&query_id);
+                if let Some(pending) = pending {
+                    if let Some(trace_id) = &trace_id {
+                        let callback = trace_callback.borrow().clone();
+                        if let Some(callback) = callback {
+                            callback(trace_id, js_sys::Date::now() - pending.sent_at_ms);
+                        }
+                    }
+                    let json = empty_response_json(pending.request.as_ref(), columns);
+                    let _ = pending
+                        .resolve
+                        .call1(&JsValue::NULL, &JsValue::from_str(&json));
+                }
+            }
+            ChannelMessage::BatchQueryResponse {
+                query_id, results, ..
+            } => {
+                let pending = pending_queries.borrow_mut().shift_remove(&query_id);
+                let Some(pending) = pending else {
+                    return;
+                };
+                match serde_json::to_string(&results) {
+                    Ok(json) => {
+                        let _ = pending
+                            .resolve
+                            .call1(&JsValue::NULL, &JsValue::from_str(&json));
+                    }
+                    Err(err) => {
+                        let _ = pending
+                            .reject
+                            .call1(&JsValue::NULL, &JsValue::from_str(&err.to_string()));
+                    }
+                }
+            }
+            ChannelMessage::EchoResponse {
+                echo_id, payload, ..
+            } => {
+                if let Some(pending) = pending_queries.borrow_mut().shift_remove(&echo_id) {
+                    let _ = pending
+                        .resolve
+                        .call1(&JsValue::NULL, &JsValue::from_str(&payload));
+                }
+            }
+            ChannelMessage::EchoRequest { echo_id, payload } => {
+                if *is_leader.borrow() {
+                    let response = ChannelMessage::EchoResponse {
+                        echo_id,
+                        payload,
+                        responder_id: sender.worker_id.clone(),
+                    };
+                    sender.post(response, trace_id);
+                }
+            }
+            ChannelMessage::RequestState { requester_id } => {
+                if *is_leader.borrow() {
+                    let response = ChannelMessage::StateResponse {
+                        from_leader_id: sender.worker_id.clone(),
+                        to_requester_id: requester_id,
+                        db_initialized: db.borrow().is_some(),
+                        pending_queue_depth: pending_queries.borrow().len() as u32,
+                        uptime_ms: js_sys::Date::now() - created_at_ms,
+                    };
+                    sender.post(response, trace_id);
+                }
+            }
+            ChannelMessage::StateResponse {
+                from_leader_id,
+                to_requester_id,
+                db_initialized,
+                pending_queue_depth,
+                uptime_ms,
+            } => {
+                // Only the leader answers, so this is how a worker started after
+                // the `NewLeader` broadcast finds out who leads. One still
+                // opening its database announces itself when done.
+                let discovered =
+                    db_initialized && known_leader_id.borrow().as_deref() != Some(&from_leader_id);
+                if discovered && !*is_leader.borrow() {
+                    *known_leader_id.borrow_mut() = Some(from_leader_id.clone());
+                    notify_ready(&ready_callbacks);
+                }
+                if to_requester_id == sender.worker_id {
+                    *last_leader_state.borrow_mut() = Some(LeaderState {
+                        leader_id: from_leader_id,
+                        db_initialized,
+                        pending_queue_depth,
+                        uptime_ms,
+                    });
+                    let waiting = std::mem::take(&mut *state_requests.borrow_mut());
+                    for resolve in waiting {
+                        let _ = resolve.call0(&JsValue::NULL);
+                    }
+                }
+            }
+            ChannelMessage::QueryProgress {
+                query_id,
+                vm_steps_completed,
+            } => {
+                // Cloned out so the callback can start other queries
+                let cb = progress_channel.borrow().get(&query_id).cloned();
+                if let Some(cb) = cb {
+                    let _ = cb.call1(&JsValue::NULL, &JsValue::from(vm_steps_completed as f64));
+                }
+            }
+            // Handled by the gossip module through the dispatcher
+            ChannelMessage::GossipState { .. } => {}
+            ChannelMessage::DatabaseReset { .. } => {
+                query_cache.borrow_mut().clear();
+            }
+            ChannelMessage::Custom { type_name, payload } => {
+                // Cloned out so a handler can register others while running
+                let handler = custom_handlers.borrow().get(&type_name).cloned();
+                if let Some(handler) = handler {
+                    handler(payload);
+                }
+            }
+            ChannelMessage::NewLeader { leader_id } => {
+                *known_leader_id.borrow_mut() = Some(leader_id.clone());
+                if let Some(observer) = &observer {
+                    observer.on_leader_changed(&leader_id);
+                }
+                events.emit(&QueryEvent::LeaderChanged { leader_id });
+                if !*is_leader.borrow() {
+                    notify_ready(&ready_callbacks);
+                }
+            }
+            ChannelMessage::Redirect {
+                worker_id,
+                recipient_id,
+                ..
+            } => {
+                let addressed = recipient_id
+                    .as_deref()
+                    .is_none_or(|recipient| recipient == sender.worker_id);
+                if addressed && !*is_leader.borrow() {
+                    redirect_pending(&known_leader_id, &pending_queries, &sender, &worker_id);
+                }
+            }
+            request => {
+                if *is_leader.borrow() {
+                    // A follower re-sends its pending requests after a redirect,
+                    // which must not run a write twice
+                    let request = {
+                        let mut handled = handled_requests.borrow_mut();
+                        let mut first_time = |request: &ChannelMessage| {
+                            let first =
+                                request.request_id().is_none_or(|id| handled.first_time(id));
+                            if !first {
+                                console_debug("Ignoring a request that was already handled");
+                            }
+                            first
+                        };
+                        match request {
+                            // A micro-batch is checked request by request
+                            ChannelMessage::BatchQueryRequest {
+                                query_id,
+                                statements,
+                                requests,
+                            } if statements.is_empty() => {
+                                let requests = requests
+                                    .into_iter()
+                                    .filter(|batched| first_time(&batched.message))
+                                    .collect();
+                                ChannelMessage::BatchQueryRequest {
+                                    query_id,
+                                    statements,
+                                    requests,
+                                }
+                            }
+                            request if first_time(&request) => request,
+                            _ => return,
+                        }
+                    };
+                    // Held back rather than run inside a transaction this worker has open
+                    if dispatch_paused.get() || active_transaction.borrow().is_some() {
+                        paused_requests.borrow_mut().push_back((request, trace_id));
+                    } else {
+                        respond_to_request(leader.clone(), sender.clone(), request, trace_id);
+                    }
+                }
+            }
+        };
+
+        // Messages are delivered per sender in send order, buffering any that arrive early
+        let reorder_buffers: Rc<RefCell<HashMap<String, MessageReorderBuffer<ChannelEnvelope>>>> =
+            Rc::new(RefCell::new(HashMap::new()));
+        let gap_timer_running = Rc::new(Cell::new(false));
+        let log = self.log();
+        let error_handlers = Rc::clone(&self.error_handlers);
+        let dispatcher = Rc::clone(&self.dispatcher);
+
+        let deliver = Rc::new(move |ready: ChannelEnvelope| {
+            log.record(MessageDirection::Received, &ready.message);
+            // Only copied when something is registered for it
+            let registered =
+                (dispatcher.handler_count(ready.message.kind()) > 0).then(|| ready.message.clone());
+            match ready.message {
+                // Batched responses are handled one by one, as if each had
+                // arrived alone
+                ChannelMessage::BatchQueryResponse {
+                    query_id,
+                    results,
+                    responses,
+                } => {
+                    for response in responses {
+                        handle_message(response.message, response.trace_id);
+                    }
+                    let message = ChannelMessage::BatchQueryResponse {
+                        query_id,
+                        results,
+                        responses: Vec::new(),
+                    };
+                    handle_message(message, ready.trace_id);
+                }
+                message => handle_message(message, ready.trace_id),
+            }
+            if let Some(message) = registered {
+                dispatcher.dispatch(&message);
+            }
+        });
+
+        let onmessage = Closure::wrap(Box::new(move |event: web_sys::MessageEvent| {
+            let data = event.data();
+
+            match serde_wasm_bindgen::from_value::<ChannelEnvelope>(data) {
+                Ok(envelope) => {
+                    let sequence = envelope.sender_sequence;
+                    let now_ms = js_sys::Date::now();
+                    let (ready, waiting) = {
+                        let mut buffers = reorder_buffers.borrow_mut();
+                        // Forget senders that have gone away, so the map does not
+                        // keep every worker ever heard from
+                        buffers.retain(|_, buffer| !buffer.is_idle(now_ms));
+                        let buffer = buffers
+                            .entry(envelope.sender_id.clone())
+                            .or_insert_with(|| MessageReorderBuffer::new(sequence));
+                        let ready = buffer.push(sequence, envelope, now_ms);
+                        (ready, !buffer.pending.is_empty())
+                    };
+                    for ready in ready {
+                        deliver(ready);
+                    }
+                    if waiting && !gap_timer_running.replace(true) {
+                        spawn_local(release_reordered(
+                            Rc::clone(&reorder_buffers),
+                            Rc::clone(&gap_timer_running),
+                            Rc::clone(&deliver),
+                        ));
+                    }
+                }
+                Err(err) => report_error(
+                    &error_handlers,
+                    WorkerError::ChannelError(format!("Failed to decode channel message: {err}")),
+                ),
+            }
+        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+
+        self.channel
+            .set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        // Replacing an earlier listener frees it, now that the channel no longer uses it
+        *self.channel_listener.borrow_mut() = Some(onmessage);
+    }
+
+    /// Listen on the channel and request leadership. With an
+    /// `expected_schema_version` configured, queries are held back until the
+    /// database is ready and its version has been checked; on a mismatch the
+    /// error is returned and every later query fails with it. If the database
+    /// is not ready within `DATABASE_READY_TIMEOUT_MS` this fails with
+    /// `NoLeaderAvailable` and lets the held queries continue.
+    pub async fn start(&self) -> Result<(), WorkerError> {
+        let expected = self.config.expected_schema_version;
+        if expected.is_some() {
+            self.pause_dispatch();
+        }
+        self.setup_channel_listener();
+        self.attempt_leadership().await;
+        if let Err(error) = self.discover_leader() {
+            report_error(&self.error_handlers, error);
+        }
+        let Some(expected) = expected else {
+            return Ok(());
+        };
+
+        let result = match self.wait_for_database(DATABASE_READY_TIMEOUT_MS).await {
+            Ok(()) => self.verify_schema_version(expected).await,
+            Err(error) => Err(error),
+        };
+        self.resume_dispatch();
+        result
+    }
+
+    // Wait up to `timeout_ms` for `is_database_ready`, failing with
+    // `NoLeaderAvailable` if no worker manages to open the database
+    async fn wait_for_database(&self, timeout_ms: f64) -> Result<(), WorkerError> {
+        let started_at = js_sys::Date::now();
+        while !self.is_database_ready() {
+            if js_sys::Date::now() - started_at >= timeout_ms {
+                return Err(WorkerError::NoLeaderAvailable);
+            }
+            sleep(PAUSE_POLL_MS).await;
+        }
+        Ok(())
+    }
+
+    // Check the schema version, rejecting all queries from now on if it differs
+    pub(crate) async fn verify_schema_version(&self, expected: i32) -> Result<(), WorkerError> {
+        let result = self.assert_schema_version(expected).await;
+        if let Err(error @ WorkerError::SchemaMismatch { .. }) = &result {
+            *self.schema_error.borrow_mut() = Some(error.clone());
+        }
+        result
+    }
+
+    fn check_schema(&self) -> Result<(), WorkerError> {
+        match &*self.schema_error.borrow() {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Request the leadership lock, becoming leader once it is granted. Does
+    /// nothing if a request is already outstanding.
+    pub async fn attempt_leadership(&self) {
+        if self.leadership_requested.replace(true) {
+            return;
+        }
+        let worker_id = self.worker_id.clone();
+        let is_leader = Rc::clone(&self.is_leader);
+        let db = Rc::clone(&self.db);
+        let observer = self.observer.clone();
+        let sender = self.sender();
+        let known_leader_id = Rc::clone(&self.known_leader_id);
+        let leadership_acquisition_ms = Rc::clone(&self.leadership_acquisition_ms);
+        let busy_timeout_ms = self.config.busy_timeout_ms;
+        let enforce_foreign_keys = self.config.enforce_foreign_keys;
+        let persist_storage = self.config.request_persistence;
+        let remote_database_url = self.config.remote_database_url.clone();
+        let error_handlers = Rc::clone(&self.error_handlers);
+        let leadership_release = Rc::clone(&self.leadership_release);
+        let shared_state = Rc::clone(&self.shared_state);
+        let ready_callbacks = Rc::clone(&self.ready_callbacks);
+        let authorizer = Rc::clone(&self.authorizer);
+        let events = self.events.clone();
+
+        // Get navigator.locks from WorkerGlobalScope
+        let global = js_sys::global();
+        let navigator = Reflect::get(&global, &JsValue::from_str("navigator")).unwrap();
+        let locks = Reflect::get(&navigator, &JsValue::from_str("locks")).unwrap();
+
+        let options = Object::new();
+        Reflect::set(
+            &options,
+            &JsValue::from_str("mode"),
+            &JsValue::from_str("exclusive"),
+        )
+        .unwrap();
+
+        let requested_at = performance_now();
+        let handler = Closure::once(move |_lock: JsValue| -> Promise {
+            let duration_ms = performance_now() - requested_at;
+            leadership_acquisition_ms.set(Some(duration_ms));
+            if duration_ms > SLOW_LEADERSHIP_ACQUISITION_MS {
+                console_warn(&format!(
+                    "Leadership lock for worker {worker_id} took {duration_ms:.0}ms to acquire"
+                ));
+            }
+            *is_leader.borrow_mut() = true;
+            store_leader_flag(&shared_state, true);
+
+            let db = Rc::clone(&db);
+            let sender = sender.clone();
+            let worker_id = worker_id.clone();
+            let ready_callbacks = Rc::clone(&ready_callbacks);
+            let authorizer = Rc::clone(&authorizer);
+            let events = events.clone();
+
+            spawn_local(async move {
+                if persist_storage {
+                    match request_persistence().await {
+                        Ok(true) => {}
+                        Ok(false) => events.emit(&QueryEvent::PersistenceNotGranted {
+                            worker_id: worker_id.clone(),
+                        }),
+                        Err(error) => report_error(&error_handlers, error),
+                    }
+                }
+                let opened = match &remote_database_url {
+                    Some(url) => open_with_remote_import(url).await,
+                    None => SQLiteDatabase::initialize_opfs()
+                        .await
+                        .map(|database| (database, None))
+                        .map_err(|e| WorkerError::from_js_error(&e)),
+                };
+                match opened {
+                    Ok((database, imported_bytes)) => {
+                        if let (Some(url), Some(bytes)) = (remote_database_url, imported_bytes) {
+                            events.emit(&QueryEvent::DatabaseImported { url, bytes });
+                        }
+                        for pragma in connection_pragmas(busy_timeout_ms, enforce_foreign_keys) {
+                            if let Err(error) = database.exec(&pragma).await {
+                                report_error(&error_handlers, error.into());
+                            }
+                        }
+                        // Installed last so the library's own setup is not checked
+                        if let Some(authorizer) = authorizer.borrow().clone() {
+                            database.set_authorizer(Some(authorizer));
+                        }
+                        *db.borrow_mut() = Some(Rc::new(database));
+                        *known_leader_id.borrow_mut() = Some(worker_id.clone());
+                        if let Some(observer) = &observer {
+                            observer.on_leader_changed(&worker_id);
+                        }
+                        events.emit(&QueryEvent::LeaderChanged {
+                            leader_id: worker_id.clone(),
+                        });
+
+                        let msg = ChannelMessage::NewLeader {
+                            leader_id: worker_id.clone(),
+                        };
+                        sender.post(msg, None);
+                        notify_ready(&ready_callbacks);
+                    }
+                    Err(error) => report_error(&error_handlers, error),
+                }
+            });
+
+            // Hold the lock until `resign_leadership` resolves this
+            Promise::new(&mut |release, _| {
+                *leadership_release.borrow_mut() = Some(release);
+            })
+        });
+
+        let request_fn = Reflect::get(&locks, &JsValue::from_str("request")).unwrap();
+        let request_fn = request_fn.dyn_ref::<Function>().unwrap();
+
+        let _ = request_fn.call3(
+            &locks,
+            &JsValue::from_str("sqlite-database"),
+            &options,
+            handler.as_ref().unchecked_ref(),
+        );
+
+        handler.forget();
+    }
+
+    /// Stop leading and release the leadership lock so another worker can take
+    /// over. The connection and any snapshots are closed. Does nothing unless
+    /// this worker is the leader.
+    pub fn resign_leadership(&self) {
+        if !*self.is_leader.borrow() {
+            return;
+        }
+        *self.is_leader.borrow_mut() = false;
+        store_leader_flag(&self.shared_state, false);
+        self.db.borrow_mut().take();
+        self.snapshots.borrow_mut().clear();
+        let was_known_leader =
+            self.known_leader_id.borrow().as_deref() == Some(self.worker_id.as_str());
+        if was_known_leader {
+            self.known_leader_id.borrow_mut().take();
+        }
+        self.leadership_requested.set(false);
+        if let Some(release) = self.leadership_release.borrow_mut().take() {
+            let _ = release.call0(&JsValue::NULL);
+        }
+    }
+
+    /// Execute `sql` on the leader and return its rows as JSON. In debug builds
+    /// the caller's source location travels with the request.
+    #[track_caller]
+    pub fn execute_query(
+        &self,
+        sql: String,
+        ctx: QueryContext,
+    ) -> impl Future<Output = Result<String, String>> + '_ {
+        self.run_query(sql, ctx, caller_location())
+    }
+
+    /// Execute `sql` on the leader with a default context, returning the
+    /// structured result rather than JSON
+    #[track_caller]
+    pub fn execute_query_simple(
+        &self,
+        sql: String,
+    ) -> impl Future<Output = Result<QueryResult, WorkerError>> + '_ {
+        self.run_parameterized(sql, Vec::new(), caller_location())
+    }
+
+    /// Execute a query tagged with `trace_id`, which travels with the request to
+    /// the leader and back and is reported in the `QueryDelivered` event
+    #[track_caller]
+    pub fn execute_query_traced(
+        &self,
+        sql: String,
+        trace_id: String,
+    ) -> impl Future<Output = Result<String, String>> + '_ {
+        self.execute_query(sql, QueryContext::default().with_trace_id(trace_id))
+    }
+
+    /// Execute a query, calling `cb` with the number of virtual machine
+    /// instructions the leader has run so far every `PROGRESS_REPORT_INTERVAL`
+    /// of them. From a follower the counts arrive over the channel, so `cb` may
+    /// lag slightly behind the query.
+    #[track_caller]
+    pub fn execute_query_with_progress(
+        &self,
+        sql: String,
+        cb: Function,
+    ) -> impl Future<Output = Result<String, String>> + '_ {
+        self.execute_query(sql, QueryContext::default().with_progress(cb))
+    }
+
+    /// Register the callback non-leaders invoke with `(trace_id, duration_ms)`
+    /// when a traced response arrives
+    pub fn set_trace_callback(&self, callback: impl Fn(&str, f64) + 'static) {
+        *self.trace_callback.borrow_mut() = Some(Rc::new(callback));
+    }
+
+    /// Change the SQL length limit enforced before dispatching queries
+    pub fn set_max_sql_length(&self, n: usize) {
+        self.max_sql_length.set(n);
+    }
+
+    fn check_sql_length(&self, sql: &str) -> Result<(), WorkerError> {
+        let max = self.max_sql_length.get();
+        if sql.len() > max {
+            return Err(WorkerError::SqlTooLong {
+                length: sql.len(),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    async fn run_query(
+        &self,
+        sql: String,
+        ctx: QueryContext,
+        source_location: Option<String>,
+    ) -> Result<String, String> {
+        self.check_sql_length(&sql).map_err(|e| e.to_string())?;
+        self.wait_while_paused().await;
+        self.check_schema().map_err(|e| e.to_string())?;
+        let query_id = Uuid::new_v4().to_string();
+        let started_at = js_sys::Date::now();
+        if let Some(observer) = &self.observer {
+            observer.on_query_start(&query_id, &sql);
+        }
+
+        let ctx = self.auto_prioritize(&query_id, &sql, ctx);
+        let trace_id = ctx.trace_id.clone();
+        let result = self
+            .dispatch_query(query_id.clone(), sql, ctx, source_location)
+            .await;
+
+        let duration_ms = js_sys::Date::now() - started_at;
+        if let Some(observer) = &self.observer {
+            observer.on_query_end(&query_id, duration_ms, result.is_ok());
+        }
+        self.events.emit(&QueryEvent::QueryDelivered {
+            query_id,
+            trace_id,
+            duration_ms,
+            success: result.is_ok(),
+        });
+        result
+    }
+
+    // Raise a write's priority to `High` when `auto_prioritize_writes` is on
+    // and the context has the default priority
+    fn auto_prioritize(&self, query_id: &str, sql: &str, ctx: QueryContext) -> QueryContext {
+        if !self.config.auto_prioritize_writes
+            || ctx.priority != QueryPriority::Normal
+            || !SqlClassifier::is_write_statement(sql)
+        {
+            return ctx;
+        }
+        console_debug(&format!(
+            "Query {query_id} is a write; raising its priority to high"
+        ));
+        ctx.with_priority(QueryPriority::High)
+    }
+
+    async fn dispatch_query(
+        &self,
+        query_id: String,
+        sql: String,
+        ctx: QueryContext,
+        source_location: Option<String>,
+    ) -> Result<String, String> {
+        if *self.is_leader.borrow() {
+            let leader = self.leader_context();
+            let checkpoint = leader
+                .checkpoint_for(ctx.read_concern)
+                .await
+                .map_err(|e| e.to_string())?;
+            if let Some(cb) = ctx.progress.clone() {
+                leader.report_progress(PROGRESS_REPORT_INTERVAL, move |steps| {
+                    let _ = cb.call1(&JsValue::NULL, &JsValue::from(steps as f64));
+                });
+            }
+            let result = leader.exec(&sql).await.map_err(|e| e.to_string());
+            if ctx.progress.is_some() {
+                leader.stop_progress();
+            }
+            if checkpoint.is_some() {
+                *self.last_query_stats.borrow_mut() = leader.stats_with_checkpoint(checkpoint);
+            }
+            result
+        } else {
+            if self.known_leader_id.borrow().is_none() {
+                self.wait_for_leader(LEADER_WAIT_MS)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            let msg = ChannelMessage::QueryRequest {
+                query_id: query_id.clone(),
+                sql,
+                origin_worker_id: self.worker_id.clone(),
+                metadata: ctx.metadata.clone(),
+                source_location,
+                read_concern: ctx.read_concern,
+                progress_interval: ctx.progress.as_ref().map(|_| PROGRESS_REPORT_INTERVAL),
+            };
+            if let Some(cb) = ctx.progress.clone() {
+                self.progress_channel
+                    .borrow_mut()
+                    .insert(query_id.clone(), cb);
+            }
+            let result = self
+                .request_from_leader_with_context(query_id.clone(), msg, &ctx)
+                .await
+                .map_err(|e| e.to_string());
+            // Normally removed with the response, but not after a timeout
+            self.progress_channel.borrow_mut().remove(&query_id);
+            result
+        }
+    }
+
+    /// Hold back new queries until `resume_dispatch` is called. Queries already
+    /// waiting are not rejected, and the leader queues requests from other
+    /// workers, although those still time out on the sender after 5 seconds.
+    pub fn pause_dispatch(&self) {
+        self.dispatch_paused.set(true);
+    }
+
+    /// Let paused queries continue and handle requests queued while paused
+    pub fn resume_dispatch(&self) {
+        self.dispatch_paused.set(false);
+        self.queued_request_flusher()();
+    }
+
+    // A callback that handles the requests from other workers queued while
+    // dispatch was paused or a transaction was open, unless either still holds
+    pub(crate) fn queued_request_flusher(&self) -> impl Fn() + 'static {
+        let leader = self.leader_context();
+        let sender = self.sender();
+        let dispatch_paused = Rc::clone(&self.dispatch_paused);
+        let active_transaction = Rc::clone(&self.active_transaction);
+        let paused_requests = Rc::clone(&self.paused_requests);
+        move || {
+            if dispatch_paused.get() || active_transaction.borrow().is_some() {
+                return;
+            }
+            let queued: Vec<_> = paused_requests.borrow_mut().drain(..).collect();
+            for (request, trace_id) in queued {
+                respond_to_request(leader.clone(), sender.clone(), request, trace_id);
+            }
+        }
+    }
+
+    pub fn is_dispatch_paused(&self) -> bool {
+        self.dispatch_paused.get()
+    }
+
+    async fn wait_while_paused(&self) {
+        while self.dispatch_paused.get() {
+            sleep(PAUSE_POLL_MS).await;
+        }
+    }
+
+    /// Wait until a leader has been announced and return its id, polling with
+    /// exponential backoff starting at 10ms for up to `max_wait_ms`. A leader
+    /// elected before this worker started is asked to identify itself.
+    pub async fn wait_for_leader(&self, max_wait_ms: u64) -> Result<String, WorkerError> {
+        let started_at = js_sys::Date::now();
+        let mut delay_ms = LEADER_POLL_INITIAL_MS as f64;
+        self.discover_leader()?;
+
+        loop {
+            if let Some(leader_id) = self.known_leader_id.borrow().clone() {
+                return Ok(leader_id);
+            }
+
+            let remaining_ms = max_wait_ms as f64 - (js_sys::Date::now() - started_at);
+            if remaining_ms <= 0.0 {
+                return Err(WorkerError::NoLeaderAvailable);
+            }
+            sleep(delay_ms.min(remaining_ms)).await;
+            delay_ms *= 2.0;
+        }
+    }
+
+    // Ask the leader, if there is one, to identify itself. It announces itself
+    // only once, when elected, so a worker started later would otherwise never
+    // learn who leads.
+    fn discover_leader(&self) -> Result<(), WorkerError> {
+        if *self.is_leader.borrow() || self.known_leader_id.borrow().is_some() {
+            return Ok(());
+        }
+        self.broadcast(ChannelMessage::RequestState {
+            requester_id: self.worker_id.clone(),
+        })
+    }
+
+    // Broadcast a request to the leader and wait for the matching response, which
+    // resolves with the response payload as a string
+    async fn request_from_leader(
+        &self,
+        query_id: String,
+        msg: ChannelMessage,
+    ) -> Result<String, WorkerError> {
+        self.request_from_leader_with_context(query_id, msg, &QueryContext::default())
+            .await
+    }
+
+    // As `request_from_leader`, tracking the request at the context's priority and
+    // waiting up to its timeout
+    async fn request_from_leader_with_context(
+        &self,
+        query_id: String,
+        msg: ChannelMessage,
+        ctx: &QueryContext,
+    ) -> Result<String, WorkerError> {
+        self.acquire_pending_slot(&query_id, ctx.priority).await?;
+        let promise = Promise::new(&mut |resolve, reject| {
+            self.pending_queries.borrow_mut().insert(
+                query_id.clone(),
+                PendingQuery::new(resolve, reject)
+                    .with_priority(ctx.priority)
+                    .with_request(msg.clone()),
+            );
+        });
+
+        if self.config.batch_dispatch && matches!(msg, ChannelMessage::QueryRequest { .. }) {
+            let dispatch = PendingDispatch {
+                query_id: query_id.clone(),
+                message: msg,
+                trace_id: ctx.trace_id.clone(),
+            };
+            self.micro_batch.push(dispatch, self.sender());
+        } else {
+            self.sender().post(msg, ctx.trace_id.clone());
+        }
+        let timeout_ms = ctx.timeout_ms_or_default() as f64;
+
+        // Timeout handling
+        let timeout_promise = Promise::new(&mut |_, reject| {
+            let query_id = query_id.clone();
+            let pending_queries = Rc::clone(&self.pending_queries);
+
+            self.time.set_timeout(
+                timeout_ms,
+                Box::new(move || {
+                    if pending_queries
+                        .borrow_mut()
+                        .shift_remove(&query_id)
+                        .is_some()
+                    {
+                        let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("Query timeout"));
+                    }
+                }),
+            );
+        });
+
+        let result = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::race(
+            &js_sys::Array::of2(&promise, &timeout_promise),
+        ))
+        .await;
+        self.schedule_fallback_drain();
+
+        match result {
+            Ok(val) => {
+                if let Some(s) = val.as_string() {
+                    Ok(s)
+                } else {
+                    Err(WorkerError::QueryFailed("Invalid response".to_string()))
+                }
+            }
+            Err(e) => Err(rejection_error(e)),
+        }
+    }
+
+    /// Execute a statement with `params` bound to its `?` placeholders and
+    /// return the structured result
+    #[track_caller]
+    pub fn execute_parameterized(
+        &self,
+        sql: String,
+        params: Vec<SqlParam>,
+    ) -> impl Future<Output = Result<QueryResult, WorkerError>> + '_ {
+        self.run_parameterized(sql, params, caller_location())
+    }
+
+    async fn run_parameterized(
+        &self,
+        sql: String,
+        params: Vec<SqlParam>,
+        source_location: Option<String>,
+    ) -> Result<QueryResult, WorkerError> {
+        self.check_sql_length(&sql)?;
+        self.wait_while_paused().await;
+        self.check_schema()?;
+        self.dispatch_parameterized(sql, params, source_location)
+            .await
+    }
+
+    // `run_parameterized` without the pause and schema checks, for queries that
+    // must run while `start` holds others back
+    pub(crate) async fn dispatch_parameterized(
+        &self,
+        sql: String,
+        params: Vec<SqlParam>,
+        source_location: Option<String>,
+    ) -> Result<QueryResult, WorkerError> {
+        let query_id = Uuid::new_v4().to_string();
+        let started_at = js_sys::Date::now();
+        if let Some(observer) = &self.observer {
+            observer.on_query_start(&query_id, &sql);
+        }
+
+        let result = if *self.is_leader.borrow() {
+            self.leader_context()
+                .execute_parameterized(&sql, &params)
+                .await
+        } else {
+            let msg = ChannelMessage::ParameterizedQueryRequest {
+                query_id: query_id.clone(),
+                sql,
+                params,
+                source_location,
+            };
+            self.request_from_leader(query_id.clone(), msg)
+                .await
+                .and_then(|json| {
+                    serde_json::from_str::<QueryResult>(&json)
+                        .map_err(|e| WorkerError::QueryFailed(e.to_string()))
+                })
+        };
+
+        if let Some(observer) = &self.observer {
+            observer.on_query_end(&query_id, js_sys::Date::now() - started_at, result.is_ok());
+        }
+        result
+    }
+
+    /// Wait up to `ms` for a locked database before failing with `SQLITE_BUSY`.
+    /// Applies to the leader's connection; 0 fails immediately and 5000ms is a
+    /// reasonable value for OPFS.
+    pub async fn set_busy_timeout(&self, ms: u64) -> Result<(), WorkerError> {
+        let value = SqlParam::Integer(i64::try_from(ms).unwrap_or(i64::MAX));
+        self.set_pragma("busy_timeout", value).await
+    }
+
+    // Set a PRAGMA in `SETTABLE_PRAGMAS` on the leader's connection, sending it
+    // there if need be
+    pub(crate) async fn set_pragma(&self, name: &str, value: SqlParam) -> Result<(), WorkerError> {
+        if *self.is_leader.borrow() {
+            return self.leader_context().set_pragma(name, &value).await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::PragmaSet {
+            query_id: query_id.clone(),
+            name: name.to_string(),
+            value,
+        };
+        self.request_from_leader(query_id, msg).await.map(|_| ())
+    }
+
+    /// Apply the schema changes in `ddls` atomically: each must be a `CREATE`,
+    /// `DROP` or `ALTER` statement, run in order in one transaction on the
+    /// leader. Fails with `NotDdl` before running anything if one is not, and
+    /// with `DdlStatementFailed` carrying the failing statement's index after
+    /// rolling back. In strict mode each `CREATE TABLE` is made `STRICT`.
+    pub async fn execute_ddl_transaction(&self, mut ddls: Vec<String>) -> Result<(), WorkerError> {
+        check_ddl(&ddls)?;
+        self.apply_strict_mode(&mut ddls).await?;
+        if *self.is_leader.borrow() {
+            return self.leader_context().execute_ddl_transaction(&ddls).await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::DdlTransaction {
+            query_id: query_id.clone(),
+            statements: ddls,
+        };
+        let json = self.request_from_leader(query_id, msg).await?;
+        let results = serde_json::from_str::<Vec<BatchResult>>(&json)
+            .map_err(|e| WorkerError::QueryFailed(e.to_string()))?;
+        let index = results.len().saturating_sub(1);
+        match results.into_iter().last().map(BatchResult::into_result) {
+            Some(Err(error)) => Err(WorkerError::DdlStatementFailed {
+                index,
+                error: Box::new(error),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop `table`, failing if it does not exist unless `if_exists` is set.
+    /// Runs on the leader, sending the request there if need be.
+    pub async fn drop_table(&self, table: &str, if_exists: bool) -> Result<(), WorkerError> {
+        validate_identifier(table)?;
+        if *self.is_leader.borrow() {
+            return self.leader_context().drop_table(table, if_exists).await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::DropTable {
+            query_id: query_id.clone(),
+            table: table.to_string(),
+            if_exists,
+        };
+        self.request_from_leader(query_id, msg).await.map(|_| ())
+    }
+
+    /// Rename `old_name` to `new_name` with `ALTER TABLE ... RENAME TO`,
+    /// on the leader
+    pub async fn rename_table(&self, old_name: &str, new_name: &str) -> Result<(), WorkerError> {
+        validate_identifier(old_name)?;
+        validate_identifier(new_name)?;
+        if *self.is_leader.borrow() {
+            return self.leader_context().rename_table(old_name, new_name).await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::RenameTable {
+            query_id: query_id.clone(),
+            old_name: old_name.to_string(),
+            new_name: new_name.to_string(),
+        };
+        self.request_from_leader(query_id, msg).await.map(|_| ())
+    }
+
+    /// Rebuild `index_name`, or every index when `None`, on the leader. Writes
+    /// are blocked while it runs, and `PRAGMA integrity_check` must pass
+    /// afterwards or this fails with `Corrupt`.
+    pub async fn reindex(&self, index_name: Option<&str>) -> Result<(), WorkerError> {
+        if let Some(name) = index_name {
+            validate_identifier(name)?;
+        }
+        if *self.is_leader.borrow() {
+            return self.leader_context().reindex(index_name).await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::ReindexRequest {
+            query_id: query_id.clone(),
+            index_name: index_name.map(str::to_string),
+        };
+        self.request_from_leader(query_id, msg).await.map(|_| ())
+    }
+
+    /// Check that `sql` is a complete statement that compiles, without running
+    /// it, so SQL typed by a user can be checked before it is dispatched. An
+    /// unterminated string, comment or trigger body fails straight away; the
+    /// rest is compiled on the leader under `EXPLAIN`. Either failure, including
+    /// a table or column that does not exist, is a `SqlSyntaxError`.
+    pub async fn validate_sql(&self, sql: String) -> Result<(), WorkerError> {
+        if !SqlClassifier::is_complete(&sql) {
+            return Err(WorkerError::SqlSyntaxError {
+                message: format!("incomplete statement: {sql}"),
+            });
+        }
+        let result = if *self.is_leader.borrow() {
+            self.leader_context().validate_sql(&sql).await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::ValidateSqlRequest {
+                query_id: query_id.clone(),
+                sql,
+            };
+            self.request_from_leader(query_id, msg).await.map(|_| ())
+        };
+        // Only SQLite's own compile errors; a busy or missing database is not
+        // the statement's fault
+        result.map_err(|err| match err.sqlite_code() {
+            Some(code) if code & 0xff == SQLITE_ERROR => WorkerError::SqlSyntaxError {
+                message: err.to_string(),
+            },
+            _ => err,
+        })
+    }
+
+    /// `validate_sql` for a borrowed statement: what `execute_query` would do
+    /// with `sql`, short of running it
+    pub async fn execute_query_dry_run(&self, sql: &str) -> Result<(), WorkerError> {
+        self.validate_sql(sql.to_string()).await
+    }
+
+    /// Run `f` between `SAVEPOINT {name}` and `RELEASE SAVEPOINT {name}` on the
+    /// leader, rolling back to the savepoint instead if `f` fails. Savepoints
+    /// nest, so `f` may open its own, and inside an open transaction only the
+    /// changes made by `f` are undone.
+    pub async fn execute_with_savepoint<'a, T>(
+        &'a self,
+        name: &str,
+        f: Pin<Box<dyn Future<Output = Result<T, WorkerError>> + 'a>>,
+    ) -> Result<T, WorkerError> {
+        validate_identifier(name)?;
+        self.savepoint(name, SavepointAction::Begin).await?;
+        match f.await {
+            Ok(value) => match self.savepoint(name, SavepointAction::Release).await {
+                Ok(()) => Ok(value),
+                Err(err) => {
+                    let _ = self.savepoint(name, SavepointAction::RollbackTo).await;
+                    Err(err)
+                }
+            },
+            Err(err) => {
+                let _ = self.savepoint(name, SavepointAction::RollbackTo).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// The savepoints opened by `execute_with_savepoint` on this worker that
+    /// are still open, outermost first
+    pub fn list_savepoints(&self) -> Vec<String> {
+        self.savepoints.borrow().clone()
+    }
+
+    /// Release this worker's open savepoints, innermost first, keeping their
+    /// changes. Stops at the first one that fails to release.
+    pub async fn release_all_savepoints(&self) -> Result<(), WorkerError> {
+        let open = self.list_savepoints();
+        for name in open.iter().rev() {
+            self.savepoint(name, SavepointAction::Release).await?;
+        }
+        Ok(())
+    }
+
+    async fn savepoint(&self, name: &str, action: SavepointAction) -> Result<(), WorkerError> {
+        if *self.is_leader.borrow() {
+            self.leader_context().savepoint(name, action).await?;
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::Savepoint {
+                query_id: query_id.clone(),
+                name: name.to_string(),
+                action,
+            };
+            self.request_from_leader(query_id, msg).await?;
+        }
+
+        let mut savepoints = self.savepoints.borrow_mut();
+        match action {
+            SavepointAction::Begin => savepoints.push(name.to_string()),
+            // Closing a savepoint closes every one opened after it too
+            SavepointAction::Release | SavepointAction::RollbackTo => {
+                if let Some(position) = savepoints.iter().rposition(|open| open == name) {
+                    savepoints.truncate(position);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send `payload` to the leader and return its echo, checking that the
+    /// channel works without involving the database. The leader echoes locally.
+    pub async fn echo(&self, payload: String) -> Result<String, WorkerError> {
+        if *self.is_leader.borrow() {
+            return Ok(payload);
+        }
+        if self.known_leader_id.borrow().is_none() {
+            self.wait_for_leader(LEADER_WAIT_MS).await?;
+        }
+
+        let echo_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::EchoRequest {
+            echo_id: echo_id.clone(),
+            payload,
+        };
+        self.request_from_leader(echo_id, msg).await
+    }
+
+    /// Ask the leader whether its database is open and how busy it is. The
+    /// answer is also kept for `last_leader_state`. Fails with
+    /// `NoLeaderAvailable` if no leader answers within
+    /// `LEADER_STATE_TIMEOUT_MS`.
+    pub async fn request_leader_state(&self) -> Result<LeaderState, WorkerError> {
+        if *self.is_leader.borrow() {
+            return Ok(LeaderState {
+                leader_id: self.worker_id.clone(),
+                db_initialized: self.db.borrow().is_some(),
+                pending_queue_depth: self.pending_queries.borrow().len() as u32,
+                uptime_ms: js_sys::Date::now() - self.created_at_ms,
+            });
+        }
+
+        let response = Promise::new(&mut |resolve, _| {
+            self.state_requests.borrow_mut().push(resolve);
+        });
+        let timeout = Promise::new(&mut |_, reject| {
+            self.time.set_timeout(
+                LEADER_STATE_TIMEOUT_MS,
+                Box::new(move || {
+                    let _ = reject.call0(&JsValue::NULL);
+                }),
+            );
+        });
+        self.broadcast(ChannelMessage::RequestState {
+            requester_id: self.worker_id.clone(),
+        })?;
+
+        let answered = wasm_bindgen_futures::JsFuture::from(Promise::race(&js_sys::Array::of2(
+            &response, &timeout,
+        )))
+        .await
+        .is_ok();
+        if !answered {
+            return Err(WorkerError::NoLeaderAvailable);
+        }
+        self.last_leader_state()
+            .ok_or(WorkerError::NoLeaderAvailable)
+    }
+
+    /// The leader's state from its last answer to `request_leader_state`
+    pub fn last_leader_state(&self) -> Option<LeaderState> {
+        self.last_leader_state.borrow().clone()
+    }
+
+    /// Register `func` as a scalar SQL function taking `arity` arguments, or any
+    /// number if -1. Non-leaders send the leader the function's source, so it
+    /// must not rely on variables it closes over. The function lives on the
+    /// leader's connection and has to be registered again if leadership moves.
+    pub async fn register_scalar_function(
+        &self,
+        name: String,
+        arity: i32,
+        func: Function,
+    ) -> Result<(), WorkerError> {
+        validate_identifier(&name)?;
+        if *self.is_leader.borrow() {
+            return self.leader_context().register_function(&name, arity, func);
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::RegisterFunction {
+            query_id: query_id.clone(),
+            name,
+            arity,
+            func_source: String::from(func.to_string()),
+        };
+        self.request_from_leader(query_id, msg).await.map(|_| ())
+    }
+
+    /// Execute several statements in one round-trip to the leader, all or none
+    /// of them: in a single transaction, or a savepoint within one already
+    /// open. Fails with `PartialBatchFailure` carrying every statement's
+    /// outcome if any of them failed, after rolling the whole batch back.
+    pub async fn execute_many(
+        &self,
+        statements: Vec<String>,
+    ) -> Result<Vec<QueryResult>, WorkerError> {
+        let results = if *self.is_leader.borrow() {
+            self.leader_context().execute_batch(&statements).await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::BatchQueryRequest {
+                query_id: query_id.clone(),
+                statements,
+                requests: Vec::new(),
+            };
+            let json = self.request_from_leader(query_id, msg).await?;
+            serde_json::from_str::<Vec<BatchResult>>(&json)
+                .map_err(|e| WorkerError::QueryFailed(e.to_string()))?
+                .into_iter()
+                .map(BatchResult::into_result)
+                .collect()
+        };
+
+        if results.iter().any(Result::is_err) {
+            return Err(WorkerError::PartialBatchFailure(results));
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Copy the rows of `src` matching the optional `filter` (a raw SQL `WHERE`
+    /// expression) into `dst`, creating `dst` with the shape of `src` if needed.
+    /// The copy runs in one transaction and returns the number of rows copied.
+    pub async fn copy_table(
+        &self,
+        src: &str,
+        dst: &str,
+        filter: Option<String>,
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(src)?;
+        validate_identifier(dst)?;
+
+        if *self.is_leader.borrow() {
+            self.leader_context()
+                .copy_table(src, dst, filter.as_deref())
+                .await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::CopyTable {
+                query_id: query_id.clone(),
+                src: src.to_string(),
+                dst: dst.to_string(),
+                filter,
+            };
+            let count = self.request_from_leader(query_id, msg).await?;
+            count
+                .parse()
+                .map_err(|_| WorkerError::QueryFailed(format!("Invalid row count: {count}")))
+        }
+    }
+
+    /// Prepare `sql` once on the leader, so that running it again through
+    /// `PreparedStatement::execute` skips parsing it. The leader keeps the
+    /// statement until the `PreparedStatement` is dropped; after a change of
+    /// leader it fails with `StatementNotFound` and must be prepared again.
+    pub async fn prepare(&self, sql: &str) -> Result<PreparedStatement<'_>, WorkerError> {
+        self.check_sql_length(sql)?;
+        let stmt_id = if *self.is_leader.borrow() {
+            self.leader_context().prepare_statement(sql)?
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::PrepareStatement {
+                query_id: query_id.clone(),
+                sql: sql.to_string(),
+            };
+            self.request_from_leader(query_id, msg).await?
+        };
+        Ok(PreparedStatement::new(self, stmt_id))
+    }
+
+    pub(crate) async fn execute_prepared(
+        &self,
+        stmt_id: &str,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, WorkerError> {
+        if *self.is_leader.borrow() {
+            return self.leader_context().execute_prepared(stmt_id, params);
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::ExecutePrepared {
+            query_id: query_id.clone(),
+            stmt_id: stmt_id.to_string(),
+            params: params.to_vec(),
+        };
+        let json = self.request_from_leader(query_id, msg).await?;
+        serde_json::from_str(&json).map_err(|e| WorkerError::QueryFailed(e.to_string()))
+    }
+
+    pub(crate) fn finalize_prepared(&self, stmt_id: &str) {
+        if *self.is_leader.borrow() {
+            self.leader_context().finalize_statement(stmt_id);
+        } else {
+            let msg = ChannelMessage::FinalizeStatement {
+                stmt_id: stmt_id.to_string(),
+            };
+            self.sender().post(msg, None);
+        }
+    }
+
+    /// Insert `rows` into `table` with multi-row `INSERT` statements of up to
+    /// `BULK_INSERT_CHUNK_ROWS` rows, in one transaction on the leader, and
+    /// return the number inserted. Every row must have the same columns, or
+    /// this fails with `InconsistentColumns` before inserting anything.
+    pub async fn bulk_insert(
+        &self,
+        table: &str,
+        rows: Vec<HashMap<String, SqlParam>>,
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(table)?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let (columns, values) = bulk_insert_rows(rows)?;
+
+        if *self.is_leader.borrow() {
+            self.leader_context()
+                .bulk_insert(table, &columns, values)
+                .await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::BulkInsertRequest {
+                query_id: query_id.clone(),
+                table: table.to_string(),
+                columns,
+                values,
+            };
+            let count = self.request_from_leader(query_id, msg).await?;
+            count
+                .parse()
+                .map_err(|_| WorkerError::QueryFailed(format!("Invalid row count: {count}")))
+        }
+    }
+
+    /// Start copying the database into the OPFS file `target_name` without
+    /// taking it offline. On the leader the copy advances with
+    /// `BackupHandle::step`; elsewhere the leader copies everything before
+    /// this returns and the handle is already done.
+    pub async fn copy_database_to(&self, target_name: &str) -> Result<BackupHandle, WorkerError> {
+        validate_backup_target(target_name)?;
+        if *self.is_leader.borrow() {
+            let backup = self.database()?.backup_to(target_name)?;
+            Ok(BackupHandle::new(backup))
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::BackupRequest {
+                query_id: query_id.clone(),
+                target_name: target_name.to_string(),
+            };
+            self.request_from_leader(query_id, msg).await?;
+            Ok(BackupHandle::completed())
+        }
+    }
+
+    /// Take a read-only snapshot of the database and return its id. The
+    /// leader copies the whole database into memory, so later writes are not
+    /// seen by the snapshot and large databases cost as much memory again, up
+    /// to `WorkerStateBuilder::max_snapshot_bytes` across all snapshots.
+    pub async fn begin_snapshot(&self) -> Result<String, WorkerError> {
+        let snapshot_id = Uuid::new_v4().to_string();
+        if *self.is_leader.borrow() {
+            self.leader_context().begin_snapshot(snapshot_id).await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::Snapshot {
+                query_id: query_id.clone(),
+                snapshot_id,
+            };
+            self.request_from_leader(query_id, msg).await
+        }
+    }
+
+    /// Run a read query against an open snapshot
+    pub async fn query_snapshot(
+        &self,
+        snapshot_id: &str,
+        sql: String,
+    ) -> Result<String, WorkerError> {
+        if *self.is_leader.borrow() {
+            self.leader_context()
+                .query_snapshot(snapshot_id, &sql)
+                .await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::QuerySnapshot {
+                query_id: query_id.clone(),
+                snapshot_id: snapshot_id.to_string(),
+                sql,
+            };
+            self.request_from_leader(query_id, msg).await
+        }
+    }
+
+    /// Drop the snapshot, freeing its copy of the database
+    pub async fn end_snapshot(&self, snapshot_id: &str) -> Result<(), WorkerError> {
+        if *self.is_leader.borrow() {
+            self.leader_context().end_snapshot(snapshot_id).await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::EndSnapshot {
+                query_id: query_id.clone(),
+                snapshot_id: snapshot_id.to_string(),
+            };
+            self.request_from_leader(query_id, msg).await.map(|_| ())
+        }
+    }
+
+    /// Open a read transaction on a snapshot of the database, so every query
+    /// run through the returned `ReadTransaction` sees the database as it was
+    /// when the transaction began, whatever is written meanwhile. Read
+    /// transactions count towards `max_snapshots` and `max_snapshot_bytes`,
+    /// holding a copy of the database each. Dropping one without
+    /// calling `end` releases it.
+    pub async fn begin_read_transaction(&self) -> Result<ReadTransaction<'_>, WorkerError> {
+        let tx_id = Uuid::new_v4().to_string();
+        if *self.is_leader.borrow() {
+            self.leader_context().begin_snapshot(tx_id.clone()).await?;
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::BeginReadTransaction {
+                query_id: query_id.clone(),
+                tx_id: tx_id.clone(),
+            };
+            self.request_from_leader(query_id, msg).await?;
+        }
+        Ok(ReadTransaction::new(self, tx_id))
+    }
+
+    pub(crate) async fn query_read_transaction(
+        &self,
+        tx_id: &str,
+        sql: String,
+        params: Vec<SqlParam>,
+    ) -> Result<QueryResult, WorkerError> {
+        self.check_sql_length(&sql)?;
+        if *self.is_leader.borrow() {
+            return self
+                .leader_context()
+                .query_read_transaction(tx_id, &sql, &params)
+                .await;
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        let msg = ChannelMessage::ReadTransactionQuery {
+            query_id: query_id.clone(),
+            tx_id: tx_id.to_string(),
+            sql,
+            params,
+        };
+        let json = self.request_from_leader(query_id, msg).await?;
+        serde_json::from_str(&json).map_err(|e| WorkerError::QueryFailed(e.to_string()))
+    }
+
+    pub(crate) async fn end_read_transaction(&self, tx_id: &str) -> Result<(), WorkerError> {
+        if *self.is_leader.borrow() {
+            self.leader_context().end_read_transaction(tx_id).await
+        } else {
+            let query_id = Uuid::new_v4().to_string();
+            let msg = ChannelMessage::EndReadTransaction {
+                query_id: query_id.clone(),
+                tx_id: tx_id.to_string(),
+            };
+            self.request_from_leader(query_id, msg).await.map(|_| ())
+        }
+    }
+
+    // Release a read transaction without waiting for the leader, which
+    // answers a follower's request to nobody
+    pub(crate) fn rollback_read_transaction(&self, tx_id: &str) {
+        if *self.is_leader.borrow() {
+            let leader = self.leader_context();
+            let tx_id = tx_id.to_string();
+            spawn_local(async move {
+                let _ = leader.end_snapshot(&tx_id).await;
+            });
+        } else {
+            let msg = ChannelMessage::EndSnapshot {
+                query_id: Uuid::new_v4().to_string(),
+                snapshot_id: tx_id.to_string(),
+            };
+            self.sender().post(msg, None);
+        }
+    }
+
+    /// Treat `new_leader` as the leader and re-issue every pending request, since
+    /// a redirect means the previous leader will not answer them. A leader
+    /// ignores requests it has already handled, so one that did arrive is not
+    /// run twice. Returns the number of requests re-issued.
+    pub fn handle_redirect(&self, new_leader: &str) -> usize {
+        redirect_pending(
+            &self.known_leader_id,
+            &self.pending_queries,
+            &self.sender(),
+            new_leader,
+        )
+    }
+
+    /// Acquire an application-level Web Lock, held until the guard is dropped.
+    /// Avoid the name `sqlite-database`, which is used for leader election.
+    pub async fn acquire_named_lock(
+        &self,
+        name: String,
+        mode: LockMode,
+    ) -> Result<LockGuard, WorkerError> {
+        acquire_named_lock(&name, mode).await
+    }
+
+    /// Number of queries waiting for a response at each priority, including
+    /// priorities with none pending. The leader executes requests as they
+    /// arrive, so its own count only covers requests it forwarded itself.
+    pub fn count_pending_by_priority(&self) -> HashMap<QueryPriority, usize> {
+        let mut counts: HashMap<QueryPriority, usize> = QueryPriority::ALL
+            .iter()
+            .map(|&priority| (priority, 0))
+            .collect();
+        for pending in self.pending_queries.borrow().values() {
+            *counts.entry(pending.priority).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Ids of the queries still waiting for a response, oldest first
+    pub fn dump_pending_queries(&self) -> Vec<String> {
+        self.pending_queries.borrow().keys().cloned().collect()
+    }
+
+    /// Summaries of the queries still waiting for a response, oldest first
+    pub fn pending_query_info(&self) -> Vec<PendingQueryInfo> {
+        self.pending_queries
+            .borrow()
+            .iter()
+            .map(|(query_id, pending)| PendingQueryInfo {
+                query_id: query_id.clone(),
+                sql: pending
+                    .request
+                    .as_ref()
+                    .and_then(ChannelMessage::sql)
+                    .map(String::from),
+                priority: pending.priority,
+                sent_at_ms: pending.sent_at_ms,
+            })
+            .collect()
+    }
+
+    /// Reject and remove pending queries sent before `cutoff_ms`, stopping at
+    /// the first query that is still within the cutoff. Returns the drained ids.
+    pub fn drain_oldest_expired(&self, cutoff_ms: f64) -> Vec<String> {
+        let mut pending_queries = self.pending_queries.borrow_mut();
+        let expired = pending_queries
+            .values()
+            .take_while(|pending| pending.sent_at_ms < cutoff_ms)
+            .count();
+
+        pending_queries
+            .drain(..expired)
+            .map(|(query_id, pending)| {
+                let _ = pending
+                    .reject
+                    .call1(&JsValue::NULL, &JsValue::from_str("Query expired"));
+                query_id
+            })
+            .collect()
+    }
+
+    /// Run `f` inside a transaction on the leader. The transaction is committed
+    /// if `f` succeeds and rolled back if it fails or is dropped before finishing.
+    /// Requests from other workers are queued until it has ended, so they never
+    /// run inside it, although they still time out on the sender after 5 seconds.
+    pub async fn run_in_transaction<'a, T, F>(&'a self, f: F) -> Result<T, WorkerError>
+    where
+        F: FnOnce() -> Pin<Box<dyn Future<Output = Result<T, WorkerError>> + 'a>>,
+    {
+        self.run_in_transaction_with("BEGIN", f).await
+    }
+
+    // `run_in_transaction`, opening the transaction with `begin`, such as
+    // `BEGIN IMMEDIATE` to take the write lock before `f` reads anything
+    pub(crate) async fn run_in_transaction_with<'a, T, F>(
+        &'a self,
+        begin: &str,
+        f: F,
+    ) -> Result<T, WorkerError>
+    where
+        F: FnOnce() -> Pin<Box<dyn Future<Output = Result<T, WorkerError>> + 'a>>,
+    {
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        let database = self.database()?;
+        if self.active_transaction.borrow().is_some() {
+            return Err(WorkerError::TransactionActive);
+        }
+
+        database.exec(begin).await.map_err(WorkerError::from)?;
+        *self.active_transaction.borrow_mut() = Some(Uuid::new_v4().to_string());
+        let mut guard = TransactionGuard {
+            database: Rc::clone(&database),
+            active_transaction: Rc::clone(&self.active_transaction),
+            savepoints: Rc::clone(&self.savepoints),
+            flush_queued: Rc::new(self.queued_request_flusher()),
+            completed: false,
+        };
+
+        match f().await {
+            Ok(value) => {
+                database.exec("COMMIT").await.map_err(WorkerError::from)?;
+                guard.completed = true;
+                Ok(value)
+            }
+            Err(err) => {
+                let _ = database.exec("ROLLBACK").await;
+                guard.completed = true;
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::CircuitState;
+    use crate::query_result::SqlValue;
+    use crate::time::MockTimeProvider;
+    use js_sys::Function;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_worker_state_creation_and_uniqueness() {
+        let results: Vec<_> = (0..5).map(|_| WorkerState::new()).collect();
+        let workers: Vec<_> = results.into_iter().filter_map(Result::ok).collect();
+
+        assert!(!workers.is_empty(), "Should create at least one worker");
+
+        let state = &workers[0];
+        assert!(!state.worker_id.is_empty(), "Worker ID should not be empty");
+        assert!(
+            state.worker_id.contains('-'),
+            "Worker ID should be valid UUID format"
+        );
+        assert!(
+            !*state.is_leader.borrow(),
+            "New workers should not start as leader"
+        );
+        assert!(
+            state.db.borrow().is_none(),
+            "Database should be uninitialized"
+        );
+        assert!(
+            state.pending_queries.borrow().is_empty(),
+            "Should have no pending queries"
+        );
+
+        if workers.len() >= 2 {
+            let mut ids = std::collections::HashSet::new();
+            for worker in &workers {
+                assert!(
+                    ids.insert(worker.worker_id.clone()),
+                    "Worker ID {} should be unique",
+                    worker.worker_id
+                );
+                assert_eq!(worker.worker_id.len(), 36, "UUID should be 36 characters");
+                assert_eq!(
+                    worker.worker_id.chars().filter(|&c| c == '-').count(),
+                    4,
+                    "UUID should have 4 dashes"
+                );
+            }
+            assert_eq!(ids.len(), workers.len(), "All worker IDs should be unique");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_feature_detection() {
+        // The browser test runner provides both APIs
+        assert!(WorkerState::is_broadcast_channel_supported());
+        assert!(WorkerState::is_navigator_locks_supported());
+        assert!(WorkerState::new().is_ok());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_leadership_state_management() {
+        if let Ok(state) = WorkerState::new() {
+            assert!(!*state.is_leader.borrow(), "Should start as follower");
+
+            *state.is_leader.borrow_mut() = true;
+            assert!(*state.is_leader.borrow(), "Should become leader");
+
+            *state.is_leader.borrow_mut() = false;
+            assert!(!*state.is_leader.borrow(), "Should become follower again");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pending_queries_management() {
+        if let Ok(state) = WorkerState::new() {
+            let pending_queries = Rc::clone(&state.pending_queries);
+
+            assert_eq!(pending_queries.borrow().len(), 0);
+
+            let test_queries = vec!["query-a", "query-b", "query-c", "query-d", "query-e"];
+            {
+                let mut queries = pending_queries.borrow_mut();
+                for query_id in &test_queries {
+                    let resolve =
+                        Function::new_no_args(&format!("return 'resolved-{}';", query_id));
+                    let reject = Function::new_no_args(&format!("return 'rejected-{}';", query_id));
+                    queries.insert(query_id.to_string(), PendingQuery::new(resolve, reject));
+                }
+            }
+
+            assert_eq!(pending_queries.borrow().len(), test_queries.len());
+            for query_id in &test_queries {
+                assert!(pending_queries.borrow().contains_key(*query_id));
+            }
+
+            for (i, query_id) in test_queries.iter().enumerate() {
+                if i % 2 == 0 {
+                    let removed = pending_queries.borrow_mut().shift_remove(*query_id);
+                    assert!(removed.is_some(), "Should remove query {}", query_id);
+                }
+            }
+
+            let remaining_count = test_queries.len() - test_queries.len().div_ceil(2);
+            assert_eq!(pending_queries.borrow().len(), remaining_count);
+
+            pending_queries.borrow_mut().clear();
+            assert_eq!(pending_queries.borrow().len(), 0);
+
+            {
+                let mut queries = pending_queries.borrow_mut();
+                let resolve = Function::new_no_args("return 'post-cleanup';");
+                let reject = Function::new_no_args("return 'rejected';");
+                queries.insert(
+                    "post-cleanup-test".to_string(),
+                    PendingQuery::new(resolve, reject),
+                );
+            }
+            assert_eq!(pending_queries.borrow().len(), 1);
+            assert!(pending_queries.borrow().contains_key("post-cleanup-test"));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_dump_pending_queries_preserves_insertion_order() {
+        if let Ok(state) = WorkerState::new() {
+            let ids = ["query-z", "query-a", "query-m"];
+            for query_id in ids {
+                let resolve = Function::new_no_args("return 'resolved';");
+                let reject = Function::new_no_args("return 'rejected';");
+                state
+                    .pending_queries
+                    .borrow_mut()
+                    .insert(query_id.to_string(), PendingQuery::new(resolve, reject));
+            }
+
+            assert_eq!(state.dump_pending_queries(), ids);
+
+            state.pending_queries.borrow_mut().shift_remove("query-a");
+            assert_eq!(state.dump_pending_queries(), ["query-z", "query-m"]);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_count_pending_by_priority() {
+        if let Ok(state) = WorkerState::new() {
+            let priorities = [
+                QueryPriority::High,
+                QueryPriority::Low,
+                QueryPriority::High,
+                QueryPriority::Normal,
+            ];
+            for (i, priority) in priorities.into_iter().enumerate() {
+                let resolve = Function::new_no_args("return 'resolved';");
+                let reject = Function::new_no_args("return 'rejected';");
+                state.pending_queries.borrow_mut().insert(
+                    format!("query-{i}"),
+                    PendingQuery::new(resolve, reject).with_priority(priority),
+                );
+            }
+
+            let counts = state.count_pending_by_priority();
+            assert_eq!(counts[&QueryPriority::High], 2);
+            assert_eq!(counts[&QueryPriority::Normal], 1);
+            assert_eq!(counts[&QueryPriority::Low], 1);
+            assert_eq!(state.health_report().pending_by_priority.high, 2);
+        }
+
+        if let Ok(state) = WorkerState::new() {
+            let counts = state.count_pending_by_priority();
+            assert_eq!(counts.len(), 3);
+            assert!(counts.values().all(|&count| count == 0));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_drain_oldest_expired() {
+        if let Ok(state) = WorkerState::new() {
+            for (query_id, sent_at_ms) in [("old-1", 100.0), ("old-2", 200.0), ("new", 900.0)] {
+                let resolve = Function::new_no_args("return 'resolved';");
+                let reject = Function::new_no_args("return 'rejected';");
+                let mut pending = PendingQuery::new(resolve, reject);
+                pending.sent_at_ms = sent_at_ms;
+                state
+                    .pending_queries
+                    .borrow_mut()
+                    .insert(query_id.to_string(), pending);
+            }
+
+            let drained = state.drain_oldest_expired(500.0);
+            assert_eq!(drained, ["old-1", "old-2"]);
+            assert_eq!(state.dump_pending_queries(), ["new"]);
+
+            assert!(state.drain_oldest_expired(500.0).is_empty());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_deserialization_error_handling() {
+        let invalid_json = JsValue::from_str("invalid json");
+        let result = serde_wasm_bindgen::from_value::<ChannelMessage>(invalid_json);
+        assert!(result.is_err(), "Should fail to deserialize invalid JSON");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_query_leader_vs_follower_paths() {
+        if let Ok(leader_state) = WorkerState::new() {
+            *leader_state.is_leader.borrow_mut() = true;
+
+            let test_queries = vec![
+                "",
+                "SELECT 1",
+                "INSERT INTO test VALUES (1, 'hello')",
+                "SELECT 'test with spaces and symbols: !@#$%^&*()'",
+                "SELECT 'Hello 世界 🌍'",
+            ];
+
+            for query in test_queries {
+                let result = leader_state
+                    .execute_query(query.to_string(), QueryContext::default())
+                    .await;
+                match result {
+                    Err(msg) => assert_eq!(
+                        msg, "Database not initialized",
+                        "Leader should get DB init error for query: {}",
+                        query
+                    ),
+                    Ok(_) => panic!(
+                        "Expected database not initialized error for query: {}",
+                        query
+                    ),
+                }
+            }
+        }
+
+        if let Ok(follower_state) = WorkerState::new() {
+            assert!(
+                !*follower_state.is_leader.borrow(),
+                "Should start as follower"
+            );
+
+            let result = follower_state
+                .execute_query("SELECT 1".to_string(), QueryContext::default())
+                .await;
+            match result {
+                Err(msg) => assert_eq!(
+                    msg,
+                    WorkerError::NoLeaderAvailable.to_string(),
+                    "Follower without a known leader should give up waiting"
+                ),
+                Ok(_) => panic!("Expected no leader error for follower"),
+            }
+
+            *follower_state.known_leader_id.borrow_mut() = Some("absent-leader".to_string());
+            let result = follower_state
+                .execute_query("SELECT 1".to_string(), QueryContext::default())
+                .await;
+            match result {
+                Err(msg) => assert!(
+                    msg.contains("timeout") || msg.contains("Query timeout"),
+                    "Follower should timeout, got: {}",
+                    msg
+                ),
+                Ok(_) => panic!("Expected timeout error for follower"),
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_send_message_retries_then_reports_failure() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let errors_clone = Rc::clone(&errors);
+        state.on_error(move |err| errors_clone.borrow_mut().push(err));
+
+        // Functions cannot be structured-cloned, so every post throws
+        let not_cloneable = Object::new();
+        Reflect::set(
+            &not_cloneable,
+            &JsValue::from_str("callback"),
+            &Function::new_no_args(""),
+        )
+        .unwrap();
+        let started_at = js_sys::Date::now();
+        let result = state.sender().post_with_retry(&not_cloneable).await;
+        assert!(js_sys::Date::now() - started_at >= SEND_RETRY_DELAY_MS * SEND_RETRIES as f64);
+
+        assert!(matches!(result, Err(WorkerError::ChannelError(_))));
+        assert_eq!(errors.borrow().len(), 1);
+        assert!(matches!(errors.borrow()[0], WorkerError::ChannelError(_)));
+        assert_eq!(state.messages_failed_to_send(), 1);
+
+        let msg = ChannelMessage::NewLeader {
+            leader_id: state.worker_id.clone(),
+        };
+        assert_eq!(state.send_message(&msg).await, Ok(()));
+        assert_eq!(state.messages_failed_to_send(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_broadcast_increments_send_sequence() {
+        if let Ok(state) = WorkerState::new() {
+            assert_eq!(state.send_sequence.get(), 0);
+            for _ in 0..3 {
+                state
+                    .broadcast(ChannelMessage::NewLeader {
+                        leader_id: state.worker_id.clone(),
+                    })
+                    .expect("Should broadcast");
+            }
+            assert_eq!(state.send_sequence.get(), 3);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_failed_broadcast_releases_send_sequence() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let msg = ChannelMessage::NewLeader {
+            leader_id: state.worker_id.clone(),
+        };
+        state.broadcast(msg.clone()).expect("Should broadcast");
+        assert_eq!(state.send_sequence.get(), 1);
+
+        // A closed channel refuses every message
+        state.channel.close();
+        assert!(matches!(
+            state.broadcast(msg),
+            Err(WorkerError::ChannelError(_))
+        ));
+        assert_eq!(state.send_sequence.get(), 1);
+        assert_eq!(state.messages_failed_to_send(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_worker_state_debug() {
+        if let Ok(state) = WorkerState::new() {
+            let output = format!("{state:?}");
+            assert!(output.contains(&state.worker_id));
+            assert!(output.contains("is_leader: false"));
+            assert!(output.contains("db_initialized: false"));
+            assert!(output.contains("pending_query_count: 0"));
+            assert!(output.contains("channel_name: \"sqlite-queries\""));
+
+            let resolve = Function::new_no_args("return 'resolved';");
+            let reject = Function::new_no_args("return 'rejected';");
+            let pending = PendingQuery::new(resolve, reject);
+            let output = format!("{pending:?}");
+            assert!(output.starts_with("PendingQuery { sent_at_ms: "));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_handle_redirect_reissues_pending_queries() {
+        if let Ok(state) = WorkerState::new() {
+            for query_id in ["redirect-a", "redirect-b"] {
+                let resolve = Function::new_no_args("return 'resolved';");
+                let reject = Function::new_no_args("return 'rejected';");
+                let request = ChannelMessage::QueryRequest {
+                    query_id: query_id.to_string(),
+                    sql: "SELECT 1".to_string(),
+                    origin_worker_id: "worker-1".to_string(),
+                    metadata: HashMap::new(),
+                    source_location: None,
+                    read_concern: None,
+                    progress_interval: None,
+                };
+                state.pending_queries.borrow_mut().insert(
+                    query_id.to_string(),
+                    PendingQuery::new(resolve, reject).with_request(request),
+                );
+            }
+            // Queries registered without a request have nothing to re-send
+            let resolve = Function::new_no_args("return 'resolved';");
+            let reject = Function::new_no_args("return 'rejected';");
+            state
+                .pending_queries
+                .borrow_mut()
+                .insert("no-request".to_string(), PendingQuery::new(resolve, reject));
+
+            let reissued = state.handle_redirect("worker-2");
+
+            assert_eq!(reissued, 2);
+            assert_eq!(state.send_sequence.get(), 2);
+            assert_eq!(state.known_leader_id.borrow().as_deref(), Some("worker-2"));
+            assert_eq!(
+                state.dump_pending_queries(),
+                ["redirect-a", "redirect-b", "no-request"],
+                "Re-issued queries stay pending until answered"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_log_ring_buffer() {
+        if let Ok(state) = WorkerState::builder().message_log_size(2).build() {
+            for i in 0..3 {
+                state
+                    .broadcast(ChannelMessage::NewLeader {
+                        leader_id: format!("leader-{i}"),
+                    })
+                    .expect("Should broadcast");
+            }
+
+            let log = state.message_log();
+            assert_eq!(log.len(), 2, "Oldest entry should be evicted");
+            assert!(log
+                .iter()
+                .all(|entry| entry.direction == MessageDirection::Sent));
+            assert_eq!(
+                log[0].message,
+                ChannelMessage::NewLeader {
+                    leader_id: "leader-1".to_string()
+                }
+            );
+            assert!(log[0].timestamp_ms <= log[1].timestamp_ms);
+
+            state.clear_message_log();
+            assert!(state.message_log().is_empty());
+        }
+
+        if let Ok(state) = WorkerState::new() {
+            assert_eq!(state.config.message_log_size, DEFAULT_MESSAGE_LOG_SIZE);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_paused_query_waits_for_resume() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let state = Rc::new(state);
+        state.pause_dispatch();
+        assert!(state.is_dispatch_paused());
+
+        let result = Rc::new(RefCell::new(None));
+        let result_clone = Rc::clone(&result);
+        let state_clone = Rc::clone(&state);
+        spawn_local(async move {
+            let value = state_clone
+                .execute_query_simple("SELECT 1 as value".to_string())
+                .await;
+            *result_clone.borrow_mut() = Some(value);
+        });
+
+        sleep(120.0).await;
+        assert!(result.borrow().is_none(), "Query must not run while paused");
+
+        state.resume_dispatch();
+        sleep(120.0).await;
+        assert!(matches!(*result.borrow(), Some(Ok(_))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_waiting_for_the_database_times_out() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            state.wait_for_database(100.0).await,
+            Err(WorkerError::NoLeaderAvailable)
+        );
+        *state.known_leader_id.borrow_mut() = Some("leader-1".to_string());
+        assert_eq!(state.wait_for_database(100.0).await, Ok(()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_follower_started_after_election_finds_leader() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        leader.setup_channel_listener();
+        // Started after the leader, so it missed the `NewLeader` broadcast
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        follower.setup_channel_listener();
+        assert_eq!(*follower.known_leader_id.borrow(), None);
+
+        let result = follower
+            .execute_query("SELECT 1 as value".to_string(), QueryContext::default())
+            .await
+            .expect("The follower should find the leader");
+        let rows: serde_json::Value = serde_json::from_str(&result).expect("Invalid JSON");
+        assert_eq!(rows[0]["value"], 1);
+        assert_eq!(
+            follower.known_leader_id.borrow().as_deref(),
+            Some(leader.worker_id.as_str())
+        );
+        assert_eq!(
+            follower.echo("ping".to_string()).await,
+            Ok("ping".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_paused_leader_queues_requests() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        leader.pause_dispatch();
+
+        let follower = Rc::new(follower);
+        let result = Rc::new(RefCell::new(None));
+        let result_clone = Rc::clone(&result);
+        let follower_clone = Rc::clone(&follower);
+        spawn_local(async move {
+            let value = follower_clone
+                .execute_query_simple("SELECT 1 as value".to_string())
+                .await;
+            *result_clone.borrow_mut() = Some(value);
+        });
+
+        sleep(100.0).await;
+        assert_eq!(leader.paused_requests.borrow().len(), 1);
+        assert!(result.borrow().is_none());
+
+        leader.resume_dispatch();
+        sleep(100.0).await;
+        assert!(leader.paused_requests.borrow().is_empty());
+        assert!(matches!(*result.borrow(), Some(Ok(_))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_on_error_reports_undecodable_messages() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let errors_clone = Rc::clone(&errors);
+        state.on_error(move |err| errors_clone.borrow_mut().push(err));
+        state.setup_channel_listener();
+
+        let raw = BroadcastChannel::new("sqlite-queries").unwrap();
+        raw.post_message(&JsValue::from_str("not an envelope"))
+            .unwrap();
+        sleep(50.0).await;
+
+        let errors = errors.borrow();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            WorkerError::ChannelError(message) if message.starts_with("Failed to decode channel message")
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_on_error_reaches_every_handler() {
+        if let Ok(state) = WorkerState::new() {
+            let calls = Rc::new(Cell::new(0));
+            for _ in 0..2 {
+                let calls = Rc::clone(&calls);
+                state.on_error(move |err| {
+                    assert_eq!(err, WorkerError::NotLeader);
+                    calls.set(calls.get() + 1);
+                });
+            }
+            state.report_error(WorkerError::NotLeader);
+            assert_eq!(calls.get(), 2);
+        }
+    }
+
+    async fn busy_timeout(state: &WorkerState) -> i64 {
+        let (timeout,): (i64,) = state
+            .execute_parameterized("PRAGMA busy_timeout".to_string(), vec![])
+            .await
+            .expect("Pragma failed")
+            .get_single()
+            .expect("Single row");
+        timeout
+    }
+
+    #[wasm_bindgen_test]
+    fn test_busy_timeout_config() {
+        assert_eq!(WorkerConfig::default().busy_timeout_ms, 0);
+        assert_eq!(WorkerConfig::default().busy_max_retries, 3);
+        assert_eq!(WorkerConfig::default().busy_backoff_ms, 50);
+        if let Ok(state) = WorkerStateBuilder::new().busy_timeout_ms(5000).build() {
+            assert_eq!(state.config.busy_timeout_ms, 5000);
+        }
+        if let Ok(state) = WorkerStateBuilder::new()
+            .busy_retry_count(5)
+            .busy_retry_delay_ms(10)
+            .build()
+        {
+            assert_eq!(
+                state.leader_context().busy_retry,
+                BusyRetry {
+                    max_retries: 5,
+                    backoff_ms: 10,
+                }
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_enforce_foreign_keys_config() {
+        assert_eq!(WorkerConfig::default().enforce_foreign_keys, None);
+        if let Ok(state) = WorkerStateBuilder::new().enforce_foreign_keys(true).build() {
+            assert_eq!(state.config.enforce_foreign_keys, Some(true));
+        }
+    }
+
+    // The OPFS VFS does not lock between connections in one worker, so a busy
+    // condition cannot be induced here; check the timeout reaches the connection
+    #[wasm_bindgen_test]
+    async fn test_set_busy_timeout_on_leader() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state.set_busy_timeout(5000).await.expect("Set failed");
+        assert_eq!(busy_timeout(&state).await, 5000);
+
+        let request = ChannelMessage::PragmaSet {
+            query_id: "pragma-1".to_string(),
+            name: "busy_timeout".to_string(),
+            value: SqlParam::Integer(250),
+        };
+        assert_eq!(
+            state.leader_context().handle_request(request).await,
+            Some(query_response("pragma-1".to_string(), Ok(String::new())))
+        );
+        assert_eq!(busy_timeout(&state).await, 250);
+
+        state.set_busy_timeout(0).await.expect("Reset failed");
+        assert_eq!(busy_timeout(&state).await, 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_pragma_set_refuses_other_pragmas() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for name in [
+            "journal_mode",
+            "writable_schema",
+            "busy_timeout; DROP TABLE x",
+        ] {
+            let request = ChannelMessage::PragmaSet {
+                query_id: "pragma-2".to_string(),
+                name: name.to_string(),
+                value: SqlParam::Text("OFF".to_string()),
+            };
+            assert_eq!(
+                state.leader_context().handle_request(request).await,
+                Some(query_response(
+                    "pragma-2".to_string(),
+                    Err(WorkerError::PragmaNotAllowed(name.to_string()).to_string())
+                )),
+                "{name} should be refused"
+            );
+        }
+        assert_eq!(
+            state
+                .set_pragma("BUSY_TIMEOUT", SqlParam::Integer(100))
+                .await,
+            Ok(()),
+            "Names are matched without regard to case"
+        );
+        state.set_busy_timeout(0).await.expect("Reset failed");
+    }
+
+    async fn table_exists(state: &WorkerState, table: &str) -> bool {
+        state
+            .execute_query_simple(format!("SELECT * FROM {table}"))
+            .await
+            .is_ok()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_ddl_transaction_rolls_back_on_failure() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        leader
+            .drop_table("ddl_orders", true)
+            .await
+            .expect("Cleanup failed");
+
+        let ddls = vec![
+            "CREATE TABLE ddl_orders (id INTEGER PRIMARY KEY, total REAL)".to_string(),
+            "CREATE INDEX ddl_orders_total ON ddl_missing (total)".to_string(),
+        ];
+        for state in [&leader, &follower] {
+            let Err(WorkerError::DdlStatementFailed { index, error }) =
+                state.execute_ddl_transaction(ddls.clone()).await
+            else {
+                panic!("Second statement should fail");
+            };
+            assert_eq!(index, 1);
+            assert!(error.to_string().contains("no such table"));
+            assert!(!table_exists(&leader, "ddl_orders").await);
+        }
+
+        assert_eq!(
+            follower
+                .execute_ddl_transaction(vec![
+                    "CREATE TABLE ddl_orders (id INTEGER PRIMARY KEY, total REAL)".to_string(),
+                    "CREATE INDEX ddl_orders_total ON ddl_orders (total)".to_string(),
+                ])
+                .await,
+            Ok(())
+        );
+        assert!(table_exists(&leader, "ddl_orders").await);
+        assert!(leader.db.borrow().as_ref().unwrap().is_autocommit());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_ddl_transaction_rejects_dml() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        assert_eq!(
+            leader
+                .execute_ddl_transaction(vec![
+                    "CREATE TABLE ddl_never (id INTEGER)".to_string(),
+                    "INSERT INTO ddl_never VALUES (1)".to_string(),
+                ])
+                .await,
+            Err(WorkerError::NotDdl {
+                index: 1,
+                sql: "INSERT INTO ddl_never VALUES (1)".to_string(),
+            })
+        );
+        assert!(!table_exists(&leader, "ddl_never").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_drop_table_if_exists() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        assert!(state.drop_table("nonexistent", false).await.is_err());
+        assert_eq!(state.drop_table("nonexistent", true).await, Ok(()));
+        assert!(matches!(
+            state.drop_table("items; DROP TABLE users", true).await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_follower_renames_and_drops_table() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        for sql in [
+            "DROP TABLE IF EXISTS rename_before",
+            "DROP TABLE IF EXISTS rename_after",
+            "CREATE TABLE rename_before (id INTEGER PRIMARY KEY)",
+        ] {
+            leader
+                .execute_query_simple(sql.to_string())
+                .await
+                .expect("Setup failed");
+        }
+
+        follower
+            .rename_table("rename_before", "rename_after")
+            .await
+            .expect("Rename failed");
+        assert!(leader
+            .execute_query_simple("SELECT * FROM rename_after".to_string())
+            .await
+            .is_ok());
+
+        follower
+            .drop_table("rename_after", false)
+            .await
+            .expect("Drop failed");
+        assert!(follower.drop_table("rename_after", false).await.is_err());
+        assert_eq!(follower.drop_table("rename_after", true).await, Ok(()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_reindex() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        for sql in [
+            "DROP TABLE IF EXISTS reindex_items",
+            "CREATE TABLE reindex_items (id INTEGER PRIMARY KEY, name TEXT)",
+            "CREATE INDEX reindex_items_name ON reindex_items (name)",
+            "INSERT INTO reindex_items (name) VALUES ('b'), ('a'), ('c')",
+        ] {
+            leader
+                .execute_query_simple(sql.to_string())
+                .await
+                .expect("Setup failed");
+        }
+
+        assert_eq!(leader.reindex(None).await, Ok(()));
+        assert_eq!(leader.reindex(Some("reindex_items_name")).await, Ok(()));
+        assert_eq!(follower.reindex(Some("reindex_items_name")).await, Ok(()));
+        assert!(follower.reindex(Some("reindex_missing")).await.is_err());
+        assert!(matches!(
+            leader.reindex(Some("name; DROP TABLE reindex_items")).await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+        // A failed REINDEX leaves no transaction behind
+        assert!(leader.db.borrow().as_ref().unwrap().is_autocommit());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_echo_round_trips_through_leader() {
+        let (Ok(leader), Ok(follower)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        *leader.is_leader.borrow_mut() = true;
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let echoed = follower.echo("hello".to_string()).await;
+        assert_eq!(echoed, Ok("hello".to_string()));
+        assert!(follower.pending_queries.borrow().is_empty());
+
+        let responder = follower
+            .message_log()
+            .into_iter()
+            .find_map(|entry| match entry.message {
+                ChannelMessage::EchoResponse { responder_id, .. } => Some(responder_id),
+                _ => None,
+            });
+        assert_eq!(responder.as_deref(), Some(leader.worker_id.as_str()));
+
+        assert_eq!(
+            leader.echo("local".to_string()).await,
+            Ok("local".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_echo_without_leader_fails() {
+        if let Ok(follower) = WorkerState::new() {
+            assert_eq!(
+                follower.echo("hello".to_string()).await,
+                Err(WorkerError::NoLeaderAvailable)
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_trace_id_round_trips_through_leader() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let traces = Rc::new(RefCell::new(Vec::new()));
+        let traces_clone = Rc::clone(&traces);
+        follower.set_trace_callback(move |trace_id, duration_ms| {
+            assert!(duration_ms >= 0.0);
+            traces_clone.borrow_mut().push(trace_id.to_string());
+        });
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_clone = Rc::clone(&events);
+        follower
+            .events
+            .subscribe(move |event| events_clone.borrow_mut().push(event.clone()));
+
+        let result = follower
+            .execute_query_traced("SELECT 1 as value".to_string(), "trace-abc".to_string())
+            .await;
+        assert!(result.is_ok(), "Traced query should succeed: {result:?}");
+
+        assert_eq!(*traces.borrow(), ["trace-abc"]);
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0],
+            QueryEvent::QueryDelivered { trace_id: Some(trace_id), success: true, .. }
+                if trace_id == "trace-abc"
+        ));
+
+        let received_request = leader.message_log().into_iter().any(|entry| {
+            entry.direction == MessageDirection::Received
+                && matches!(entry.message, ChannelMessage::QueryRequest { .. })
+        });
+        assert!(received_request, "Leader should have executed the request");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_context_metadata_reaches_leader() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let ctx = QueryContext::new()
+            .with_metadata("user", "alice")
+            .with_priority(QueryPriority::High)
+            .with_timeout_ms(2000);
+        let result = follower
+            .execute_query("SELECT 1 as value".to_string(), ctx)
+            .await;
+        assert!(result.is_ok(), "Query should succeed: {result:?}");
+
+        let metadata = leader
+            .message_log()
+            .into_iter()
+            .find_map(|entry| match entry.message {
+                ChannelMessage::QueryRequest { metadata, .. } => Some(metadata),
+                _ => None,
+            })
+            .expect("Leader should have logged the request");
+        assert_eq!(metadata.get("user").map(String::as_str), Some("alice"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_context_timeout() {
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        *follower.known_leader_id.borrow_mut() = Some("absent-leader".to_string());
+
+        let started_at = js_sys::Date::now();
+        let result = follower
+            .execute_query(
+                "SELECT 1".to_string(),
+                QueryContext::new().with_timeout_ms(50),
+            )
+            .await;
+        assert!(result.is_err());
+        assert!(js_sys::Date::now() - started_at < DEFAULT_QUERY_TIMEOUT_MS as f64);
+        assert!(follower.pending_queries.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_timeout_with_mock_clock() {
+        let time = MockTimeProvider::new();
+        let Ok(follower) = WorkerStateBuilder::new()
+            .time_provider(Rc::new(time.clone()))
+            .build()
+        else {
+            return;
+        };
+        *follower.known_leader_id.borrow_mut() = Some("absent-leader".to_string());
+        let follower = Rc::new(follower);
+
+        let result = Rc::new(RefCell::new(None));
+        let (query_follower, query_result) = (Rc::clone(&follower), Rc::clone(&result));
+        spawn_local(async move {
+            let outcome = query_follower
+                .execute_query(
+                    "SELECT 1".to_string(),
+                    QueryContext::new().with_timeout_ms(5000),
+                )
+                .await;
+            *query_result.borrow_mut() = Some(outcome);
+        });
+        sleep(0.0).await;
+        assert_eq!(follower.pending_queries.borrow().len(), 1);
+
+        time.advance(4999.0);
+        sleep(0.0).await;
+        assert!(
+            result.borrow().is_none(),
+            "Not timed out before the deadline"
+        );
+
+        time.advance(1.0);
+        sleep(0.0).await;
+        assert!(matches!(&*result.borrow(), Some(Err(err)) if err.contains("Query timeout")));
+        assert!(follower.pending_queries.borrow().is_empty());
+        assert_eq!(time.pending_timers(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_leader_reports_query_origin() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let executed = Rc::new(RefCell::new(Vec::new()));
+        let executed_clone = Rc::clone(&executed);
+        leader.events.subscribe(move |event| {
+            if let QueryEvent::QueryExecuted {
+                origin_worker_id, ..
+            } = event
+            {
+                executed_clone.borrow_mut().push(origin_worker_id.clone());
+            }
+        });
+
+        let result = follower
+            .execute_query("SELECT 1 as value".to_string(), QueryContext::default())
+            .await;
+        assert!(result.is_ok(), "Query should succeed: {result:?}");
+
+        let received_origin =
+            leader
+                .message_log()
+                .into_iter()
+                .find_map(|entry| match entry.message {
+                    ChannelMessage::QueryRequest {
+                        origin_worker_id, ..
+                    } => Some(origin_worker_id),
+                    _ => None,
+                });
+        assert_eq!(
+            received_origin.as_deref(),
+            Some(follower.worker_id.as_str())
+        );
+        assert_eq!(*executed.borrow(), [follower.worker_id.clone()]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_untraced_query_emits_event_without_trace() {
+        if let Ok(state) = WorkerState::new() {
+            *state.is_leader.borrow_mut() = true;
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let events_clone = Rc::clone(&events);
+            state
+                .events
+                .subscribe(move |event| events_clone.borrow_mut().push(event.clone()));
+
+            let _ = state
+                .execute_query("SELECT 1".to_string(), QueryContext::default())
+                .await;
+
+            let events = events.borrow();
+            assert!(
+                matches!(
+                    events[0],
+                    QueryEvent::QueryDelivered {
+                        trace_id: None,
+                        success: false,
+                        ..
+                    }
+                ),
+                "Untraced failing query should report no trace id"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_wait_for_leader() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+
+        let started_at = js_sys::Date::now();
+        assert_eq!(
+            state.wait_for_leader(100).await,
+            Err(WorkerError::NoLeaderAvailable)
+        );
+        assert!(js_sys::Date::now() - started_at >= 100.0);
+
+        let known_leader_id = Rc::clone(&state.known_leader_id);
+        spawn_local(async move {
+            sleep(30.0).await;
+            *known_leader_id.borrow_mut() = Some("leader-1".to_string());
+        });
+        assert_eq!(
+            state.wait_for_leader(1000).await,
+            Ok("leader-1".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_wait_for_leader_sees_announcement() {
+        let (Ok(follower), Ok(leader)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        follower.setup_channel_listener();
+        leader
+            .broadcast(ChannelMessage::NewLeader {
+                leader_id: leader.worker_id.clone(),
+            })
+            .unwrap();
+
+        assert_eq!(follower.wait_for_leader(1000).await, Ok(leader.worker_id));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_channel_listener_removed_on_drop() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let channel = state.channel.clone();
+        assert!(channel.onmessage().is_none());
+
+        state.setup_channel_listener();
+        state.setup_channel_listener();
+        assert!(channel.onmessage().is_some());
+
+        drop(state);
+        assert!(channel.onmessage().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_on_database_ready() {
+        let (Ok(follower), Ok(leader)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        follower.setup_channel_listener();
+        let calls = Rc::new(Cell::new(0));
+        for _ in 0..2 {
+            let calls = Rc::clone(&calls);
+            follower.on_database_ready(Box::new(move || calls.set(calls.get() + 1)));
+        }
+        assert!(!follower.is_database_ready());
+        assert_eq!(calls.get(), 0);
+
+        leader
+            .broadcast(ChannelMessage::NewLeader {
+                leader_id: leader.worker_id.clone(),
+            })
+            .unwrap();
+        sleep(50.0).await;
+        assert!(follower.is_database_ready());
+        assert_eq!(calls.get(), 2);
+        assert!(follower.ready_callbacks.borrow().is_empty());
+
+        // Already ready: called straight away
+        let late = Rc::new(Cell::new(false));
+        let late_flag = Rc::clone(&late);
+        follower.on_database_ready(Box::new(move || late_flag.set(true)));
+        assert!(late.get());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_max_sql_length() {
+        let Ok(state) = WorkerState::builder().max_sql_length(20).build() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+
+        // Under the limit the query is dispatched and fails on the missing database
+        let under = format!("SELECT {}", "1".repeat(13));
+        assert_eq!(under.len(), 20);
+        assert_eq!(
+            state
+                .execute_query(under.clone(), QueryContext::default())
+                .await,
+            Err("Database not initialized".to_string())
+        );
+
+        let over = format!("SELECT {}", "1".repeat(14));
+        assert_eq!(
+            state
+                .execute_query(over.clone(), QueryContext::default())
+                .await,
+            Err(WorkerError::SqlTooLong {
+                length: 21,
+                max: 20
+            }
+            .to_string())
+        );
+        assert_eq!(
+            state.execute_parameterized(over.clone(), vec![]).await,
+            Err(WorkerError::SqlTooLong {
+                length: 21,
+                max: 20
+            })
+        );
+
+        state.set_max_sql_length(21);
+        assert_eq!(
+            state.execute_parameterized(over, vec![]).await,
+            Err(WorkerError::DatabaseNotInitialized)
+        );
+
+        if let Ok(state) = WorkerState::new() {
+            assert_eq!(state.max_sql_length.get(), DEFAULT_MAX_SQL_LENGTH);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_setup_channel_listener() {
+        if let Ok(state) = WorkerState::new() {
+            state.setup_channel_listener();
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_attempt_leadership_behavior() {
+        if let Ok(state) = WorkerState::new() {
+            assert!(!*state.is_leader.borrow(), "Should start as follower");
+            assert!(
+                state.db.borrow().is_none(),
+                "Database should be uninitialized"
+            );
+
+            state.attempt_leadership().await;
+        }
+
+        let workers: Vec<_> = (0..3).filter_map(|_| WorkerState::new().ok()).collect();
+        if workers.len() >= 2 {
+            for worker in &workers {
+                assert!(!*worker.is_leader.borrow(), "All should start as followers");
+            }
+
+            for worker in &workers {
+                worker.attempt_leadership().await;
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fork_for_test_isolates_state() {
+        let Ok(original) = WorkerState::new() else {
+            return;
+        };
+        *original.is_leader.borrow_mut() = true;
+        *original.known_leader_id.borrow_mut() = Some(original.worker_id.clone());
+
+        let fork = original.fork_for_test();
+        assert_eq!(fork.worker_id, original.worker_id);
+        assert_eq!(fork.channel.name(), original.channel.name());
+        assert!(!*fork.is_leader.borrow());
+        assert!(fork.db.borrow().is_none());
+        assert_eq!(*fork.known_leader_id.borrow(), None);
+
+        let resolve = Function::new_no_args("return 'resolved';");
+        let reject = Function::new_no_args("return 'rejected';");
+        fork.pending_queries
+            .borrow_mut()
+            .insert("fork-only".to_string(), PendingQuery::new(resolve, reject));
+        assert!(original.pending_queries.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_fork_handles_response_meant_for_original() {
+        let (Ok(original), Ok(responder)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        let fork = original.fork_for_test();
+        fork.setup_channel_listener();
+
+        let resolved = Rc::new(RefCell::new(None));
+        let resolved_clone = Rc::clone(&resolved);
+        let on_resolve = Closure::wrap(Box::new(move |value: JsValue| {
+            *resolved_clone.borrow_mut() = value.as_string();
+        }) as Box<dyn FnMut(JsValue)>);
+        let reject = Function::new_no_args("return 'rejected';");
+
+        // Both tabs are waiting on a query with the same id
+        for state in [&original, &fork] {
+            state.pending_queries.borrow_mut().insert(
+                "shared-query".to_string(),
+                PendingQuery::new(
+                    on_resolve.as_ref().unchecked_ref::<Function>().clone(),
+                    reject.clone(),
+                ),
+            );
+        }
+
+        responder
+            .broadcast(ChannelMessage::QueryResponse {
+                query_id: "shared-query".to_string(),
+                result: Some("[]".to_string()),
+                error: None,
+                error_code: None,
+                stats: None,
+            })
+            .unwrap();
+        sleep(50.0).await;
+
+        assert_eq!(resolved.borrow().as_deref(), Some("[]"));
+        assert!(fork.pending_queries.borrow().is_empty());
+        assert!(
+            original
+                .pending_queries
+                .borrow()
+                .contains_key("shared-query"),
+            "The original's pending queries are not touched by the fork's listener"
+        );
+        on_resolve.forget();
+    }
+
+    fn upper2() -> Function {
+        Function::new_with_args("text", "return (text + text).toUpperCase();")
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_register_scalar_function_on_leader() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .register_scalar_function("UPPER2".to_string(), 1, upper2())
+            .await
+            .expect("Registration failed");
+
+        let result = state
+            .execute_parameterized("SELECT UPPER2(?) AS doubled".to_string(), vec!["ab".into()])
+            .await
+            .expect("Query failed");
+        assert_eq!(result.rows, vec![vec![SqlValue::Text("ABAB".to_string())]]);
+
+        assert_eq!(
+            state
+                .register_scalar_function("bad name".to_string(), 1, upper2())
+                .await,
+            Err(WorkerError::InvalidIdentifier("bad name".to_string()))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_register_function_request_from_source() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let leader = state.leader_context();
+
+        // The source a follower would send for `upper2()`
+        let request = ChannelMessage::RegisterFunction {
+            query_id: "register-1".to_string(),
+            name: "UPPER2_SRC".to_string(),
+            arity: 1,
+            func_source: String::from(upper2().to_string()),
+        };
+        assert_eq!(
+            leader.handle_request(request).await,
+            Some(query_response("register-1".to_string(), Ok(String::new())))
+        );
+        let result = leader
+            .execute_parameterized("SELECT UPPER2_SRC('xy')", &[])
+            .await
+            .expect("Query failed");
+        assert_eq!(result.rows, vec![vec![SqlValue::Text("XYXY".to_string())]]);
+
+        let invalid = ChannelMessage::RegisterFunction {
+            query_id: "register-2".to_string(),
+            name: "BROKEN".to_string(),
+            arity: 1,
+            func_source: "not a function".to_string(),
+        };
+        let Some(ChannelMessage::QueryResponse { error, .. }) =
+            leader.handle_request(invalid).await
+        else {
+            panic!("Expected a query response");
+        };
+        assert!(error.is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_leader_sends_empty_response_for_no_rows() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let leader = state.leader_context();
+        leader
+            .execute_parameterized("DROP TABLE IF EXISTS empty_items", &[])
+            .await
+            .expect("Drop failed");
+        leader
+            .execute_parameterized("CREATE TABLE empty_items (id INTEGER PRIMARY KEY)", &[])
+            .await
+            .expect("Create failed");
+
+        let select = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "empty-1".to_string(),
+            sql: "SELECT id FROM empty_items".to_string(),
+            params: vec![],
+            source_location: None,
+        };
+        assert_eq!(
+            leader.handle_request(select).await,
+            Some(ChannelMessage::EmptyResponse {
+                query_id: "empty-1".to_string(),
+                columns: vec!["id".to_string()],
+            })
+        );
+
+        // Statements that changed rows still report their counts
+        let insert = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "empty-2".to_string(),
+            sql: "INSERT INTO empty_items (id) VALUES (1)".to_string(),
+            params: vec![],
+            source_location: None,
+        };
+        assert!(matches!(
+            leader.handle_request(insert).await,
+            Some(ChannelMessage::QueryResponse { .. })
+        ));
+
+        // As do statements that changed nothing but report an insert rowid
+        let delete = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "empty-4".to_string(),
+            sql: "DELETE FROM empty_items WHERE id = 2".to_string(),
+            params: vec![],
+            source_location: None,
+        };
+        let Some(ChannelMessage::QueryResponse {
+            result: Some(json), ..
+        }) = leader.handle_request(delete).await
+        else {
+            panic!("Expected a query response");
+        };
+        let result: QueryResult = serde_json::from_str(&json).expect("Should be a QueryResult");
+        assert_eq!((result.rows_affected, result.last_insert_rowid), (0, 1));
+
+        let missing = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "empty-3".to_string(),
+            sql: "SELECT id FROM no_such_table".to_string(),
+            params: vec![],
+            source_location: None,
+        };
+        let Some(ChannelMessage::QueryResponse { error, .. }) =
+            leader.handle_request(missing).await
+        else {
+            panic!("Expected a query response");
+        };
+        assert!(error.is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_empty_response_resolves_follower() {
+        let (Ok(follower), Ok(responder)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        follower.setup_channel_listener();
+        let resolved = js_sys::Array::new();
+        let resolve = Function::new_with_args("value", "this.push(value);").bind(&resolved);
+        let reject = Function::new_no_args("");
+        follower.pending_queries.borrow_mut().insert(
+            "empty-follower".to_string(),
+            PendingQuery::new(resolve, reject),
+        );
+
+        responder
+            .broadcast(ChannelMessage::EmptyResponse {
+                query_id: "empty-follower".to_string(),
+                columns: vec!["id".to_string()],
+            })
+            .unwrap();
+        sleep(50.0).await;
+
+        assert!(follower.pending_queries.borrow().is_empty());
+        let json = resolved
+            .get(0)
+            .as_string()
+            .expect("Should resolve with JSON");
+        let result: QueryResult = serde_json::from_str(&json).expect("Should be a QueryResult");
+        assert_eq!(result.columns, vec!["id".to_string()]);
+        assert!(result.rows.is_empty());
+        assert_eq!(result.rows_affected, 0);
+        assert_eq!(result.get_single::<(i64,)>(), Err(WorkerError::NotFound));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_custom_message_roundtrip() {
+        let (Ok(sender), Ok(receiver)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        receiver.setup_channel_listener();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let received_clone = Rc::clone(&received);
+        receiver.register_custom_handler(
+            "cursor-moved",
+            Box::new(move |payload| received_clone.borrow_mut().push(payload)),
+        );
+
+        sender
+            .send_custom("unhandled".to_string(), serde_json::json!(null))
+            .unwrap();
+        sender
+            .send_custom("cursor-moved".to_string(), serde_json::json!({ "x": 3 }))
+            .unwrap();
+        sleep(50.0).await;
+
+        assert_eq!(*received.borrow(), vec![serde_json::json!({ "x": 3 })]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_circuit_breaker_guards_leader_queries() {
+        let time = MockTimeProvider::new();
+        let Ok(state) = WorkerStateBuilder::new()
+            .circuit_breaker(2, 1000)
+            .time_provider(Rc::new(time.clone()))
+            .build()
+        else {
+            return;
+        };
+        let Some(state) = lead_with_db(state).await else {
+            return;
+        };
+        let leader = state.leader_context();
+
+        for _ in 0..2 {
+            let result = leader
+                .execute_parameterized("SELECT * FROM circuit_missing", &[])
+                .await;
+            assert!(matches!(result, Err(WorkerError::SqliteError { .. })));
+        }
+        assert_eq!(
+            state.circuit_breaker.state(),
+            CircuitState::Closed,
+            "A query's own errors do not count"
+        );
+
+        // SQLITE_IOERR_READ
+        let io_error = WorkerError::from_sqlite_code(266, "disk I/O error".to_string());
+        for _ in 0..2 {
+            leader.record_outcome::<()>(&Err(io_error.clone()));
+        }
+        assert_eq!(state.circuit_breaker.state(), CircuitState::Open);
+        assert_eq!(
+            leader.execute_parameterized("SELECT 1", &[]).await,
+            Err(WorkerError::CircuitOpen),
+            "An open circuit rejects without running the query"
+        );
+        assert!(leader
+            .exec("SELECT 1")
+            .await
+            .unwrap_err()
+            .contains("Circuit"));
+
+        time.advance(1000.0);
+        assert!(leader.execute_parameterized("SELECT 1", &[]).await.is_ok());
+        assert_eq!(state.circuit_breaker.state(), CircuitState::Closed);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_caller_location_by_build_mode() {
+        let location = caller_location();
+        #[cfg(debug_assertions)]
+        {
+            let location = location.expect("Debug builds capture the caller");
+            assert!(!location.is_empty());
+            assert!(location.contains("coordination.rs"));
+        }
+        #[cfg(not(debug_assertions))]
+        assert_eq!(location, None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_request_carries_source_location() {
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        let follower = Rc::new(follower);
+        *follower.known_leader_id.borrow_mut() = Some("absent-leader".to_string());
+
+        let follower_clone = Rc::clone(&follower);
+        spawn_local(async move {
+            let _ = follower_clone
+                .execute_query("SELECT 1".to_string(), QueryContext::default())
+                .await;
+        });
+        sleep(20.0).await;
+
+        let log = follower.message_log();
+        let Some(ChannelMessage::QueryRequest {
+            source_location, ..
+        }) = log.first().map(|entry| &entry.message)
+        else {
+            panic!("Follower should have sent a query request");
+        };
+        #[cfg(debug_assertions)]
+        assert!(source_location
+            .as_deref()
+            .is_some_and(|location| location.contains("coordination.rs")));
+        #[cfg(not(debug_assertions))]
+        assert_eq!(*source_location, None);
+    }
+
+    async fn count_rows(state: &WorkerState, table: &str) -> i64 {
+        let result = state
+            .execute_query_simple(format!("SELECT COUNT(*) as count FROM {table}"))
+            .await
+            .expect("Count failed");
+        result.get_single::<(i64,)>().unwrap().0
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_run_in_transaction_requires_leader() {
+        if let Ok(state) = WorkerState::new() {
+            let result: Result<(), WorkerError> = state
+                .run_in_transaction(|| Box::pin(async { Ok(()) }))
+                .await;
+            assert_eq!(result, Err(WorkerError::NotLeader));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_run_in_transaction_commits_on_success() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple("CREATE TABLE IF NOT EXISTS tx_commit (id INTEGER)".to_string())
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM tx_commit".to_string())
+            .await
+            .expect("Delete failed");
+
+        let state_ref = &state;
+        let result = state
+            .run_in_transaction(|| {
+                Box::pin(async move {
+                    state_ref
+                        .execute_query_simple("INSERT INTO tx_commit VALUES (1), (2)".to_string())
+                        .await
+                })
+            })
+            .await;
 
-                        let msg = ChannelMessage::NewLeader {
-                            leader_id: worker_id.clone(),
-                        };
-                        let msg_js = serde_wasm_bindgen::to_value(&msg).unwrap();
-                        let _ = channel.post_message(&msg_js);
-                    }
-                    Err(_e) => {}
-                }
-            });
+        assert!(result.is_ok(), "Transaction should commit: {result:?}");
+        assert_eq!(count_rows(&state, "tx_commit").await, 2);
+        assert!(state.active_transaction.borrow().is_none());
+    }
 
-            // Never resolve = hold lock forever
-            Promise::new(&mut |_, _| {})
-        });
+    #[wasm_bindgen_test]
+    async fn test_run_in_transaction_rolls_back_on_error() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple("CREATE TABLE IF NOT EXISTS tx_rollback (id INTEGER)".to_string())
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM tx_rollback".to_string())
+            .await
+            .expect("Delete failed");
 
-        let request_fn = Reflect::get(&locks, &JsValue::from_str("request")).unwrap();
-        let request_fn = request_fn.dyn_ref::<Function>().unwrap();
+        let state_ref = &state;
+        let result: Result<(), WorkerError> = state
+            .run_in_transaction(|| {
+                Box::pin(async move {
+                    state_ref
+                        .execute_query_simple("INSERT INTO tx_rollback VALUES (1)".to_string())
+                        .await?;
+                    Err(WorkerError::QueryFailed("closure failed".to_string()))
+                })
+            })
+            .await;
 
-        let _ = request_fn.call3(
-            &locks,
-            &JsValue::from_str("sqlite-database"),
-            &options,
-            handler.as_ref().unchecked_ref(),
+        assert_eq!(
+            result,
+            Err(WorkerError::QueryFailed("closure failed".to_string()))
+        );
+        assert_eq!(
+            count_rows(&state, "tx_rollback").await,
+            0,
+            "Insert should be rolled back"
         );
+        assert!(state.active_transaction.borrow().is_none());
+    }
 
-        handler.forget();
+    #[wasm_bindgen_test]
+    async fn test_run_in_transaction_queues_follower_requests() {
+        let Some(leader) = leader_with_db().await else {
+            return;
+        };
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        leader
+            .execute_query_simple("CREATE TABLE IF NOT EXISTS tx_queued (id INTEGER)".to_string())
+            .await
+            .expect("Create failed");
+        leader
+            .execute_query_simple("DELETE FROM tx_queued".to_string())
+            .await
+            .expect("Delete failed");
+
+        let follower = Rc::new(follower);
+        let result = Rc::new(RefCell::new(None));
+        let leader_ref = &leader;
+        let outcome = leader
+            .run_in_transaction(|| {
+                let result = Rc::clone(&result);
+                let follower = Rc::clone(&follower);
+                Box::pin(async move {
+                    leader_ref
+                        .execute_query_simple("INSERT INTO tx_queued VALUES (1)".to_string())
+                        .await?;
+                    spawn_local(async move {
+                        let value = follower
+                            .execute_query_simple("SELECT count(*) AS n FROM tx_queued".to_string())
+                            .await;
+                        *result.borrow_mut() = Some(value);
+                    });
+                    sleep(100.0).await;
+                    assert_eq!(leader_ref.paused_requests.borrow().len(), 1);
+                    assert!(result.borrow().is_none());
+                    Err::<(), _>(WorkerError::QueryFailed("roll back".to_string()))
+                })
+            })
+            .await;
+        assert!(outcome.is_err());
+
+        sleep(100.0).await;
+        assert!(leader.paused_requests.borrow().is_empty());
+        let value = result
+            .borrow_mut()
+            .take()
+            .expect("Follower was not answered");
+        let value = value.expect("Follower query failed");
+        assert_eq!(
+            value.get_single::<(i64,)>(),
+            Ok((0,)),
+            "The queued read should not see the rolled back insert"
+        );
     }
 
-    pub async fn execute_query(&self, sql: String) -> Result<String, String> {
-        if *self.is_leader.borrow() {
-            let database = self.db.borrow().clone();
-            if let Some(database) = database {
-                database.exec(&sql).await
-            } else {
-                Err("Database not initialized".to_string())
+    #[wasm_bindgen_test]
+    async fn test_nested_savepoints_roll_back_each_level() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "CREATE TABLE IF NOT EXISTS sp_items (id INTEGER)".to_string(),
+                "DELETE FROM sp_items".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+        let insert = |id: i64| {
+            let state = &state;
+            async move {
+                state
+                    .execute_query_simple(format!("INSERT INTO sp_items VALUES ({id})"))
+                    .await
             }
-        } else {
-            let query_id = Uuid::new_v4().to_string();
-
-            let promise = Promise::new(&mut |resolve, reject| {
-                self.pending_queries
-                    .borrow_mut()
-                    .insert(query_id.clone(), PendingQuery { resolve, reject });
-            });
+        };
+        let failure = || WorkerError::QueryFailed("closure failed".to_string());
 
-            let msg = ChannelMessage::QueryRequest {
-                query_id: query_id.clone(),
-                sql,
-            };
-            let msg_js = serde_wasm_bindgen::to_value(&msg).unwrap();
-            let _ = self.channel.post_message(&msg_js);
+        // The inner savepoint rolls back alone and the outer one keeps its rows
+        let result = state
+            .execute_with_savepoint(
+                "outer",
+                Box::pin(async {
+                    insert(1).await?;
+                    let inner: Result<(), WorkerError> = state
+                        .execute_with_savepoint(
+                            "inner",
+                            Box::pin(async {
+                                insert(2).await?;
+                                Err(failure())
+                            }),
+                        )
+                        .await;
+                    assert_eq!(inner, Err(failure()));
+                    insert(3).await
+                }),
+            )
+            .await;
+        assert!(result.is_ok(), "Outer savepoint should release: {result:?}");
+        assert_eq!(count_rows(&state, "sp_items").await, 2);
 
-            // Timeout handling
-            let timeout_promise = Promise::new(&mut |_, reject| {
-                let query_id = query_id.clone();
-                let pending_queries = Rc::clone(&self.pending_queries);
+        // Both levels fail, undoing everything either of them did
+        let result: Result<(), WorkerError> = state
+            .execute_with_savepoint(
+                "outer",
+                Box::pin(async {
+                    insert(4).await?;
+                    let _ = state
+                        .execute_with_savepoint(
+                            "inner",
+                            Box::pin(async {
+                                insert(5).await?;
+                                Err::<(), _>(failure())
+                            }),
+                        )
+                        .await;
+                    Err(failure())
+                }),
+            )
+            .await;
+        assert_eq!(result, Err(failure()));
+        assert_eq!(count_rows(&state, "sp_items").await, 2);
+        assert!(state.database().unwrap().is_autocommit());
 
-                let callback = Closure::once(move || {
-                    if pending_queries.borrow_mut().remove(&query_id).is_some() {
-                        let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("Query timeout"));
-                    }
-                });
+        assert!(matches!(
+            state
+                .execute_with_savepoint("bad name", Box::pin(async { Ok(()) }))
+                .await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+    }
 
-                let global = js_sys::global();
-                let set_timeout = Reflect::get(&global, &JsValue::from_str("setTimeout")).unwrap();
-                let set_timeout = set_timeout.dyn_ref::<Function>().unwrap();
-                set_timeout
-                    .call2(
-                        &JsValue::NULL,
-                        callback.as_ref().unchecked_ref(),
-                        &JsValue::from_f64(5000.0),
-                    )
-                    .unwrap();
-                callback.forget();
-            });
+    #[wasm_bindgen_test]
+    async fn test_savepoint_stack() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "CREATE TABLE IF NOT EXISTS sp_stack_items (id INTEGER)".to_string(),
+                "DELETE FROM sp_stack_items".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
 
-            let result = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::race(
-                &js_sys::Array::of2(&promise, &timeout_promise),
-            ))
+        let result = state
+            .run_in_transaction(|| {
+                Box::pin(async {
+                    state
+                        .execute_with_savepoint(
+                            "outer",
+                            Box::pin(async {
+                                state
+                                    .execute_with_savepoint(
+                                        "inner",
+                                        Box::pin(async {
+                                            assert_eq!(
+                                                state.list_savepoints(),
+                                                vec!["outer".to_string(), "inner".to_string()]
+                                            );
+                                            state
+                                                .execute_query_simple(
+                                                    "INSERT INTO sp_stack_items VALUES (1)"
+                                                        .to_string(),
+                                                )
+                                                .await
+                                        }),
+                                    )
+                                    .await?;
+                                assert_eq!(state.list_savepoints(), vec!["outer".to_string()]);
+                                Ok(())
+                            }),
+                        )
+                        .await
+                })
+            })
             .await;
+        assert!(result.is_ok(), "Transaction should commit: {result:?}");
+        assert!(state.list_savepoints().is_empty());
+        assert!(state.health_report().open_savepoints.is_empty());
+        assert_eq!(count_rows(&state, "sp_stack_items").await, 1);
 
-            match result {
-                Ok(val) => {
-                    if let Some(s) = val.as_string() {
-                        Ok(s)
-                    } else {
-                        Err("Invalid response".to_string())
-                    }
-                }
-                Err(e) => Err(format!("{e:?}")),
-            }
-        }
+        // Savepoints left open are released innermost first, keeping their rows
+        state
+            .savepoint("first", SavepointAction::Begin)
+            .await
+            .unwrap();
+        state
+            .execute_query_simple("INSERT INTO sp_stack_items VALUES (2)".to_string())
+            .await
+            .unwrap();
+        state
+            .savepoint("second", SavepointAction::Begin)
+            .await
+            .unwrap();
+        assert_eq!(
+            state.health_report().open_savepoints,
+            vec!["first".to_string(), "second".to_string()]
+        );
+        state
+            .release_all_savepoints()
+            .await
+            .expect("Release failed");
+        assert!(state.list_savepoints().is_empty());
+        assert!(state.database().unwrap().is_autocommit());
+        assert_eq!(count_rows(&state, "sp_stack_items").await, 2);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use js_sys::Function;
-    use wasm_bindgen_test::*;
+    #[wasm_bindgen_test]
+    async fn test_validate_sql() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "CREATE TABLE IF NOT EXISTS dry_run_items (id INTEGER)".to_string(),
+                "DELETE FROM dry_run_items".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
 
-    wasm_bindgen_test_configure!(run_in_browser);
+        for sql in [
+            "SELECT * FROM dry_run_items",
+            "INSERT INTO dry_run_items VALUES (1);",
+            "CREATE TABLE dry_run_created (id INTEGER)",
+        ] {
+            let result = state.validate_sql(sql.to_string()).await;
+            assert_eq!(result, Ok(()), "{sql} should be valid");
+        }
+        // Nothing was run
+        assert_eq!(count_rows(&state, "dry_run_items").await, 0);
+        assert!(state
+            .execute_query_dry_run("SELECT * FROM dry_run_created")
+            .await
+            .is_err());
+
+        for sql in [
+            "SELEC * FROM dry_run_items",
+            "SELECT * FROM dry_run_missing",
+            "SELECT 'unterminated",
+        ] {
+            assert!(
+                matches!(
+                    state.execute_query_dry_run(sql).await,
+                    Err(WorkerError::SqlSyntaxError { .. })
+                ),
+                "{sql} should be a syntax error"
+            );
+        }
+        assert_eq!(state.circuit_breaker.state(), CircuitState::Closed);
+    }
 
     #[wasm_bindgen_test]
-    fn test_worker_state_creation_and_uniqueness() {
-        let results: Vec<_> = (0..5).map(|_| WorkerState::new()).collect();
-        let workers: Vec<_> = results.into_iter().filter_map(Result::ok).collect();
+    async fn test_execute_parameterized_on_leader() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple(
+                "CREATE TABLE IF NOT EXISTS param_users (id INTEGER, name TEXT)".to_string(),
+            )
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM param_users".to_string())
+            .await
+            .expect("Delete failed");
 
-        assert!(!workers.is_empty(), "Should create at least one worker");
+        state
+            .execute_parameterized(
+                "INSERT INTO param_users VALUES (?, ?)".to_string(),
+                vec![SqlParam::Integer(1), SqlParam::from("Alice")],
+            )
+            .await
+            .expect("Insert failed");
 
-        let state = &workers[0];
-        assert!(!state.worker_id.is_empty(), "Worker ID should not be empty");
-        assert!(
-            state.worker_id.contains('-'),
-            "Worker ID should be valid UUID format"
-        );
-        assert!(
-            !*state.is_leader.borrow(),
-            "New workers should not start as leader"
-        );
-        assert!(
-            state.db.borrow().is_none(),
-            "Database should be uninitialized"
-        );
-        assert!(
-            state.pending_queries.borrow().is_empty(),
-            "Should have no pending queries"
-        );
+        let result = state
+            .execute_parameterized(
+                "SELECT name FROM param_users WHERE id = ?".to_string(),
+                vec![SqlParam::Integer(1)],
+            )
+            .await
+            .expect("Select failed");
+        assert_eq!(result.rows, vec![vec![SqlParam::from("Alice")]]);
+    }
 
-        if workers.len() >= 2 {
-            let mut ids = std::collections::HashSet::new();
-            for worker in &workers {
-                assert!(
-                    ids.insert(worker.worker_id.clone()),
-                    "Worker ID {} should be unique",
-                    worker.worker_id
-                );
-                assert_eq!(worker.worker_id.len(), 36, "UUID should be 36 characters");
-                assert_eq!(
-                    worker.worker_id.chars().filter(|&c| c == '-').count(),
-                    4,
-                    "UUID should have 4 dashes"
-                );
-            }
-            assert_eq!(ids.len(), workers.len(), "All worker IDs should be unique");
-        }
+    #[wasm_bindgen_test]
+    async fn test_blob_param_survives_channel_roundtrip() {
+        let (Some(leader), Ok(follower)) = (leader_with_db().await, WorkerState::new()) else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        // Includes bytes that are not valid UTF-8 and bytes that are
+        let blob: Vec<u8> = (0..=255).chain(b"text".iter().copied()).collect();
+        let result = follower
+            .execute_parameterized("SELECT ?".to_string(), vec![SqlParam::Blob(blob.clone())])
+            .await
+            .expect("Query failed");
+        assert_eq!(result.rows, vec![vec![SqlValue::Blob(blob)]]);
     }
 
     #[wasm_bindgen_test]
-    fn test_leadership_state_management() {
-        if let Ok(state) = WorkerState::new() {
-            assert!(!*state.is_leader.borrow(), "Should start as follower");
+    async fn test_follower_receives_sqlite_error_code() {
+        let (Some(leader), Ok(follower)) = (leader_with_db().await, WorkerState::new()) else {
+            return;
+        };
+        leader
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS coded_users".to_string(),
+                "CREATE TABLE coded_users (email TEXT UNIQUE)".to_string(),
+                "INSERT INTO coded_users VALUES ('a@example.com')".to_string(),
+            ])
+            .await
+            .expect("Seed failed");
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
 
-            *state.is_leader.borrow_mut() = true;
-            assert!(*state.is_leader.borrow(), "Should become leader");
+        let error = follower
+            .execute_parameterized(
+                "INSERT INTO coded_users VALUES (?)".to_string(),
+                vec![SqlParam::from("a@example.com")],
+            )
+            .await
+            .expect_err("Duplicate should fail");
+        // SQLITE_CONSTRAINT_UNIQUE
+        assert_eq!(error.sqlite_code(), Some(2067));
+        assert!(matches!(error, WorkerError::ConstraintViolation { .. }));
+        assert!(error.to_string().contains("UNIQUE constraint failed"));
 
-            *state.is_leader.borrow_mut() = false;
-            assert!(!*state.is_leader.borrow(), "Should become follower again");
-        }
+        let syntax = follower
+            .execute_parameterized("SELEC 1".to_string(), Vec::new())
+            .await
+            .expect_err("Syntax error should fail");
+        assert!(matches!(syntax, WorkerError::SqliteError { code: 1, .. }));
     }
 
     #[wasm_bindgen_test]
-    fn test_pending_queries_management() {
-        if let Ok(state) = WorkerState::new() {
-            let pending_queries = Rc::clone(&state.pending_queries);
+    async fn test_follower_receives_query_progress() {
+        let (Some(leader), Ok(follower)) = (leader_with_db().await, WorkerState::new()) else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
 
-            assert_eq!(pending_queries.borrow().len(), 0);
+        let steps = js_sys::Array::new();
+        let cb = Function::new_with_args("steps", "this.push(steps)").bind(&steps);
+        let result = follower
+            .execute_query_with_progress(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n \
+                 WHERE x < 100000) SELECT sum(x) AS total FROM n"
+                    .to_string(),
+                cb,
+            )
+            .await;
+        assert!(result.is_ok(), "Query failed: {result:?}");
+        assert!(steps.length() > 1, "Called {} times", steps.length());
+        assert_eq!(
+            steps.get(0).as_f64(),
+            Some(f64::from(crate::query_context::PROGRESS_REPORT_INTERVAL))
+        );
+        assert!(follower.progress_channel.borrow().is_empty());
+    }
 
-            let test_queries = vec!["query-a", "query-b", "query-c", "query-d", "query-e"];
-            {
-                let mut queries = pending_queries.borrow_mut();
-                for query_id in &test_queries {
-                    let resolve =
-                        Function::new_no_args(&format!("return 'resolved-{}';", query_id));
-                    let reject = Function::new_no_args(&format!("return 'rejected-{}';", query_id));
-                    queries.insert(query_id.to_string(), PendingQuery { resolve, reject });
-                }
-            }
+    async fn seed_copy_source(state: &WorkerState, src: &str) {
+        state
+            .execute_many(vec![
+                format!("DROP TABLE IF EXISTS {src}"),
+                format!("CREATE TABLE {src} (id INTEGER, active INTEGER)"),
+                format!("INSERT INTO {src} VALUES (1, 1), (2, 0), (3, 1)"),
+            ])
+            .await
+            .expect("Seed failed");
+    }
 
-            assert_eq!(pending_queries.borrow().len(), test_queries.len());
-            for query_id in &test_queries {
-                assert!(pending_queries.borrow().contains_key(*query_id));
-            }
+    #[wasm_bindgen_test]
+    async fn test_copy_table_creates_destination() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        seed_copy_source(&state, "copy_src_all").await;
+        state
+            .execute_query_simple("DROP TABLE IF EXISTS copy_dst_all".to_string())
+            .await
+            .expect("Drop failed");
 
-            for (i, query_id) in test_queries.iter().enumerate() {
-                if i % 2 == 0 {
-                    let removed = pending_queries.borrow_mut().remove(*query_id);
-                    assert!(removed.is_some(), "Should remove query {}", query_id);
-                }
-            }
+        let copied = state
+            .copy_table("copy_src_all", "copy_dst_all", None)
+            .await
+            .expect("Copy failed");
+        assert_eq!(copied, 3);
+        assert_eq!(count_rows(&state, "copy_dst_all").await, 3);
+    }
 
-            let remaining_count = test_queries.len() - test_queries.len().div_ceil(2);
-            assert_eq!(pending_queries.borrow().len(), remaining_count);
+    #[wasm_bindgen_test]
+    async fn test_copy_table_with_filter() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        seed_copy_source(&state, "copy_src_filter").await;
+        state
+            .execute_query_simple("DROP TABLE IF EXISTS copy_dst_filter".to_string())
+            .await
+            .expect("Drop failed");
 
-            pending_queries.borrow_mut().clear();
-            assert_eq!(pending_queries.borrow().len(), 0);
+        let copied = state
+            .copy_table(
+                "copy_src_filter",
+                "copy_dst_filter",
+                Some("active = 1".to_string()),
+            )
+            .await
+            .expect("Copy failed");
+        assert_eq!(copied, 2);
+        assert_eq!(count_rows(&state, "copy_dst_filter").await, 2);
+    }
 
-            {
-                let mut queries = pending_queries.borrow_mut();
-                let resolve = Function::new_no_args("return 'post-cleanup';");
-                let reject = Function::new_no_args("return 'rejected';");
-                queries.insert(
-                    "post-cleanup-test".to_string(),
-                    PendingQuery { resolve, reject },
-                );
-            }
-            assert_eq!(pending_queries.borrow().len(), 1);
-            assert!(pending_queries.borrow().contains_key("post-cleanup-test"));
-        }
+    #[wasm_bindgen_test]
+    async fn test_copy_table_is_atomic() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        seed_copy_source(&state, "copy_src_atomic").await;
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS copy_dst_atomic".to_string(),
+                "CREATE TABLE copy_dst_atomic (id INTEGER UNIQUE, active INTEGER)".to_string(),
+                "INSERT INTO copy_dst_atomic VALUES (3, 1)".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+
+        let result = state
+            .copy_table("copy_src_atomic", "copy_dst_atomic", None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(WorkerError::ConstraintViolation { .. })
+        ));
+        assert_eq!(
+            count_rows(&state, "copy_dst_atomic").await,
+            1,
+            "Rows copied before the conflict should be rolled back"
+        );
+        assert!(state.database().unwrap().is_autocommit());
     }
 
     #[wasm_bindgen_test]
-    fn test_message_deserialization_error_handling() {
-        let invalid_json = JsValue::from_str("invalid json");
-        let result = serde_wasm_bindgen::from_value::<ChannelMessage>(invalid_json);
-        assert!(result.is_err(), "Should fail to deserialize invalid JSON");
+    async fn test_copy_table_rejects_unsafe_names() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let result = state
+            .copy_table("users; DROP TABLE users", "backup", None)
+            .await;
+        assert_eq!(
+            result,
+            Err(WorkerError::InvalidIdentifier(
+                "users; DROP TABLE users".to_string()
+            ))
+        );
     }
 
     #[wasm_bindgen_test]
-    async fn test_execute_query_leader_vs_follower_paths() {
-        if let Ok(leader_state) = WorkerState::new() {
-            *leader_state.is_leader.borrow_mut() = true;
+    async fn test_execute_many_success() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple("CREATE TABLE IF NOT EXISTS batch_ok (id INTEGER)".to_string())
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM batch_ok".to_string())
+            .await
+            .expect("Delete failed");
 
-            let test_queries = vec![
-                "",
-                "SELECT 1",
-                "INSERT INTO test VALUES (1, 'hello')",
-                "SELECT 'test with spaces and symbols: !@#$%^&*()'",
-                "SELECT 'Hello 世界 🌍'",
-            ];
+        let results = state
+            .execute_many(vec![
+                "INSERT INTO batch_ok VALUES (1)".to_string(),
+                "INSERT INTO batch_ok VALUES (2)".to_string(),
+                "SELECT id FROM batch_ok ORDER BY id".to_string(),
+            ])
+            .await
+            .expect("Batch should succeed");
 
-            for query in test_queries {
-                let result = leader_state.execute_query(query.to_string()).await;
-                match result {
-                    Err(msg) => assert_eq!(
-                        msg, "Database not initialized",
-                        "Leader should get DB init error for query: {}",
-                        query
-                    ),
-                    Ok(_) => panic!(
-                        "Expected database not initialized error for query: {}",
-                        query
-                    ),
-                }
-            }
-        }
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].rows_affected, 1);
+        assert_eq!(results[2].columns, ["id"]);
+        assert_eq!(results[2].rows.len(), 2);
+        assert!(state.database().unwrap().is_autocommit());
+    }
 
-        if let Ok(follower_state) = WorkerState::new() {
-            assert!(
-                !*follower_state.is_leader.borrow(),
-                "Should start as follower"
-            );
+    #[wasm_bindgen_test]
+    async fn test_execute_many_partial_failure() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple(
+                "CREATE TABLE IF NOT EXISTS batch_partial (id INTEGER)".to_string(),
+            )
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM batch_partial".to_string())
+            .await
+            .expect("Delete failed");
+        let failing_batch = || {
+            vec![
+                "INSERT INTO batch_partial VALUES (1)".to_string(),
+                "SELECT * FROM batch_missing_table".to_string(),
+                "INSERT INTO batch_partial VALUES (2)".to_string(),
+            ]
+        };
 
-            let result = follower_state.execute_query("SELECT 1".to_string()).await;
-            match result {
-                Err(msg) => assert!(
-                    msg.contains("timeout") || msg.contains("Query timeout"),
-                    "Follower should timeout, got: {}",
-                    msg
-                ),
-                Ok(_) => panic!("Expected timeout error for follower"),
-            }
-        }
+        let result = state.execute_many(failing_batch()).await;
+        let Err(WorkerError::PartialBatchFailure(results)) = result else {
+            panic!("Expected partial batch failure, got: {result:?}");
+        };
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(WorkerError::SqliteError { .. })));
+        assert!(results[2].is_ok());
+        assert_eq!(count_rows(&state, "batch_partial").await, 0);
+        assert!(state.database().unwrap().is_autocommit());
+
+        // Inside a transaction only the batch is undone
+        let state_ref = &state;
+        let outcome = state
+            .run_in_transaction(|| {
+                Box::pin(async move {
+                    state_ref
+                        .execute_query_simple("INSERT INTO batch_partial VALUES (3)".to_string())
+                        .await?;
+                    assert!(state_ref.execute_many(failing_batch()).await.is_err());
+                    assert!(!state_ref.database().unwrap().is_autocommit());
+                    Ok(())
+                })
+            })
+            .await;
+        assert_eq!(outcome, Ok(()));
+        assert_eq!(count_rows(&state, "batch_partial").await, 1);
     }
 
     #[wasm_bindgen_test]
-    fn test_setup_channel_listener() {
+    async fn test_execute_many_failed_commit() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        // Has no effect inside a transaction, so not part of the batch
+        state
+            .execute_query_simple("PRAGMA foreign_keys = ON".to_string())
+            .await
+            .unwrap();
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS batch_child".to_string(),
+                "DROP TABLE IF EXISTS batch_parent".to_string(),
+                "CREATE TABLE batch_parent (id INTEGER PRIMARY KEY)".to_string(),
+                "CREATE TABLE batch_child (parent_id INTEGER REFERENCES batch_parent(id) \
+                 DEFERRABLE INITIALLY DEFERRED)"
+                    .to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+
+        // The deferred foreign key is only checked, and fails, at COMMIT
+        let result = state
+            .execute_many(vec![
+                "INSERT INTO batch_child VALUES (1)".to_string(),
+                "SELECT 1".to_string(),
+            ])
+            .await;
+        let Err(WorkerError::PartialBatchFailure(results)) = result else {
+            panic!("Expected the commit to fail, got: {result:?}");
+        };
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(WorkerError::ConstraintViolation { .. }))));
+        assert!(state.database().unwrap().is_autocommit());
+
+        let count = state
+            .execute_parameterized("SELECT COUNT(*) FROM batch_child".to_string(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(count.get_single::<(i64,)>().unwrap(), (0,));
+        state
+            .execute_query_simple("PRAGMA foreign_keys = OFF".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_builder_configures_max_snapshots() {
+        if let Ok(state) = WorkerState::builder()
+            .max_snapshots(2)
+            .max_snapshot_bytes(4096)
+            .build()
+        {
+            assert_eq!(state.config.max_snapshots, 2);
+            assert_eq!(state.config.max_snapshot_bytes, 4096);
+            assert!(state.snapshots.borrow().is_empty());
+        }
         if let Ok(state) = WorkerState::new() {
-            state.setup_channel_listener();
+            assert_eq!(state.config.max_snapshots, DEFAULT_MAX_SNAPSHOTS);
+            assert_eq!(state.config.max_snapshot_bytes, DEFAULT_MAX_SNAPSHOT_BYTES);
         }
     }
 
     #[wasm_bindgen_test]
-    async fn test_attempt_leadership_behavior() {
-        if let Ok(state) = WorkerState::new() {
-            assert!(!*state.is_leader.borrow(), "Should start as follower");
-            assert!(
-                state.db.borrow().is_none(),
-                "Database should be uninitialized"
-            );
+    async fn test_snapshot_lifecycle() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_query_simple(
+                "CREATE TABLE IF NOT EXISTS snapshot_test (id INTEGER)".to_string(),
+            )
+            .await
+            .expect("Create failed");
 
-            state.attempt_leadership().await;
-        }
+        let snapshot_id = state.begin_snapshot().await.expect("Should begin snapshot");
+        assert_eq!(state.snapshots.borrow().len(), 1);
 
-        let workers: Vec<_> = (0..3).filter_map(|_| WorkerState::new().ok()).collect();
-        if workers.len() >= 2 {
-            for worker in &workers {
-                assert!(!*worker.is_leader.borrow(), "All should start as followers");
-            }
+        let result = state
+            .query_snapshot(
+                &snapshot_id,
+                "SELECT COUNT(*) as count FROM snapshot_test".to_string(),
+            )
+            .await;
+        assert!(result.is_ok(), "Snapshot query should succeed: {result:?}");
 
-            for worker in &workers {
-                worker.attempt_leadership().await;
-            }
-        }
+        state
+            .end_snapshot(&snapshot_id)
+            .await
+            .expect("Should end snapshot");
+        assert!(state.snapshots.borrow().is_empty());
+
+        let after_end = state
+            .query_snapshot(&snapshot_id, "SELECT 1".to_string())
+            .await;
+        assert_eq!(after_end, Err(WorkerError::SnapshotNotFound(snapshot_id)));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_snapshot_limit() {
+        let Some(mut state) = leader_with_db().await else {
+            return;
+        };
+        state.config.max_snapshots = 1;
+
+        let first = state.begin_snapshot().await.expect("Should begin snapshot");
+        let second = state.begin_snapshot().await;
+        assert_eq!(second, Err(WorkerError::SnapshotLimitReached(1)));
+
+        state
+            .end_snapshot(&first)
+            .await
+            .expect("Should end snapshot");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_snapshot_memory_limit() {
+        let Some(mut state) = leader_with_db().await else {
+            return;
+        };
+        let size = database_size(&state.database().unwrap()).await.unwrap();
+        assert!(size > 0);
+        // Room for one copy of the database but not two
+        state.config.max_snapshot_bytes = size * 2 - 1;
+
+        let first = state.begin_snapshot().await.expect("Should begin snapshot");
+        assert_eq!(
+            state.begin_read_transaction().await.map(|_| ()),
+            Err(WorkerError::SnapshotMemoryExceeded {
+                size,
+                held: size,
+                limit: size * 2 - 1
+            })
+        );
+        assert_eq!(state.snapshots.borrow().len(), 1);
+
+        state
+            .end_snapshot(&first)
+            .await
+            .expect("Should end snapshot");
+        let second = state
+            .begin_snapshot()
+            .await
+            .expect("The memory is free again");
+        state
+            .end_snapshot(&second)
+            .await
+            .expect("Should end snapshot");
     }
 
     #[wasm_bindgen_test]
@@ -451,7 +6321,7 @@ mod tests {
                 let reject = Function::new_no_args("return 'rejected';");
                 pending_clone
                     .borrow_mut()
-                    .insert("test-ref".to_string(), PendingQuery { resolve, reject });
+                    .insert("test-ref".to_string(), PendingQuery::new(resolve, reject));
             }
             assert_eq!(
                 state.pending_queries.borrow().len(),
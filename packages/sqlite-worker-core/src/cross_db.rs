@@ -0,0 +1,240 @@
+use std::rc::Rc;
+use uuid::Uuid;
+
+use crate::coordination::WorkerState;
+use crate::database::{SQLiteDatabase, VFS_PREFIX};
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::QueryResult;
+
+// Schemas every connection already has
+const RESERVED_SCHEMAS: [&str; 2] = ["main", "temp"];
+
+// OPFS file attached under `schema`
+fn schema_file(schema: &str) -> String {
+    format!("{VFS_PREFIX}{schema}.db")
+}
+
+fn validate_schema(schema: &str) -> Result<(), WorkerError> {
+    validate_identifier(schema)?;
+    if RESERVED_SCHEMAS.contains(&schema.to_ascii_lowercase().as_str()) {
+        return Err(WorkerError::InvalidIdentifier(schema.to_string()));
+    }
+    Ok(())
+}
+
+async fn detach_all(database: &SQLiteDatabase, schemas: &[String]) {
+    for schema in schemas {
+        let _ = database.exec(&format!("DETACH DATABASE {schema}")).await;
+    }
+}
+
+impl WorkerState {
+    /// Attach the OPFS database `<schema>.db` under each schema name in
+    /// `databases`, creating missing files, and begin a transaction spanning
+    /// them and `main`. Returns the transaction's id for `execute_cross_db`,
+    /// `commit_cross_db` and `rollback_cross_db`. Leader only, and fails with
+    /// `TransactionActive` if a transaction is already open. Requests from
+    /// other workers are queued until the transaction ends.
+    pub async fn begin_cross_db_transaction(
+        &self,
+        databases: &[&str],
+    ) -> Result<String, WorkerError> {
+        for schema in databases {
+            validate_schema(schema)?;
+        }
+        let database = self.cross_db_connection()?;
+        if self.active_transaction.borrow().is_some() || !database.is_autocommit() {
+            return Err(WorkerError::TransactionActive);
+        }
+
+        let mut attached = Vec::with_capacity(databases.len());
+        for schema in databases {
+            let sql = format!("ATTACH DATABASE '{}' AS {schema}", schema_file(schema));
+            if let Err(err) = database.exec(&sql).await {
+                detach_all(&database, &attached).await;
+                return Err(err.into());
+            }
+            attached.push(schema.to_string());
+        }
+        if let Err(err) = database.exec("BEGIN").await {
+            detach_all(&database, &attached).await;
+            return Err(err.into());
+        }
+
+        let tx_id = Uuid::new_v4().to_string();
+        *self.active_transaction.borrow_mut() = Some(tx_id.clone());
+        *self.cross_db_schemas.borrow_mut() = attached;
+        Ok(tx_id)
+    }
+
+    /// Run `sql`, which may name any attached schema, inside the transaction
+    /// `tx_id`
+    pub async fn execute_cross_db(
+        &self,
+        tx_id: &str,
+        sql: String,
+    ) -> Result<QueryResult, WorkerError> {
+        let database = self.cross_db_transaction(tx_id)?;
+        Ok(database.query(&sql).await?)
+    }
+
+    /// Commit the transaction `tx_id` and detach its databases. If the commit
+    /// fails the transaction stays open for `rollback_cross_db`.
+    pub async fn commit_cross_db(&self, tx_id: &str) -> Result<(), WorkerError> {
+        let database = self.cross_db_transaction(tx_id)?;
+        database.exec("COMMIT").await?;
+        self.end_cross_db(&database).await;
+        Ok(())
+    }
+
+    /// Roll back the transaction `tx_id` and detach its databases
+    pub async fn rollback_cross_db(&self, tx_id: &str) -> Result<(), WorkerError> {
+        let database = self.cross_db_transaction(tx_id)?;
+        let rolled_back = database.exec("ROLLBACK").await;
+        self.end_cross_db(&database).await;
+        rolled_back.map(|_| ()).map_err(WorkerError::from)
+    }
+
+    fn cross_db_connection(&self) -> Result<Rc<SQLiteDatabase>, WorkerError> {
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        self.db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)
+    }
+
+    // The connection, if `tx_id` is the open cross-database transaction
+    fn cross_db_transaction(&self, tx_id: &str) -> Result<Rc<SQLiteDatabase>, WorkerError> {
+        let database = self.cross_db_connection()?;
+        let is_open = self.active_transaction.borrow().as_deref() == Some(tx_id)
+            && !self.cross_db_schemas.borrow().is_empty();
+        if !is_open {
+            return Err(WorkerError::TransactionNotFound(tx_id.to_string()));
+        }
+        Ok(database)
+    }
+
+    async fn end_cross_db(&self, database: &SQLiteDatabase) {
+        let schemas = std::mem::take(&mut *self.cross_db_schemas.borrow_mut());
+        self.active_transaction.borrow_mut().take();
+        detach_all(database, &schemas).await;
+        self.queued_request_flusher()();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    async fn count(state: &WorkerState, tx_id: &str, table: &str) -> i64 {
+        let (count,): (i64,) = state
+            .execute_cross_db(tx_id, format!("SELECT COUNT(*) FROM {table}"))
+            .await
+            .expect("Count failed")
+            .get_single()
+            .expect("Single row");
+        count
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_move_row_between_databases() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for sql in [
+            "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, name TEXT)",
+            "DELETE FROM users",
+            "INSERT INTO users VALUES (1, 'alice'), (2, 'bob')",
+        ] {
+            state
+                .execute_query_simple(sql.to_string())
+                .await
+                .expect("Setup failed");
+        }
+
+        let tx_id = state
+            .begin_cross_db_transaction(&["aux"])
+            .await
+            .expect("Begin failed");
+        for sql in [
+            "CREATE TABLE IF NOT EXISTS aux.archived_users (id INTEGER PRIMARY KEY, name TEXT)",
+            "DELETE FROM aux.archived_users",
+            "INSERT INTO aux.archived_users SELECT * FROM main.users WHERE id = 1",
+            "DELETE FROM main.users WHERE id = 1",
+        ] {
+            state
+                .execute_cross_db(&tx_id, sql.to_string())
+                .await
+                .expect("Statement failed");
+        }
+        state.commit_cross_db(&tx_id).await.expect("Commit failed");
+        assert!(state.active_transaction.borrow().is_none());
+        // Detached again after the commit
+        assert!(state
+            .execute_query_simple("SELECT * FROM aux.archived_users".to_string())
+            .await
+            .is_err());
+
+        let tx_id = state
+            .begin_cross_db_transaction(&["aux"])
+            .await
+            .expect("Begin failed");
+        assert_eq!(count(&state, &tx_id, "main.users").await, 1);
+        assert_eq!(count(&state, &tx_id, "aux.archived_users").await, 1);
+        state
+            .execute_cross_db(&tx_id, "DROP TABLE aux.archived_users".to_string())
+            .await
+            .expect("Drop failed");
+        state.commit_cross_db(&tx_id).await.expect("Commit failed");
+        let _ = state
+            .execute_query_simple("DROP TABLE users".to_string())
+            .await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_cross_db_transaction_checks() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        assert!(matches!(
+            state.begin_cross_db_transaction(&["main"]).await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+
+        let tx_id = state
+            .begin_cross_db_transaction(&["aux"])
+            .await
+            .expect("Begin failed");
+        assert_eq!(
+            state.begin_cross_db_transaction(&["aux"]).await,
+            Err(WorkerError::TransactionActive)
+        );
+        assert_eq!(
+            state.commit_cross_db("other").await,
+            Err(WorkerError::TransactionNotFound("other".to_string()))
+        );
+        state
+            .rollback_cross_db(&tx_id)
+            .await
+            .expect("Rollback failed");
+        assert_eq!(
+            state.execute_cross_db(&tx_id, "SELECT 1".to_string()).await,
+            Err(WorkerError::TransactionNotFound(tx_id))
+        );
+
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            follower.begin_cross_db_transaction(&["aux"]).await,
+            Err(WorkerError::NotLeader)
+        );
+    }
+}
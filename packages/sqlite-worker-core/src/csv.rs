@@ -0,0 +1,406 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_builder::sql_literal;
+use crate::query_result::{QueryResult, SqlParam, SqlValue};
+
+// RFC 4180 ends every record with CRLF
+const RECORD_END: &str = "\r\n";
+
+/// Format `result` as RFC 4180 CSV, optionally starting with a header record
+/// of column names. NULL becomes an empty field and blobs are written as
+/// lowercase hex.
+pub fn to_csv(result: &QueryResult, include_headers: bool) -> String {
+    let mut csv = String::new();
+    if include_headers {
+        let headers: Vec<String> = result.columns.iter().map(|name| csv_field(name)).collect();
+        csv.push_str(&headers.join(","));
+        csv.push_str(RECORD_END);
+    }
+    for row in &result.rows {
+        let fields: Vec<String> = row.iter().map(csv_value).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str(RECORD_END);
+    }
+    csv
+}
+
+fn csv_value(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null => String::new(),
+        SqlValue::Integer(val) => val.to_string(),
+        SqlValue::Real(val) => val.to_string(),
+        SqlValue::Text(text) => csv_field(text),
+        SqlValue::Blob(bytes) => bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+    }
+}
+
+// Quote fields containing a separator, quote or line break, doubling quotes
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// A parsed CSV field; `None` for an empty field written without quotes,
+/// which `import_from_csv` stores as NULL to match `to_csv`
+pub type CsvField = Option<String>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum CsvState {
+    // Before the first character of a field
+    FieldStart,
+    Unquoted,
+    Quoted,
+    // Just after a quote inside a quoted field, which either closes the field
+    // or escapes a second quote
+    QuoteInQuoted,
+}
+
+// Builds records from the parser's output, checking they are all as wide as
+// the first
+struct CsvRecords {
+    records: Vec<Vec<CsvField>>,
+    record: Vec<CsvField>,
+    field: String,
+    // Line the current record started on, and the line being read
+    record_line: usize,
+    line: usize,
+}
+
+impl CsvRecords {
+    fn end_field(&mut self, quoted: bool) {
+        let field = std::mem::take(&mut self.field);
+        self.record
+            .push((quoted || !field.is_empty()).then_some(field));
+    }
+
+    fn end_record(&mut self) -> Result<(), WorkerError> {
+        let record = std::mem::take(&mut self.record);
+        if let Some(expected) = self.records.first().map(Vec::len) {
+            if record.len() != expected {
+                return Err(invalid_csv(
+                    self.record_line,
+                    format!("expected {expected} fields, found {}", record.len()),
+                ));
+            }
+        }
+        self.records.push(record);
+        Ok(())
+    }
+
+    fn end_line(&mut self) {
+        self.line += 1;
+        self.record_line = self.line;
+    }
+}
+
+fn invalid_csv(line: usize, reason: String) -> WorkerError {
+    WorkerError::InvalidCsv { line, reason }
+}
+
+/// Parse RFC 4180 CSV into records of fields. Quoted fields may hold
+/// separators, doubled quotes and line breaks; records end with CRLF or LF and
+/// blank lines are skipped. Fails with `InvalidCsv` for stray or unterminated
+/// quotes and records of differing widths.
+pub fn parse_csv(csv: &str) -> Result<Vec<Vec<CsvField>>, WorkerError> {
+    let mut parsed = CsvRecords {
+        records: Vec::new(),
+        record: Vec::new(),
+        field: String::new(),
+        record_line: 1,
+        line: 1,
+    };
+    let mut state = CsvState::FieldStart;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        // Outside quotes a CRLF pair is one line break
+        let line_break = c == '\n' || c == '\r';
+        if c == '\r' && state != CsvState::Quoted && chars.peek() == Some(&'\n') {
+            chars.next();
+        }
+
+        state = match (state, c) {
+            (CsvState::Quoted, '"') => CsvState::QuoteInQuoted,
+            (CsvState::Quoted, _) => {
+                if c == '\n' {
+                    parsed.line += 1;
+                }
+                parsed.field.push(c);
+                CsvState::Quoted
+            }
+            (CsvState::QuoteInQuoted, '"') => {
+                parsed.field.push('"');
+                CsvState::Quoted
+            }
+            (CsvState::QuoteInQuoted | CsvState::Unquoted | CsvState::FieldStart, ',') => {
+                parsed.end_field(state == CsvState::QuoteInQuoted);
+                CsvState::FieldStart
+            }
+            (_, _) if line_break => {
+                // A line break at the start of a record is a blank line
+                if state != CsvState::FieldStart || !parsed.record.is_empty() {
+                    parsed.end_field(state == CsvState::QuoteInQuoted);
+                    parsed.end_record()?;
+                }
+                parsed.end_line();
+                CsvState::FieldStart
+            }
+            (CsvState::QuoteInQuoted, _) => {
+                return Err(invalid_csv(
+                    parsed.line,
+                    format!("unexpected {c:?} after a closing quote"),
+                ));
+            }
+            (CsvState::FieldStart, '"') => CsvState::Quoted,
+            (CsvState::Unquoted, '"') => {
+                return Err(invalid_csv(
+                    parsed.line,
+                    "quote inside an unquoted field".to_string(),
+                ));
+            }
+            (CsvState::FieldStart | CsvState::Unquoted, _) => {
+                parsed.field.push(c);
+                CsvState::Unquoted
+            }
+        };
+    }
+
+    match state {
+        CsvState::Quoted => {
+            return Err(invalid_csv(
+                parsed.record_line,
+                "unterminated quoted field".to_string(),
+            ));
+        }
+        CsvState::FieldStart if parsed.record.is_empty() => {}
+        _ => {
+            parsed.end_field(state == CsvState::QuoteInQuoted);
+            parsed.end_record()?;
+        }
+    }
+    Ok(parsed.records)
+}
+
+impl WorkerState {
+    /// Every row of `table` as RFC 4180 CSV; see `to_csv`
+    pub async fn export_to_csv(
+        &self,
+        table: String,
+        include_headers: bool,
+    ) -> Result<String, WorkerError> {
+        validate_identifier(&table)?;
+        let result = self
+            .execute_parameterized(format!("SELECT * FROM {table}"), Vec::new())
+            .await?;
+        Ok(to_csv(&result, include_headers))
+    }
+
+    /// Insert the records of `csv` into `table` in one transaction and return
+    /// how many were inserted. The first record names the columns; with
+    /// `create_if_absent` a missing table is created from them, all typed
+    /// `TEXT`. Values are inserted as text, and empty unquoted fields as NULL.
+    pub async fn import_from_csv(
+        &self,
+        table: &str,
+        csv: &str,
+        create_if_absent: bool,
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(table)?;
+        let mut records = parse_csv(csv)?.into_iter();
+        let columns: Vec<String> = records
+            .next()
+            .ok_or_else(|| invalid_csv(1, "missing header row".to_string()))?
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect();
+        for column in &columns {
+            validate_identifier(column)?;
+        }
+
+        let mut statements = Vec::new();
+        if create_if_absent {
+            let definitions: Vec<String> = columns
+                .iter()
+                .map(|column| format!("{column} TEXT"))
+                .collect();
+            statements.push(format!(
+                "CREATE TABLE IF NOT EXISTS {table} ({})",
+                definitions.join(", ")
+            ));
+        }
+        // One statement, so a failing row leaves none of them inserted
+        let rows: Vec<String> = records
+            .map(|record| {
+                let values: Vec<String> = record
+                    .into_iter()
+                    .map(|field| sql_literal(&field.map_or(SqlParam::Null, SqlParam::Text)))
+                    .collect();
+                format!("({})", values.join(", "))
+            })
+            .collect();
+        if !rows.is_empty() {
+            statements.push(format!(
+                "INSERT INTO {table} ({}) VALUES {}",
+                columns.join(", "),
+                rows.join(", ")
+            ));
+        }
+
+        if !statements.is_empty() {
+            self.execute_many(statements).await?;
+        }
+        Ok(rows.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_csv_quoting() {
+        let result = QueryResult {
+            columns: vec!["id".to_string(), "note, text".to_string()],
+            rows: vec![
+                vec![SqlValue::Integer(1), SqlValue::Text("plain".to_string())],
+                vec![
+                    SqlValue::Real(2.5),
+                    SqlValue::Text("say \"hi\", then\nleave".to_string()),
+                ],
+                vec![SqlValue::Null, SqlValue::Blob(vec![0x0a, 0xff])],
+            ],
+            rows_affected: 0,
+            last_insert_rowid: 0,
+        };
+        assert_eq!(
+            to_csv(&result, true),
+            "id,\"note, text\"\r\n\
+             1,plain\r\n\
+             2.5,\"say \"\"hi\"\", then\nleave\"\r\n\
+             ,0aff\r\n"
+        );
+        assert!(to_csv(&result, false).starts_with("1,plain\r\n"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parse_csv() {
+        let records = parse_csv(
+            "name,quote\r\n\
+             \"Smith, Jane\",\"She said \"\"hi\"\"\"\r\n\
+             \n\
+             plain,\"two\nlines\"\n\
+             ,\"\"",
+        )
+        .expect("Should parse");
+        let text = |value: &str| Some(value.to_string());
+        assert_eq!(
+            records,
+            vec![
+                vec![text("name"), text("quote")],
+                vec![text("Smith, Jane"), text("She said \"hi\"")],
+                vec![text("plain"), text("two\nlines")],
+                vec![None, text("")],
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parse_malformed_csv() {
+        let reason = |csv: &str| match parse_csv(csv) {
+            Err(WorkerError::InvalidCsv { line, reason }) => (line, reason),
+            other => panic!("Expected InvalidCsv, got {other:?}"),
+        };
+        assert_eq!(
+            reason("a,b\n\"open,1"),
+            (2, "unterminated quoted field".to_string())
+        );
+        assert_eq!(
+            reason("a,b\n\"x\"y,1"),
+            (2, "unexpected 'y' after a closing quote".to_string())
+        );
+        assert_eq!(
+            reason("a,b\n1,2\nx\"y,3"),
+            (3, "quote inside an unquoted field".to_string())
+        );
+        assert_eq!(
+            reason("a,b\n1,2,3"),
+            (2, "expected 2 fields, found 3".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_import_from_csv() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let _ = state
+            .execute_query_simple("DROP TABLE IF EXISTS csv_import".to_string())
+            .await;
+
+        let csv = "name,note\r\n\"Smith, Jane\",\"said \"\"hi\"\"\"\r\nBob,\r\n";
+        assert_eq!(state.import_from_csv("csv_import", csv, true).await, Ok(2));
+        assert_eq!(
+            state
+                .export_to_csv("csv_import".to_string(), true)
+                .await
+                .expect("Export failed"),
+            csv
+        );
+
+        let missing = state.import_from_csv("csv_missing", csv, false).await;
+        assert!(missing.is_err());
+        assert_eq!(
+            state
+                .import_from_csv("csv_import", "name,note\n\"broken", true)
+                .await,
+            Err(WorkerError::InvalidCsv {
+                line: 2,
+                reason: "unterminated quoted field".to_string()
+            })
+        );
+
+        let _ = state
+            .execute_query_simple("DROP TABLE csv_import".to_string())
+            .await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_export_table_to_csv() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        for sql in [
+            "DROP TABLE IF EXISTS csv_export",
+            "CREATE TABLE csv_export (id INTEGER, quote TEXT)",
+            "INSERT INTO csv_export VALUES (1, 'She said \"no\"'), (2, NULL)",
+        ] {
+            state
+                .execute_query_simple(sql.to_string())
+                .await
+                .expect("Setup failed");
+        }
+
+        let csv = state
+            .export_to_csv("csv_export".to_string(), true)
+            .await
+            .expect("Export failed");
+        assert_eq!(csv, "id,quote\r\n1,\"She said \"\"no\"\"\"\r\n2,\r\n");
+        assert!(state
+            .export_to_csv("csv_export; DROP".to_string(), false)
+            .await
+            .is_err());
+
+        let _ = state
+            .execute_query_simple("DROP TABLE csv_export".to_string())
+            .await;
+    }
+}
@@ -1,11 +1,46 @@
-use crate::database_functions::register_custom_functions;
+use crate::authorizer::{authorizer_callback, Authorizer};
+use crate::database_functions::{register_custom_functions, register_js_function};
+use crate::error::SqliteError;
+use crate::query_result::{QueryResult, SqlParam, SqlValue};
+use crate::query_stats::QueryStats;
+use crate::vtab::{register_module, VTabImpl};
 use sqlite_wasm_rs::export::{install_opfs_sahpool, *};
-use std::ffi::{CStr, CString};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_uint, c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+pub(crate) const VFS_PREFIX: &str = "opfs-sahpool:";
+// File name of the shared database within the OPFS pool
+pub const DB_FILE_NAME: &str = "worker.db";
+
+// Every SQLite database file starts with this, followed by the rest of its
+// 100-byte header
+pub const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const SQLITE_HEADER_BYTES: usize = 100;
+
+/// Whether `bytes` could be a SQLite database file, judging by its header
+pub fn has_sqlite_header(bytes: &[u8]) -> bool {
+    bytes.len() >= SQLITE_HEADER_BYTES && bytes.starts_with(SQLITE_MAGIC)
+}
+
+/// Called periodically while a statement runs; returning true interrupts it
+pub type ProgressHandler = Box<dyn Fn() -> bool>;
+
 // Real SQLite database using sqlite-wasm-rs FFI
 pub struct SQLiteDatabase {
     db: *mut sqlite3,
+    authorizer: RefCell<Option<Box<Authorizer>>>,
+    progress_handler: RefCell<Option<Box<ProgressHandler>>>,
+    // Statements kept by `prepare_statement`, by id
+    statements: RefCell<HashMap<String, *mut sqlite3_stmt>>,
+}
+
+unsafe extern "C" fn progress_handler_callback(user_data: *mut c_void) -> c_int {
+    let handler = &*(user_data as *const ProgressHandler);
+    handler() as c_int
 }
 
 unsafe impl Send for SQLiteDatabase {}
@@ -18,17 +53,89 @@ impl SQLiteDatabase {
             .await
             .map_err(|e| JsValue::from_str(&format!("Failed to install OPFS VFS: {e:?}")))?;
 
+        Self::open(DB_FILE_NAME, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+    }
+
+    /// Replace the OPFS database file with `bytes` and open it, installing the
+    /// VFS if needed. `bytes` must be a complete database; see `has_sqlite_header`.
+    pub async fn import_opfs(bytes: &[u8]) -> Result<Self, JsValue> {
+        let pool = install_opfs_sahpool(None, true)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to install OPFS VFS: {e:?}")))?;
+        pool.import_db(&format!("{VFS_PREFIX}{DB_FILE_NAME}"), bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to import database: {e:?}")))?;
+
+        Self::open(DB_FILE_NAME, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+    }
+
+    /// Delete the OPFS database file, installing the VFS if needed. Every
+    /// connection to it must be closed first.
+    pub async fn delete_opfs() -> Result<(), JsValue> {
+        let pool = install_opfs_sahpool(None, true)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to install OPFS VFS: {e:?}")))?;
+        pool.delete_db(&format!("{VFS_PREFIX}{DB_FILE_NAME}"))
+            .map_err(|e| JsValue::from_str(&format!("Failed to delete database: {e:?}")))?;
+        Ok(())
+    }
+
+    /// Open another database file in the OPFS pool, creating it if missing.
+    /// The VFS must already be installed by `initialize_opfs`.
+    pub fn open_file(file_name: &str) -> Result<Self, JsValue> {
+        Self::open(file_name, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+    }
+
+    /// Open an additional read-only connection to the OPFS database.
+    /// The VFS must already be installed by `initialize_opfs`.
+    pub fn open_read_only() -> Result<Self, JsValue> {
+        Self::open(DB_FILE_NAME, SQLITE_OPEN_READONLY)
+    }
+
+    /// Open a copy of the database file `bytes` in memory, leaving OPFS alone.
+    /// Changes to it are lost when it is closed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SqliteError> {
+        let database = Self::open_path(":memory:", SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+            .map_err(|e| SqliteError {
+                code: SQLITE_CANTOPEN,
+                extended_code: SQLITE_CANTOPEN,
+                message: e.as_string().unwrap_or_default(),
+            })?;
+        let size = bytes.len() as sqlite3_int64;
+        // SQLite takes ownership of the buffer and frees it on close
+        let buffer = unsafe { sqlite3_malloc64(bytes.len().max(1) as u64) } as *mut u8;
+        if buffer.is_null() {
+            return Err(unsafe { database.last_error(SQLITE_NOMEM, "Failed to load database") });
+        }
+        let main = CString::new("main").unwrap();
+        let ret = unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+            sqlite3_deserialize(
+                database.db,
+                main.as_ptr(),
+                buffer,
+                size,
+                size,
+                (SQLITE_DESERIALIZE_FREEONCLOSE | SQLITE_DESERIALIZE_RESIZEABLE) as c_uint,
+            )
+        };
+        if ret != SQLITE_OK {
+            return Err(unsafe { database.last_error(ret, "Failed to load database") });
+        }
+        Ok(database)
+    }
+
+    fn open(file_name: &str, flags: c_int) -> Result<Self, JsValue> {
         // Open database with OPFS
+        Self::open_path(&format!("{VFS_PREFIX}{file_name}"), flags)
+    }
+
+    fn open_path(path: &str, flags: c_int) -> Result<Self, JsValue> {
         let mut db = std::ptr::null_mut();
-        let db_name = CString::new("opfs-sahpool:worker.db").unwrap();
+        let db_name = CString::new(path)
+            .map_err(|_| JsValue::from_str("Database name contains a nul byte"))?;
 
         let ret = unsafe {
-            sqlite3_open_v2(
-                db_name.as_ptr(),
-                &mut db as *mut _,
-                SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
-                std::ptr::null(),
-            )
+            sqlite3_open_v2(db_name.as_ptr(), &mut db as *mut _, flags, std::ptr::null())
         };
 
         if ret != SQLITE_OK {
@@ -48,14 +155,106 @@ impl SQLiteDatabase {
         // Register custom functions
         register_custom_functions(db).map_err(|e| JsValue::from_str(&e))?;
 
-        Ok(SQLiteDatabase { db })
+        Ok(SQLiteDatabase {
+            db,
+            authorizer: RefCell::new(None),
+            progress_handler: RefCell::new(None),
+            statements: RefCell::new(HashMap::new()),
+        })
+    }
+
+    // The connection's last error, described as happening while doing `context`
+    unsafe fn last_error(&self, ret: c_int, context: &str) -> SqliteError {
+        let ptr = sqlite3_errmsg(self.db);
+        let error_msg = if !ptr.is_null() {
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        } else {
+            format!("SQLite error code: {ret}")
+        };
+        SqliteError {
+            code: ret & 0xff,
+            extended_code: sqlite3_extended_errcode(self.db),
+            message: format!("{context}: {error_msg}"),
+        }
+    }
+
+    /// Execute a statement and collect its result set
+    pub async fn query(&self, sql: &str) -> Result<QueryResult, SqliteError> {
+        self.query_with_params(sql, &[]).await
+    }
+
+    /// Execute a statement with `params` bound to its `?` placeholders in order
+    pub async fn query_with_params(
+        &self,
+        sql: &str,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, SqliteError> {
+        let stmt = self.prepare(sql)?;
+        let result = unsafe { self.run_statement(stmt, params) };
+        unsafe {
+            sqlite3_finalize(stmt);
+        }
+        result
+    }
+
+    /// Prepare `sql` once and keep it as `stmt_id` for `query_prepared`,
+    /// replacing any statement already kept under that id
+    pub fn prepare_statement(&self, stmt_id: &str, sql: &str) -> Result<(), SqliteError> {
+        let stmt = self.prepare(sql)?;
+        if let Some(previous) = self
+            .statements
+            .borrow_mut()
+            .insert(stmt_id.to_string(), stmt)
+        {
+            unsafe {
+                sqlite3_finalize(previous);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the statement kept as `stmt_id` with `params`, or `None` if there
+    /// is no such statement
+    pub fn query_prepared(
+        &self,
+        stmt_id: &str,
+        params: &[SqlParam],
+    ) -> Option<Result<QueryResult, SqliteError>> {
+        let stmt = *self.statements.borrow().get(stmt_id)?;
+        let result = unsafe { self.run_statement(stmt, params) };
+        unsafe {
+            sqlite3_reset(stmt);
+            sqlite3_clear_bindings(stmt);
+        }
+        Some(result)
+    }
+
+    /// Finalize the statement kept as `stmt_id`, returning whether there was one
+    pub fn finalize_statement(&self, stmt_id: &str) -> bool {
+        match self.statements.borrow_mut().remove(stmt_id) {
+            Some(stmt) => {
+                unsafe {
+                    sqlite3_finalize(stmt);
+                }
+                true
+            }
+            None => false,
+        }
     }
 
-    pub async fn exec(&self, sql: &str) -> Result<String, String> {
-        let sql_cstr = CString::new(sql).map_err(|e| format!("Invalid SQL string: {e}"))?;
+    /// Number of statements kept by `prepare_statement`
+    pub fn prepared_statement_count(&self) -> usize {
+        self.statements.borrow().len()
+    }
+
+    fn prepare(&self, sql: &str) -> Result<*mut sqlite3_stmt, SqliteError> {
+        let sql_cstr = CString::new(sql).map_err(|e| SqliteError {
+            code: SQLITE_MISUSE,
+            extended_code: SQLITE_MISUSE,
+            message: format!("Invalid SQL string: {e}"),
+        })?;
         let mut stmt = std::ptr::null_mut();
 
-        // Prepare statement
         let ret = unsafe {
             sqlite3_prepare_v2(
                 self.db,
@@ -67,116 +266,84 @@ impl SQLiteDatabase {
         };
 
         if ret != SQLITE_OK {
-            let error_msg = unsafe {
-                let ptr = sqlite3_errmsg(self.db);
-                if !ptr.is_null() {
-                    CStr::from_ptr(ptr).to_string_lossy().into_owned()
-                } else {
-                    format!("SQLite error code: {ret}")
-                }
+            return Err(unsafe { self.last_error(ret, "Failed to prepare statement") });
+        }
+        Ok(stmt)
+    }
+
+    // Bind `params` to `stmt`, step it to completion and collect the rows,
+    // leaving the caller to reset or finalize it
+    unsafe fn run_statement(
+        &self,
+        stmt: *mut sqlite3_stmt,
+        params: &[SqlParam],
+    ) -> Result<QueryResult, SqliteError> {
+        // Bind parameters
+        for (i, param) in params.iter().enumerate() {
+            let ret = bind_param(stmt, i as c_int + 1, param);
+            if ret != SQLITE_OK {
+                return Err(self.last_error(ret, &format!("Failed to bind parameter {}", i + 1)));
+            }
+        }
+
+        // Get column names
+        let col_count = sqlite3_column_count(stmt);
+        let mut columns = Vec::with_capacity(col_count as usize);
+        for i in 0..col_count {
+            let ptr = sqlite3_column_name(stmt, i);
+            let col_name = if !ptr.is_null() {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            } else {
+                format!("column_{i}")
             };
-            return Err(format!("Failed to prepare statement: {error_msg}"));
+            columns.push(col_name);
         }
 
         // Execute and collect results
-        let mut results = Vec::new();
-        let mut column_names = Vec::new();
-        let mut first_row = true;
+        let mut rows = Vec::new();
 
         loop {
-            let step_result = unsafe { sqlite3_step(stmt) };
+            let step_result = sqlite3_step(stmt);
 
             match step_result {
                 SQLITE_ROW => {
-                    if first_row {
-                        // Get column names
-                        let col_count = unsafe { sqlite3_column_count(stmt) };
-                        for i in 0..col_count {
-                            let col_name = unsafe {
-                                let ptr = sqlite3_column_name(stmt, i);
-                                if !ptr.is_null() {
-                                    CStr::from_ptr(ptr).to_string_lossy().into_owned()
-                                } else {
-                                    format!("column_{i}")
-                                }
-                            };
-                            column_names.push(col_name);
-                        }
-                        first_row = false;
-                    }
-
-                    // Get row data
-                    let mut row_obj = std::collections::HashMap::new();
-                    let col_count = unsafe { sqlite3_column_count(stmt) };
-
-                    for i in 0..col_count {
-                        let col_type = unsafe { sqlite3_column_type(stmt, i) };
-                        let value = match col_type {
-                            SQLITE_INTEGER => {
-                                let val = unsafe { sqlite3_column_int64(stmt, i) };
-                                serde_json::Value::Number(serde_json::Number::from(val))
-                            }
-                            SQLITE_FLOAT => {
-                                let val = unsafe { sqlite3_column_double(stmt, i) };
-                                serde_json::Value::Number(
-                                    serde_json::Number::from_f64(val)
-                                        .unwrap_or(serde_json::Number::from(0)),
-                                )
-                            }
-                            SQLITE_TEXT => {
-                                let ptr = unsafe { sqlite3_column_text(stmt, i) };
-                                if !ptr.is_null() {
-                                    let text = unsafe {
-                                        CStr::from_ptr(ptr as *const i8)
-                                            .to_string_lossy()
-                                            .into_owned()
-                                    };
-                                    serde_json::Value::String(text)
-                                } else {
-                                    serde_json::Value::Null
-                                }
-                            }
-                            SQLITE_BLOB => {
-                                let len = unsafe { sqlite3_column_bytes(stmt, i) };
-                                serde_json::Value::String(format!("<blob {len} bytes>"))
-                            }
-                            _ => serde_json::Value::Null,
-                        };
-
-                        if let Some(col_name) = column_names.get(i as usize) {
-                            row_obj.insert(col_name.clone(), value);
-                        }
-                    }
-
-                    results.push(serde_json::Value::Object(row_obj.into_iter().collect()));
+                    let row: Vec<SqlValue> =
+                        (0..col_count).map(|i| column_value(stmt, i)).collect();
+                    rows.push(row);
                 }
                 SQLITE_DONE => break,
-                _ => {
-                    let error_msg = unsafe {
-                        let ptr = sqlite3_errmsg(self.db);
-                        if !ptr.is_null() {
-                            CStr::from_ptr(ptr).to_string_lossy().into_owned()
-                        } else {
-                            format!("SQLite error code: {step_result}")
-                        }
-                    };
-                    unsafe {
-                        sqlite3_finalize(stmt);
-                    }
-                    return Err(format!("Query execution failed: {error_msg}"));
-                }
+                _ => return Err(self.last_error(step_result, "Query execution failed")),
             }
         }
 
-        // Cleanup
-        unsafe {
-            sqlite3_finalize(stmt);
-        }
+        // Statements without a result set report the rows they changed
+        let (rows_affected, last_insert_rowid) = if col_count == 0 {
+            (
+                sqlite3_changes(self.db) as u64,
+                sqlite3_last_insert_rowid(self.db),
+            )
+        } else {
+            (0, 0)
+        };
+
+        Ok(QueryResult {
+            columns,
+            rows,
+            rows_affected,
+            last_insert_rowid,
+        })
+    }
+
+    pub async fn exec(&self, sql: &str) -> Result<String, SqliteError> {
+        let result = self.query(sql).await?;
 
         // Return results
-        if sql.trim().to_lowercase().starts_with("select") && !results.is_empty() {
-            serde_json::to_string_pretty(&results)
-                .map_err(|e| format!("JSON serialization error: {e}"))
+        if sql.trim().to_lowercase().starts_with("select") && !result.rows.is_empty() {
+            serde_json::to_string_pretty(&result.to_json_rows()).map_err(|e| SqliteError {
+                code: SQLITE_ERROR,
+                extended_code: SQLITE_ERROR,
+                message: format!("JSON serialization error: {e}"),
+            })
         } else if sql.trim().to_lowercase().starts_with("select") {
             Ok("[]".to_string())
         } else {
@@ -187,12 +354,273 @@ impl SQLiteDatabase {
             ))
         }
     }
+
+    /// Register `func` as a scalar SQL function on this connection
+    pub fn register_scalar_function(
+        &self,
+        name: &str,
+        arity: i32,
+        func: js_sys::Function,
+    ) -> Result<(), String> {
+        register_js_function(self.db, name, arity, func)
+    }
+
+    /// Register `module` as a virtual table module on this connection
+    pub fn register_virtual_table(
+        &self,
+        name: &str,
+        module: Rc<dyn VTabImpl>,
+    ) -> Result<(), String> {
+        register_module(self.db, name, module)
+    }
+
+    /// Have SQLite ask `authorizer` about each action while preparing
+    /// statements, replacing any earlier one; `None` removes it
+    pub fn set_authorizer(&self, authorizer: Option<Authorizer>) {
+        let authorizer = authorizer.map(Box::new);
+        unsafe {
+            match &authorizer {
+                Some(authorizer) => sqlite3_set_authorizer(
+                    self.db,
+                    Some(authorizer_callback),
+                    &**authorizer as *const Authorizer as *mut c_void,
+                ),
+                None => sqlite3_set_authorizer(self.db, None, std::ptr::null_mut()),
+            };
+        }
+        // The previous authorizer is only freed once SQLite no longer points at it
+        *self.authorizer.borrow_mut() = authorizer;
+    }
+
+    /// Call `handler` about every `interval` virtual machine instructions
+    /// while a statement runs, replacing any earlier handler; `None` removes
+    /// it. A statement whose handler returns true fails with
+    /// `SQLITE_INTERRUPT`.
+    pub fn set_progress_handler(&self, interval: u32, handler: Option<ProgressHandler>) {
+        let handler = handler.map(Box::new);
+        unsafe {
+            match &handler {
+                Some(handler) => sqlite3_progress_handler(
+                    self.db,
+                    interval.min(c_int::MAX as u32) as c_int,
+                    Some(progress_handler_callback),
+                    &**handler as *const ProgressHandler as *mut c_void,
+                ),
+                None => sqlite3_progress_handler(self.db, 0, None, std::ptr::null_mut()),
+            };
+        }
+        // The previous handler is only freed once SQLite no longer points at it
+        *self.progress_handler.borrow_mut() = handler;
+    }
+
+    /// Current memory and page cache counters. The cache hit and miss counts
+    /// are reset by each call.
+    pub fn query_stats(&self) -> QueryStats {
+        let global = |op| {
+            let (mut current, mut highwater) = (0, 0);
+            unsafe { sqlite3_status(op, &mut current, &mut highwater, 0) };
+            current
+        };
+        let connection = |op| {
+            let (mut current, mut highwater) = (0, 0);
+            unsafe { sqlite3_db_status(self.db, op, &mut current, &mut highwater, 1) };
+            current
+        };
+        QueryStats {
+            pagecache_used: global(SQLITE_STATUS_PAGECACHE_USED),
+            pagecache_overflow: global(SQLITE_STATUS_PAGECACHE_OVERFLOW),
+            malloc_used: global(SQLITE_STATUS_MEMORY_USED),
+            stmt_cache_hits: connection(SQLITE_DBSTATUS_CACHE_HIT),
+            stmt_cache_misses: connection(SQLITE_DBSTATUS_CACHE_MISS),
+            wal_checkpoint: None,
+        }
+    }
+
+    /// Whether the connection is outside an explicit transaction
+    pub fn is_autocommit(&self) -> bool {
+        unsafe { sqlite3_get_autocommit(self.db) != 0 }
+    }
+
+    /// The main database as the bytes of a database file
+    pub fn serialize(&self) -> Result<Vec<u8>, SqliteError> {
+        let main = CString::new("main").unwrap();
+        let mut size: sqlite3_int64 = 0;
+        let data = unsafe { sqlite3_serialize(self.db, main.as_ptr(), &mut size, 0) };
+        if data.is_null() {
+            return Err(unsafe { self.last_error(SQLITE_NOMEM, "Failed to serialize database") });
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(data, size as usize) }.to_vec();
+        unsafe { sqlite3_free(data as *mut c_void) };
+        Ok(bytes)
+    }
+
+    /// Start an online backup of this database into the OPFS file
+    /// `file_name`, replacing its contents. Other connections can keep reading
+    /// while it runs.
+    pub fn backup_to(self: &Rc<Self>, file_name: &str) -> Result<DatabaseBackup, SqliteError> {
+        let target = Self::open_file(file_name).map_err(|e| SqliteError {
+            code: SQLITE_CANTOPEN,
+            extended_code: SQLITE_CANTOPEN,
+            message: e.as_string().unwrap_or_default(),
+        })?;
+        self.backup_into(target)
+    }
+
+    /// Start an online backup of this database into a new in-memory
+    /// database, read back with `DatabaseBackup::target_bytes`
+    pub fn backup_to_memory(self: &Rc<Self>) -> Result<DatabaseBackup, SqliteError> {
+        let target = Self::open_path(":memory:", SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)
+            .map_err(|e| SqliteError {
+                code: SQLITE_CANTOPEN,
+                extended_code: SQLITE_CANTOPEN,
+                message: e.as_string().unwrap_or_default(),
+            })?;
+        self.backup_into(target)
+    }
+
+    fn backup_into(self: &Rc<Self>, target: SQLiteDatabase) -> Result<DatabaseBackup, SqliteError> {
+        let main = CString::new("main").unwrap();
+        let backup =
+            unsafe { sqlite3_backup_init(target.db, main.as_ptr(), self.db, main.as_ptr()) };
+        if backup.is_null() {
+            // A failed init leaves its error on the destination connection
+            return Err(unsafe {
+                target.last_error(sqlite3_errcode(target.db), "Failed to start backup")
+            });
+        }
+        Ok(DatabaseBackup {
+            backup,
+            target,
+            _source: Rc::clone(self),
+        })
+    }
+}
+
+/// An online backup in progress, finished when dropped
+pub struct DatabaseBackup {
+    backup: *mut sqlite3_backup,
+    // Both connections must stay open until the backup is finished
+    target: SQLiteDatabase,
+    _source: Rc<SQLiteDatabase>,
+}
+
+impl DatabaseBackup {
+    /// Copy up to `pages` pages, or every remaining page if negative.
+    /// Returns true once the whole database has been copied.
+    pub fn step(&self, pages: i32) -> Result<bool, SqliteError> {
+        match unsafe { sqlite3_backup_step(self.backup, pages) } {
+            SQLITE_DONE => Ok(true),
+            // The source was locked by another connection; try again later
+            SQLITE_OK | SQLITE_BUSY | SQLITE_LOCKED => Ok(false),
+            ret => Err(unsafe { self.target.last_error(ret, "Backup step failed") }),
+        }
+    }
+
+    /// Pages still to copy as of the last `step`
+    pub fn remaining(&self) -> i32 {
+        unsafe { sqlite3_backup_remaining(self.backup) }
+    }
+
+    /// Pages in the source database as of the last `step`
+    pub fn page_count(&self) -> i32 {
+        unsafe { sqlite3_backup_pagecount(self.backup) }
+    }
+
+    /// The copy as the bytes of a database file, complete once `step` has
+    /// returned true
+    pub fn target_bytes(&self) -> Result<Vec<u8>, SqliteError> {
+        self.target.serialize()
+    }
+
+    /// Release the backup, reporting any error from its last step
+    pub fn finish(mut self) -> Result<(), SqliteError> {
+        match self.release() {
+            SQLITE_OK => Ok(()),
+            ret => Err(unsafe { self.target.last_error(ret, "Backup failed") }),
+        }
+    }
+
+    fn release(&mut self) -> c_int {
+        if self.backup.is_null() {
+            return SQLITE_OK;
+        }
+        let ret = unsafe { sqlite3_backup_finish(self.backup) };
+        self.backup = std::ptr::null_mut();
+        ret
+    }
+}
+
+impl Drop for DatabaseBackup {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+// Bind a parameter to its 1-based placeholder index, letting SQLite copy the data
+unsafe fn bind_param(stmt: *mut sqlite3_stmt, index: c_int, param: &SqlParam) -> c_int {
+    let transient = Some(std::mem::transmute::<
+        isize,
+        unsafe extern "C" fn(*mut std::ffi::c_void),
+    >(-1isize)); // SQLITE_TRANSIENT
+
+    match param {
+        SqlParam::Null => sqlite3_bind_null(stmt, index),
+        SqlParam::Integer(val) => sqlite3_bind_int64(stmt, index, *val),
+        SqlParam::Real(val) => sqlite3_bind_double(stmt, index, *val),
+        SqlParam::Text(text) => sqlite3_bind_text(
+            stmt,
+            index,
+            text.as_ptr() as *const c_char,
+            text.len() as c_int,
+            transient,
+        ),
+        SqlParam::Blob(bytes) => sqlite3_bind_blob(
+            stmt,
+            index,
+            bytes.as_ptr() as *const std::ffi::c_void,
+            bytes.len() as c_int,
+            transient,
+        ),
+    }
+}
+
+// Read a single column of the current row
+unsafe fn column_value(stmt: *mut sqlite3_stmt, i: c_int) -> SqlValue {
+    match sqlite3_column_type(stmt, i) {
+        SQLITE_INTEGER => SqlValue::Integer(sqlite3_column_int64(stmt, i)),
+        SQLITE_FLOAT => SqlValue::Real(sqlite3_column_double(stmt, i)),
+        SQLITE_TEXT => {
+            let ptr = sqlite3_column_text(stmt, i);
+            if !ptr.is_null() {
+                let text = CStr::from_ptr(ptr as *const c_char)
+                    .to_string_lossy()
+                    .into_owned();
+                SqlValue::Text(text)
+            } else {
+                SqlValue::Null
+            }
+        }
+        SQLITE_BLOB => {
+            let ptr = sqlite3_column_blob(stmt, i);
+            let len = sqlite3_column_bytes(stmt, i);
+            if !ptr.is_null() && len > 0 {
+                SqlValue::Blob(std::slice::from_raw_parts(ptr as *const u8, len as usize).to_vec())
+            } else {
+                SqlValue::Blob(Vec::new())
+            }
+        }
+        _ => SqlValue::Null,
+    }
 }
 
 impl Drop for SQLiteDatabase {
     fn drop(&mut self) {
         if !self.db.is_null() {
             unsafe {
+                // The connection will not close while statements are open
+                for (_, stmt) in self.statements.get_mut().drain() {
+                    sqlite3_finalize(stmt);
+                }
                 sqlite3_close(self.db);
             }
         }
@@ -204,7 +632,7 @@ mod tests {
     use super::*;
     use wasm_bindgen_test::*;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
 
     #[wasm_bindgen_test]
     async fn test_opfs_initialization_success() {
@@ -590,13 +1018,43 @@ mod tests {
 
         let result = db.exec("INVALID SQL SYNTAX HERE").await;
         assert!(result.is_err());
-        let error = result.unwrap_err();
+        let error = result.unwrap_err().message;
         assert!(
             error.contains("Failed to prepare statement"),
             "Invalid SQL should produce prepare statement error"
         );
     }
 
+    #[wasm_bindgen_test]
+    async fn test_errors_carry_result_codes() {
+        let Some(db) = get_test_db().await else {
+            return;
+        };
+
+        let syntax = db.exec("INVALID SQL SYNTAX HERE").await.unwrap_err();
+        assert_eq!(syntax.code, SQLITE_ERROR);
+
+        db.exec("CREATE TABLE IF NOT EXISTS error_code_test (id INTEGER, name TEXT UNIQUE)")
+            .await
+            .expect("Create failed");
+        db.exec("DELETE FROM error_code_test")
+            .await
+            .expect("Delete failed");
+        db.exec("INSERT INTO error_code_test VALUES (1, 'a')")
+            .await
+            .expect("Insert failed");
+        let unique = db
+            .exec("INSERT INTO error_code_test VALUES (2, 'a')")
+            .await
+            .unwrap_err();
+        assert_eq!(unique.code, SQLITE_CONSTRAINT);
+        assert_eq!(unique.extended_code, SQLITE_CONSTRAINT_UNIQUE);
+        assert!(matches!(
+            crate::error::WorkerError::from(unique),
+            crate::error::WorkerError::ConstraintViolation { .. }
+        ));
+    }
+
     #[wasm_bindgen_test]
     async fn test_sql_with_null_byte_error() {
         let Some(db) = get_test_db().await else {
@@ -605,7 +1063,7 @@ mod tests {
 
         let result = db.exec("SELECT * FROM table\0WITH NULL").await;
         assert!(result.is_err());
-        let error = result.unwrap_err();
+        let error = result.unwrap_err().message;
         assert!(
             error.contains("Invalid SQL string"),
             "SQL with null bytes should produce invalid string error"
@@ -620,7 +1078,7 @@ mod tests {
 
         let result = db.exec("SELECT * FROM nonexistent_table").await;
         assert!(result.is_err());
-        let error = result.unwrap_err();
+        let error = result.unwrap_err().message;
         assert!(
             error.contains("Query execution failed") || error.contains("no such table"),
             "Query on nonexistent table should fail with appropriate error"
@@ -647,6 +1105,172 @@ mod tests {
         );
     }
 
+    #[wasm_bindgen_test]
+    async fn test_register_scalar_function_bridges_values() {
+        let Some(db) = get_test_db().await else {
+            return;
+        };
+
+        let describe = js_sys::Function::new_with_args(
+            "value",
+            "if (value instanceof Uint8Array) return value.length; \
+             if (value === null) return 'null'; \
+             if (value === 'boom') throw new Error('boom failed'); \
+             return typeof value === 'number' ? value * 2 : value + '!';",
+        );
+        db.register_scalar_function("js_describe", 1, describe)
+            .expect("Registration failed");
+
+        let result = db
+            .query("SELECT js_describe(21), js_describe(1.25), js_describe('hi'), js_describe(NULL), js_describe(X'010203')")
+            .await
+            .expect("Query failed");
+        assert_eq!(
+            result.rows[0],
+            vec![
+                SqlValue::Integer(42),
+                SqlValue::Real(2.5),
+                SqlValue::Text("hi!".to_string()),
+                SqlValue::Text("null".to_string()),
+                SqlValue::Integer(3),
+            ]
+        );
+
+        let err = db.query("SELECT js_describe('boom')").await.unwrap_err();
+        assert!(
+            err.message.contains("boom failed"),
+            "Unexpected error: {err}"
+        );
+
+        let arity = db.query("SELECT js_describe(1, 2)").await;
+        assert!(arity.is_err(), "Wrong argument count should be rejected");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_returns_structured_result() {
+        let Some(db) = get_test_db().await else {
+            return;
+        };
+
+        db.exec("CREATE TABLE IF NOT EXISTS structured_test (id INTEGER, label TEXT, data BLOB)")
+            .await
+            .expect("Create failed");
+        db.exec("DELETE FROM structured_test")
+            .await
+            .expect("Delete failed");
+
+        let insert = db
+            .query("INSERT INTO structured_test VALUES (1, 'one', X'0102'), (2, NULL, NULL)")
+            .await
+            .expect("Insert failed");
+        assert!(insert.columns.is_empty());
+        assert_eq!(insert.rows_affected, 2);
+
+        let select = db
+            .query("SELECT id, label, data FROM structured_test ORDER BY id")
+            .await
+            .expect("Select failed");
+        assert_eq!(select.columns, vec!["id", "label", "data"]);
+        assert_eq!(select.rows_affected, 0);
+        assert_eq!(
+            select.rows,
+            vec![
+                vec![
+                    SqlValue::Integer(1),
+                    SqlValue::Text("one".to_string()),
+                    SqlValue::Blob(vec![1, 2]),
+                ],
+                vec![SqlValue::Integer(2), SqlValue::Null, SqlValue::Null],
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_with_params_binds_values() {
+        if let Some(db) = get_test_db().await {
+            db.exec("CREATE TABLE IF NOT EXISTS param_test (id INTEGER, name TEXT, score REAL, data BLOB)")
+                .await
+                .expect("Create failed");
+            db.exec("DELETE FROM param_test")
+                .await
+                .expect("Delete failed");
+
+            let inserted = db
+                .query_with_params(
+                    "INSERT INTO param_test VALUES (?, ?, ?, ?)",
+                    &[
+                        SqlParam::Integer(7),
+                        SqlParam::from("O'Brien"),
+                        SqlParam::Real(9.5),
+                        SqlParam::Blob(vec![0, 1, 2]),
+                    ],
+                )
+                .await
+                .expect("Insert failed");
+            assert_eq!(inserted.rows_affected, 1);
+
+            let result = db
+                .query_with_params(
+                    "SELECT name, score, data FROM param_test WHERE id = ?",
+                    &[SqlParam::Integer(7)],
+                )
+                .await
+                .expect("Select failed");
+            assert_eq!(
+                result.rows,
+                vec![vec![
+                    SqlValue::Text("O'Brien".to_string()),
+                    SqlValue::Real(9.5),
+                    SqlValue::Blob(vec![0, 1, 2]),
+                ]]
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_with_params_rejects_extra_params() {
+        if let Some(db) = get_test_db().await {
+            let result = db
+                .query_with_params("SELECT ?", &[SqlParam::Null, SqlParam::Null])
+                .await;
+            let error = result.unwrap_err();
+            assert!(error.message.contains("Failed to bind parameter 2"));
+            assert_eq!(error.code, SQLITE_RANGE);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_is_autocommit_tracks_transactions() {
+        let Some(db) = get_test_db().await else {
+            return;
+        };
+
+        assert!(db.is_autocommit());
+        db.exec("BEGIN").await.expect("Begin failed");
+        assert!(!db.is_autocommit());
+        db.exec("ROLLBACK").await.expect("Rollback failed");
+        assert!(db.is_autocommit());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_only_connection_rejects_writes() {
+        let Some(db) = get_test_db().await else {
+            return;
+        };
+        db.exec("CREATE TABLE IF NOT EXISTS read_only_test (id INTEGER)")
+            .await
+            .expect("Create failed");
+
+        let reader = SQLiteDatabase::open_read_only().expect("Should open read-only connection");
+        let select = reader
+            .exec("SELECT COUNT(*) as count FROM read_only_test")
+            .await;
+        assert!(select.is_ok(), "Read-only connection should allow SELECT");
+
+        let insert = reader.exec("INSERT INTO read_only_test VALUES (1)").await;
+        assert!(insert.is_err(), "Read-only connection should reject writes");
+    }
+
     #[wasm_bindgen_test]
     async fn test_database_drop_cleanup() {
         {
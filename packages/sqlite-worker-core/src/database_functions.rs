@@ -1,10 +1,14 @@
 use alloy::primitives::U256;
+use js_sys::{Array, Function, Uint8Array};
 use rain_math_float::Float;
 use sqlite_wasm_rs::export::*;
-use std::ffi::{c_int, CStr, CString};
+use std::ffi::{c_int, c_void, CStr, CString};
 use std::ops::Add;
 use std::os::raw::c_char;
 use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+use crate::query_result::SqlValue;
 
 // Custom function using rain-math-float library - sums two Float values
 unsafe extern "C" fn rain_math_process(
@@ -106,6 +110,152 @@ unsafe extern "C" fn rain_math_process(
     );
 }
 
+// Largest integer a JavaScript number represents exactly
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+pub(crate) fn transient() -> Option<unsafe extern "C" fn(*mut c_void)> {
+    Some(unsafe { std::mem::transmute::<isize, unsafe extern "C" fn(*mut c_void)>(-1isize) })
+    // SQLITE_TRANSIENT
+}
+
+pub(crate) unsafe fn result_error(context: *mut sqlite3_context, message: &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    sqlite3_result_error(context, message.as_ptr(), -1);
+}
+
+// Read a function argument
+pub(crate) unsafe fn argument_value(value: *mut sqlite3_value) -> SqlValue {
+    match sqlite3_value_type(value) {
+        SQLITE_INTEGER => SqlValue::Integer(sqlite3_value_int64(value)),
+        SQLITE_FLOAT => SqlValue::Real(sqlite3_value_double(value)),
+        SQLITE_TEXT => {
+            let ptr = sqlite3_value_text(value);
+            if ptr.is_null() {
+                SqlValue::Null
+            } else {
+                SqlValue::Text(
+                    CStr::from_ptr(ptr as *const c_char)
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            }
+        }
+        SQLITE_BLOB => {
+            let ptr = sqlite3_value_blob(value);
+            let len = sqlite3_value_bytes(value);
+            if ptr.is_null() || len <= 0 {
+                SqlValue::Blob(Vec::new())
+            } else {
+                SqlValue::Blob(std::slice::from_raw_parts(ptr as *const u8, len as usize).to_vec())
+            }
+        }
+        _ => SqlValue::Null,
+    }
+}
+
+fn sql_value_to_js(value: &SqlValue) -> JsValue {
+    match value {
+        SqlValue::Null => JsValue::NULL,
+        SqlValue::Integer(val) => JsValue::from_f64(*val as f64),
+        SqlValue::Real(val) => JsValue::from_f64(*val),
+        SqlValue::Text(text) => JsValue::from_str(text),
+        SqlValue::Blob(bytes) => Uint8Array::from(bytes.as_slice()).into(),
+    }
+}
+
+// Set the function result from a JavaScript return value
+unsafe fn set_js_result(context: *mut sqlite3_context, value: &JsValue) {
+    if value.is_null() || value.is_undefined() {
+        sqlite3_result_null(context);
+    } else if let Some(flag) = value.as_bool() {
+        sqlite3_result_int64(context, flag as i64);
+    } else if let Some(number) = value.as_f64() {
+        if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER {
+            sqlite3_result_int64(context, number as i64);
+        } else {
+            sqlite3_result_double(context, number);
+        }
+    } else if let Some(text) = value.as_string() {
+        sqlite3_result_text(
+            context,
+            text.as_ptr() as *const c_char,
+            text.len() as c_int,
+            transient(),
+        );
+    } else if let Some(bytes) = value.dyn_ref::<Uint8Array>() {
+        let bytes = bytes.to_vec();
+        sqlite3_result_blob(
+            context,
+            bytes.as_ptr() as *const c_void,
+            bytes.len() as c_int,
+            transient(),
+        );
+    } else {
+        result_error(context, "Unsupported return value from JavaScript function");
+    }
+}
+
+// Scalar function that calls the JavaScript function stored as its user data
+unsafe extern "C" fn js_scalar_function(
+    context: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let func = &*(sqlite3_user_data(context) as *const Function);
+    let args = Array::new();
+    for i in 0..argc as isize {
+        args.push(&sql_value_to_js(&argument_value(*argv.offset(i))));
+    }
+
+    match func.apply(&JsValue::NULL, &args) {
+        Ok(value) => set_js_result(context, &value),
+        Err(err) => {
+            let message = err
+                .dyn_ref::<js_sys::Error>()
+                .map(|err| String::from(err.message()))
+                .or_else(|| err.as_string())
+                .unwrap_or_else(|| format!("{err:?}"));
+            result_error(context, &message);
+        }
+    }
+}
+
+unsafe extern "C" fn drop_js_function(user_data: *mut c_void) {
+    drop(Box::from_raw(user_data as *mut Function));
+}
+
+/// Register a JavaScript function as a scalar SQL function taking `arity`
+/// arguments, or any number if `arity` is -1
+pub fn register_js_function(
+    db: *mut sqlite3,
+    name: &str,
+    arity: c_int,
+    func: Function,
+) -> Result<(), String> {
+    let func_name = CString::new(name).map_err(|e| e.to_string())?;
+    let user_data = Box::into_raw(Box::new(func)) as *mut c_void;
+    // SQLite calls the destructor itself if registration fails
+    let ret = unsafe {
+        sqlite3_create_function_v2(
+            db,
+            func_name.as_ptr(),
+            arity,
+            SQLITE_UTF8,
+            user_data,
+            Some(js_scalar_function),
+            None,
+            None,
+            Some(drop_js_function),
+        )
+    };
+
+    if ret != SQLITE_OK {
+        return Err(format!("Failed to register {name} function"));
+    }
+
+    Ok(())
+}
+
 /// Register all custom functions with the SQLite database
 pub fn register_custom_functions(db: *mut sqlite3) -> Result<(), String> {
     // Register rain_math_process function
@@ -136,7 +286,7 @@ mod tests {
     use super::*;
     use wasm_bindgen_test::*;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
 
     #[wasm_bindgen_test]
     fn test_u256_hex_conversion() {
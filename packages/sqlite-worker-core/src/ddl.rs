@@ -0,0 +1,138 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::SqlParam;
+
+impl WorkerState {
+    /// The `CREATE TABLE` statement `table` was created with, as stored in
+    /// `sqlite_master`
+    pub async fn get_table_ddl(&self, table: &str) -> Result<String, WorkerError> {
+        self.schema_sql("table", table)
+            .await?
+            .ok_or_else(|| WorkerError::TableNotFound(table.to_string()))
+    }
+
+    /// The `CREATE INDEX` statement for `index`. Indexes SQLite creates for
+    /// UNIQUE and PRIMARY KEY constraints have none and are reported as not found.
+    pub async fn get_index_ddl(&self, index: &str) -> Result<String, WorkerError> {
+        self.schema_sql("index", index)
+            .await?
+            .ok_or_else(|| WorkerError::IndexNotFound(index.to_string()))
+    }
+
+    /// The `CREATE TRIGGER` statement for `trigger`
+    pub async fn get_trigger_ddl(&self, trigger: &str) -> Result<String, WorkerError> {
+        self.schema_sql("trigger", trigger)
+            .await?
+            .ok_or_else(|| WorkerError::TriggerNotFound(trigger.to_string()))
+    }
+
+    /// Every statement needed to recreate the main database's schema, each
+    /// ending in `;` on its own line. Tables come first, then views, indexes
+    /// and triggers, so the script runs in order against an empty database.
+    /// Objects SQLite creates itself, such as `sqlite_sequence`, the
+    /// `sqlite_stat*` tables and automatic indexes, are left out.
+    pub async fn export_schema(&self) -> Result<String, WorkerError> {
+        let result = self
+            .execute_parameterized(
+                "SELECT sql FROM sqlite_master \
+                 WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+                 ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'view' THEN 1 \
+                 WHEN 'index' THEN 2 ELSE 3 END, name"
+                    .to_string(),
+                Vec::new(),
+            )
+            .await?;
+        result
+            .rows
+            .iter()
+            .try_fold(String::new(), |mut script, row| {
+                let [SqlParam::Text(sql)] = row.as_slice() else {
+                    return Err(WorkerError::RowDecode(format!(
+                        "expected schema SQL, got {row:?}"
+                    )));
+                };
+                script.push_str(sql);
+                script.push_str(";\n");
+                Ok(script)
+            })
+    }
+
+    async fn schema_sql(&self, kind: &str, name: &str) -> Result<Option<String>, WorkerError> {
+        let result = self
+            .execute_parameterized(
+                "SELECT sql FROM sqlite_master WHERE type = ? AND name = ?".to_string(),
+                vec![SqlParam::from(kind), SqlParam::from(name)],
+            )
+            .await?;
+        match result.get_single::<(Option<String>,)>() {
+            Ok((sql,)) => Ok(sql),
+            Err(WorkerError::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_schema_ddl() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for sql in [
+            "DROP TABLE IF EXISTS ddl_items",
+            "CREATE TABLE ddl_items (id INTEGER PRIMARY KEY, name TEXT UNIQUE)",
+            "CREATE INDEX ddl_items_name ON ddl_items (name)",
+            "CREATE TRIGGER ddl_items_touch AFTER INSERT ON ddl_items BEGIN SELECT 1; END",
+        ] {
+            state
+                .execute_parameterized(sql.to_string(), Vec::new())
+                .await
+                .expect("Setup failed");
+        }
+
+        let ddl = state.get_table_ddl("ddl_items").await.expect("Table DDL");
+        assert!(ddl.starts_with("CREATE TABLE"));
+        assert!(state
+            .get_index_ddl("ddl_items_name")
+            .await
+            .expect("Index DDL")
+            .starts_with("CREATE INDEX"));
+        assert!(state
+            .get_trigger_ddl("ddl_items_touch")
+            .await
+            .expect("Trigger DDL")
+            .starts_with("CREATE TRIGGER"));
+
+        let schema = state.export_schema().await.expect("Export failed");
+        assert!(schema.starts_with("CREATE TABLE"), "{schema}");
+        assert!(schema.contains("CREATE INDEX ddl_items_name ON ddl_items (name);\n"));
+        assert!(schema.ends_with(";\n"));
+        let index = schema.find("CREATE INDEX ddl_items_name").unwrap();
+        let trigger = schema.find("CREATE TRIGGER ddl_items_touch").unwrap();
+        assert!(index < trigger);
+        assert!(!schema.contains("sqlite_autoindex"));
+
+        assert_eq!(
+            state.get_table_ddl("ddl_missing").await,
+            Err(WorkerError::TableNotFound("ddl_missing".to_string()))
+        );
+        // The index backing the UNIQUE constraint has no DDL of its own
+        assert_eq!(
+            state.get_index_ddl("sqlite_autoindex_ddl_items_1").await,
+            Err(WorkerError::IndexNotFound(
+                "sqlite_autoindex_ddl_items_1".to_string()
+            ))
+        );
+        assert_eq!(
+            state.get_trigger_ddl("ddl_items_name").await,
+            Err(WorkerError::TriggerNotFound("ddl_items_name".to_string()))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::messages::ChannelMessage;
+
+/// The variant of a `ChannelMessage`, used to choose its handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    NewLeader,
+    Redirect,
+    QueryRequest,
+    QueryProgress,
+    QueryResponse,
+    EmptyResponse,
+    Snapshot,
+    QuerySnapshot,
+    EndSnapshot,
+    BeginReadTransaction,
+    ReadTransactionQuery,
+    EndReadTransaction,
+    ParameterizedQueryRequest,
+    CopyTable,
+    PrepareStatement,
+    ExecutePrepared,
+    FinalizeStatement,
+    BulkInsertRequest,
+    BatchQueryRequest,
+    BatchQueryResponse,
+    PragmaSet,
+    DdlTransaction,
+    DropTable,
+    RenameTable,
+    ReindexRequest,
+    ValidateSqlRequest,
+    Savepoint,
+    BackupRequest,
+    RegisterFunction,
+    EchoRequest,
+    EchoResponse,
+    RequestState,
+    StateResponse,
+    GossipState,
+    DatabaseReset,
+    Custom,
+}
+
+impl ChannelMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            ChannelMessage::NewLeader { .. } => MessageKind::NewLeader,
+            ChannelMessage::Redirect { .. } => MessageKind::Redirect,
+            ChannelMessage::QueryRequest { .. } => MessageKind::QueryRequest,
+            ChannelMessage::QueryProgress { .. } => MessageKind::QueryProgress,
+            ChannelMessage::QueryResponse { .. } => MessageKind::QueryResponse,
+            ChannelMessage::EmptyResponse { .. } => MessageKind::EmptyResponse,
+            ChannelMessage::Snapshot { .. } => MessageKind::Snapshot,
+            ChannelMessage::QuerySnapshot { .. } => MessageKind::QuerySnapshot,
+            ChannelMessage::EndSnapshot { .. } => MessageKind::EndSnapshot,
+            ChannelMessage::BeginReadTransaction { .. } => MessageKind::BeginReadTransaction,
+            ChannelMessage::ReadTransactionQuery { .. } => MessageKind::ReadTransactionQuery,
+            ChannelMessage::EndReadTransaction { .. } => MessageKind::EndReadTransaction,
+            ChannelMessage::ParameterizedQueryRequest { .. } => {
+                MessageKind::ParameterizedQueryRequest
+            }
+            ChannelMessage::CopyTable { .. } => MessageKind::CopyTable,
+            ChannelMessage::PrepareStatement { .. } => MessageKind::PrepareStatement,
+            ChannelMessage::ExecutePrepared { .. } => MessageKind::ExecutePrepared,
+            ChannelMessage::FinalizeStatement { .. } => MessageKind::FinalizeStatement,
+            ChannelMessage::BulkInsertRequest { .. } => MessageKind::BulkInsertRequest,
+            ChannelMessage::BatchQueryRequest { .. } => MessageKind::BatchQueryRequest,
+            ChannelMessage::BatchQueryResponse { .. } => MessageKind::BatchQueryResponse,
+            ChannelMessage::PragmaSet { .. } => MessageKind::PragmaSet,
+            ChannelMessage::DdlTransaction { .. } => MessageKind::DdlTransaction,
+            ChannelMessage::DropTable { .. } => MessageKind::DropTable,
+            ChannelMessage::RenameTable { .. } => MessageKind::RenameTable,
+            ChannelMessage::ReindexRequest { .. } => MessageKind::ReindexRequest,
+            ChannelMessage::ValidateSqlRequest { .. } => MessageKind::ValidateSqlRequest,
+            ChannelMessage::Savepoint { .. } => MessageKind::Savepoint,
+            ChannelMessage::BackupRequest { .. } => MessageKind::BackupRequest,
+            ChannelMessage::RegisterFunction { .. } => MessageKind::RegisterFunction,
+            ChannelMessage::EchoRequest { .. } => MessageKind::EchoRequest,
+            ChannelMessage::EchoResponse { .. } => MessageKind::EchoResponse,
+            ChannelMessage::RequestState { .. } => MessageKind::RequestState,
+            ChannelMessage::StateResponse { .. } => MessageKind::StateResponse,
+            ChannelMessage::GossipState { .. } => MessageKind::GossipState,
+            ChannelMessage::DatabaseReset { .. } => MessageKind::DatabaseReset,
+            ChannelMessage::Custom { .. } => MessageKind::Custom,
+        }
+    }
+}
+
+pub type MessageHandler = Rc<dyn Fn(ChannelMessage)>;
+
+/// Handlers for received channel messages, registered per `MessageKind` so
+/// that modules can listen for their own messages without touching the
+/// channel listener. Every handler for a kind runs, in registration order,
+/// after the worker's built-in handling.
+#[derive(Default)]
+pub struct MessageDispatcher {
+    handlers: RefCell<HashMap<MessageKind, Vec<MessageHandler>>>,
+}
+
+impl MessageDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, kind: MessageKind, handler: Box<dyn Fn(ChannelMessage)>) {
+        self.handlers
+            .borrow_mut()
+            .entry(kind)
+            .or_default()
+            .push(Rc::from(handler));
+    }
+
+    pub fn handler_count(&self, kind: MessageKind) -> usize {
+        self.handlers.borrow().get(&kind).map_or(0, Vec::len)
+    }
+
+    /// Pass `message` to each handler for its kind, returning how many ran
+    pub fn dispatch(&self, message: &ChannelMessage) -> usize {
+        // Cloned out so a handler can register others while running
+        let handlers = self
+            .handlers
+            .borrow()
+            .get(&message.kind())
+            .cloned()
+            .unwrap_or_default();
+        for handler in &handlers {
+            handler(message.clone());
+        }
+        handlers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{sleep, WorkerState};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn new_leader(leader_id: &str) -> ChannelMessage {
+        ChannelMessage::NewLeader {
+            leader_id: leader_id.to_string(),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_handlers_run_in_registration_order() {
+        let dispatcher = MessageDispatcher::new();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        for name in ["first", "second", "third"] {
+            let calls = Rc::clone(&calls);
+            dispatcher.register(
+                MessageKind::NewLeader,
+                Box::new(move |message| calls.borrow_mut().push((name, message))),
+            );
+        }
+        assert_eq!(dispatcher.handler_count(MessageKind::NewLeader), 3);
+
+        assert_eq!(dispatcher.dispatch(&new_leader("leader-1")), 3);
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                ("first", new_leader("leader-1")),
+                ("second", new_leader("leader-1")),
+                ("third", new_leader("leader-1")),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_dispatch_only_reaches_matching_kind() {
+        let dispatcher = MessageDispatcher::new();
+        let calls = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&calls);
+        dispatcher.register(
+            MessageKind::EchoRequest,
+            Box::new(move |_| *counter.borrow_mut() += 1),
+        );
+
+        assert_eq!(dispatcher.dispatch(&new_leader("leader-1")), 0);
+        let echo = ChannelMessage::EchoRequest {
+            echo_id: "echo-1".to_string(),
+            payload: "ping".to_string(),
+        };
+        assert_eq!(echo.kind(), MessageKind::EchoRequest);
+        assert_eq!(dispatcher.dispatch(&echo), 1);
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_handler_can_register_during_dispatch() {
+        let dispatcher = Rc::new(MessageDispatcher::new());
+        let inner = Rc::clone(&dispatcher);
+        dispatcher.register(
+            MessageKind::NewLeader,
+            Box::new(move |_| inner.register(MessageKind::NewLeader, Box::new(|_| {}))),
+        );
+
+        assert_eq!(dispatcher.dispatch(&new_leader("leader-1")), 1);
+        assert_eq!(dispatcher.handler_count(MessageKind::NewLeader), 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_channel_messages_reach_registered_handlers_after_builtin() {
+        let (Ok(sender), Ok(receiver)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        receiver.setup_channel_listener();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let builtin = Rc::clone(&calls);
+        receiver.register_custom_handler(
+            "plugin",
+            Box::new(move |_| builtin.borrow_mut().push("custom handler")),
+        );
+        for name in ["first plugin", "second plugin"] {
+            let calls = Rc::clone(&calls);
+            receiver.register_message_handler(
+                MessageKind::Custom,
+                Box::new(move |message| {
+                    assert!(matches!(message, ChannelMessage::Custom { .. }));
+                    calls.borrow_mut().push(name);
+                }),
+            );
+        }
+
+        sender
+            .send_custom("plugin".to_string(), serde_json::json!({}))
+            .expect("Send failed");
+        sleep(50.0).await;
+        assert_eq!(
+            *calls.borrow(),
+            vec!["custom handler", "first plugin", "second plugin"]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use js_sys::{Function, Object, Reflect};
+use serde::Serialize;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::events::QueryEvent;
+
+pub const QUERY_COMPLETE_EVENT: &str = "sqlite-query-complete";
+pub const LEADER_CHANGED_EVENT: &str = "sqlite-leader-changed";
+pub const ERROR_EVENT: &str = "sqlite-error";
+pub const PERSISTENCE_NOT_GRANTED_EVENT: &str = "sqlite-persistence-not-granted";
+
+// `detail` of an `ERROR_EVENT`
+#[derive(Serialize)]
+struct ErrorDetail {
+    message: String,
+    // Extended SQLite result code when the error came from SQLite
+    code: Option<i32>,
+}
+
+// DOM event name for `event`. The leader's `QueryExecuted` has no DOM event,
+// as the requesting worker reports the completion, and neither do the
+// routine `AnalysisComplete` and `DatabaseImported`.
+fn dom_event_name(event: &QueryEvent) -> Option<&'static str> {
+    match event {
+        QueryEvent::QueryDelivered { .. } => Some(QUERY_COMPLETE_EVENT),
+        QueryEvent::LeaderChanged { .. } => Some(LEADER_CHANGED_EVENT),
+        QueryEvent::QueryExecuted { .. } => None,
+        QueryEvent::PersistenceNotGranted { .. } => Some(PERSISTENCE_NOT_GRANTED_EVENT),
+        QueryEvent::AnalysisComplete(_) => None,
+        QueryEvent::DatabaseImported { .. } => None,
+    }
+}
+
+// Dispatch a `CustomEvent` named `name` carrying `detail` on the global
+// object, if it can dispatch events
+fn dispatch_dom_event(name: &str, detail: &impl Serialize) {
+    let global = js_sys::global();
+    let constructor = Reflect::get(&global, &JsValue::from_str("CustomEvent"))
+        .ok()
+        .and_then(|constructor| constructor.dyn_into::<Function>().ok());
+    let dispatch = Reflect::get(&global, &JsValue::from_str("dispatchEvent"))
+        .ok()
+        .and_then(|dispatch| dispatch.dyn_into::<Function>().ok());
+    let (Some(constructor), Some(dispatch), Ok(detail)) =
+        (constructor, dispatch, serde_wasm_bindgen::to_value(detail))
+    else {
+        return;
+    };
+
+    let options = Object::new();
+    let _ = Reflect::set(&options, &JsValue::from_str("detail"), &detail);
+    let args = js_sys::Array::of2(&JsValue::from_str(name), &options);
+    if let Ok(event) = Reflect::construct(&constructor, &args) {
+        let _ = dispatch.call1(&global, &event);
+    }
+}
+
+impl WorkerState {
+    /// Mirror this worker's events as DOM `CustomEvent`s on the global object
+    /// (`window`, or `self` in a worker) for frameworks that listen for them:
+    /// `sqlite-query-complete` when a query's result is delivered,
+    /// `sqlite-leader-changed` when a leader takes over,
+    /// `sqlite-persistence-not-granted` when the browser refuses persistent
+    /// storage and `sqlite-error` for errors reported to `on_error` handlers. Each event's `detail` holds the
+    /// event's fields, such as `queryId`. Off by default.
+    pub fn enable_dom_events(&self, enabled: bool) {
+        self.dom_events.set(enabled);
+        if self.dom_events_installed.replace(true) {
+            return;
+        }
+
+        let dom_events = Rc::clone(&self.dom_events);
+        self.events.subscribe(move |event| {
+            if let Some(name) = dom_event_name(event).filter(|_| dom_events.get()) {
+                dispatch_dom_event(name, event);
+            }
+        });
+        let dom_events = Rc::clone(&self.dom_events);
+        self.on_error(move |error: WorkerError| {
+            if dom_events.get() {
+                let detail = ErrorDetail {
+                    code: error.sqlite_code(),
+                    message: error.to_string(),
+                };
+                dispatch_dom_event(ERROR_EVENT, &detail);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use crate::query_context::QueryContext;
+    use std::cell::RefCell;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // Record the `detail` of each `name` event dispatched on the global object
+    fn listen(name: &str) -> (Rc<RefCell<Vec<JsValue>>>, Closure<dyn FnMut(JsValue)>) {
+        let details = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&details);
+        let listener = Closure::wrap(Box::new(move |event: JsValue| {
+            if let Ok(detail) = Reflect::get(&event, &JsValue::from_str("detail")) {
+                recorded.borrow_mut().push(detail);
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+
+        let global = js_sys::global();
+        let add = Reflect::get(&global, &JsValue::from_str("addEventListener"))
+            .unwrap()
+            .dyn_into::<Function>()
+            .unwrap();
+        add.call2(&global, &JsValue::from_str(name), listener.as_ref())
+            .unwrap();
+        (details, listener)
+    }
+
+    fn unlisten(name: &str, listener: &Closure<dyn FnMut(JsValue)>) {
+        let global = js_sys::global();
+        let remove = Reflect::get(&global, &JsValue::from_str("removeEventListener"))
+            .unwrap()
+            .dyn_into::<Function>()
+            .unwrap();
+        let _ = remove.call2(&global, &JsValue::from_str(name), listener.as_ref());
+    }
+
+    fn detail_field(detail: &JsValue, field: &str) -> Option<String> {
+        Reflect::get(detail, &JsValue::from_str(field))
+            .ok()?
+            .as_string()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_completion_dispatches_dom_event() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let delivered = Rc::new(RefCell::new(Vec::new()));
+        let delivered_clone = Rc::clone(&delivered);
+        state.events.subscribe(move |event| {
+            if let QueryEvent::QueryDelivered { query_id, .. } = event {
+                delivered_clone.borrow_mut().push(query_id.clone());
+            }
+        });
+        let (details, listener) = listen(QUERY_COMPLETE_EVENT);
+
+        state.enable_dom_events(true);
+        state
+            .execute_query("SELECT 1".to_string(), QueryContext::default())
+            .await
+            .expect("Query failed");
+        assert_eq!(details.borrow().len(), 1);
+        assert_eq!(
+            detail_field(&details.borrow()[0], "queryId"),
+            delivered.borrow().first().cloned()
+        );
+        assert_eq!(
+            detail_field(&details.borrow()[0], "type").as_deref(),
+            Some("query-delivered")
+        );
+
+        state.enable_dom_events(false);
+        state
+            .execute_query("SELECT 1".to_string(), QueryContext::default())
+            .await
+            .expect("Query failed");
+        assert_eq!(details.borrow().len(), 1);
+        unlisten(QUERY_COMPLETE_EVENT, &listener);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reported_error_dispatches_dom_event() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let (details, listener) = listen(ERROR_EVENT);
+        state.enable_dom_events(true);
+        // Enabling twice must not dispatch twice
+        state.enable_dom_events(true);
+
+        state.report_error(WorkerError::NotLeader);
+        assert_eq!(details.borrow().len(), 1);
+        assert_eq!(
+            detail_field(&details.borrow()[0], "message"),
+            Some(WorkerError::NotLeader.to_string())
+        );
+        unlisten(ERROR_EVENT, &listener);
+    }
+}
@@ -0,0 +1,371 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use js_sys::{Array, ArrayBuffer, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::SqlParam;
+
+// AES-256 takes a 32-byte key
+const KEY_BYTES: usize = 32;
+// AES-GCM's recommended nonce length, and the tag it appends to the ciphertext
+const NONCE_BYTES: usize = 12;
+const TAG_BYTES: usize = 16;
+
+fn crypto_failed(context: &str, error: JsValue) -> WorkerError {
+    WorkerError::CryptoFailed(format!("{context}: {error:?}"))
+}
+
+// The global `crypto` object and its `subtle` interface
+fn web_crypto() -> Result<(JsValue, JsValue), WorkerError> {
+    let unavailable = || WorkerError::CryptoFailed("crypto.subtle is not available".to_string());
+    let crypto = Reflect::get(&js_sys::global(), &JsValue::from_str("crypto"))
+        .ok()
+        .filter(JsValue::is_object)
+        .ok_or_else(unavailable)?;
+    let subtle = Reflect::get(&crypto, &JsValue::from_str("subtle"))
+        .ok()
+        .filter(JsValue::is_object)
+        .ok_or_else(unavailable)?;
+    Ok((crypto, subtle))
+}
+
+// Call the promise-returning `subtle.{method}(...args)` and await it
+async fn call_subtle(
+    subtle: &JsValue,
+    method: &str,
+    args: &[JsValue],
+) -> Result<JsValue, WorkerError> {
+    let function: Function = Reflect::get(subtle, &JsValue::from_str(method))
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| crypto_failed(method, e))?;
+    let args: Array = args.iter().collect();
+    let promise: Promise = Reflect::apply(&function, subtle, &args)
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| crypto_failed(method, e))?;
+    JsFuture::from(promise)
+        .await
+        .map_err(|e| crypto_failed(method, e))
+}
+
+// `{ name: "AES-GCM", iv: nonce }`, or just the name without a nonce
+fn aes_gcm(nonce: Option<&Uint8Array>) -> Result<Object, WorkerError> {
+    let algorithm = Object::new();
+    Reflect::set(
+        &algorithm,
+        &JsValue::from_str("name"),
+        &JsValue::from_str("AES-GCM"),
+    )
+    .map_err(|e| crypto_failed("algorithm", e))?;
+    if let Some(nonce) = nonce {
+        Reflect::set(&algorithm, &JsValue::from_str("iv"), nonce)
+            .map_err(|e| crypto_failed("algorithm", e))?;
+    }
+    Ok(algorithm)
+}
+
+async fn import_key(subtle: &JsValue, key: &[u8]) -> Result<JsValue, WorkerError> {
+    if key.len() != KEY_BYTES {
+        return Err(WorkerError::InvalidEncryptionKey { length: key.len() });
+    }
+    let usages: Array = ["encrypt", "decrypt"]
+        .into_iter()
+        .map(JsValue::from_str)
+        .collect();
+    call_subtle(
+        subtle,
+        "importKey",
+        &[
+            JsValue::from_str("raw"),
+            Uint8Array::from(key).into(),
+            aes_gcm(None)?.into(),
+            JsValue::FALSE,
+            usages.into(),
+        ],
+    )
+    .await
+}
+
+// Base64 of a fresh random nonce followed by the AES-GCM ciphertext of
+// `plaintext`
+async fn encrypt_value(
+    crypto: &JsValue,
+    subtle: &JsValue,
+    key: &JsValue,
+    plaintext: &[u8],
+) -> Result<String, WorkerError> {
+    let nonce = Uint8Array::new_with_length(NONCE_BYTES as u32);
+    let get_random_values: Function = Reflect::get(crypto, &JsValue::from_str("getRandomValues"))
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| crypto_failed("getRandomValues", e))?;
+    get_random_values
+        .call1(crypto, &nonce)
+        .map_err(|e| crypto_failed("getRandomValues", e))?;
+
+    let ciphertext: ArrayBuffer = call_subtle(
+        subtle,
+        "encrypt",
+        &[
+            aes_gcm(Some(&nonce))?.into(),
+            key.clone(),
+            Uint8Array::from(plaintext).into(),
+        ],
+    )
+    .await?
+    .dyn_into()
+    .map_err(|e| crypto_failed("encrypt", e))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(Uint8Array::new(&ciphertext).to_vec());
+    Ok(STANDARD.encode(sealed))
+}
+
+// The bytes `encrypt_column` encrypts for a stored value: text as UTF-8,
+// a blob as it is and a number as its decimal text
+fn plaintext_bytes(value: &SqlParam) -> Option<Vec<u8>> {
+    match value {
+        SqlParam::Text(text) => Some(text.as_bytes().to_vec()),
+        SqlParam::Blob(bytes) => Some(bytes.clone()),
+        SqlParam::Integer(number) => Some(number.to_string().into_bytes()),
+        SqlParam::Real(number) => Some(number.to_string().into_bytes()),
+        SqlParam::Null => None,
+    }
+}
+
+/// Decrypt a value `WorkerState::encrypt_column` stored with the same `key`
+/// back to the bytes that were encrypted. Fails with `CryptoFailed` if
+/// `ciphertext` is malformed, was encrypted with another key or has been
+/// altered.
+pub async fn decrypt_column_bytes(ciphertext: &str, key: &[u8]) -> Result<Vec<u8>, WorkerError> {
+    let sealed = STANDARD
+        .decode(ciphertext)
+        .map_err(|e| WorkerError::CryptoFailed(format!("invalid base64: {e}")))?;
+    if sealed.len() < NONCE_BYTES + TAG_BYTES {
+        return Err(WorkerError::CryptoFailed(
+            "ciphertext is too short".to_string(),
+        ));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+
+    let (_, subtle) = web_crypto()?;
+    let key = import_key(&subtle, key).await?;
+    let plaintext: ArrayBuffer = call_subtle(
+        &subtle,
+        "decrypt",
+        &[
+            aes_gcm(Some(&Uint8Array::from(nonce)))?.into(),
+            key,
+            Uint8Array::from(ciphertext).into(),
+        ],
+    )
+    .await?
+    .dyn_into()
+    .map_err(|e| crypto_failed("decrypt", e))?;
+    Ok(Uint8Array::new(&plaintext).to_vec())
+}
+
+/// `decrypt_column_bytes` for a value that was text, or a number
+pub async fn decrypt_column_value(ciphertext: &str, key: &[u8]) -> Result<String, WorkerError> {
+    String::from_utf8(decrypt_column_bytes(ciphertext, key).await?)
+        .map_err(|_| WorkerError::CryptoFailed("plaintext is not UTF-8".to_string()))
+}
+
+impl WorkerState {
+    /// Encrypt every non-NULL value of `table.column` in place with
+    /// AES-256-GCM under the 32-byte `key`, using the Web Crypto API, and
+    /// return how many values were encrypted. Text is encrypted as UTF-8, a
+    /// blob as its bytes and a number as its decimal text, and each value is
+    /// stored as base64 of a random nonce followed by the ciphertext; read
+    /// them back with `decrypt_column_value` or `decrypt_column_bytes`.
+    ///
+    /// Leader only. The values are read and rewritten in one `BEGIN IMMEDIATE`
+    /// transaction, so no write can slip in between, and rows are found by
+    /// rowid, so a `WITHOUT ROWID` table fails with `WithoutRowid`. This
+    /// protects the column's values, not the database file, and running it
+    /// twice encrypts the values twice.
+    pub async fn encrypt_column(
+        &self,
+        table: &str,
+        column: &str,
+        key: &[u8],
+    ) -> Result<u64, WorkerError> {
+        validate_identifier(table)?;
+        validate_identifier(column)?;
+        let (crypto, subtle) = web_crypto()?;
+        let key = import_key(&subtle, key).await?;
+        if self.is_without_rowid(table).await? {
+            return Err(WorkerError::WithoutRowid(table.to_string()));
+        }
+
+        let (crypto, subtle, key) = (&crypto, &subtle, &key);
+        self.run_in_transaction_with("BEGIN IMMEDIATE", || {
+            Box::pin(async move {
+                let result = self
+                    .execute_parameterized(
+                        format!("SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL"),
+                        Vec::new(),
+                    )
+                    .await?;
+                for row in &result.rows {
+                    let decoded = match row.as_slice() {
+                        [SqlParam::Integer(rowid), value] => {
+                            plaintext_bytes(value).map(|plaintext| (rowid, plaintext))
+                        }
+                        _ => None,
+                    };
+                    let Some((rowid, plaintext)) = decoded else {
+                        return Err(WorkerError::RowDecode(format!(
+                            "expected a rowid and a value, got {row:?}"
+                        )));
+                    };
+                    let sealed = encrypt_value(crypto, subtle, key, &plaintext).await?;
+                    self.execute_parameterized(
+                        format!("UPDATE {table} SET {column} = ? WHERE rowid = ?"),
+                        vec![SqlParam::Text(sealed), SqlParam::Integer(*rowid)],
+                    )
+                    .await?;
+                }
+                Ok(result.rows.len() as u64)
+            })
+        })
+        .await
+    }
+
+    // Whether `table` is declared `WITHOUT ROWID`
+    async fn is_without_rowid(&self, table: &str) -> Result<bool, WorkerError> {
+        let result = self
+            .execute_parameterized(
+                "SELECT 1 FROM pragma_table_list(?) WHERE wr".to_string(),
+                vec![SqlParam::Text(table.to_string())],
+            )
+            .await?;
+        Ok(!result.rows.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    const KEY: [u8; KEY_BYTES] = [7; KEY_BYTES];
+
+    #[wasm_bindgen_test]
+    async fn test_encrypt_value_round_trip() {
+        let Ok((crypto, subtle)) = web_crypto() else {
+            return;
+        };
+        let key = import_key(&subtle, &KEY).await.expect("Import failed");
+        let first = encrypt_value(&crypto, &subtle, &key, b"123-45-6789")
+            .await
+            .expect("Encrypt failed");
+        let second = encrypt_value(&crypto, &subtle, &key, b"123-45-6789")
+            .await
+            .expect("Encrypt failed");
+        assert_ne!(first, second, "Each value gets its own nonce");
+
+        assert_eq!(
+            decrypt_column_value(&first, &KEY).await.unwrap(),
+            "123-45-6789"
+        );
+        assert!(matches!(
+            decrypt_column_value(&first, &[8; KEY_BYTES]).await,
+            Err(WorkerError::CryptoFailed(_))
+        ));
+        assert!(matches!(
+            decrypt_column_value("bm90IGVub3VnaA==", &KEY).await,
+            Err(WorkerError::CryptoFailed(_))
+        ));
+        assert_eq!(
+            decrypt_column_value(&first, &KEY[..16]).await,
+            Err(WorkerError::InvalidEncryptionKey { length: 16 })
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_encrypt_column() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS encrypted_people".to_string(),
+                "CREATE TABLE encrypted_people (name TEXT, ssn TEXT)".to_string(),
+                "INSERT INTO encrypted_people VALUES ('Ada', '123-45-6789'), ('Bob', NULL), \
+                 ('Cy', x'00ff10')"
+                    .to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+
+        let encrypted = state
+            .encrypt_column("encrypted_people", "ssn", &KEY)
+            .await
+            .expect("Encrypt failed");
+        assert_eq!(encrypted, 2);
+        assert!(state.active_transaction.borrow().is_none());
+
+        let (ssn,): (String,) = state
+            .execute_parameterized(
+                "SELECT ssn FROM encrypted_people WHERE name = 'Ada'".to_string(),
+                Vec::new(),
+            )
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_ne!(ssn, "123-45-6789");
+        assert_eq!(
+            decrypt_column_value(&ssn, &KEY).await.unwrap(),
+            "123-45-6789"
+        );
+        let (blob,): (String,) = state
+            .execute_parameterized(
+                "SELECT ssn FROM encrypted_people WHERE name = 'Cy'".to_string(),
+                Vec::new(),
+            )
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(
+            decrypt_column_bytes(&blob, &KEY).await.unwrap(),
+            vec![0x00, 0xff, 0x10],
+            "A blob's bytes are encrypted as they are"
+        );
+
+        assert_eq!(
+            state
+                .encrypt_column("encrypted_people", "ssn", &[0; 8])
+                .await,
+            Err(WorkerError::InvalidEncryptionKey { length: 8 })
+        );
+        assert!(matches!(
+            state
+                .encrypt_column("encrypted_people", "ssn;--", &KEY)
+                .await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS encrypted_keyed".to_string(),
+                "CREATE TABLE encrypted_keyed (id TEXT PRIMARY KEY, secret TEXT) WITHOUT ROWID"
+                    .to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+        assert_eq!(
+            state
+                .encrypt_column("encrypted_keyed", "secret", &KEY)
+                .await,
+            Err(WorkerError::WithoutRowid("encrypted_keyed".to_string()))
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use js_sys::Reflect;
+use sqlite_wasm_rs::export::{
+    SQLITE_BUSY, SQLITE_CONSTRAINT, SQLITE_CORRUPT, SQLITE_ERROR, SQLITE_FULL, SQLITE_INTERRUPT,
+};
+use thiserror::Error;
+use wasm_bindgen::prelude::*;
+
+use crate::query_result::QueryResult;
+
+// `name` of the DOMException thrown when storage runs out
+const QUOTA_EXCEEDED_ERROR: &str = "QuotaExceededError";
+
+/// An error reported by SQLite, with its primary and extended result codes
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{message}")]
+pub struct SqliteError {
+    // Primary result code such as `SQLITE_CONSTRAINT`
+    pub code: i32,
+    // Extended result code such as `SQLITE_CONSTRAINT_UNIQUE`
+    pub extended_code: i32,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum WorkerError {
+    #[error("BroadcastChannel is not supported in this environment")]
+    BroadcastChannelUnsupported,
+    #[error("navigator.locks is not supported in this environment")]
+    NavigatorLocksUnsupported,
+    #[error("SharedArrayBuffer is not available; the page must be cross-origin isolated")]
+    SharedArrayBufferUnsupported,
+    #[error("No leader available")]
+    NoLeaderAvailable,
+    #[error("SQL is {length} bytes, exceeding the limit of {max}")]
+    SqlTooLong { length: usize, max: usize },
+    #[error("Database not initialized")]
+    DatabaseNotInitialized,
+    #[error("Not a SQLite database file: {0}")]
+    InvalidDatabaseFile(String),
+    #[error("Fetching {url} failed: {reason}")]
+    RemoteFetchFailed { url: String, reason: String },
+    #[error("Operation is only available on the leader worker")]
+    NotLeader,
+    #[error("Resetting the database is disabled; see WorkerStateBuilder::allow_reset")]
+    ResetDisabled,
+    #[error("A transaction is already active")]
+    TransactionActive,
+    #[error("Transaction not found: {0}")]
+    TransactionNotFound(String),
+    #[error("Prepared statement not found: {0}")]
+    StatementNotFound(String),
+    #[error("Snapshot limit of {0} reached")]
+    SnapshotLimitReached(usize),
+    #[error("A {size} byte snapshot would take snapshots past {limit} bytes ({held} held)")]
+    SnapshotMemoryExceeded {
+        size: usize,
+        held: usize,
+        limit: usize,
+    },
+    #[error("Snapshot not found: {0}")]
+    SnapshotNotFound(String),
+    #[error("Table not found: {0}")]
+    TableNotFound(String),
+    #[error("Index not found: {0}")]
+    IndexNotFound(String),
+    #[error("Trigger not found: {0}")]
+    TriggerNotFound(String),
+    #[error("{message}")]
+    SqliteError {
+        code: i32,
+        extended_code: i32,
+        message: String,
+    },
+    #[error("{message}")]
+    ConstraintViolation { extended_code: i32, message: String },
+    #[error("{message}")]
+    Busy { message: String },
+    #[error("{message}")]
+    Corrupt { message: String },
+    // `validate_sql` found the statement incomplete or unable to compile
+    #[error("SQL syntax error: {message}")]
+    SqlSyntaxError { message: String },
+    #[error("Storage quota exceeded: {message}")]
+    StorageQuotaExceeded { message: String },
+    #[error("navigator.storage.estimate is not available in this environment")]
+    StorageEstimateUnavailable,
+    #[error("navigator.storage.persist is not available in this environment")]
+    StoragePersistenceUnavailable,
+    #[error(
+        "{} of {} batch statements failed",
+        .0.iter().filter(|result| result.is_err()).count(),
+        .0.len()
+    )]
+    PartialBatchFailure(Vec<Result<QueryResult, WorkerError>>),
+    #[error("Statement {index} is not DDL: {sql}")]
+    NotDdl { index: usize, sql: String },
+    #[error("DDL statement {index} failed: {error}")]
+    DdlStatementFailed {
+        index: usize,
+        error: Box<WorkerError>,
+    },
+    #[error("No rows returned")]
+    NotFound,
+    #[error("Expected exactly one row, got {0}")]
+    UnexpectedRowCount(usize),
+    #[error("Failed to decode row: {0}")]
+    RowDecode(String),
+    #[error("{feature} requires SQLite {required} or later, found {found}")]
+    FeatureUnsupported {
+        feature: String,
+        required: String,
+        found: String,
+    },
+    #[error("Circuit breaker is open after repeated query failures")]
+    CircuitOpen,
+    #[error("Query queue is full: {pending} pending and {queued} waiting")]
+    QueueFull { pending: usize, queued: usize },
+    #[error("Database schema version is {actual}, expected {expected}")]
+    SchemaMismatch { actual: i32, expected: i32 },
+    #[error("Invalid backup target: {0}")]
+    InvalidBackupTarget(String),
+    #[error("Invalid identifier: {0}")]
+    InvalidIdentifier(String),
+    #[error("PRAGMA {0} may not be set")]
+    PragmaNotAllowed(String),
+    #[error("Invalid CSV on line {line}: {reason}")]
+    InvalidCsv { line: usize, reason: String },
+    #[error("Encryption key is {length} bytes, expected 32")]
+    InvalidEncryptionKey { length: usize },
+    #[error("Web Crypto failed: {0}")]
+    CryptoFailed(String),
+    #[error("{0} is a WITHOUT ROWID table")]
+    WithoutRowid(String),
+    #[error("IndexedDB failed: {0}")]
+    IndexedDbFailed(String),
+    #[error("Row {row} has columns {found:?}, expected {expected:?}")]
+    InconsistentColumns {
+        row: usize,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    #[error("{message}")]
+    QueryInterrupted { message: String },
+    #[error("Channel error: {0}")]
+    ChannelError(String),
+    #[error("{0}")]
+    QueryFailed(String),
+}
+
+impl From<SqliteError> for WorkerError {
+    fn from(error: SqliteError) -> Self {
+        let SqliteError {
+            code,
+            extended_code,
+            message,
+        } = error;
+        match code {
+            SQLITE_CONSTRAINT => WorkerError::ConstraintViolation {
+                extended_code,
+                message,
+            },
+            SQLITE_BUSY => WorkerError::Busy { message },
+            SQLITE_CORRUPT => WorkerError::Corrupt { message },
+            SQLITE_INTERRUPT => WorkerError::QueryInterrupted { message },
+            // The OPFS VFS reports a `QuotaExceededError` from a write this way
+            SQLITE_FULL => WorkerError::StorageQuotaExceeded { message },
+            _ => WorkerError::SqliteError {
+                code,
+                extended_code,
+                message,
+            },
+        }
+    }
+}
+
+impl WorkerError {
+    /// A generic `SQLITE_ERROR` with `message`, for failures that carry no code
+    pub fn sqlite(message: String) -> Self {
+        WorkerError::SqliteError {
+            code: SQLITE_ERROR,
+            extended_code: SQLITE_ERROR,
+            message,
+        }
+    }
+
+    /// The extended SQLite result code behind this error, such as 2067 for
+    /// `SQLITE_CONSTRAINT_UNIQUE`, if it came from SQLite
+    pub fn sqlite_code(&self) -> Option<i32> {
+        match self {
+            WorkerError::SqliteError { extended_code, .. }
+            | WorkerError::ConstraintViolation { extended_code, .. } => Some(*extended_code),
+            WorkerError::Busy { .. } => Some(SQLITE_BUSY),
+            WorkerError::Corrupt { .. } => Some(SQLITE_CORRUPT),
+            WorkerError::QueryInterrupted { .. } => Some(SQLITE_INTERRUPT),
+            WorkerError::StorageQuotaExceeded { .. } => Some(SQLITE_FULL),
+            _ => None,
+        }
+    }
+
+    /// Rebuild the error for an extended result code received from another
+    /// worker, inverting `sqlite_code`
+    pub fn from_sqlite_code(extended_code: i32, message: String) -> Self {
+        WorkerError::from(SqliteError {
+            code: extended_code & 0xff,
+            extended_code,
+            message,
+        })
+    }
+}
+
+impl WorkerError {
+    /// Convert an exception thrown by JavaScript, recognising a
+    /// `QuotaExceededError` DOMException by its `name`
+    pub fn from_js_error(error: &JsValue) -> Self {
+        let name = Reflect::get(error, &JsValue::from_str("name"))
+            .ok()
+            .and_then(|name| name.as_string());
+        let message = Reflect::get(error, &JsValue::from_str("message"))
+            .ok()
+            .and_then(|message| message.as_string())
+            .or_else(|| error.as_string())
+            .unwrap_or_else(|| format!("{error:?}"));
+        // Errors passed along as text keep the name only in their message
+        if name.as_deref() == Some(QUOTA_EXCEEDED_ERROR) || message.contains(QUOTA_EXCEEDED_ERROR) {
+            WorkerError::StorageQuotaExceeded { message }
+        } else {
+            WorkerError::QueryFailed(message)
+        }
+    }
+}
+
+impl From<WorkerError> for JsValue {
+    fn from(value: WorkerError) -> Self {
+        let error = JsError::new(&value.to_string());
+        let error: JsValue = error.into();
+        // Let JavaScript callers check `name` as they would for the DOMException
+        if matches!(value, WorkerError::StorageQuotaExceeded { .. }) {
+            let _ = Reflect::set(
+                &error,
+                &JsValue::from_str("name"),
+                &JsValue::from_str(QUOTA_EXCEEDED_ERROR),
+            );
+        }
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_worker_error_display() {
+        assert_eq!(
+            WorkerError::DatabaseNotInitialized.to_string(),
+            "Database not initialized"
+        );
+        assert_eq!(
+            WorkerError::QueryFailed("no such table: missing".to_string()).to_string(),
+            "no such table: missing"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_feature_detection_errors_display() {
+        assert_eq!(
+            WorkerError::BroadcastChannelUnsupported.to_string(),
+            "BroadcastChannel is not supported in this environment"
+        );
+        assert_eq!(
+            WorkerError::NavigatorLocksUnsupported.to_string(),
+            "navigator.locks is not supported in this environment"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_partial_batch_failure_display() {
+        let err = WorkerError::PartialBatchFailure(vec![
+            Ok(QueryResult::default()),
+            Err(WorkerError::sqlite("no such table: missing".to_string())),
+            Ok(QueryResult::default()),
+        ]);
+        assert_eq!(err.to_string(), "1 of 3 batch statements failed");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_worker_error_to_js_value() {
+        let js_value = JsValue::from(WorkerError::NotLeader);
+        assert!(js_value.is_object());
+    }
+
+    fn sqlite_error(code: i32, extended_code: i32) -> SqliteError {
+        SqliteError {
+            code,
+            extended_code,
+            message: "failed".to_string(),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sqlite_error_codes_map_to_variants() {
+        // SQLITE_CONSTRAINT_UNIQUE
+        assert_eq!(
+            WorkerError::from(sqlite_error(SQLITE_CONSTRAINT, 2067)),
+            WorkerError::ConstraintViolation {
+                extended_code: 2067,
+                message: "failed".to_string(),
+            }
+        );
+        assert_eq!(
+            WorkerError::from(sqlite_error(SQLITE_BUSY, SQLITE_BUSY)),
+            WorkerError::Busy {
+                message: "failed".to_string(),
+            }
+        );
+        assert_eq!(
+            WorkerError::from(sqlite_error(SQLITE_CORRUPT, SQLITE_CORRUPT)),
+            WorkerError::Corrupt {
+                message: "failed".to_string(),
+            }
+        );
+        assert_eq!(
+            WorkerError::from(sqlite_error(SQLITE_INTERRUPT, SQLITE_INTERRUPT)),
+            WorkerError::QueryInterrupted {
+                message: "failed".to_string(),
+            }
+        );
+        // SQLITE_ERROR, as for a syntax error
+        assert_eq!(
+            WorkerError::from(sqlite_error(1, 1)),
+            WorkerError::SqliteError {
+                code: 1,
+                extended_code: 1,
+                message: "failed".to_string(),
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_quota_exceeded_errors() {
+        assert_eq!(
+            WorkerError::from(sqlite_error(SQLITE_FULL, SQLITE_FULL)),
+            WorkerError::StorageQuotaExceeded {
+                message: "failed".to_string(),
+            }
+        );
+
+        let dom_error = js_sys::Error::new("The quota has been exceeded.");
+        dom_error.set_name(QUOTA_EXCEEDED_ERROR);
+        let error = WorkerError::from_js_error(&dom_error);
+        assert_eq!(
+            error,
+            WorkerError::StorageQuotaExceeded {
+                message: "The quota has been exceeded.".to_string(),
+            }
+        );
+        let name = Reflect::get(&JsValue::from(error), &JsValue::from_str("name")).unwrap();
+        assert_eq!(name.as_string().as_deref(), Some(QUOTA_EXCEEDED_ERROR));
+
+        let other = js_sys::Error::new("boom");
+        assert_eq!(
+            WorkerError::from_js_error(&other),
+            WorkerError::QueryFailed("boom".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sqlite_code_roundtrip() {
+        for error in [
+            WorkerError::from(sqlite_error(SQLITE_CONSTRAINT, 2067)),
+            WorkerError::from(sqlite_error(SQLITE_BUSY, SQLITE_BUSY)),
+            WorkerError::from(sqlite_error(SQLITE_CORRUPT, SQLITE_CORRUPT)),
+            WorkerError::from(sqlite_error(SQLITE_INTERRUPT, SQLITE_INTERRUPT)),
+            WorkerError::from(sqlite_error(SQLITE_FULL, SQLITE_FULL)),
+            // SQLITE_IOERR_READ
+            WorkerError::from(sqlite_error(10, 266)),
+        ] {
+            let code = error.sqlite_code().expect("Should carry a code");
+            assert_eq!(
+                WorkerError::from_sqlite_code(code, error.to_string()),
+                error
+            );
+        }
+        assert_eq!(WorkerError::NotLeader.sqlite_code(), None);
+    }
+}
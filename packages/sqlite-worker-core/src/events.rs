@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::analysis::AnalysisResult;
+
+/// Lifecycle events emitted by a `WorkerState` to its subscribers
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum QueryEvent {
+    // A query's result (or error) was handed back to the caller
+    #[serde(rename = "query-delivered")]
+    QueryDelivered {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "traceId")]
+        trace_id: Option<String>,
+        #[serde(rename = "durationMs")]
+        duration_ms: f64,
+        success: bool,
+    },
+    // The leader finished executing a query sent by `origin_worker_id`
+    #[serde(rename = "query-executed")]
+    QueryExecuted {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "originWorkerId")]
+        origin_worker_id: String,
+        #[serde(rename = "durationMs")]
+        duration_ms: f64,
+    },
+    // A worker announced itself as leader, possibly this one
+    #[serde(rename = "leader-changed")]
+    LeaderChanged {
+        #[serde(rename = "leaderId")]
+        leader_id: String,
+    },
+    // The browser declined to keep the origin's storage, OPFS included,
+    // under storage pressure; see `WorkerState::request_storage_persistence`
+    #[serde(rename = "persistence-not-granted")]
+    PersistenceNotGranted {
+        #[serde(rename = "workerId")]
+        worker_id: String,
+    },
+    // `ANALYZE` finished, run by `WorkerState::run_analysis` or automatically
+    // after `auto_analyze_after_n_writes` writes
+    #[serde(rename = "analysis-complete")]
+    AnalysisComplete(AnalysisResult),
+    // The leader filled the empty database with the `bytes`-byte file at
+    // `url`; see `WorkerStateBuilder::remote_database_url`
+    #[serde(rename = "database-imported")]
+    DatabaseImported { url: String, bytes: u64 },
+}
+
+/// Shared list of event subscribers
+#[derive(Clone, Default)]
+pub struct EventEmitter {
+    listeners: Rc<RefCell<Vec<Rc<dyn Fn(&QueryEvent)>>>>,
+}
+
+impl EventEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, listener: impl Fn(&QueryEvent) + 'static) {
+        self.listeners.borrow_mut().push(Rc::new(listener));
+    }
+
+    pub fn listener_count(&self) -> usize {
+        self.listeners.borrow().len()
+    }
+
+    pub fn emit(&self, event: &QueryEvent) {
+        // Clone the list so listeners can subscribe while being notified
+        let listeners = self.listeners.borrow().clone();
+        for listener in listeners {
+            listener(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn delivered(query_id: &str) -> QueryEvent {
+        QueryEvent::QueryDelivered {
+            query_id: query_id.to_string(),
+            trace_id: Some("trace-1".to_string()),
+            duration_ms: 2.5,
+            success: true,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_emitter_notifies_all_listeners() {
+        let emitter = EventEmitter::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        for _ in 0..2 {
+            let received = Rc::clone(&received);
+            emitter.subscribe(move |event| received.borrow_mut().push(event.clone()));
+        }
+        assert_eq!(emitter.listener_count(), 2);
+
+        emitter.emit(&delivered("query-1"));
+        assert_eq!(*received.borrow(), vec![delivered("query-1"); 2]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_listener_can_subscribe_during_emit() {
+        let emitter = EventEmitter::new();
+        let inner = emitter.clone();
+        emitter.subscribe(move |_| inner.subscribe(|_| {}));
+
+        emitter.emit(&delivered("query-1"));
+        assert_eq!(emitter.listener_count(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_query_event_serialization() {
+        let json = serde_json::to_string(&delivered("query-1")).expect("Should serialize");
+        assert!(json.contains("\"type\":\"query-delivered\""));
+        assert!(json.contains("\"traceId\":\"trace-1\""));
+        assert!(json.contains("\"durationMs\":2.5"));
+
+        let executed = QueryEvent::QueryExecuted {
+            query_id: "query-2".to_string(),
+            origin_worker_id: "worker-1".to_string(),
+            duration_ms: 1.0,
+        };
+        let json = serde_json::to_string(&executed).expect("Should serialize");
+        assert!(json.contains("\"type\":\"query-executed\""));
+        assert!(json.contains("\"originWorkerId\":\"worker-1\""));
+
+        let leader_changed = QueryEvent::LeaderChanged {
+            leader_id: "worker-2".to_string(),
+        };
+        let json = serde_json::to_string(&leader_changed).expect("Should serialize");
+        assert_eq!(
+            json,
+            "{\"type\":\"leader-changed\",\"leaderId\":\"worker-2\"}"
+        );
+
+        let not_granted = QueryEvent::PersistenceNotGranted {
+            worker_id: "worker-3".to_string(),
+        };
+        let json = serde_json::to_string(&not_granted).expect("Should serialize");
+        assert_eq!(
+            json,
+            "{\"type\":\"persistence-not-granted\",\"workerId\":\"worker-3\"}"
+        );
+
+        let analyzed = QueryEvent::AnalysisComplete(AnalysisResult {
+            tables_analyzed: vec!["users".to_string()],
+            duration_ms: 4.0,
+        });
+        let json = serde_json::to_string(&analyzed).expect("Should serialize");
+        assert_eq!(
+            json,
+            "{\"type\":\"analysis-complete\",\"tablesAnalyzed\":[\"users\"],\"durationMs\":4.0}"
+        );
+
+        let imported = QueryEvent::DatabaseImported {
+            url: "https://example.com/data.db".to_string(),
+            bytes: 4096,
+        };
+        let json = serde_json::to_string(&imported).expect("Should serialize");
+        assert_eq!(
+            json,
+            "{\"type\":\"database-imported\",\"url\":\"https://example.com/data.db\",\"bytes\":4096}"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::row::{FromRow, FromSqlValue, Row};
+
+/// One node of the tree printed by `EXPLAIN QUERY PLAN`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExplainRow {
+    pub id: i32,
+    #[serde(rename = "parentId")]
+    pub parent_id: i32,
+    pub detail: String,
+}
+
+impl ExplainRow {
+    /// Whether this step reads a whole table or index rather than searching it
+    pub fn is_table_scan(&self) -> bool {
+        self.detail.contains("SCAN")
+    }
+}
+
+impl FromRow for ExplainRow {
+    fn from_row(row: &Row) -> Result<Self, WorkerError> {
+        let column = |name: &str| {
+            row.get(name)
+                .ok_or_else(|| WorkerError::RowDecode(format!("missing column {name}")))
+        };
+        let integer = |name: &str| -> Result<i32, WorkerError> {
+            let value = i64::from_sql_value(column(name)?)?;
+            i32::try_from(value)
+                .map_err(|_| WorkerError::RowDecode(format!("{name} out of range: {value}")))
+        };
+        Ok(ExplainRow {
+            id: integer("id")?,
+            parent_id: integer("parent")?,
+            detail: String::from_sql_value(column("detail")?)?,
+        })
+    }
+}
+
+impl WorkerState {
+    /// Run `EXPLAIN QUERY PLAN` for `sql` and return the plan's steps in order
+    pub async fn explain_query_plan(&self, sql: String) -> Result<Vec<ExplainRow>, WorkerError> {
+        let result = self
+            .execute_parameterized(format!("EXPLAIN QUERY PLAN {sql}"), Vec::new())
+            .await?;
+        result
+            .into_iter()
+            .map(|row| ExplainRow::from_row(&row))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use crate::query_result::SqlValue;
+    use std::rc::Rc;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn plan_row(id: i64, parent: i64, detail: &str) -> Row {
+        let columns: Rc<[String]> = ["id", "parent", "notused", "detail"]
+            .map(String::from)
+            .into();
+        Row::new(
+            columns,
+            vec![
+                SqlValue::Integer(id),
+                SqlValue::Integer(parent),
+                SqlValue::Integer(0),
+                SqlValue::Text(detail.to_string()),
+            ],
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn test_explain_row_from_row() {
+        let row = ExplainRow::from_row(&plan_row(2, 0, "SCAN users")).unwrap();
+        assert_eq!(
+            row,
+            ExplainRow {
+                id: 2,
+                parent_id: 0,
+                detail: "SCAN users".to_string(),
+            }
+        );
+        assert!(row.is_table_scan());
+
+        let search = ExplainRow::from_row(&plan_row(
+            3,
+            0,
+            "SEARCH users USING INTEGER PRIMARY KEY (rowid=?)",
+        ))
+        .unwrap();
+        assert!(!search.is_table_scan());
+
+        let too_large = ExplainRow::from_row(&plan_row(i64::MAX, 0, "SCAN users"));
+        assert!(matches!(too_large, Err(WorkerError::RowDecode(_))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_explain_query_plan() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_parameterized(
+                "CREATE TABLE IF NOT EXISTS explain_users (id INTEGER PRIMARY KEY, name TEXT)"
+                    .to_string(),
+                vec![],
+            )
+            .await
+            .expect("Create failed");
+
+        let plan = state
+            .explain_query_plan("SELECT name FROM explain_users".to_string())
+            .await
+            .expect("Explain failed");
+        assert!(!plan.is_empty());
+        assert!(plan.iter().any(ExplainRow::is_table_scan));
+
+        let plan = state
+            .explain_query_plan("SELECT name FROM explain_users WHERE id = 1".to_string())
+            .await
+            .expect("Explain failed");
+        assert!(!plan.is_empty());
+        assert!(!plan.iter().any(ExplainRow::is_table_scan));
+    }
+}
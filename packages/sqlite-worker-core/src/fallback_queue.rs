@@ -0,0 +1,298 @@
+use indexmap::IndexMap;
+use js_sys::{Function, Promise, Reflect};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::messages::PendingQuery;
+use crate::priority::QueryPriority;
+
+pub const DEFAULT_MAX_PENDING_QUERIES: usize = 1000;
+pub const DEFAULT_FALLBACK_QUEUE_SIZE: usize = 1000;
+
+/// A query waiting for a free slot in `pending_queries` before it is sent
+pub struct QueuedQuery {
+    pub query_id: String,
+    pub queued_at_ms: f64,
+    pub priority: QueryPriority,
+    resolve: Function,
+}
+
+impl std::fmt::Debug for QueuedQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueuedQuery")
+            .field("query_id", &self.query_id)
+            .field("queued_at_ms", &self.queued_at_ms)
+            .field("priority", &self.priority)
+            .finish_non_exhaustive()
+    }
+}
+
+pub type FallbackQueue = Rc<RefCell<VecDeque<QueuedQuery>>>;
+
+impl WorkerState {
+    /// Number of queries waiting for a slot in `pending_queries`
+    pub fn fallback_queue_len(&self) -> usize {
+        self.fallback_queue.borrow().len()
+    }
+
+    // Wait until `query_id` may be added to `pending_queries`. Queries wait in
+    // the fallback queue while it is full or others are already waiting,
+    // ordered by priority and then arrival, and fail with `QueueFull` if the
+    // fallback queue is full too.
+    pub(crate) async fn acquire_pending_slot(
+        &self,
+        query_id: &str,
+        priority: QueryPriority,
+    ) -> Result<(), WorkerError> {
+        let max_pending = self.config.max_pending_queries;
+        if self.fallback_queue.borrow().is_empty()
+            && self.pending_queries.borrow().len() + self.promoted_queries.get() < max_pending
+        {
+            return Ok(());
+        }
+        let max_queued = self.config.fallback_queue_size;
+        if self.fallback_queue.borrow().len() >= max_queued {
+            return Err(WorkerError::QueueFull {
+                pending: max_pending,
+                queued: max_queued,
+            });
+        }
+
+        let promise = Promise::new(&mut |resolve, _| {
+            let mut queue = self.fallback_queue.borrow_mut();
+            // Behind every query of the same or a higher priority
+            let position = queue
+                .iter()
+                .position(|queued| queued.priority > priority)
+                .unwrap_or(queue.len());
+            queue.insert(
+                position,
+                QueuedQuery {
+                    query_id: query_id.to_string(),
+                    queued_at_ms: js_sys::Date::now(),
+                    priority,
+                    resolve,
+                },
+            );
+        });
+        let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+        // The caller claims its slot before yielding again
+        self.promoted_queries.set(self.promoted_queries.get() - 1);
+        Ok(())
+    }
+
+    // Move waiting queries into free slots once the current task finishes.
+    // Without `queueMicrotask` they are moved at once.
+    pub(crate) fn schedule_fallback_drain(&self) {
+        if self.fallback_queue.borrow().is_empty() {
+            return;
+        }
+        let queue = Rc::clone(&self.fallback_queue);
+        let pending_queries = Rc::clone(&self.pending_queries);
+        let promoted = Rc::clone(&self.promoted_queries);
+        let max_pending = self.config.max_pending_queries;
+        let drain = move || drain_fallback_queue(&queue, &pending_queries, &promoted, max_pending);
+
+        let global = js_sys::global();
+        let queue_microtask = Reflect::get(&global, &JsValue::from_str("queueMicrotask"))
+            .ok()
+            .and_then(|queue_microtask| queue_microtask.dyn_into::<Function>().ok());
+        match queue_microtask {
+            Some(queue_microtask) => {
+                let _ = queue_microtask.call1(&JsValue::NULL, &Closure::once_into_js(drain));
+            }
+            None => drain(),
+        }
+    }
+}
+
+// Wake queued queries from the front while slots are free, counting each as
+// promoted until it adds itself to `pending_queries`
+fn drain_fallback_queue(
+    queue: &FallbackQueue,
+    pending_queries: &RefCell<IndexMap<String, PendingQuery>>,
+    promoted: &Cell<usize>,
+    max_pending: usize,
+) {
+    while pending_queries.borrow().len() + promoted.get() < max_pending {
+        let Some(queued) = queue.borrow_mut().pop_front() else {
+            break;
+        };
+        promoted.set(promoted.get() + 1);
+        let _ = queued.resolve.call0(&JsValue::NULL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, WorkerStateBuilder};
+    use crate::messages::{ChannelMessage, MessageDirection};
+    use crate::query_context::QueryContext;
+    use std::future::Future;
+    use std::task::Poll;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // Drive every future concurrently on this task, so all of them issue their
+    // requests before any response arrives
+    async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+        let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+        let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+        std::future::poll_fn(|cx| {
+            let mut done = true;
+            for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+                if output.is_none() {
+                    match future.as_mut().poll(cx) {
+                        Poll::Ready(value) => *output = Some(value),
+                        Poll::Pending => done = false,
+                    }
+                }
+            }
+            if done {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        outputs.into_iter().map(Option::unwrap).collect()
+    }
+
+    async fn leader_and_follower(
+        builder: WorkerStateBuilder,
+    ) -> Option<(WorkerState, WorkerState)> {
+        let leader = leader_with_db().await?;
+        leader.setup_channel_listener();
+
+        let follower = builder.build().ok()?;
+        follower.setup_channel_listener();
+        Some((leader, follower))
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_burst_beyond_pending_limit_resolves_in_order() {
+        let builder = WorkerStateBuilder::new()
+            .max_pending_queries(2)
+            .fallback_queue_size(16);
+        let Some((_leader, follower)) = leader_and_follower(builder).await else {
+            return;
+        };
+
+        let completed = Rc::new(RefCell::new(Vec::new()));
+        let queries = (0..10).map(|i| {
+            let completed = Rc::clone(&completed);
+            let follower = &follower;
+            async move {
+                let result = follower
+                    .execute_query(format!("SELECT {i} AS n"), QueryContext::default())
+                    .await;
+                assert!(follower.pending_queries.borrow().len() <= 2);
+                completed.borrow_mut().push(i);
+                result
+            }
+        });
+        let results = join_all(queries.collect()).await;
+
+        for (i, result) in results.into_iter().enumerate() {
+            let rows: serde_json::Value =
+                serde_json::from_str(&result.expect("Query failed")).expect("Invalid JSON");
+            assert_eq!(rows[0]["n"], i);
+        }
+        assert_eq!(*completed.borrow(), (0..10).collect::<Vec<_>>());
+        assert_eq!(follower.fallback_queue_len(), 0);
+        assert_eq!(follower.promoted_queries.get(), 0);
+    }
+
+    // SQL of the query requests the leader received, in order
+    fn received_sql(leader: &WorkerState) -> Vec<String> {
+        leader
+            .message_log()
+            .into_iter()
+            .filter(|entry| entry.direction == MessageDirection::Received)
+            .filter_map(|entry| match entry.message {
+                ChannelMessage::QueryRequest { sql, .. } => Some(sql),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn run_reads_and_write(auto_prioritize_writes: bool) -> Option<Vec<String>> {
+        let builder = WorkerStateBuilder::new()
+            .max_pending_queries(1)
+            .auto_prioritize_writes(auto_prioritize_writes);
+        let (leader, follower) = leader_and_follower(builder).await?;
+        leader
+            .execute_query_simple(
+                "CREATE TABLE IF NOT EXISTS prioritized_writes (x INTEGER)".to_string(),
+            )
+            .await
+            .ok()?;
+
+        let queries = [
+            "SELECT 1",
+            "SELECT 2",
+            "SELECT 3",
+            "INSERT INTO prioritized_writes VALUES (1)",
+        ]
+        .map(|sql| follower.execute_query(sql.to_string(), QueryContext::default()));
+        for result in join_all(queries.into_iter().collect()).await {
+            result.expect("Query failed");
+        }
+        Some(received_sql(&leader))
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_auto_prioritized_write_runs_before_queued_reads() {
+        let Some(order) = run_reads_and_write(true).await else {
+            return;
+        };
+        // The first read already holds the only slot when the others queue
+        assert_eq!(
+            order,
+            [
+                "SELECT 1",
+                "INSERT INTO prioritized_writes VALUES (1)",
+                "SELECT 2",
+                "SELECT 3"
+            ]
+        );
+
+        let Some(order) = run_reads_and_write(false).await else {
+            return;
+        };
+        assert_eq!(
+            order.last().map(String::as_str),
+            Some("INSERT INTO prioritized_writes VALUES (1)")
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_full_fallback_queue_rejects() {
+        let builder = WorkerStateBuilder::new()
+            .max_pending_queries(1)
+            .fallback_queue_size(1);
+        let Some((_leader, follower)) = leader_and_follower(builder).await else {
+            return;
+        };
+
+        let queries =
+            (0..3).map(|i| follower.execute_query(format!("SELECT {i}"), QueryContext::default()));
+        let results = join_all(queries.collect()).await;
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        assert_eq!(
+            results[2],
+            Err(WorkerError::QueueFull {
+                pending: 1,
+                queued: 1
+            }
+            .to_string())
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::{Interval, WorkerState};
+use crate::dispatcher::{MessageDispatcher, MessageKind};
+use crate::error::WorkerError;
+use crate::messages::{ChannelMessage, WorkerGossip};
+
+// Peers missing from gossip for this many intervals are dropped
+pub const GOSSIP_PEER_TIMEOUT_INTERVALS: u64 = 3;
+
+/// Merge `peers` into `known`, keeping whichever entry for each worker was seen
+/// most recently. Entries describing `self_id` are ignored, since a worker
+/// always knows its own state best.
+pub(crate) fn merge_gossip(
+    known: &mut HashMap<String, WorkerGossip>,
+    self_id: &str,
+    peers: Vec<WorkerGossip>,
+) {
+    for peer in peers {
+        if peer.worker_id == self_id {
+            continue;
+        }
+        let newer = known
+            .get(&peer.worker_id)
+            .is_none_or(|existing| peer.last_seen_ms > existing.last_seen_ms);
+        if newer {
+            known.insert(peer.worker_id.clone(), peer);
+        }
+    }
+}
+
+// Merge each `GossipState` that `worker_id` receives into `known_workers`
+pub(crate) fn register_gossip_handler(
+    dispatcher: &MessageDispatcher,
+    worker_id: String,
+    known_workers: Rc<RefCell<HashMap<String, WorkerGossip>>>,
+) {
+    dispatcher.register(
+        MessageKind::GossipState,
+        Box::new(move |message| {
+            if let ChannelMessage::GossipState { peers, .. } = message {
+                merge_gossip(&mut known_workers.borrow_mut(), &worker_id, peers);
+            }
+        }),
+    );
+}
+
+// Drop peers last seen before `cutoff_ms`
+fn prune_gossip(known: &mut HashMap<String, WorkerGossip>, cutoff_ms: f64) {
+    known.retain(|_, peer| peer.last_seen_ms >= cutoff_ms);
+}
+
+impl WorkerState {
+    /// Workers this one has heard about through gossip, sorted by worker id
+    pub fn known_workers(&self) -> Vec<WorkerGossip> {
+        let mut workers: Vec<WorkerGossip> =
+            self.known_workers.borrow().values().cloned().collect();
+        workers.sort_by(|a, b| a.worker_id.cmp(&b.worker_id));
+        workers
+    }
+
+    /// Merge gossip received from another worker into the local view
+    pub fn merge_gossip(&self, peers: Vec<WorkerGossip>) {
+        merge_gossip(&mut self.known_workers.borrow_mut(), &self.worker_id, peers);
+    }
+
+    /// Broadcast this worker's view of the cluster every `interval_ms`,
+    /// pruning peers not seen for `GOSSIP_PEER_TIMEOUT_INTERVALS` intervals.
+    /// Restarting replaces any loop already running.
+    pub fn start_gossip_loop(&self, interval_ms: u64) -> Result<(), WorkerError> {
+        self.stop_gossip_loop();
+
+        let worker_id = self.worker_id.clone();
+        let is_leader = Rc::clone(&self.is_leader);
+        let known_workers = Rc::clone(&self.known_workers);
+        let sender = self.sender();
+        let timeout_ms = (interval_ms * GOSSIP_PEER_TIMEOUT_INTERVALS) as f64;
+
+        let tick = Closure::wrap(Box::new(move || {
+            let now_ms = js_sys::Date::now();
+            let mut peers = {
+                let mut known = known_workers.borrow_mut();
+                prune_gossip(&mut known, now_ms - timeout_ms);
+                known.values().cloned().collect::<Vec<_>>()
+            };
+            peers.push(WorkerGossip {
+                worker_id: worker_id.clone(),
+                is_leader: *is_leader.borrow(),
+                last_seen_ms: now_ms,
+            });
+            sender.post(
+                ChannelMessage::GossipState {
+                    from: worker_id.clone(),
+                    peers,
+                },
+                None,
+            );
+        }) as Box<dyn FnMut()>);
+
+        *self.gossip_interval.borrow_mut() = Some(Interval::start(tick, interval_ms)?);
+        Ok(())
+    }
+
+    /// Stop the loop started by `start_gossip_loop`, if any
+    pub fn stop_gossip_loop(&self) {
+        self.gossip_interval.borrow_mut().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::sleep;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn gossip(worker_id: &str, is_leader: bool, last_seen_ms: f64) -> WorkerGossip {
+        WorkerGossip {
+            worker_id: worker_id.to_string(),
+            is_leader,
+            last_seen_ms,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_merge_latest_timestamp_wins() {
+        let mut known = HashMap::new();
+        merge_gossip(&mut known, "self", vec![gossip("a", true, 100.0)]);
+
+        // Two peers disagree about whether `a` leads; the fresher report wins
+        merge_gossip(&mut known, "self", vec![gossip("a", false, 200.0)]);
+        assert!(!known["a"].is_leader);
+
+        merge_gossip(&mut known, "self", vec![gossip("a", true, 150.0)]);
+        assert!(!known["a"].is_leader, "Stale gossip must not override");
+        assert_eq!(known["a"].last_seen_ms, 200.0);
+
+        merge_gossip(
+            &mut known,
+            "self",
+            vec![gossip("b", true, 50.0), gossip("self", true, 999.0)],
+        );
+        assert_eq!(known.len(), 2);
+        assert!(!known.contains_key("self"), "Own entry is never merged");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_prune_gossip() {
+        let mut known = HashMap::new();
+        merge_gossip(
+            &mut known,
+            "self",
+            vec![gossip("old", false, 100.0), gossip("fresh", false, 500.0)],
+        );
+        prune_gossip(&mut known, 300.0);
+        assert_eq!(known.keys().collect::<Vec<_>>(), ["fresh"]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_gossip_loop_announces_worker() {
+        let (Ok(gossiper), Ok(listener)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        listener.setup_channel_listener();
+        *gossiper.is_leader.borrow_mut() = true;
+
+        gossiper.start_gossip_loop(20).expect("Should start");
+        sleep(80.0).await;
+        gossiper.stop_gossip_loop();
+        assert!(gossiper.gossip_interval.borrow().is_none());
+
+        let known = listener.known_workers();
+        let entry = known
+            .iter()
+            .find(|peer| peer.worker_id == gossiper.worker_id)
+            .expect("Gossiper should be known");
+        assert!(entry.is_leader);
+    }
+}
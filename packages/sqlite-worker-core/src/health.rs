@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::priority::PriorityCounts;
+
+/// Point-in-time summary of a worker's state for diagnostics
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    #[serde(rename = "workerId")]
+    pub worker_id: String,
+    #[serde(rename = "isLeader")]
+    pub is_leader: bool,
+    #[serde(rename = "dbInitialized")]
+    pub db_initialized: bool,
+    #[serde(rename = "knownLeaderId")]
+    pub known_leader_id: Option<String>,
+    #[serde(rename = "pendingQueries")]
+    pub pending_queries: usize,
+    #[serde(rename = "pendingByPriority")]
+    pub pending_by_priority: PriorityCounts,
+    #[serde(rename = "leadershipAcquisitionMs")]
+    pub leadership_acquisition_ms: Option<f64>,
+    #[serde(rename = "messagesFailedToSend")]
+    pub messages_failed_to_send: u64,
+    // See `WorkerState::list_savepoints`
+    #[serde(rename = "openSavepoints")]
+    pub open_savepoints: Vec<String>,
+    // Storage figures, only filled in by `health_report_with_storage`
+    #[serde(rename = "pageCount")]
+    pub page_count: Option<u64>,
+    #[serde(rename = "freePageCount")]
+    pub free_page_count: Option<u64>,
+    #[serde(rename = "fragmentationRatio")]
+    pub fragmentation_ratio: Option<f64>,
+}
+
+/// The leader's status as reported in its last `StateResponse`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeaderState {
+    #[serde(rename = "leaderId")]
+    pub leader_id: String,
+    #[serde(rename = "dbInitialized")]
+    pub db_initialized: bool,
+    // Queries the leader itself is waiting on
+    #[serde(rename = "pendingQueueDepth")]
+    pub pending_queue_depth: u32,
+    // Time since the leader's worker state was created
+    #[serde(rename = "uptimeMs")]
+    pub uptime_ms: f64,
+}
+
+impl WorkerState {
+    pub fn health_report(&self) -> HealthReport {
+        HealthReport {
+            worker_id: self.worker_id.clone(),
+            is_leader: *self.is_leader.borrow(),
+            db_initialized: self.db.borrow().is_some(),
+            known_leader_id: self.known_leader_id.borrow().clone(),
+            pending_queries: self.pending_queries.borrow().len(),
+            pending_by_priority: PriorityCounts::from(&self.count_pending_by_priority()),
+            leadership_acquisition_ms: self.leadership_acquisition_ms.get(),
+            messages_failed_to_send: self.messages_failed_to_send(),
+            open_savepoints: self.list_savepoints(),
+            page_count: None,
+            free_page_count: None,
+            fragmentation_ratio: None,
+        }
+    }
+
+    /// `health_report` plus the database's page counts, which are left empty
+    /// if they cannot be read
+    pub async fn health_report_with_storage(&self) -> HealthReport {
+        let mut report = self.health_report();
+        if let (Ok(pages), Ok(free)) = (
+            self.database_page_count().await,
+            self.database_free_page_count().await,
+        ) {
+            report.page_count = Some(pages);
+            report.free_page_count = Some(free);
+            report.fragmentation_ratio = Some(fragmentation_ratio(free, pages));
+        }
+        report
+    }
+
+    /// Number of pages in the database file
+    pub async fn database_page_count(&self) -> Result<u64, WorkerError> {
+        self.pragma_count("page_count").await
+    }
+
+    /// Number of unused pages waiting to be reused or removed by `VACUUM`
+    pub async fn database_free_page_count(&self) -> Result<u64, WorkerError> {
+        self.pragma_count("freelist_count").await
+    }
+
+    /// Share of the database's pages that are free, from 0.0 to 1.0
+    pub async fn database_fragmentation_ratio(&self) -> Result<f64, WorkerError> {
+        let pages = self.database_page_count().await?;
+        let free = self.database_free_page_count().await?;
+        Ok(fragmentation_ratio(free, pages))
+    }
+
+    /// The linked SQLite library's version, such as `"3.45.1"`
+    pub async fn get_sqlite_version(&self) -> Result<String, WorkerError> {
+        let (version,): (String,) = self
+            .execute_parameterized("SELECT sqlite_version()".to_string(), Vec::new())
+            .await?
+            .get_single()?;
+        Ok(version)
+    }
+
+    // Fail with `FeatureUnsupported` unless the linked SQLite is at least
+    // `required`
+    pub(crate) async fn require_sqlite_version(
+        &self,
+        feature: &str,
+        required: (u32, u32, u32),
+    ) -> Result<(), WorkerError> {
+        let version = self.get_sqlite_version().await?;
+        if parse_sqlite_version(&version).is_none_or(|found| found < required) {
+            let (major, minor, patch) = required;
+            return Err(WorkerError::FeatureUnsupported {
+                feature: feature.to_string(),
+                required: format!("{major}.{minor}.{patch}"),
+                found: version,
+            });
+        }
+        Ok(())
+    }
+
+    async fn pragma_count(&self, pragma: &str) -> Result<u64, WorkerError> {
+        let (count,): (i64,) = self
+            .execute_parameterized(format!("PRAGMA {pragma}"), Vec::new())
+            .await?
+            .get_single()?;
+        u64::try_from(count).map_err(|_| WorkerError::RowDecode(format!("negative {pragma}")))
+    }
+}
+
+/// `(major, minor, patch)` from a version string such as `"3.45.1"`
+pub fn parse_sqlite_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split('.').map(str::parse::<u32>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+fn fragmentation_ratio(free_pages: u64, total_pages: u64) -> f64 {
+    if total_pages == 0 {
+        0.0
+    } else {
+        free_pages as f64 / total_pages as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_health_report_for_new_worker() {
+        if let Ok(state) = WorkerState::new() {
+            let report = state.health_report();
+            assert_eq!(report.worker_id, state.worker_id);
+            assert!(!report.is_leader);
+            assert!(!report.db_initialized);
+            assert_eq!(report.known_leader_id, None);
+            assert_eq!(report.pending_queries, 0);
+            assert_eq!(report.pending_by_priority, PriorityCounts::default());
+            assert_eq!(report.leadership_acquisition_ms, None);
+            assert_eq!(report.messages_failed_to_send, 0);
+            assert!(report.open_savepoints.is_empty());
+
+            let json = serde_json::to_string(&report).expect("Should serialize");
+            assert!(json.contains("\"pendingByPriority\":{\"high\":0,\"normal\":0,\"low\":0}"));
+            assert!(json.contains("\"leadershipAcquisitionMs\":null"));
+            assert_eq!(report.page_count, None);
+            assert_eq!(report.fragmentation_ratio, None);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_follower_reads_leader_state() {
+        let (Ok(leader), Ok(follower)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        *leader.is_leader.borrow_mut() = true;
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        assert_eq!(follower.last_leader_state(), None);
+
+        let state = follower
+            .request_leader_state()
+            .await
+            .expect("Leader should respond");
+        assert_eq!(state.leader_id, leader.worker_id);
+        assert!(!state.db_initialized);
+        assert_eq!(state.pending_queue_depth, 0);
+        assert!(state.uptime_ms >= 0.0);
+        assert_eq!(follower.last_leader_state(), Some(state));
+
+        let Ok(database) = crate::database::SQLiteDatabase::initialize_opfs().await else {
+            return;
+        };
+        *leader.db.borrow_mut() = Some(std::rc::Rc::new(database));
+        let state = follower
+            .request_leader_state()
+            .await
+            .expect("Leader should respond");
+        assert!(state.db_initialized);
+
+        // The leader answers for itself without the channel
+        assert!(leader.request_leader_state().await.unwrap().db_initialized);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_request_leader_state_without_leader() {
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            follower.request_leader_state().await,
+            Err(WorkerError::NoLeaderAvailable)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fragmentation_ratio() {
+        assert_eq!(fragmentation_ratio(0, 0), 0.0);
+        assert_eq!(fragmentation_ratio(0, 10), 0.0);
+        assert_eq!(fragmentation_ratio(5, 20), 0.25);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_database_page_counts() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let pages = state
+            .database_page_count()
+            .await
+            .expect("page_count failed");
+        let free = state
+            .database_free_page_count()
+            .await
+            .expect("freelist_count failed");
+        assert!(free <= pages);
+
+        let ratio = state
+            .database_fragmentation_ratio()
+            .await
+            .expect("Ratio failed");
+        assert!((0.0..=1.0).contains(&ratio));
+
+        let report = state.health_report_with_storage().await;
+        assert!(report.page_count.is_some());
+        assert!(report
+            .fragmentation_ratio
+            .is_some_and(|ratio| (0.0..=1.0).contains(&ratio)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parse_sqlite_version() {
+        assert_eq!(parse_sqlite_version("3.45.1"), Some((3, 45, 1)));
+        assert_eq!(parse_sqlite_version("3.35"), Some((3, 35, 0)));
+        assert_eq!(parse_sqlite_version("three"), None);
+        assert!(parse_sqlite_version("3.34.1") < parse_sqlite_version("3.35.0"));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::error::WorkerError;
+
+/// Whether `name` matches `^[A-Za-z_][A-Za-z0-9_]*$` and can be spliced into
+/// SQL as a table or column name without quoting
+pub fn is_safe_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+pub fn validate_identifier(name: &str) -> Result<(), WorkerError> {
+    if is_safe_identifier(name) {
+        Ok(())
+    } else {
+        Err(WorkerError::InvalidIdentifier(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_is_safe_identifier() {
+        for name in ["users", "_private", "Table_2", "a"] {
+            assert!(is_safe_identifier(name), "{name} should be safe");
+        }
+        for name in [
+            "",
+            "2fast",
+            "drop table",
+            "users;--",
+            "a-b",
+            "\"quoted\"",
+            "café",
+        ] {
+            assert!(!is_safe_identifier(name), "{name} should be rejected");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_validate_identifier() {
+        assert_eq!(validate_identifier("users"), Ok(()));
+        assert_eq!(
+            validate_identifier("users; DROP TABLE users"),
+            Err(WorkerError::InvalidIdentifier(
+                "users; DROP TABLE users".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use js_sys::{Array, Function, Promise, Reflect, Uint8Array};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::coordination::WorkerState;
+use crate::database::{SQLiteDatabase, DB_FILE_NAME};
+use crate::error::WorkerError;
+
+/// Object store `copy_to_indexeddb` writes to, holding the copy under the
+/// key `DB_FILE_NAME`
+pub const INDEXEDDB_BACKUP_STORE: &str = "sqlite-backups";
+// Pages copied between progress reports
+const BACKUP_STEP_PAGES: i32 = 64;
+
+fn idb_failed(context: &str, error: JsValue) -> WorkerError {
+    WorkerError::IndexedDbFailed(format!("{context}: {error:?}"))
+}
+
+// Call `target.{method}(...args)`
+fn call_method(target: &JsValue, method: &str, args: &[JsValue]) -> Result<JsValue, WorkerError> {
+    let function: Function = Reflect::get(target, &JsValue::from_str(method))
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| idb_failed(method, e))?;
+    let args: Array = args.iter().collect();
+    Reflect::apply(&function, target, &args).map_err(|e| idb_failed(method, e))
+}
+
+// Wait for the `event` event of an IndexedDB request or transaction, failing
+// with its `error` if it reports one or is aborted instead
+async fn wait_for(target: &JsValue, event: &str) -> Result<(), WorkerError> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let _ = Reflect::set(target, &JsValue::from_str(&format!("on{event}")), &resolve);
+        let _ = Reflect::set(target, &JsValue::from_str("onerror"), &reject);
+        let _ = Reflect::set(target, &JsValue::from_str("onabort"), &reject);
+    });
+    JsFuture::from(promise).await.map(drop).map_err(|_| {
+        let error = Reflect::get(target, &JsValue::from_str("error")).unwrap_or(JsValue::NULL);
+        idb_failed(event, error)
+    })
+}
+
+// Open the IndexedDB database `name`, creating the backup store if an upgrade
+// to `version` is needed
+async fn open_idb(name: &str, version: Option<f64>) -> Result<JsValue, WorkerError> {
+    let factory = Reflect::get(&js_sys::global(), &JsValue::from_str("indexedDB"))
+        .ok()
+        .filter(JsValue::is_object)
+        .ok_or_else(|| WorkerError::IndexedDbFailed("indexedDB is not available".to_string()))?;
+    let mut args = vec![JsValue::from_str(name)];
+    args.extend(version.map(JsValue::from_f64));
+    let request = call_method(&factory, "open", &args)?;
+
+    let upgrade = {
+        let request = request.clone();
+        Closure::<dyn FnMut()>::new(move || {
+            if let Ok(db) = Reflect::get(&request, &JsValue::from_str("result")) {
+                if !has_backup_store(&db) {
+                    let _ = call_method(
+                        &db,
+                        "createObjectStore",
+                        &[JsValue::from_str(INDEXEDDB_BACKUP_STORE)],
+                    );
+                }
+            }
+        })
+    };
+    let _ = Reflect::set(
+        &request,
+        &JsValue::from_str("onupgradeneeded"),
+        upgrade.as_ref(),
+    );
+    wait_for(&request, "success").await?;
+    Reflect::get(&request, &JsValue::from_str("result")).map_err(|e| idb_failed("open", e))
+}
+
+fn has_backup_store(db: &JsValue) -> bool {
+    Reflect::get(db, &JsValue::from_str("objectStoreNames"))
+        .and_then(|names| {
+            call_method(
+                &names,
+                "contains",
+                &[JsValue::from_str(INDEXEDDB_BACKUP_STORE)],
+            )
+            .map_err(|_| JsValue::NULL)
+        })
+        .is_ok_and(|contains| contains.as_bool() == Some(true))
+}
+
+// The IndexedDB database `name` with the backup store, bumping its version to
+// add the store if it was created by something else
+async fn open_backup_idb(name: &str) -> Result<JsValue, WorkerError> {
+    let db = open_idb(name, None).await?;
+    if has_backup_store(&db) {
+        return Ok(db);
+    }
+    let version = Reflect::get(&db, &JsValue::from_str("version"))
+        .ok()
+        .and_then(|version| version.as_f64())
+        .unwrap_or(0.0);
+    call_method(&db, "close", &[])?;
+    open_idb(name, Some(version + 1.0)).await
+}
+
+// Store `bytes` under `DB_FILE_NAME` in the backup store of the IndexedDB
+// database `name`
+async fn idb_write_bytes(name: &str, bytes: &[u8]) -> Result<(), WorkerError> {
+    let db = open_backup_idb(name).await?;
+    let store_name = JsValue::from_str(INDEXEDDB_BACKUP_STORE);
+    let result = async {
+        let transaction = call_method(
+            &db,
+            "transaction",
+            &[store_name.clone(), JsValue::from_str("readwrite")],
+        )?;
+        let store = call_method(&transaction, "objectStore", &[store_name])?;
+        call_method(
+            &store,
+            "put",
+            &[
+                Uint8Array::from(bytes).into(),
+                JsValue::from_str(DB_FILE_NAME),
+            ],
+        )?;
+        wait_for(&transaction, "complete").await
+    }
+    .await;
+    let _ = call_method(&db, "close", &[]);
+    result
+}
+
+// Copy `database` with the online backup API `BACKUP_STEP_PAGES` pages at a
+// time, calling `on_progress` with the pages copied and the total after each
+// step, and return the copy's bytes
+fn backup_to_bytes(
+    database: &Rc<SQLiteDatabase>,
+    on_progress: &dyn Fn(u32, u32),
+) -> Result<Vec<u8>, WorkerError> {
+    let backup = database.backup_to_memory()?;
+    loop {
+        let done = backup.step(BACKUP_STEP_PAGES)?;
+        let total = backup.page_count().max(0) as u32;
+        let remaining = backup.remaining().max(0) as u32;
+        on_progress(total - remaining.min(total), total);
+        if done {
+            break;
+        }
+    }
+    let bytes = backup.target_bytes()?;
+    backup.finish()?;
+    Ok(bytes)
+}
+
+impl WorkerState {
+    /// Back up the leader's database into the IndexedDB database
+    /// `idb_db_name`, whose storage browsers evict separately from OPFS. The
+    /// pages are copied with SQLite's online backup API, calling `on_progress`
+    /// with the pages copied so far and the total, and the resulting file is
+    /// stored in `INDEXEDDB_BACKUP_STORE` under `DB_FILE_NAME`, replacing any
+    /// earlier copy. Open a copy read back from there with
+    /// `SQLiteDatabase::from_bytes`. The callback cannot be sent to another
+    /// worker, so this fails with `NotLeader` elsewhere.
+    pub async fn copy_to_indexeddb(
+        &self,
+        idb_db_name: &str,
+        on_progress: impl Fn(u32, u32),
+    ) -> Result<(), WorkerError> {
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        let database = self
+            .db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)?;
+        let bytes = backup_to_bytes(&database, &on_progress)?;
+        idb_write_bytes(idb_db_name, &bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::lead_with_db;
+    use std::cell::RefCell;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // The bytes `copy_to_indexeddb` stored in the IndexedDB database `name`
+    async fn idb_read_bytes(name: &str) -> Result<Vec<u8>, WorkerError> {
+        let db = open_backup_idb(name).await?;
+        let store_name = JsValue::from_str(INDEXEDDB_BACKUP_STORE);
+        let transaction = call_method(&db, "transaction", &[store_name.clone()])?;
+        let store = call_method(&transaction, "objectStore", &[store_name])?;
+        let request = call_method(&store, "get", &[JsValue::from_str(DB_FILE_NAME)])?;
+        wait_for(&request, "success").await?;
+        let bytes = Reflect::get(&request, &JsValue::from_str("result"))
+            .ok()
+            .and_then(|result| result.dyn_into::<Uint8Array>().ok())
+            .map(|bytes| bytes.to_vec());
+        let _ = call_method(&db, "close", &[]);
+        bytes.ok_or_else(|| WorkerError::IndexedDbFailed("no backup stored".to_string()))
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_copy_to_indexeddb() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            state.copy_to_indexeddb("idb-backup-test", |_, _| {}).await,
+            Err(WorkerError::NotLeader)
+        );
+        let Some(state) = lead_with_db(state).await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS idb_items".to_string(),
+                "CREATE TABLE idb_items (id INTEGER, payload TEXT)".to_string(),
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200) \
+                 INSERT INTO idb_items SELECT i, hex(randomblob(512)) FROM n"
+                    .to_string(),
+            ])
+            .await
+            .expect("Seed failed");
+
+        let progress = RefCell::new(Vec::new());
+        state
+            .copy_to_indexeddb("idb-backup-test", |copied, total| {
+                progress.borrow_mut().push((copied, total))
+            })
+            .await
+            .expect("Copy failed");
+        let progress = progress.into_inner();
+        assert!(progress.len() > 1, "Progress: {progress:?}");
+        let &(copied, total) = progress.last().unwrap();
+        assert_eq!(copied, total);
+
+        let bytes = idb_read_bytes("idb-backup-test")
+            .await
+            .expect("Read failed");
+        let copy = SQLiteDatabase::from_bytes(&bytes).expect("Open copy failed");
+        let result = copy
+            .query("SELECT count(*) FROM idb_items")
+            .await
+            .expect("Copy should contain the table");
+        assert_eq!(result.rows, vec![vec![200i64.into()]]);
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::SqlParam;
+use crate::row::FromRow;
+
+/// An index on a table, as reported by `PRAGMA index_list` and `PRAGMA index_info`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub name: String,
+    // Indexed columns in index order
+    pub columns: Vec<String>,
+    pub unique: bool,
+    // Whether the index was created with a WHERE clause
+    pub partial: bool,
+}
+
+impl WorkerState {
+    /// The indexes on `table`, including those SQLite creates for UNIQUE and
+    /// PRIMARY KEY constraints
+    pub async fn list_indexes(&self, table: &str) -> Result<Vec<IndexInfo>, WorkerError> {
+        validate_identifier(table)?;
+        let indexes: Vec<(String, bool, bool)> = self
+            .execute_parameterized(
+                "SELECT name, \"unique\", partial FROM pragma_index_list(?)".to_string(),
+                vec![SqlParam::from(table)],
+            )
+            .await?
+            .into_iter()
+            .map(|row| FromRow::from_row(&row))
+            .collect::<Result<_, _>>()?;
+
+        let mut infos = Vec::with_capacity(indexes.len());
+        for (name, unique, partial) in indexes {
+            // Expression columns have no name and come back as NULL
+            let columns: Vec<(Option<String>,)> = self
+                .execute_parameterized(
+                    "SELECT name FROM pragma_index_info(?) ORDER BY seqno".to_string(),
+                    vec![SqlParam::from(name.as_str())],
+                )
+                .await?
+                .into_iter()
+                .map(|row| FromRow::from_row(&row))
+                .collect::<Result<_, _>>()?;
+            infos.push(IndexInfo {
+                name,
+                columns: columns
+                    .into_iter()
+                    .map(|(column,)| column.unwrap_or_default())
+                    .collect(),
+                unique,
+                partial,
+            });
+        }
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(infos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_list_indexes_rejects_invalid_table() {
+        if let Ok(state) = WorkerState::new() {
+            let result = state.list_indexes("items; DROP TABLE items").await;
+            assert!(matches!(result, Err(WorkerError::InvalidIdentifier(_))));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_list_indexes() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for sql in [
+            "DROP TABLE IF EXISTS index_items",
+            "CREATE TABLE index_items (id INTEGER PRIMARY KEY, owner TEXT, slug TEXT, archived INTEGER)",
+            "CREATE UNIQUE INDEX index_items_owner_slug ON index_items (owner, slug)",
+            "CREATE INDEX index_items_live ON index_items (slug) WHERE archived = 0",
+        ] {
+            state
+                .execute_parameterized(sql.to_string(), Vec::new())
+                .await
+                .expect("Setup failed");
+        }
+
+        let indexes = state
+            .list_indexes("index_items")
+            .await
+            .expect("Listing indexes failed");
+        assert_eq!(
+            indexes,
+            vec![
+                IndexInfo {
+                    name: "index_items_live".to_string(),
+                    columns: vec!["slug".to_string()],
+                    unique: false,
+                    partial: true,
+                },
+                IndexInfo {
+                    name: "index_items_owner_slug".to_string(),
+                    columns: vec!["owner".to_string(), "slug".to_string()],
+                    unique: true,
+                    partial: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::{QueryResult, SqlParam};
+
+// First SQLite release with the JSON functions built in by default
+const JSON_MIN_VERSION: (u32, u32, u32) = (3, 38, 0);
+
+impl WorkerState {
+    /// Run `SELECT {projection} FROM json_each(?)` with `json_str` bound as
+    /// the parameter, giving one row per top-level element. `projection` is
+    /// inserted verbatim, so it must come from trusted code. Needs SQLite
+    /// 3.38 or later.
+    pub async fn execute_json_each(
+        &self,
+        json_str: String,
+        projection: String,
+    ) -> Result<QueryResult, WorkerError> {
+        self.require_sqlite_version("JSON functions", JSON_MIN_VERSION)
+            .await?;
+        self.execute_parameterized(
+            format!("SELECT {projection} FROM json_each(?)"),
+            vec![SqlParam::Text(json_str)],
+        )
+        .await
+    }
+
+    /// Like `execute_json_each`, but walks every element below `path`, such
+    /// as `"$.items"`, using `json_tree`
+    pub async fn execute_json_tree(
+        &self,
+        json_str: String,
+        path: String,
+        projection: String,
+    ) -> Result<QueryResult, WorkerError> {
+        self.require_sqlite_version("JSON functions", JSON_MIN_VERSION)
+            .await?;
+        self.execute_parameterized(
+            format!("SELECT {projection} FROM json_tree(?, ?)"),
+            vec![SqlParam::Text(json_str), SqlParam::Text(path)],
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_execute_json_each_array() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let result = state
+            .execute_json_each(r#"[10, "two", null]"#.to_string(), "key, value".to_string())
+            .await
+            .expect("json_each failed");
+        assert_eq!(result.columns, vec!["key", "value"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![SqlParam::Integer(0), SqlParam::Integer(10)],
+                vec![SqlParam::Integer(1), SqlParam::from("two")],
+                vec![SqlParam::Integer(2), SqlParam::Null],
+            ]
+        );
+
+        let (total,): (i64,) = state
+            .execute_json_each("[1, 2, 3]".to_string(), "sum(value)".to_string())
+            .await
+            .expect("json_each failed")
+            .get_single()
+            .unwrap();
+        assert_eq!(total, 6);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_json_tree_nested_object() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let json = r#"{"user": {"name": "Alice", "tags": ["admin", "ops"]}, "id": 7}"#;
+        let result = state
+            .execute_json_tree(
+                json.to_string(),
+                "$.user".to_string(),
+                "fullkey, atom".to_string(),
+            )
+            .await
+            .expect("json_tree failed");
+
+        // Containers have no atom, so keep only the leaves
+        let leaves: Vec<_> = result
+            .rows
+            .into_iter()
+            .filter(|row| row[1] != SqlParam::Null)
+            .collect();
+        assert_eq!(
+            leaves,
+            vec![
+                vec![SqlParam::from("$.user.name"), SqlParam::from("Alice")],
+                vec![SqlParam::from("$.user.tags[0]"), SqlParam::from("admin")],
+                vec![SqlParam::from("$.user.tags[1]"), SqlParam::from("ops")],
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_json_each_rejects_invalid_json() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let result = state
+            .execute_json_each("[1, 2".to_string(), "value".to_string())
+            .await;
+        assert!(result.is_err());
+    }
+}
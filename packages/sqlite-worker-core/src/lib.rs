@@ -1,9 +1,60 @@
 use wasm_bindgen::prelude::*;
 
+mod analysis;
+mod api;
+mod authorizer;
+mod backup;
+mod busy_retry;
+mod change_tracking;
+mod circuit_breaker;
 mod coordination;
+mod cross_db;
+mod csv;
 mod database;
 mod database_functions;
+mod ddl;
+mod dispatcher;
+mod dom_events;
+mod encryption;
+mod error;
+mod events;
+mod explain;
+mod fallback_queue;
+mod gossip;
+mod health;
+mod identifier;
+mod indexeddb;
+mod indexes;
+mod json;
+mod locks;
 mod messages;
+mod micro_batch;
+mod observer;
+mod pragma;
+mod prepared;
+mod priority;
+mod progress;
+mod query_builder;
+mod query_cache;
+mod query_context;
+mod query_result;
+mod query_stats;
+mod read_concern;
+mod read_transaction;
+mod remote;
+mod reset;
+mod row;
+mod shared_state;
+mod sql_classifier;
+mod storage;
+mod strict;
+mod sw;
+mod time;
+mod triggers;
+mod type_bindings;
+mod visibility;
+mod vtab;
+mod watch;
 mod worker;
 
 // Export the worker entry point
@@ -13,16 +64,68 @@ pub fn worker_main() {
     let _ = worker::main();
 }
 
+// Export the service worker entry point. The service worker never leads, as
+// it cannot open the database, and only forwards read queries. Like the
+// dedicated worker's, it is an export of this library rather than a binary
+// target, as wasm-bindgen only generates the JS bindings a worker script
+// imports for a cdylib.
+#[wasm_bindgen]
+pub fn sw_main() {
+    console_error_panic_hook::set_once();
+    let _ = sw::main();
+}
+
 // Re-export modules that might be needed
+pub use analysis::*;
+pub use api::*;
+pub use authorizer::*;
+pub use backup::*;
+pub use busy_retry::*;
+pub use change_tracking::*;
+pub use circuit_breaker::*;
 pub use coordination::*;
+pub use csv::*;
 pub use database::*;
+pub use dispatcher::*;
+pub use dom_events::*;
+pub use encryption::*;
+pub use error::*;
+pub use events::*;
+pub use explain::*;
+pub use fallback_queue::*;
+pub use gossip::*;
+pub use health::*;
+pub use identifier::*;
+pub use indexeddb::*;
+pub use indexes::*;
+pub use locks::*;
 pub use messages::*;
+pub use micro_batch::*;
+pub use observer::*;
+pub use prepared::*;
+pub use priority::*;
+pub use query_builder::*;
+pub use query_cache::*;
+pub use query_context::*;
+pub use query_result::*;
+pub use query_stats::*;
+pub use read_concern::*;
+pub use read_transaction::*;
+pub use row::*;
+pub use shared_state::*;
+pub use sql_classifier::*;
+pub use storage::*;
+pub use sw::handle_fetch;
+pub use time::*;
+pub use triggers::*;
+pub use vtab::*;
+pub use watch::*;
 
 #[cfg(test)]
 mod tests {
     use wasm_bindgen_test::*;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
 
     #[wasm_bindgen_test]
     fn worker_main_does_not_panic() {}
@@ -0,0 +1,188 @@
+use js_sys::{Function, Object, Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::error::WorkerError;
+
+/// Web Locks request mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockMode {
+    #[default]
+    Exclusive,
+    Shared,
+}
+
+impl LockMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockMode::Exclusive => "exclusive",
+            LockMode::Shared => "shared",
+        }
+    }
+}
+
+/// Holds a Web Lock until dropped
+pub struct LockGuard {
+    name: String,
+    mode: LockMode,
+    // Resolves the promise returned to the lock manager, which releases the lock
+    release: Function,
+}
+
+impl LockGuard {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl std::fmt::Debug for LockGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LockGuard")
+            .field("name", &self.name)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = self.release.call0(&JsValue::NULL);
+    }
+}
+
+// navigator.locks from the current global scope
+fn lock_manager() -> Result<JsValue, WorkerError> {
+    let global = js_sys::global();
+    let navigator = Reflect::get(&global, &JsValue::from_str("navigator"))
+        .map_err(|_| WorkerError::NavigatorLocksUnsupported)?;
+    let locks = Reflect::get(&navigator, &JsValue::from_str("locks"))
+        .map_err(|_| WorkerError::NavigatorLocksUnsupported)?;
+    if locks.is_object() {
+        Ok(locks)
+    } else {
+        Err(WorkerError::NavigatorLocksUnsupported)
+    }
+}
+
+/// Wait until the Web Lock `name` is granted in `mode` and hold it until the
+/// returned guard is dropped
+pub async fn acquire_named_lock(name: &str, mode: LockMode) -> Result<LockGuard, WorkerError> {
+    let locks = lock_manager()?;
+    let request_fn = Reflect::get(&locks, &JsValue::from_str("request"))
+        .ok()
+        .and_then(|request| request.dyn_into::<Function>().ok())
+        .ok_or(WorkerError::NavigatorLocksUnsupported)?;
+
+    let options = Object::new();
+    Reflect::set(
+        &options,
+        &JsValue::from_str("mode"),
+        &JsValue::from_str(mode.as_str()),
+    )
+    .map_err(|e| WorkerError::QueryFailed(format!("{e:?}")))?;
+
+    let mut granted_callbacks = None;
+    let granted = Promise::new(&mut |resolve, reject| {
+        granted_callbacks = Some((resolve, reject));
+    });
+    let (granted_resolve, granted_reject) =
+        granted_callbacks.expect("Promise executor runs synchronously");
+
+    // Hand the release function back to the caller and keep the lock until it is called
+    let handler = Closure::once(move |_lock: JsValue| -> Promise {
+        Promise::new(&mut |release, _| {
+            let _ = granted_resolve.call1(&JsValue::NULL, &release);
+        })
+    });
+    let on_error = Closure::once(move |err: JsValue| {
+        let _ = granted_reject.call1(&JsValue::NULL, &err);
+    });
+
+    let request = request_fn
+        .call3(
+            &locks,
+            &JsValue::from_str(name),
+            &options,
+            handler.as_ref().unchecked_ref(),
+        )
+        .map_err(|e| WorkerError::QueryFailed(format!("Lock request failed: {e:?}")))?;
+    let _ = Promise::from(request).catch(&on_error);
+
+    // The lock manager may call these after this future is dropped
+    handler.forget();
+    on_error.forget();
+
+    let release = JsFuture::from(granted)
+        .await
+        .map_err(|e| WorkerError::QueryFailed(format!("Lock request failed: {e:?}")))?;
+
+    Ok(LockGuard {
+        name: name.to_string(),
+        mode,
+        release: release.unchecked_into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::sleep;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use wasm_bindgen_futures::spawn_local;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_lock_mode_as_str() {
+        assert_eq!(LockMode::Exclusive.as_str(), "exclusive");
+        assert_eq!(LockMode::Shared.as_str(), "shared");
+        assert_eq!(LockMode::default(), LockMode::Exclusive);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_exclusive_locks_serialize() {
+        let first = acquire_named_lock("test-exclusive", LockMode::Exclusive)
+            .await
+            .expect("Should acquire lock");
+        assert_eq!(first.name(), "test-exclusive");
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_clone = Rc::clone(&events);
+        spawn_local(async move {
+            let _second = acquire_named_lock("test-exclusive", LockMode::Exclusive)
+                .await
+                .expect("Should acquire lock");
+            events_clone.borrow_mut().push("second-acquired");
+        });
+
+        sleep(50.0).await;
+        assert!(
+            events.borrow().is_empty(),
+            "Second acquisition must wait for the first guard"
+        );
+
+        events.borrow_mut().push("first-released");
+        drop(first);
+        sleep(50.0).await;
+
+        assert_eq!(*events.borrow(), ["first-released", "second-acquired"]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_shared_locks_are_concurrent() {
+        let first = acquire_named_lock("test-shared", LockMode::Shared)
+            .await
+            .expect("Should acquire lock");
+        let second = acquire_named_lock("test-shared", LockMode::Shared)
+            .await
+            .expect("Shared lock should not wait");
+        assert_eq!(first.mode(), LockMode::Shared);
+        assert_eq!(second.mode(), LockMode::Shared);
+    }
+}
@@ -1,5 +1,24 @@
+use indexmap::IndexSet;
 use js_sys::Function;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::WorkerError;
+use crate::priority::QueryPriority;
+use crate::query_result::{QueryResult, SqlParam};
+use crate::query_stats::QueryStats;
+use crate::read_concern::ReadConcern;
+
+// Limit on early messages held back while waiting for a missing sequence number
+pub const MAX_REORDER_BUFFER: usize = 64;
+// How long early messages wait for a missing sequence number before the gap
+// is skipped, as a lost message never arrives
+pub const REORDER_GAP_TIMEOUT_MS: f64 = 100.0;
+// How long a sender can go quiet, with nothing held back, before its reorder
+// buffer is dropped
+pub const REORDER_IDLE_TIMEOUT_MS: f64 = 60_000.0;
+// Request ids a leader remembers to recognise a request sent twice
+pub const MAX_HANDLED_REQUESTS: usize = 1024;
 
 // Message types for BroadcastChannel communication
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -10,11 +29,55 @@ pub enum ChannelMessage {
         #[serde(rename = "leaderId")]
         leader_id: String,
     },
+    // Ask non-leaders to send their queries to `worker_id` instead. Without a
+    // recipient the redirect applies to every non-leader.
+    #[serde(rename = "redirect")]
+    Redirect {
+        #[serde(rename = "workerId")]
+        worker_id: String,
+        reason: String,
+        #[serde(rename = "recipientId", default)]
+        recipient_id: Option<String>,
+    },
     #[serde(rename = "query-request")]
     QueryRequest {
         #[serde(rename = "queryId")]
         query_id: String,
         sql: String,
+        // Worker that sent the query
+        #[serde(rename = "originWorkerId", default)]
+        origin_worker_id: String,
+        // Caller-supplied `QueryContext` metadata, for the leader's logs
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        metadata: HashMap<String, String>,
+        // Call site of `execute_query` in debug builds
+        #[serde(
+            rename = "sourceLocation",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        source_location: Option<String>,
+        #[serde(
+            rename = "readConcern",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        read_concern: Option<ReadConcern>,
+        // Ask for `QueryProgress` about every this many VM instructions
+        #[serde(
+            rename = "progressInterval",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        progress_interval: Option<u32>,
+    },
+    // Sent by the leader while it runs a `QueryRequest` that asked for progress
+    #[serde(rename = "query-progress")]
+    QueryProgress {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "vmStepsCompleted")]
+        vm_steps_completed: u64,
     },
     #[serde(rename = "query-response")]
     QueryResponse {
@@ -22,7 +85,514 @@ pub enum ChannelMessage {
         query_id: String,
         result: Option<String>,
         error: Option<String>,
+        // Extended SQLite result code when `error` came from SQLite
+        #[serde(rename = "errorCode", default)]
+        error_code: Option<i32>,
+        // Read on the leader just after a plain query ran
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stats: Option<QueryStats>,
+    },
+    // Sent instead of `QueryResponse` when a query returned no rows and
+    // changed nothing
+    #[serde(rename = "empty-response")]
+    EmptyResponse {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        // The column names of the empty result set, if the statement had one
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        columns: Vec<String>,
+    },
+    #[serde(rename = "snapshot")]
+    Snapshot {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "snapshotId")]
+        snapshot_id: String,
+    },
+    #[serde(rename = "query-snapshot")]
+    QuerySnapshot {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "snapshotId")]
+        snapshot_id: String,
+        sql: String,
+    },
+    #[serde(rename = "end-snapshot")]
+    EndSnapshot {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "snapshotId")]
+        snapshot_id: String,
+    },
+    // Open a read transaction with its own snapshot, as `Snapshot` does,
+    // for parameterized queries
+    #[serde(rename = "begin-read-transaction")]
+    BeginReadTransaction {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "txId")]
+        tx_id: String,
+    },
+    #[serde(rename = "read-transaction-query")]
+    ReadTransactionQuery {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "txId")]
+        tx_id: String,
+        sql: String,
+        params: Vec<SqlParam>,
+    },
+    // Commit the read transaction; `EndSnapshot` rolls it back
+    #[serde(rename = "end-read-transaction")]
+    EndReadTransaction {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "txId")]
+        tx_id: String,
+    },
+    #[serde(rename = "parameterized-query-request")]
+    ParameterizedQueryRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        sql: String,
+        params: Vec<SqlParam>,
+        #[serde(
+            rename = "sourceLocation",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        source_location: Option<String>,
+    },
+    #[serde(rename = "copy-table")]
+    CopyTable {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        src: String,
+        dst: String,
+        filter: Option<String>,
+    },
+    // Prepare a statement on the leader, which answers with its id
+    #[serde(rename = "prepare-statement")]
+    PrepareStatement {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        sql: String,
+    },
+    // Run a statement the leader prepared earlier
+    #[serde(rename = "execute-prepared")]
+    ExecutePrepared {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "stmtId")]
+        stmt_id: String,
+        params: Vec<SqlParam>,
+    },
+    // Let the leader release a prepared statement; there is no response
+    #[serde(rename = "finalize-statement")]
+    FinalizeStatement {
+        #[serde(rename = "stmtId")]
+        stmt_id: String,
+    },
+    // Insert rows of `columns` into a table on the leader in one transaction
+    #[serde(rename = "bulk-insert-request")]
+    BulkInsertRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        table: String,
+        columns: Vec<String>,
+        values: Vec<Vec<SqlParam>>,
+    },
+    #[serde(rename = "batch-query-request")]
+    BatchQueryRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        statements: Vec<String>,
+        // Query requests a follower made within one microtask, sent together.
+        // The leader handles each as if it had been sent alone and returns
+        // their responses in `responses`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        requests: Vec<BatchedMessage>,
+    },
+    #[serde(rename = "batch-query-response")]
+    BatchQueryResponse {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        results: Vec<BatchResult>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        responses: Vec<BatchedMessage>,
+    },
+    // Set one of the few pragmas the leader allows on its connection
+    #[serde(rename = "pragma-set")]
+    PragmaSet {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        name: String,
+        value: SqlParam,
+    },
+    // Run schema changes on the leader in one transaction
+    #[serde(rename = "ddl-transaction")]
+    DdlTransaction {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        statements: Vec<String>,
+    },
+    // Drop a table on the leader
+    #[serde(rename = "drop-table")]
+    DropTable {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        table: String,
+        #[serde(rename = "ifExists")]
+        if_exists: bool,
+    },
+    // Rename a table on the leader
+    #[serde(rename = "rename-table")]
+    RenameTable {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "oldName")]
+        old_name: String,
+        #[serde(rename = "newName")]
+        new_name: String,
+    },
+    // Rebuild one index, or all of them if `index_name` is `None`, on the leader
+    #[serde(rename = "reindex-request")]
+    ReindexRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "indexName")]
+        index_name: Option<String>,
     },
+    // Compile `sql` on the leader with `EXPLAIN` without running it
+    #[serde(rename = "validate-sql-request")]
+    ValidateSqlRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        sql: String,
+    },
+    // Open, release or roll back to a savepoint on the leader
+    #[serde(rename = "savepoint")]
+    Savepoint {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        name: String,
+        action: SavepointAction,
+    },
+    // Copy the leader's database into another OPFS file with the online backup API
+    #[serde(rename = "backup-request")]
+    BackupRequest {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        #[serde(rename = "targetName")]
+        target_name: String,
+    },
+    // Register a scalar SQL function on the leader from its JavaScript source
+    #[serde(rename = "register-function")]
+    RegisterFunction {
+        #[serde(rename = "queryId")]
+        query_id: String,
+        name: String,
+        arity: i32,
+        #[serde(rename = "funcSource")]
+        func_source: String,
+    },
+    // Connectivity check answered by the leader without touching the database
+    #[serde(rename = "echo-request")]
+    EchoRequest {
+        #[serde(rename = "echoId")]
+        echo_id: String,
+        payload: String,
+    },
+    #[serde(rename = "echo-response")]
+    EchoResponse {
+        #[serde(rename = "echoId")]
+        echo_id: String,
+        payload: String,
+        #[serde(rename = "responderId")]
+        responder_id: String,
+    },
+    // Ask the leader for a `StateResponse`
+    #[serde(rename = "request-state")]
+    RequestState {
+        #[serde(rename = "requesterId")]
+        requester_id: String,
+    },
+    #[serde(rename = "state-response")]
+    StateResponse {
+        #[serde(rename = "fromLeaderId")]
+        from_leader_id: String,
+        #[serde(rename = "toRequesterId")]
+        to_requester_id: String,
+        #[serde(rename = "dbInitialized")]
+        db_initialized: bool,
+        #[serde(rename = "pendingQueueDepth")]
+        pending_queue_depth: u32,
+        #[serde(rename = "uptimeMs")]
+        uptime_ms: f64,
+    },
+    // Periodic broadcast of what `from` knows about the other workers
+    #[serde(rename = "gossip-state")]
+    GossipState {
+        from: String,
+        peers: Vec<WorkerGossip>,
+    },
+    // The leader replaced its database file with an empty one, so results
+    // read before now are stale
+    #[serde(rename = "database-reset")]
+    DatabaseReset {
+        #[serde(rename = "leaderId")]
+        leader_id: String,
+    },
+    // Application-defined message, delivered to the handler registered for
+    // `type_name` with `WorkerState::register_custom_handler`
+    #[serde(rename = "custom")]
+    Custom {
+        #[serde(rename = "typeName")]
+        type_name: String,
+        payload: serde_json::Value,
+    },
+}
+
+// Step of `execute_with_savepoint` carried out on the leader
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SavepointAction {
+    Begin,
+    Release,
+    // Undo the changes since the savepoint, then release it
+    RollbackTo,
+}
+
+// One worker as last seen by a gossiping peer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorkerGossip {
+    #[serde(rename = "workerId")]
+    pub worker_id: String,
+    #[serde(rename = "isLeader")]
+    pub is_leader: bool,
+    #[serde(rename = "lastSeenMs")]
+    pub last_seen_ms: f64,
+}
+
+// Outcome of one statement in a batch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    pub result: Option<QueryResult>,
+    pub error: Option<String>,
+    #[serde(rename = "errorCode", default)]
+    pub error_code: Option<i32>,
+}
+
+impl From<Result<QueryResult, WorkerError>> for BatchResult {
+    fn from(value: Result<QueryResult, WorkerError>) -> Self {
+        match value {
+            Ok(result) => BatchResult {
+                result: Some(result),
+                error: None,
+                error_code: None,
+            },
+            Err(err) => BatchResult {
+                result: None,
+                error_code: err.sqlite_code(),
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+impl BatchResult {
+    pub fn into_result(self) -> Result<QueryResult, WorkerError> {
+        match (self.result, self.error) {
+            (_, Some(message)) => Err(match self.error_code {
+                Some(code) => WorkerError::from_sqlite_code(code, message),
+                None => WorkerError::sqlite(message),
+            }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(WorkerError::QueryFailed("Batch result missing".to_string())),
+        }
+    }
+}
+
+// Wrapper for every message sent over the BroadcastChannel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChannelEnvelope {
+    #[serde(rename = "senderId")]
+    pub sender_id: String,
+    #[serde(rename = "senderSequence")]
+    pub sender_sequence: u64,
+    // Correlation id carried from a traced request to its response
+    #[serde(rename = "traceId", default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(flatten)]
+    pub message: ChannelMessage,
+}
+
+// A request batched into a `BatchQueryRequest`, or its response in the
+// `BatchQueryResponse`, with the trace id it would have carried in an envelope
+// of its own
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchedMessage {
+    #[serde(rename = "traceId", default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(flatten)]
+    pub message: ChannelMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+/// A channel message recorded in a worker's message log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggedMessage {
+    pub direction: MessageDirection,
+    pub message: ChannelMessage,
+    #[serde(rename = "timestampMs")]
+    pub timestamp_ms: f64,
+}
+
+/// Restores send order for messages from a single sender
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageReorderBuffer<T = ChannelMessage> {
+    pub pending: BTreeMap<u64, T>,
+    pub next_expected: u64,
+    // When messages first queued up behind the current gap
+    pub gap_since_ms: Option<f64>,
+    // When the sender's latest message arrived
+    pub last_message_ms: f64,
+}
+
+impl<T> MessageReorderBuffer<T> {
+    pub fn new(next_expected: u64) -> Self {
+        MessageReorderBuffer {
+            pending: BTreeMap::new(),
+            next_expected,
+            gap_since_ms: None,
+            last_message_ms: 0.0,
+        }
+    }
+
+    /// Accept a message at `now_ms` and return every message that can now be
+    /// delivered in order. Messages older than the next expected sequence are
+    /// delivered immediately, and a gap is skipped once messages have waited
+    /// behind it for `REORDER_GAP_TIMEOUT_MS` or too many pile up.
+    pub fn push(&mut self, sequence: u64, message: T, now_ms: f64) -> Vec<T> {
+        self.last_message_ms = now_ms;
+        if sequence < self.next_expected {
+            return vec![message];
+        }
+
+        self.pending.insert(sequence, message);
+        if self.pending.len() > MAX_REORDER_BUFFER {
+            self.skip_gap();
+        }
+        self.release_expired(now_ms)
+    }
+
+    /// Skip the gap if messages have waited behind it for
+    /// `REORDER_GAP_TIMEOUT_MS` by `now_ms`, returning those now in order.
+    /// Called on a timer, as the messages would otherwise wait for the next
+    /// one to arrive.
+    pub fn release_expired(&mut self, now_ms: f64) -> Vec<T> {
+        if self
+            .gap_since_ms
+            .is_some_and(|since| now_ms - since >= REORDER_GAP_TIMEOUT_MS)
+        {
+            self.skip_gap();
+        }
+
+        let mut ready = Vec::new();
+        while let Some(message) = self.pending.remove(&self.next_expected) {
+            ready.push(message);
+            self.next_expected += 1;
+        }
+        self.gap_since_ms = if self.pending.is_empty() {
+            None
+        } else if ready.is_empty() {
+            Some(self.gap_since_ms.unwrap_or(now_ms))
+        } else {
+            // A new gap, so its messages get the full timeout
+            Some(now_ms)
+        };
+        ready
+    }
+
+    /// Whether nothing is held back and the sender has sent nothing for
+    /// `REORDER_IDLE_TIMEOUT_MS` by `now_ms`, as when its tab has closed, so
+    /// the buffer can be dropped. A sender heard from again starts a new one.
+    pub fn is_idle(&self, now_ms: f64) -> bool {
+        self.pending.is_empty() && now_ms - self.last_message_ms >= REORDER_IDLE_TIMEOUT_MS
+    }
+
+    // Continue from the earliest message held back
+    fn skip_gap(&mut self) {
+        if let Some(&first) = self.pending.keys().next() {
+            self.next_expected = first;
+        }
+    }
+}
+
+/// Ids of the requests a leader has recently handled, so a request re-sent
+/// after a redirect is not run twice
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HandledRequests {
+    ids: IndexSet<String>,
+}
+
+impl HandledRequests {
+    /// Record `request_id`, returning whether it had not been seen before.
+    /// Only the last `MAX_HANDLED_REQUESTS` ids are remembered.
+    pub fn first_time(&mut self, request_id: &str) -> bool {
+        if self.ids.contains(request_id) {
+            return false;
+        }
+        self.ids.insert(request_id.to_string());
+        if self.ids.len() > MAX_HANDLED_REQUESTS {
+            self.ids.shift_remove_index(0);
+        }
+        true
+    }
+}
+
+/// Build the response the leader broadcasts for a request
+pub fn query_response(query_id: String, result: Result<String, String>) -> ChannelMessage {
+    match result {
+        Ok(res) => ChannelMessage::QueryResponse {
+            query_id,
+            result: Some(res),
+            error: None,
+            error_code: None,
+            stats: None,
+        },
+        Err(err) => ChannelMessage::QueryResponse {
+            query_id,
+            result: None,
+            error: Some(err),
+            error_code: None,
+            stats: None,
+        },
+    }
+}
+
+/// As `query_response`, keeping the SQLite result code of a failure so the
+/// requester can rebuild a structured error
+pub fn coded_query_response(
+    query_id: String,
+    result: Result<String, WorkerError>,
+) -> ChannelMessage {
+    match result {
+        Ok(res) => query_response(query_id, Ok(res)),
+        Err(err) => ChannelMessage::QueryResponse {
+            query_id,
+            result: None,
+            error_code: err.sqlite_code(),
+            error: Some(err.to_string()),
+            stats: None,
+        },
+    }
 }
 
 // Messages from main thread
@@ -46,9 +616,101 @@ pub enum MainThreadMessage {
     WorkerReady,
 }
 
+impl ChannelMessage {
+    /// SQL carried by a query request
+    pub fn sql(&self) -> Option<&str> {
+        match self {
+            ChannelMessage::QueryRequest { sql, .. }
+            | ChannelMessage::ParameterizedQueryRequest { sql, .. }
+            | ChannelMessage::QuerySnapshot { sql, .. }
+            | ChannelMessage::ReadTransactionQuery { sql, .. }
+            | ChannelMessage::ValidateSqlRequest { sql, .. } => Some(sql),
+            _ => None,
+        }
+    }
+
+    /// Id of a request the leader answers, which its response carries back
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            ChannelMessage::QueryRequest { query_id, .. }
+            | ChannelMessage::Snapshot { query_id, .. }
+            | ChannelMessage::QuerySnapshot { query_id, .. }
+            | ChannelMessage::EndSnapshot { query_id, .. }
+            | ChannelMessage::BeginReadTransaction { query_id, .. }
+            | ChannelMessage::ReadTransactionQuery { query_id, .. }
+            | ChannelMessage::EndReadTransaction { query_id, .. }
+            | ChannelMessage::ParameterizedQueryRequest { query_id, .. }
+            | ChannelMessage::CopyTable { query_id, .. }
+            | ChannelMessage::PrepareStatement { query_id, .. }
+            | ChannelMessage::ExecutePrepared { query_id, .. }
+            | ChannelMessage::BulkInsertRequest { query_id, .. }
+            | ChannelMessage::BatchQueryRequest { query_id, .. }
+            | ChannelMessage::PragmaSet { query_id, .. }
+            | ChannelMessage::DdlTransaction { query_id, .. }
+            | ChannelMessage::DropTable { query_id, .. }
+            | ChannelMessage::RenameTable { query_id, .. }
+            | ChannelMessage::ReindexRequest { query_id, .. }
+            | ChannelMessage::ValidateSqlRequest { query_id, .. }
+            | ChannelMessage::Savepoint { query_id, .. }
+            | ChannelMessage::BackupRequest { query_id, .. }
+            | ChannelMessage::RegisterFunction { query_id, .. } => Some(query_id),
+            ChannelMessage::EchoRequest { echo_id, .. } => Some(echo_id),
+            _ => None,
+        }
+    }
+}
+
+/// Read-only summary of a `PendingQuery`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingQueryInfo {
+    #[serde(rename = "queryId")]
+    pub query_id: String,
+    pub sql: Option<String>,
+    pub priority: QueryPriority,
+    #[serde(rename = "sentAtMs")]
+    pub sent_at_ms: f64,
+}
+
 pub struct PendingQuery {
     pub resolve: Function,
     pub reject: Function,
+    pub sent_at_ms: f64,
+    pub priority: QueryPriority,
+    // Request that was sent, kept so it can be re-issued after a redirect
+    pub request: Option<ChannelMessage>,
+}
+
+// The JS callbacks carry no useful information, so only the metadata is shown
+impl std::fmt::Debug for PendingQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingQuery")
+            .field("sent_at_ms", &self.sent_at_ms)
+            .field("priority", &self.priority)
+            .field("request", &self.request)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PendingQuery {
+    pub fn new(resolve: Function, reject: Function) -> Self {
+        PendingQuery {
+            resolve,
+            reject,
+            sent_at_ms: js_sys::Date::now(),
+            priority: QueryPriority::default(),
+            request: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: QueryPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_request(mut self, request: ChannelMessage) -> Self {
+        self.request = Some(request);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -56,7 +718,7 @@ mod tests {
     use super::*;
     use wasm_bindgen_test::*;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
 
     fn assert_serialization_roundtrip<
         T: Serialize + for<'a> Deserialize<'a> + PartialEq + std::fmt::Debug,
@@ -85,32 +747,782 @@ mod tests {
         let query_request = ChannelMessage::QueryRequest {
             query_id: "query-456".to_string(),
             sql: "SELECT * FROM users".to_string(),
+            origin_worker_id: "worker-1".to_string(),
+            metadata: HashMap::new(),
+            source_location: None,
+            read_concern: None,
+            progress_interval: None,
         };
         assert_serialization_roundtrip(query_request, "query-request", |json| {
             assert!(json.contains("\"queryId\":\"query-456\""));
             assert!(json.contains("\"sql\":\"SELECT * FROM users\""));
+            assert!(json.contains("\"originWorkerId\":\"worker-1\""));
+            assert!(!json.contains("sourceLocation"));
+        });
+
+        let located_request = ChannelMessage::QueryRequest {
+            query_id: "query-457".to_string(),
+            sql: "SELECT 1".to_string(),
+            origin_worker_id: "worker-1".to_string(),
+            metadata: HashMap::from([("user".to_string(), "alice".to_string())]),
+            source_location: Some("src/app.rs:10:5".to_string()),
+            read_concern: Some(ReadConcern::Linearizable),
+            progress_interval: None,
+        };
+        assert_serialization_roundtrip(located_request, "query-request", |json| {
+            assert!(json.contains("\"sourceLocation\":\"src/app.rs:10:5\""));
+            assert!(json.contains("\"readConcern\":\"linearizable\""));
+            assert!(json.contains("\"metadata\":{\"user\":\"alice\"}"));
         });
 
         let query_success = ChannelMessage::QueryResponse {
             query_id: "query-789".to_string(),
             result: Some("[{\"id\": 1, \"name\": \"test\"}]".to_string()),
             error: None,
+            error_code: None,
+            stats: None,
         };
         assert_serialization_roundtrip(query_success, "query-response", |json| {
             assert!(json.contains("\"queryId\":\"query-789\""));
             assert!(json.contains("\"result\":\""));
             assert!(json.contains("\"error\":null"));
+            assert!(!json.contains("\"stats\""));
+        });
+
+        let query_with_stats = ChannelMessage::QueryResponse {
+            query_id: "query-stats".to_string(),
+            result: Some("[]".to_string()),
+            error: None,
+            error_code: None,
+            stats: Some(QueryStats {
+                malloc_used: 1024,
+                ..QueryStats::default()
+            }),
+        };
+        assert_serialization_roundtrip(query_with_stats, "query-response", |json| {
+            assert!(json.contains("\"stats\":{\"pagecacheUsed\":0"));
+            assert!(json.contains("\"mallocUsed\":1024"));
         });
 
         let query_error = ChannelMessage::QueryResponse {
             query_id: "query-error".to_string(),
             result: None,
             error: Some("SQL syntax error".to_string()),
+            error_code: Some(1),
+            stats: None,
         };
         assert_serialization_roundtrip(query_error, "query-response", |json| {
             assert!(json.contains("\"error\":\"SQL syntax error\""));
+            assert!(json.contains("\"errorCode\":1"));
             assert!(json.contains("\"result\":null"));
         });
+
+        let empty = ChannelMessage::EmptyResponse {
+            query_id: "query-empty".to_string(),
+            columns: vec!["id".to_string()],
+        };
+        assert_serialization_roundtrip(empty, "empty-response", |json| {
+            assert!(json.contains("\"queryId\":\"query-empty\""));
+            assert!(json.contains("\"columns\":[\"id\"]"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_snapshot_messages_serialization() {
+        let begin = ChannelMessage::Snapshot {
+            query_id: "query-1".to_string(),
+            snapshot_id: "snap-1".to_string(),
+        };
+        assert_serialization_roundtrip(begin, "snapshot", |json| {
+            assert!(json.contains("\"queryId\":\"query-1\""));
+            assert!(json.contains("\"snapshotId\":\"snap-1\""));
+        });
+
+        let query = ChannelMessage::QuerySnapshot {
+            query_id: "query-2".to_string(),
+            snapshot_id: "snap-1".to_string(),
+            sql: "SELECT 1".to_string(),
+        };
+        assert_serialization_roundtrip(query, "query-snapshot", |json| {
+            assert!(json.contains("\"sql\":\"SELECT 1\""));
+        });
+
+        let end = ChannelMessage::EndSnapshot {
+            query_id: "query-3".to_string(),
+            snapshot_id: "snap-1".to_string(),
+        };
+        assert_serialization_roundtrip(end, "end-snapshot", |json| {
+            assert!(json.contains("\"snapshotId\":\"snap-1\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_read_transaction_messages_serialization() {
+        let begin = ChannelMessage::BeginReadTransaction {
+            query_id: "query-1".to_string(),
+            tx_id: "tx-1".to_string(),
+        };
+        assert_serialization_roundtrip(begin, "begin-read-transaction", |json| {
+            assert!(json.contains("\"queryId\":\"query-1\""));
+            assert!(json.contains("\"txId\":\"tx-1\""));
+        });
+
+        let query = ChannelMessage::ReadTransactionQuery {
+            query_id: "query-2".to_string(),
+            tx_id: "tx-1".to_string(),
+            sql: "SELECT * FROM items WHERE id = ?".to_string(),
+            params: vec![SqlParam::Integer(7)],
+        };
+        assert_serialization_roundtrip(query, "read-transaction-query", |json| {
+            assert!(json.contains("\"txId\":\"tx-1\""));
+            assert!(json.contains("\"params\":[7]"));
+        });
+
+        let end = ChannelMessage::EndReadTransaction {
+            query_id: "query-3".to_string(),
+            tx_id: "tx-1".to_string(),
+        };
+        assert_serialization_roundtrip(end, "end-read-transaction", |json| {
+            assert!(json.contains("\"txId\":\"tx-1\""));
+        });
+    }
+
+    fn leader_message(id: u64) -> ChannelMessage {
+        ChannelMessage::NewLeader {
+            leader_id: format!("leader-{id}"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_channel_envelope_serialization() {
+        let envelope = ChannelEnvelope {
+            sender_id: "worker-1".to_string(),
+            sender_sequence: 7,
+            trace_id: None,
+            message: ChannelMessage::QueryRequest {
+                query_id: "query-1".to_string(),
+                sql: "SELECT 1".to_string(),
+                origin_worker_id: "worker-1".to_string(),
+                metadata: HashMap::new(),
+                source_location: None,
+                read_concern: None,
+                progress_interval: None,
+            },
+        };
+        assert_serialization_roundtrip(envelope.clone(), "query-request", |json| {
+            assert!(json.contains("\"senderId\":\"worker-1\""));
+            assert!(json.contains("\"senderSequence\":7"));
+            assert!(json.contains("\"queryId\":\"query-1\""));
+            assert!(!json.contains("traceId"));
+        });
+
+        let traced = ChannelEnvelope {
+            trace_id: Some("trace-1".to_string()),
+            ..envelope
+        };
+        assert_serialization_roundtrip(traced, "query-request", |json| {
+            assert!(json.contains("\"traceId\":\"trace-1\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reorder_buffer_in_order_delivery() {
+        let mut buffer = MessageReorderBuffer::new(0);
+        assert_eq!(
+            buffer.push(0, leader_message(0), 0.0),
+            vec![leader_message(0)]
+        );
+        assert_eq!(
+            buffer.push(1, leader_message(1), 0.0),
+            vec![leader_message(1)]
+        );
+        assert_eq!(buffer.next_expected, 2);
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reorder_buffer_fills_gap() {
+        let mut buffer = MessageReorderBuffer::new(4);
+        assert!(buffer.push(5, leader_message(5), 0.0).is_empty());
+        assert!(buffer.push(6, leader_message(6), 0.0).is_empty());
+        assert_eq!(buffer.pending.len(), 2);
+
+        let ready = buffer.push(4, leader_message(4), 0.0);
+        assert_eq!(
+            ready,
+            vec![leader_message(4), leader_message(5), leader_message(6)]
+        );
+        assert_eq!(buffer.next_expected, 7);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reorder_buffer_late_and_overflow() {
+        let mut buffer = MessageReorderBuffer::new(10);
+        assert_eq!(
+            buffer.push(3, leader_message(3), 0.0),
+            vec![leader_message(3)]
+        );
+
+        for sequence in 12..(12 + MAX_REORDER_BUFFER as u64) {
+            assert!(buffer
+                .push(sequence, leader_message(sequence), 0.0)
+                .is_empty());
+        }
+        let ready = buffer.push(
+            12 + MAX_REORDER_BUFFER as u64,
+            leader_message(12 + MAX_REORDER_BUFFER as u64),
+            0.0,
+        );
+        assert_eq!(ready.len(), MAX_REORDER_BUFFER + 1);
+        assert_eq!(ready[0], leader_message(12));
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reorder_buffer_skips_gap_after_timeout() {
+        let mut buffer = MessageReorderBuffer::new(0);
+        assert!(buffer.push(1, leader_message(1), 1000.0).is_empty());
+        assert!(buffer.push(2, leader_message(2), 1050.0).is_empty());
+        assert!(buffer.release_expired(1099.0).is_empty());
+        assert_eq!(buffer.gap_since_ms, Some(1000.0));
+
+        let ready = buffer.release_expired(1000.0 + REORDER_GAP_TIMEOUT_MS);
+        assert_eq!(ready, vec![leader_message(1), leader_message(2)]);
+        assert_eq!(buffer.next_expected, 3);
+        assert_eq!(buffer.gap_since_ms, None);
+
+        // The lost message is still delivered if it turns up late
+        assert_eq!(
+            buffer.push(0, leader_message(0), 1200.0),
+            vec![leader_message(0)]
+        );
+
+        // A message arriving after the timeout skips the gap itself
+        assert!(buffer.push(5, leader_message(5), 2000.0).is_empty());
+        assert_eq!(
+            buffer.push(6, leader_message(6), 2000.0 + REORDER_GAP_TIMEOUT_MS),
+            vec![leader_message(5), leader_message(6)]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reorder_buffer_idle_after_timeout() {
+        let mut buffer = MessageReorderBuffer::new(0);
+        buffer.push(0, leader_message(0), 1000.0);
+        assert!(!buffer.is_idle(1000.0 + REORDER_IDLE_TIMEOUT_MS - 1.0));
+        assert!(buffer.is_idle(1000.0 + REORDER_IDLE_TIMEOUT_MS));
+
+        // Never idle while a message waits behind a gap
+        buffer.push(2, leader_message(2), 1000.0);
+        assert!(!buffer.is_idle(1000.0 + REORDER_IDLE_TIMEOUT_MS * 2.0));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_handled_requests_recognise_repeats() {
+        let mut handled = HandledRequests::default();
+        assert!(handled.first_time("query-1"));
+        assert!(handled.first_time("query-2"));
+        assert!(!handled.first_time("query-1"));
+
+        for n in 0..MAX_HANDLED_REQUESTS {
+            handled.first_time(&format!("filler-{n}"));
+        }
+        // Long forgotten, so handled again
+        assert!(handled.first_time("query-1"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_request_ids() {
+        let request = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "param-1".to_string(),
+            sql: "SELECT ?".to_string(),
+            params: vec![SqlParam::Integer(1)],
+            source_location: None,
+        };
+        assert_eq!(request.request_id(), Some("param-1"));
+        let echo = ChannelMessage::EchoRequest {
+            echo_id: "echo-1".to_string(),
+            payload: "ping".to_string(),
+        };
+        assert_eq!(echo.request_id(), Some("echo-1"));
+        let response = query_response("param-1".to_string(), Ok("[]".to_string()));
+        assert_eq!(response.request_id(), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parameterized_query_request_serialization() {
+        let msg = ChannelMessage::ParameterizedQueryRequest {
+            query_id: "param-1".to_string(),
+            sql: "SELECT * FROM users WHERE id = ? AND name = ?".to_string(),
+            params: vec![SqlParam::Integer(1), SqlParam::from("Alice")],
+            source_location: None,
+        };
+        assert_serialization_roundtrip(msg, "parameterized-query-request", |json| {
+            assert!(json.contains("\"params\":[1,\"Alice\"]"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_logged_message_serialization() {
+        let logged = LoggedMessage {
+            direction: MessageDirection::Received,
+            message: ChannelMessage::NewLeader {
+                leader_id: "leader-1".to_string(),
+            },
+            timestamp_ms: 1234.0,
+        };
+        let json = serde_json::to_string(&logged).expect("Should serialize");
+        assert!(json.contains("\"direction\":\"received\""));
+        assert!(json.contains("\"timestampMs\":1234.0"));
+        assert!(json.contains("\"type\":\"new-leader\""));
+
+        let deserialized: LoggedMessage = serde_json::from_str(&json).expect("Should deserialize");
+        assert_eq!(deserialized, logged);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_redirect_serialization() {
+        let msg = ChannelMessage::Redirect {
+            worker_id: "worker-2".to_string(),
+            reason: "rebalancing".to_string(),
+            recipient_id: Some("worker-1".to_string()),
+        };
+        assert_serialization_roundtrip(msg, "redirect", |json| {
+            assert!(json.contains("\"workerId\":\"worker-2\""));
+            assert!(json.contains("\"recipientId\":\"worker-1\""));
+        });
+
+        let broadcast: ChannelMessage =
+            serde_json::from_str(r#"{"type":"redirect","workerId":"worker-2","reason":"r"}"#)
+                .expect("Should deserialize without recipient");
+        assert_eq!(
+            broadcast,
+            ChannelMessage::Redirect {
+                worker_id: "worker-2".to_string(),
+                reason: "r".to_string(),
+                recipient_id: None,
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_copy_table_serialization() {
+        let msg = ChannelMessage::CopyTable {
+            query_id: "copy-1".to_string(),
+            src: "users".to_string(),
+            dst: "users_backup".to_string(),
+            filter: Some("active = 1".to_string()),
+        };
+        assert_serialization_roundtrip(msg, "copy-table", |json| {
+            assert!(json.contains("\"dst\":\"users_backup\""));
+            assert!(json.contains("\"filter\":\"active = 1\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_prepared_statement_messages_serialization() {
+        let prepare = ChannelMessage::PrepareStatement {
+            query_id: "prepare-1".to_string(),
+            sql: "SELECT * FROM users WHERE id = ?".to_string(),
+        };
+        assert_serialization_roundtrip(prepare, "prepare-statement", |json| {
+            assert!(json.contains("\"sql\":\"SELECT * FROM users WHERE id = ?\""));
+        });
+
+        let execute = ChannelMessage::ExecutePrepared {
+            query_id: "execute-1".to_string(),
+            stmt_id: "stmt-1".to_string(),
+            params: vec![SqlParam::Integer(1)],
+        };
+        assert_serialization_roundtrip(execute, "execute-prepared", |json| {
+            assert!(json.contains("\"stmtId\":\"stmt-1\""));
+        });
+
+        let finalize = ChannelMessage::FinalizeStatement {
+            stmt_id: "stmt-1".to_string(),
+        };
+        assert_serialization_roundtrip(finalize, "finalize-statement", |json| {
+            assert!(json.contains("\"stmtId\":\"stmt-1\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_bulk_insert_request_serialization() {
+        let msg = ChannelMessage::BulkInsertRequest {
+            query_id: "bulk-1".to_string(),
+            table: "users".to_string(),
+            columns: vec!["age".to_string(), "name".to_string()],
+            values: vec![
+                vec![SqlParam::Integer(30), SqlParam::from("Alice")],
+                vec![SqlParam::Null, SqlParam::from("Bob")],
+            ],
+        };
+        assert_serialization_roundtrip(msg, "bulk-insert-request", |json| {
+            assert!(json.contains("\"columns\":[\"age\",\"name\"]"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_savepoint_serialization() {
+        let msg = ChannelMessage::Savepoint {
+            query_id: "savepoint-1".to_string(),
+            name: "outer".to_string(),
+            action: SavepointAction::RollbackTo,
+        };
+        assert_serialization_roundtrip(msg, "savepoint", |json| {
+            assert!(json.contains("\"name\":\"outer\""));
+            assert!(json.contains("\"action\":\"rollback-to\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_batch_messages_serialization() {
+        let request = ChannelMessage::BatchQueryRequest {
+            query_id: "batch-1".to_string(),
+            statements: vec!["SELECT 1".to_string(), "SELECT 2".to_string()],
+            requests: Vec::new(),
+        };
+        assert_serialization_roundtrip(request, "batch-query-request", |json| {
+            assert!(json.contains("\"statements\":[\"SELECT 1\",\"SELECT 2\"]"));
+            assert!(!json.contains("requests"));
+        });
+
+        let response = ChannelMessage::BatchQueryResponse {
+            query_id: "batch-1".to_string(),
+            results: vec![
+                BatchResult::from(Ok(QueryResult::default())),
+                BatchResult::from(Err(WorkerError::sqlite("syntax error".to_string()))),
+            ],
+            responses: Vec::new(),
+        };
+        assert_serialization_roundtrip(response, "batch-query-response", |json| {
+            assert!(json.contains("\"error\":\"syntax error\""));
+            assert!(!json.contains("responses"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_batched_requests_serialization() {
+        let request = ChannelMessage::BatchQueryRequest {
+            query_id: "batch-1".to_string(),
+            statements: Vec::new(),
+            requests: vec![BatchedMessage {
+                trace_id: Some("trace-1".to_string()),
+                message: ChannelMessage::QueryRequest {
+                    query_id: "query-1".to_string(),
+                    sql: "SELECT 1".to_string(),
+                    origin_worker_id: "worker-1".to_string(),
+                    metadata: HashMap::from([("page".to_string(), "home".to_string())]),
+                    source_location: None,
+                    read_concern: None,
+                    progress_interval: None,
+                },
+            }],
+        };
+        assert_serialization_roundtrip(request, "batch-query-request", |json| {
+            assert!(json.contains("\"queryId\":\"batch-1\""));
+            assert!(json.contains("\"traceId\":\"trace-1\""));
+            assert!(json.contains("\"type\":\"query-request\""));
+            assert!(json.contains("\"page\":\"home\""));
+        });
+
+        let response = ChannelMessage::BatchQueryResponse {
+            query_id: "batch-1".to_string(),
+            results: Vec::new(),
+            responses: vec![BatchedMessage {
+                trace_id: None,
+                message: ChannelMessage::EmptyResponse {
+                    query_id: "query-1".to_string(),
+                    columns: Vec::new(),
+                },
+            }],
+        };
+        assert_serialization_roundtrip(response, "batch-query-response", |json| {
+            assert!(json.contains("\"type\":\"empty-response\""));
+            assert!(!json.contains("traceId"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pragma_set_serialization() {
+        let msg = ChannelMessage::PragmaSet {
+            query_id: "pragma-1".to_string(),
+            name: "busy_timeout".to_string(),
+            value: SqlParam::Integer(5000),
+        };
+        assert_serialization_roundtrip(msg, "pragma-set", |json| {
+            assert!(json.contains("\"name\":\"busy_timeout\""));
+            assert!(json.contains("\"value\":5000"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_ddl_transaction_serialization() {
+        let msg = ChannelMessage::DdlTransaction {
+            query_id: "ddl-1".to_string(),
+            statements: vec!["CREATE TABLE t (x)".to_string()],
+        };
+        assert_serialization_roundtrip(msg, "ddl-transaction", |json| {
+            assert!(json.contains("\"statements\":[\"CREATE TABLE t (x)\"]"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_table_request_serialization() {
+        let msg = ChannelMessage::DropTable {
+            query_id: "drop-1".to_string(),
+            table: "items".to_string(),
+            if_exists: true,
+        };
+        assert_serialization_roundtrip(msg, "drop-table", |json| {
+            assert!(json.contains("\"ifExists\":true"));
+        });
+
+        let msg = ChannelMessage::RenameTable {
+            query_id: "rename-1".to_string(),
+            old_name: "items".to_string(),
+            new_name: "archived_items".to_string(),
+        };
+        assert_serialization_roundtrip(msg, "rename-table", |json| {
+            assert!(json.contains("\"oldName\":\"items\""));
+            assert!(json.contains("\"newName\":\"archived_items\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reindex_request_serialization() {
+        let msg = ChannelMessage::ReindexRequest {
+            query_id: "reindex-1".to_string(),
+            index_name: Some("items_name".to_string()),
+        };
+        assert_serialization_roundtrip(msg, "reindex-request", |json| {
+            assert!(json.contains("\"indexName\":\"items_name\""));
+        });
+
+        let msg = ChannelMessage::ReindexRequest {
+            query_id: "reindex-2".to_string(),
+            index_name: None,
+        };
+        assert_serialization_roundtrip(msg, "reindex-request", |json| {
+            assert!(json.contains("\"indexName\":null"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_validate_sql_request_serialization() {
+        let msg = ChannelMessage::ValidateSqlRequest {
+            query_id: "validate-1".to_string(),
+            sql: "SELECT * FROM users".to_string(),
+        };
+        assert_eq!(msg.sql(), Some("SELECT * FROM users"));
+        assert_serialization_roundtrip(msg, "validate-sql-request", |json| {
+            assert!(json.contains("\"queryId\":\"validate-1\""));
+            assert!(json.contains("\"sql\":\"SELECT * FROM users\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_backup_request_serialization() {
+        let msg = ChannelMessage::BackupRequest {
+            query_id: "backup-1".to_string(),
+            target_name: "archive.db".to_string(),
+        };
+        let json = serde_json::to_string(&msg).expect("Should serialize");
+        assert!(json.contains("\"type\":\"backup-request\""));
+        assert!(json.contains("\"targetName\":\"archive.db\""));
+        let back: ChannelMessage = serde_json::from_str(&json).expect("Should deserialize");
+        assert_eq!(back, msg);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_register_function_serialization() {
+        let msg = ChannelMessage::RegisterFunction {
+            query_id: "fn-1".to_string(),
+            name: "UPPER2".to_string(),
+            arity: 1,
+            func_source: "(text) => text.toUpperCase()".to_string(),
+        };
+        assert_serialization_roundtrip(msg, "register-function", |json| {
+            assert!(json.contains("\"arity\":1"));
+            assert!(json.contains("\"funcSource\":\"(text) => text.toUpperCase()\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_echo_messages_serialization() {
+        let request = ChannelMessage::EchoRequest {
+            echo_id: "echo-1".to_string(),
+            payload: "ping".to_string(),
+        };
+        assert_serialization_roundtrip(request, "echo-request", |json| {
+            assert!(json.contains("\"echoId\":\"echo-1\""));
+        });
+
+        let response = ChannelMessage::EchoResponse {
+            echo_id: "echo-1".to_string(),
+            payload: "ping".to_string(),
+            responder_id: "leader-1".to_string(),
+        };
+        assert_serialization_roundtrip(response, "echo-response", |json| {
+            assert!(json.contains("\"responderId\":\"leader-1\""));
+            assert!(json.contains("\"payload\":\"ping\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_state_messages_serialization() {
+        let request = ChannelMessage::RequestState {
+            requester_id: "worker-1".to_string(),
+        };
+        assert_serialization_roundtrip(request, "request-state", |json| {
+            assert!(json.contains("\"requesterId\":\"worker-1\""));
+        });
+
+        let response = ChannelMessage::StateResponse {
+            from_leader_id: "leader-1".to_string(),
+            to_requester_id: "worker-1".to_string(),
+            db_initialized: true,
+            pending_queue_depth: 2,
+            uptime_ms: 1500.0,
+        };
+        assert_serialization_roundtrip(response, "state-response", |json| {
+            assert!(json.contains("\"toRequesterId\":\"worker-1\""));
+            assert!(json.contains("\"dbInitialized\":true"));
+            assert!(json.contains("\"pendingQueueDepth\":2"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_gossip_state_serialization() {
+        let msg = ChannelMessage::GossipState {
+            from: "worker-1".to_string(),
+            peers: vec![WorkerGossip {
+                worker_id: "worker-2".to_string(),
+                is_leader: true,
+                last_seen_ms: 1500.0,
+            }],
+        };
+        assert_serialization_roundtrip(msg, "gossip-state", |json| {
+            assert!(json.contains("\"from\":\"worker-1\""));
+            assert!(json
+                .contains("{\"workerId\":\"worker-2\",\"isLeader\":true,\"lastSeenMs\":1500.0}"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_database_reset_serialization() {
+        let msg = ChannelMessage::DatabaseReset {
+            leader_id: "leader-1".to_string(),
+        };
+        assert_serialization_roundtrip(msg, "database-reset", |json| {
+            assert!(json.contains("\"leaderId\":\"leader-1\""));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_query_progress_serialization() {
+        let request = ChannelMessage::QueryRequest {
+            query_id: "query-1".to_string(),
+            sql: "SELECT 1".to_string(),
+            origin_worker_id: "worker-1".to_string(),
+            metadata: HashMap::new(),
+            source_location: None,
+            read_concern: None,
+            progress_interval: Some(100_000),
+        };
+        assert_serialization_roundtrip(request, "query-request", |json| {
+            assert!(json.contains("\"progressInterval\":100000"));
+        });
+
+        let progress = ChannelMessage::QueryProgress {
+            query_id: "query-1".to_string(),
+            vm_steps_completed: 300_000,
+        };
+        assert_serialization_roundtrip(progress, "query-progress", |json| {
+            assert!(json.contains("\"queryId\":\"query-1\""));
+            assert!(json.contains("\"vmStepsCompleted\":300000"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_custom_message_serialization() {
+        let msg = ChannelMessage::Custom {
+            type_name: "cursor-moved".to_string(),
+            payload: serde_json::json!({ "x": 3, "y": [1, 2] }),
+        };
+        assert_serialization_roundtrip(msg, "custom", |json| {
+            assert!(json.contains("\"typeName\":\"cursor-moved\""));
+            assert!(json.contains("\"payload\":{\"x\":3,\"y\":[1,2]}"));
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn test_batch_result_conversion() {
+        let ok = BatchResult::from(Ok(QueryResult::default()));
+        assert_eq!(ok.into_result(), Ok(QueryResult::default()));
+
+        let err = BatchResult::from(Err(WorkerError::sqlite("syntax error".to_string())));
+        assert_eq!(
+            err.into_result(),
+            Err(WorkerError::sqlite("syntax error".to_string()))
+        );
+
+        let constraint = BatchResult::from(Err(WorkerError::ConstraintViolation {
+            extended_code: 2067,
+            message: "UNIQUE constraint failed".to_string(),
+        }));
+        assert_eq!(constraint.error_code, Some(2067));
+        assert_eq!(
+            constraint.into_result().unwrap_err().sqlite_code(),
+            Some(2067)
+        );
+
+        let empty = BatchResult {
+            result: None,
+            error: None,
+            error_code: None,
+        };
+        assert!(empty.into_result().is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_query_response_builder() {
+        assert_eq!(
+            query_response("q".to_string(), Ok("done".to_string())),
+            ChannelMessage::QueryResponse {
+                query_id: "q".to_string(),
+                result: Some("done".to_string()),
+                error: None,
+                error_code: None,
+                stats: None,
+            }
+        );
+        assert_eq!(
+            query_response("q".to_string(), Err("failed".to_string())),
+            ChannelMessage::QueryResponse {
+                query_id: "q".to_string(),
+                result: None,
+                error: Some("failed".to_string()),
+                error_code: None,
+                stats: None,
+            }
+        );
+        assert_eq!(
+            coded_query_response(
+                "q".to_string(),
+                Err(WorkerError::ConstraintViolation {
+                    extended_code: 2067,
+                    message: "UNIQUE constraint failed: users.email".to_string(),
+                })
+            ),
+            ChannelMessage::QueryResponse {
+                query_id: "q".to_string(),
+                result: None,
+                error: Some("UNIQUE constraint failed: users.email".to_string()),
+                error_code: Some(2067),
+                stats: None,
+            }
+        );
     }
 
     #[wasm_bindgen_test]
@@ -167,6 +1579,11 @@ mod tests {
         let empty_sql = ChannelMessage::QueryRequest {
             query_id: "test".to_string(),
             sql: String::new(),
+            origin_worker_id: "worker-1".to_string(),
+            metadata: HashMap::new(),
+            source_location: None,
+            read_concern: None,
+            progress_interval: None,
         };
         assert_serialization_roundtrip(empty_sql, "query-request", |json| {
             assert!(json.contains("\"sql\":\"\""));
@@ -175,6 +1592,11 @@ mod tests {
         let special_chars = ChannelMessage::QueryRequest {
             query_id: "query\"with\"quotes".to_string(),
             sql: "SELECT 'test\nwith\nnewlines'".to_string(),
+            origin_worker_id: "worker-1".to_string(),
+            metadata: HashMap::new(),
+            source_location: None,
+            read_concern: None,
+            progress_interval: None,
         };
         assert_serialization_roundtrip(special_chars, "query-request", |_| {});
     }
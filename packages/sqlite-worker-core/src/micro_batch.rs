@@ -0,0 +1,298 @@
+use js_sys::{Function, Reflect};
+use std::cell::RefCell;
+use std::rc::Rc;
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::{ChannelSender, WorkerState};
+use crate::messages::{BatchedMessage, ChannelMessage};
+
+/// A query request held back until the end of the current microtask
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingDispatch {
+    pub query_id: String,
+    pub message: ChannelMessage,
+    pub trace_id: Option<String>,
+}
+
+/// Coalesces query requests made within one microtask into a single
+/// `BatchQueryRequest`, used when `WorkerConfig::batch_dispatch` is set. The
+/// leader answers each request in it exactly as it would have answered it
+/// alone.
+#[derive(Debug, Default)]
+pub struct MicroBatch {
+    queue: Rc<RefCell<Vec<PendingDispatch>>>,
+}
+
+impl MicroBatch {
+    pub fn new() -> Self {
+        MicroBatch::default()
+    }
+
+    /// Queue `dispatch`, scheduling a flush with `queueMicrotask` if it is the
+    /// first since the last flush. Without `queueMicrotask` it is sent at once.
+    pub(crate) fn push(self: &Rc<Self>, dispatch: PendingDispatch, sender: ChannelSender) {
+        let first = {
+            let mut queue = self.queue.borrow_mut();
+            queue.push(dispatch);
+            queue.len() == 1
+        };
+        if !first {
+            return;
+        }
+        let global = js_sys::global();
+        let queue_microtask = Reflect::get(&global, &JsValue::from_str("queueMicrotask"))
+            .ok()
+            .and_then(|queue_microtask| queue_microtask.dyn_into::<Function>().ok());
+        let Some(queue_microtask) = queue_microtask else {
+            self.flush(&sender);
+            return;
+        };
+        let batch = Rc::downgrade(self);
+        let flush = Closure::once_into_js(move || {
+            if let Some(batch) = batch.upgrade() {
+                batch.flush(&sender);
+            }
+        });
+        let _ = queue_microtask.call1(&JsValue::NULL, &flush);
+    }
+
+    /// Send everything queued: a lone request as is, several as one batch
+    pub(crate) fn flush(&self, sender: &ChannelSender) {
+        let mut queued = std::mem::take(&mut *self.queue.borrow_mut());
+        match queued.len() {
+            0 => {}
+            1 => {
+                let dispatch = queued.remove(0);
+                sender.post(dispatch.message, dispatch.trace_id);
+            }
+            _ => {
+                let requests = queued
+                    .into_iter()
+                    .map(|dispatch| BatchedMessage {
+                        trace_id: dispatch.trace_id,
+                        message: dispatch.message,
+                    })
+                    .collect();
+                sender.post(
+                    ChannelMessage::BatchQueryRequest {
+                        query_id: Uuid::new_v4().to_string(),
+                        statements: Vec::new(),
+                        requests,
+                    },
+                    None,
+                );
+            }
+        }
+    }
+}
+
+impl WorkerState {
+    /// Send the query requests queued for batching now rather than at the end
+    /// of the current microtask
+    pub fn flush_batch(&self) {
+        self.micro_batch.flush(&self.sender());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, WorkerStateBuilder, LEADER_WAIT_MS};
+    use crate::dispatcher::MessageKind;
+    use crate::messages::MessageDirection;
+    use crate::query_context::QueryContext;
+    use std::future::Future;
+    use std::task::Poll;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn sent_requests(state: &WorkerState) -> (usize, usize) {
+        let log = state.message_log();
+        let sent = log
+            .iter()
+            .filter(|entry| entry.direction == MessageDirection::Sent);
+        sent.fold((0, 0), |(single, batch), entry| match entry.message {
+            ChannelMessage::QueryRequest { .. } => (single + 1, batch),
+            ChannelMessage::BatchQueryRequest { .. } => (single, batch + 1),
+            _ => (single, batch),
+        })
+    }
+
+    // A leader with a database and a follower that knows it
+    async fn leader_and_follower(batch_dispatch: bool) -> Option<(WorkerState, WorkerState)> {
+        let leader = leader_with_db().await?;
+        leader.setup_channel_listener();
+
+        let follower = WorkerStateBuilder::new()
+            .batch_dispatch(batch_dispatch)
+            .build()
+            .ok()?;
+        follower.setup_channel_listener();
+        // Found before the queries, which would otherwise each wait for it
+        follower.wait_for_leader(LEADER_WAIT_MS).await.ok()?;
+        Some((leader, follower))
+    }
+
+    // Run `statements` concurrently from a follower, returning the results and
+    // the (single, batch) requests it sent
+    async fn run_concurrent(
+        batch_dispatch: bool,
+        statements: Vec<String>,
+    ) -> Option<(Vec<Result<String, String>>, (usize, usize))> {
+        let (_leader, follower) = leader_and_follower(batch_dispatch).await?;
+        let queries = statements
+            .into_iter()
+            .map(|sql| follower.execute_query(sql, QueryContext::default()));
+        let results = join_all(queries.collect()).await;
+        Some((results, sent_requests(&follower)))
+    }
+
+    // Drive every future concurrently on this task, so all of them issue their
+    // requests before any response arrives
+    async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+        let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+        let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+        std::future::poll_fn(|cx| {
+            let mut done = true;
+            for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+                if output.is_none() {
+                    match future.as_mut().poll(cx) {
+                        Poll::Ready(value) => *output = Some(value),
+                        Poll::Pending => done = false,
+                    }
+                }
+            }
+            if done {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        outputs.into_iter().map(Option::unwrap).collect()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_concurrent_queries_are_batched() {
+        let statements: Vec<String> = (0..100).map(|i| format!("SELECT {i} AS n")).collect();
+        let Some((results, sent)) = run_concurrent(true, statements.clone()).await else {
+            return;
+        };
+        assert_eq!(sent, (0, 1));
+        for (i, result) in results.into_iter().enumerate() {
+            let rows: serde_json::Value =
+                serde_json::from_str(&result.expect("Query failed")).expect("Invalid JSON");
+            assert_eq!(rows[0]["n"], i);
+        }
+
+        let Some((_, sent)) = run_concurrent(false, statements).await else {
+            return;
+        };
+        assert_eq!(sent, (100, 0));
+    }
+
+    // Not a pass/fail check beyond the queries succeeding: reports how long 100
+    // concurrent follower queries take with and without batching
+    #[wasm_bindgen_test]
+    async fn bench_concurrent_queries() {
+        for batch_dispatch in [false, true] {
+            let Some((_leader, follower)) = leader_and_follower(batch_dispatch).await else {
+                return;
+            };
+            let queries = (0..100).map(|i| {
+                follower.execute_query(format!("SELECT {i} AS n"), QueryContext::default())
+            });
+            let started_at = js_sys::Date::now();
+            let results = join_all(queries.collect()).await;
+            let elapsed_ms = js_sys::Date::now() - started_at;
+            assert!(results.iter().all(Result::is_ok));
+            console_log!(
+                "100 concurrent queries with batch_dispatch = {batch_dispatch}: {elapsed_ms}ms, \
+                 {:?} (single, batch) requests sent",
+                sent_requests(&follower)
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_batched_results_match_unbatched() {
+        // Each run starts from the same table, and the user's own transaction
+        // is not disturbed by the batch
+        let statements: Vec<String> = [
+            "DROP TABLE IF EXISTS micro_batch_items",
+            "CREATE TABLE micro_batch_items (n INTEGER)",
+            "BEGIN",
+            "INSERT INTO micro_batch_items VALUES (1), (2)",
+            "COMMIT",
+            "SELECT n FROM micro_batch_items ORDER BY n",
+            "SELECT * FROM micro_batch_missing",
+        ]
+        .iter()
+        .map(|sql| sql.to_string())
+        .collect();
+
+        let Some((batched, sent)) = run_concurrent(true, statements.clone()).await else {
+            return;
+        };
+        assert_eq!(sent, (0, 1));
+        let Some((unbatched, _)) = run_concurrent(false, statements).await else {
+            return;
+        };
+        assert_eq!(batched, unbatched);
+        assert!(batched[3].as_ref().unwrap().contains("Rows affected: 2"));
+        assert!(batched[6].is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_batched_requests_keep_their_metadata() {
+        let Some((leader, follower)) = leader_and_follower(true).await else {
+            return;
+        };
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let received_clone = Rc::clone(&received);
+        leader.register_message_handler(
+            MessageKind::BatchQueryRequest,
+            Box::new(move |message| received_clone.borrow_mut().push(message)),
+        );
+
+        let tagged = |tag: &str| {
+            QueryContext::default()
+                .with_metadata("tag", tag)
+                .with_trace_id(format!("trace-{tag}"))
+        };
+        let results = join_all(vec![
+            follower.execute_query("SELECT 1".to_string(), tagged("first")),
+            follower.execute_query("SELECT 2".to_string(), tagged("second")),
+        ])
+        .await;
+        assert!(results.iter().all(Result::is_ok));
+
+        let received = received.borrow();
+        let [ChannelMessage::BatchQueryRequest { requests, .. }] = received.as_slice() else {
+            panic!("Expected one batch, got {received:?}");
+        };
+        let tags: Vec<_> = requests
+            .iter()
+            .map(|batched| match &batched.message {
+                ChannelMessage::QueryRequest {
+                    metadata,
+                    origin_worker_id,
+                    ..
+                } => {
+                    assert_eq!(origin_worker_id, &follower.worker_id);
+                    (metadata["tag"].clone(), batched.trace_id.clone())
+                }
+                other => panic!("Expected a query request, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("first".to_string(), Some("trace-first".to_string())),
+                ("second".to_string(), Some("trace-second".to_string())),
+            ]
+        );
+    }
+}
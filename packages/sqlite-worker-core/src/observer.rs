@@ -0,0 +1,151 @@
+use js_sys::{Array, Function, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+/// Hook into the query and leadership lifecycle of a `WorkerState`, e.g. for
+/// DevTools extensions or APM agents
+pub trait ConnectionObserver {
+    fn on_query_start(&self, id: &str, sql: &str);
+    fn on_query_end(&self, id: &str, duration_ms: f64, success: bool);
+    fn on_leader_changed(&self, new_leader_id: &str);
+}
+
+/// Observer that logs every lifecycle event with `console.table`
+pub struct ConsoleObserver;
+
+impl ConsoleObserver {
+    fn log(entries: &[(&str, JsValue)]) {
+        let row = Object::new();
+        for (key, value) in entries {
+            let _ = Reflect::set(&row, &JsValue::from_str(key), value);
+        }
+
+        let global = js_sys::global();
+        let Ok(console) = Reflect::get(&global, &JsValue::from_str("console")) else {
+            return;
+        };
+        if let Ok(table) = Reflect::get(&console, &JsValue::from_str("table")) {
+            if let Some(table) = table.dyn_ref::<Function>() {
+                let _ = table.call1(&console, &Array::of1(&row));
+            }
+        }
+    }
+}
+
+impl ConnectionObserver for ConsoleObserver {
+    fn on_query_start(&self, id: &str, sql: &str) {
+        Self::log(&[
+            ("event", JsValue::from_str("query-start")),
+            ("id", JsValue::from_str(id)),
+            ("sql", JsValue::from_str(sql)),
+        ]);
+    }
+
+    fn on_query_end(&self, id: &str, duration_ms: f64, success: bool) {
+        Self::log(&[
+            ("event", JsValue::from_str("query-end")),
+            ("id", JsValue::from_str(id)),
+            ("durationMs", JsValue::from_f64(duration_ms)),
+            ("success", JsValue::from_bool(success)),
+        ]);
+    }
+
+    fn on_leader_changed(&self, new_leader_id: &str) {
+        Self::log(&[
+            ("event", JsValue::from_str("leader-changed")),
+            ("leaderId", JsValue::from_str(new_leader_id)),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{sleep, WorkerState};
+    use crate::messages::ChannelMessage;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl ConnectionObserver for RecordingObserver {
+        fn on_query_start(&self, id: &str, sql: &str) {
+            self.events.borrow_mut().push(format!("start:{id}:{sql}"));
+        }
+
+        fn on_query_end(&self, id: &str, _duration_ms: f64, success: bool) {
+            self.events.borrow_mut().push(format!("end:{id}:{success}"));
+        }
+
+        fn on_leader_changed(&self, new_leader_id: &str) {
+            self.events
+                .borrow_mut()
+                .push(format!("leader:{new_leader_id}"));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_console_observer_does_not_panic() {
+        let observer = ConsoleObserver;
+        observer.on_query_start("query-1", "SELECT 1");
+        observer.on_query_end("query-1", 1.5, true);
+        observer.on_leader_changed("leader-1");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_observer_records_query_lifecycle() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let observer = RecordingObserver {
+            events: Rc::clone(&events),
+        };
+        let Ok(state) = WorkerState::builder().observer(Box::new(observer)).build() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+
+        let result = state.execute_query_simple("SELECT 1".to_string()).await;
+        assert!(result.is_err(), "Database is not initialized");
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].starts_with("start:"));
+        assert!(events[0].ends_with(":SELECT 1"));
+        assert!(events[1].starts_with("end:"));
+        assert!(events[1].ends_with(":false"));
+
+        let start_id = events[0].split(':').nth(1).unwrap();
+        let end_id = events[1].split(':').nth(1).unwrap();
+        assert_eq!(start_id, end_id, "Start and end should share the query id");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_observer_records_leader_change() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let observer = RecordingObserver {
+            events: Rc::clone(&events),
+        };
+        let Ok(state) = WorkerState::builder().observer(Box::new(observer)).build() else {
+            return;
+        };
+        let Ok(announcer) = WorkerState::new() else {
+            return;
+        };
+        state.setup_channel_listener();
+
+        announcer
+            .broadcast(ChannelMessage::NewLeader {
+                leader_id: announcer.worker_id.clone(),
+            })
+            .unwrap();
+        sleep(50.0).await;
+
+        assert!(events
+            .borrow()
+            .contains(&format!("leader:{}", announcer.worker_id)));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::SqlParam;
+
+impl WorkerState {
+    /// The `PRAGMA application_id` an application stored to mark the database
+    /// file as its own format
+    pub async fn get_application_id(&self) -> Result<i32, WorkerError> {
+        self.pragma_i32("application_id").await
+    }
+
+    pub async fn set_application_id(&self, id: i32) -> Result<(), WorkerError> {
+        self.set_pragma("application_id", SqlParam::Integer(id.into()))
+            .await
+    }
+
+    /// The `PRAGMA user_version` schema version, for applications running
+    /// their own migrations
+    pub async fn get_user_version(&self) -> Result<i32, WorkerError> {
+        self.pragma_i32("user_version").await
+    }
+
+    pub async fn set_user_version(&self, version: i32) -> Result<(), WorkerError> {
+        self.set_pragma("user_version", SqlParam::Integer(version.into()))
+            .await
+    }
+
+    /// Turn foreign key enforcement on the leader's connection on or off.
+    /// SQLite leaves it off by default, and ignores the change inside a
+    /// transaction.
+    pub async fn enable_foreign_keys(&self, enable: bool) -> Result<(), WorkerError> {
+        self.set_pragma("foreign_keys", SqlParam::Integer(enable.into()))
+            .await
+    }
+
+    pub async fn foreign_keys_enabled(&self) -> Result<bool, WorkerError> {
+        Ok(self.pragma_i32("foreign_keys").await? != 0)
+    }
+
+    /// Fail with `SchemaMismatch` unless `PRAGMA user_version` is `expected`.
+    /// Runs even while dispatch is paused, so `start` can check it first.
+    pub async fn assert_schema_version(&self, expected: i32) -> Result<(), WorkerError> {
+        let (actual,): (i64,) = self
+            .dispatch_parameterized("PRAGMA user_version".to_string(), Vec::new(), None)
+            .await?
+            .get_single()?;
+        let actual = i32::try_from(actual)
+            .map_err(|_| WorkerError::RowDecode(format!("user_version out of range: {actual}")))?;
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(WorkerError::SchemaMismatch { actual, expected })
+        }
+    }
+
+    async fn pragma_i32(&self, pragma: &str) -> Result<i32, WorkerError> {
+        let (value,): (i64,) = self
+            .execute_parameterized(format!("PRAGMA {pragma}"), Vec::new())
+            .await?
+            .get_single()?;
+        i32::try_from(value)
+            .map_err(|_| WorkerError::RowDecode(format!("{pragma} out of range: {value}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_application_id_roundtrip() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for id in [0x5157_4b52, -1, 0] {
+            state.set_application_id(id).await.expect("Set failed");
+            assert_eq!(state.get_application_id().await, Ok(id));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_user_version_roundtrip() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let original = state.get_user_version().await.expect("Get failed");
+        state.set_user_version(42).await.expect("Set failed");
+        assert_eq!(state.get_user_version().await, Ok(42));
+        state
+            .set_user_version(original)
+            .await
+            .expect("Restore failed");
+        assert_eq!(state.get_user_version().await, Ok(original));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_foreign_key_enforcement() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        for sql in [
+            "CREATE TABLE IF NOT EXISTS fk_parent (id INTEGER PRIMARY KEY)",
+            "CREATE TABLE IF NOT EXISTS fk_child (parent_id INTEGER REFERENCES fk_parent(id))",
+            "DELETE FROM fk_child",
+        ] {
+            state
+                .execute_query_simple(sql.to_string())
+                .await
+                .expect("Setup failed");
+        }
+        let dangling = "INSERT INTO fk_child VALUES (404)".to_string();
+
+        state
+            .enable_foreign_keys(true)
+            .await
+            .expect("Enable failed");
+        assert_eq!(state.foreign_keys_enabled().await, Ok(true));
+        assert!(matches!(
+            state.execute_query_simple(dangling.clone()).await,
+            Err(err) if err.to_string().contains("FOREIGN KEY constraint failed")
+        ));
+
+        state
+            .enable_foreign_keys(false)
+            .await
+            .expect("Disable failed");
+        assert_eq!(state.foreign_keys_enabled().await, Ok(false));
+        assert!(state.execute_query_simple(dangling).await.is_ok());
+
+        for sql in ["DROP TABLE fk_child", "DROP TABLE fk_parent"] {
+            let _ = state.execute_query_simple(sql.to_string()).await;
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_assert_schema_version() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let original = state.get_user_version().await.expect("Get failed");
+        state.set_user_version(3).await.expect("Set failed");
+
+        assert_eq!(state.assert_schema_version(3).await, Ok(()));
+        assert_eq!(
+            state.assert_schema_version(4).await,
+            Err(WorkerError::SchemaMismatch {
+                actual: 3,
+                expected: 4
+            })
+        );
+        state
+            .set_user_version(original)
+            .await
+            .expect("Restore failed");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_schema_mismatch_rejects_queries() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let original = state.get_user_version().await.expect("Get failed");
+        state.set_user_version(3).await.expect("Set failed");
+
+        state
+            .verify_schema_version(3)
+            .await
+            .expect("Matching version should pass");
+        assert!(state
+            .execute_query_simple("SELECT 1".to_string())
+            .await
+            .is_ok());
+
+        let mismatch = WorkerError::SchemaMismatch {
+            actual: 3,
+            expected: 5,
+        };
+        assert_eq!(state.verify_schema_version(5).await, Err(mismatch.clone()));
+        assert_eq!(
+            state.execute_query_simple("SELECT 1".to_string()).await,
+            Err(mismatch.clone())
+        );
+        assert_eq!(state.get_user_version().await, Err(mismatch));
+
+        *state.schema_error.borrow_mut() = None;
+        state
+            .set_user_version(original)
+            .await
+            .expect("Restore failed");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::{QueryResult, SqlParam};
+
+/// A statement the leader parsed once with `WorkerState::prepare`, run any
+/// number of times with new parameters. Dropping it lets the leader finalize
+/// the statement.
+pub struct PreparedStatement<'a> {
+    state: &'a WorkerState,
+    stmt_id: String,
+}
+
+impl<'a> PreparedStatement<'a> {
+    pub(crate) fn new(state: &'a WorkerState, stmt_id: String) -> Self {
+        PreparedStatement { state, stmt_id }
+    }
+
+    /// The leader's id for the statement
+    pub fn stmt_id(&self) -> &str {
+        &self.stmt_id
+    }
+
+    /// Run the statement with `params` bound to its `?` placeholders in order
+    pub async fn execute(&self, params: &[SqlParam]) -> Result<QueryResult, WorkerError> {
+        self.state.execute_prepared(&self.stmt_id, params).await
+    }
+}
+
+impl Drop for PreparedStatement<'_> {
+    fn drop(&mut self) {
+        self.state.finalize_prepared(&self.stmt_id);
+    }
+}
+
+impl std::fmt::Debug for PreparedStatement<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedStatement")
+            .field("stmt_id", &self.stmt_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, sleep};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn prepared_count(leader: &WorkerState) -> usize {
+        leader
+            .db
+            .borrow()
+            .as_ref()
+            .map_or(0, |database| database.prepared_statement_count())
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_prepared_statement_on_leader() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS prepared_items".to_string(),
+                "CREATE TABLE prepared_items (id INTEGER, name TEXT)".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+
+        let insert = state
+            .prepare("INSERT INTO prepared_items VALUES (?, ?)")
+            .await
+            .expect("Prepare failed");
+        for id in 0..100 {
+            let result = insert
+                .execute(&[SqlParam::Integer(id), SqlParam::Text(format!("item-{id}"))])
+                .await
+                .expect("Insert failed");
+            assert_eq!(result.rows_affected, 1);
+        }
+
+        let select = state
+            .prepare("SELECT name FROM prepared_items WHERE id = ?")
+            .await
+            .expect("Prepare failed");
+        assert_eq!(prepared_count(&state), 2);
+        let (name,): (String,) = select
+            .execute(&[SqlParam::Integer(42)])
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(name, "item-42");
+        // Bindings from the previous run do not carry over
+        assert!(select.execute(&[]).await.unwrap().rows.is_empty());
+
+        drop(insert);
+        drop(select);
+        assert_eq!(prepared_count(&state), 0);
+
+        assert!(state.prepare("SELEC nothing").await.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_prepared_statement_from_follower() {
+        let (Some(leader), Ok(follower)) = (leader_with_db().await, WorkerState::new()) else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        let select = follower
+            .prepare("SELECT ? * 2 AS doubled")
+            .await
+            .expect("Prepare failed");
+        assert_eq!(prepared_count(&leader), 1);
+        for n in [1, 5, 21] {
+            let (doubled,): (i64,) = select
+                .execute(&[SqlParam::Integer(n)])
+                .await
+                .unwrap()
+                .get_single()
+                .unwrap();
+            assert_eq!(doubled, n * 2);
+        }
+
+        let stmt_id = select.stmt_id().to_string();
+        drop(select);
+        sleep(50.0).await;
+        assert_eq!(prepared_count(&leader), 0);
+        assert_eq!(
+            leader.execute_prepared(&stmt_id, &[]).await,
+            Err(WorkerError::StatementNotFound(stmt_id))
+        );
+    }
+}
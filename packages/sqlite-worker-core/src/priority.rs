@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Relative urgency of a query
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl QueryPriority {
+    pub const ALL: [QueryPriority; 3] = [
+        QueryPriority::High,
+        QueryPriority::Normal,
+        QueryPriority::Low,
+    ];
+}
+
+/// Number of queries at each priority, serialized as `{ high, normal, low }`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PriorityCounts {
+    pub high: usize,
+    pub normal: usize,
+    pub low: usize,
+}
+
+impl From<&HashMap<QueryPriority, usize>> for PriorityCounts {
+    fn from(counts: &HashMap<QueryPriority, usize>) -> Self {
+        let count = |priority| counts.get(&priority).copied().unwrap_or(0);
+        PriorityCounts {
+            high: count(QueryPriority::High),
+            normal: count(QueryPriority::Normal),
+            low: count(QueryPriority::Low),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_query_priority_defaults_and_serialization() {
+        assert_eq!(QueryPriority::default(), QueryPriority::Normal);
+        assert_eq!(
+            serde_json::to_string(&QueryPriority::High).unwrap(),
+            "\"high\""
+        );
+        assert!(QueryPriority::High < QueryPriority::Low);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_priority_counts_from_map() {
+        let counts = HashMap::from([(QueryPriority::High, 2), (QueryPriority::Low, 1)]);
+        let counts = PriorityCounts::from(&counts);
+        assert_eq!(
+            counts,
+            PriorityCounts {
+                high: 2,
+                normal: 0,
+                low: 1
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&counts).unwrap(),
+            r#"{"high":2,"normal":0,"low":1}"#
+        );
+    }
+}
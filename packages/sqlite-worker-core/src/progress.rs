@@ -0,0 +1,111 @@
+use js_sys::Function;
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::QueryResult;
+
+impl WorkerState {
+    /// Run `sql` on the leader's connection, calling `cb` with the number of
+    /// virtual machine instructions run so far about every
+    /// `progress_interval` of them. If `cb` returns `true` the query stops
+    /// and fails with `QueryInterrupted`. The callback cannot be sent to
+    /// another worker, so this fails with `NotLeader` elsewhere.
+    pub async fn query_with_progress(
+        &self,
+        sql: String,
+        progress_interval: u32,
+        cb: Function,
+    ) -> Result<QueryResult, WorkerError> {
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        let database = self
+            .db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)?;
+
+        let progress_interval = progress_interval.max(1);
+        let calls = Cell::new(0u64);
+        database.set_progress_handler(
+            progress_interval,
+            Some(Box::new(move || {
+                calls.set(calls.get() + 1);
+                let steps = calls.get() * u64::from(progress_interval);
+                cb.call1(&JsValue::NULL, &JsValue::from(steps as f64))
+                    .is_ok_and(|interrupt| interrupt.as_bool() == Some(true))
+            })),
+        );
+        let result = database.query(&sql).await;
+        database.set_progress_handler(0, None);
+        result.map_err(WorkerError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // 100k rows from a recursive CTE, summed so the query runs for a while
+    const LONG_QUERY: &str = "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n \
+                              WHERE x < 100000) SELECT sum(x) AS total FROM n";
+
+    #[wasm_bindgen_test]
+    async fn test_query_with_progress_reports_steps() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let steps = js_sys::Array::new();
+        let cb = Function::new_with_args("steps", "this.push(steps); return false;").bind(&steps);
+
+        let (total,): (i64,) = state
+            .query_with_progress(LONG_QUERY.to_string(), 1000, cb)
+            .await
+            .expect("Query failed")
+            .get_single()
+            .unwrap();
+        assert_eq!(total, 5_000_050_000);
+        assert!(steps.length() > 1, "Called {} times", steps.length());
+        assert_eq!(steps.get(0).as_f64(), Some(1000.0));
+        assert_eq!(steps.get(1).as_f64(), Some(2000.0));
+
+        // The handler is removed once the query finishes
+        let before = steps.length();
+        state
+            .execute_query_simple(LONG_QUERY.to_string())
+            .await
+            .expect("Query failed");
+        assert_eq!(steps.length(), before);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_with_progress_interrupts() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let cb = Function::new_with_args("steps", "return steps >= 5000;");
+        let result = state
+            .query_with_progress(LONG_QUERY.to_string(), 1000, cb)
+            .await;
+        assert!(
+            matches!(result, Err(WorkerError::QueryInterrupted { .. })),
+            "{result:?}"
+        );
+
+        let Ok(follower) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            follower
+                .query_with_progress("SELECT 1".to_string(), 1000, Function::new_no_args(""))
+                .await,
+            Err(WorkerError::NotLeader)
+        );
+    }
+}
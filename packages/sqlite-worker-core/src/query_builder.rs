@@ -0,0 +1,1081 @@
+use std::collections::HashMap;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::{QueryResult, SqlParam};
+
+// First SQLite release with `RETURNING` clauses
+const RETURNING_MIN_VERSION: (u32, u32, u32) = (3, 35, 0);
+
+// First SQLite release with `INSERT ... ON CONFLICT DO UPDATE`
+const UPSERT_MIN_VERSION: (u32, u32, u32) = (3, 24, 0);
+
+// Most rows `bulk_insert` puts in one statement
+pub const BULK_INSERT_CHUNK_ROWS: usize = 500;
+// SQLite's default limit on `?` parameters in one statement
+const MAX_VARIABLE_NUMBER: usize = 32766;
+
+// Validated column names in a stable order, paired with their values
+fn sorted_columns(
+    row: HashMap<String, SqlParam>,
+) -> Result<(Vec<String>, Vec<SqlParam>), WorkerError> {
+    let mut entries: Vec<(String, SqlParam)> = row.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (column, _) in &entries {
+        validate_identifier(column)?;
+    }
+    Ok(entries.into_iter().unzip())
+}
+
+/// `INSERT` statement and parameters for a column-value map
+pub fn insert_sql(
+    table: &str,
+    row: HashMap<String, SqlParam>,
+) -> Result<(String, Vec<SqlParam>), WorkerError> {
+    validate_identifier(table)?;
+    if row.is_empty() {
+        return Ok((format!("INSERT INTO {table} DEFAULT VALUES"), Vec::new()));
+    }
+
+    let (columns, params) = sorted_columns(row)?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let sql = format!(
+        "INSERT INTO {table} ({}) VALUES ({placeholders})",
+        columns.join(", ")
+    );
+    Ok((sql, params))
+}
+
+/// The shared, validated column names of `rows` and each row's values in
+/// that order. Fails with `InconsistentColumns` if a row's columns differ
+/// from the first row's.
+pub fn bulk_insert_rows(
+    rows: Vec<HashMap<String, SqlParam>>,
+) -> Result<(Vec<String>, Vec<Vec<SqlParam>>), WorkerError> {
+    let mut columns = Vec::new();
+    let mut values = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        let (found, row_values) = sorted_columns(row)?;
+        if index == 0 {
+            if found.is_empty() {
+                return Err(WorkerError::QueryFailed(
+                    "Bulk insert requires at least one column".to_string(),
+                ));
+            }
+            columns = found;
+        } else if found != columns {
+            return Err(WorkerError::InconsistentColumns {
+                row: index,
+                expected: columns,
+                found,
+            });
+        }
+        values.push(row_values);
+    }
+    Ok((columns, values))
+}
+
+/// Rows per `bulk_insert` statement for `column_count` columns, keeping the
+/// parameters under SQLite's limit
+pub fn bulk_insert_chunk_rows(column_count: usize) -> usize {
+    (MAX_VARIABLE_NUMBER / column_count.max(1)).clamp(1, BULK_INSERT_CHUNK_ROWS)
+}
+
+/// Multi-row `INSERT` statement for `row_count` rows of `columns`
+pub fn bulk_insert_sql(table: &str, columns: &[String], row_count: usize) -> String {
+    let row = format!("({})", vec!["?"; columns.len()].join(", "));
+    format!(
+        "INSERT INTO {table} ({}) VALUES {}",
+        columns.join(", "),
+        vec![row.as_str(); row_count].join(", ")
+    )
+}
+
+/// `INSERT ... ON CONFLICT ({conflict_cols}) DO UPDATE` statement and
+/// parameters for a column-value map, updating every column not in
+/// `conflict_cols`. With no other columns the conflicting row is left as it is.
+pub fn upsert_sql(
+    table: &str,
+    row: HashMap<String, SqlParam>,
+    conflict_cols: &[&str],
+) -> Result<(String, Vec<SqlParam>), WorkerError> {
+    validate_identifier(table)?;
+    check_conflict_columns(&row, conflict_cols)?;
+
+    let (columns, params) = sorted_columns(row)?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let assignments: Vec<String> = columns
+        .iter()
+        .filter(|column| !conflict_cols.contains(&column.as_str()))
+        .map(|column| format!("{column} = excluded.{column}"))
+        .collect();
+    let action = if assignments.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!("DO UPDATE SET {}", assignments.join(", "))
+    };
+    let sql = format!(
+        "INSERT INTO {table} ({}) VALUES ({placeholders}) ON CONFLICT ({}) {action}",
+        columns.join(", "),
+        conflict_cols.join(", ")
+    );
+    Ok((sql, params))
+}
+
+/// `INSERT OR REPLACE` statement and parameters for a column-value map. A
+/// conflicting row is deleted and the new one inserted in its place.
+pub fn insert_or_replace_sql(
+    table: &str,
+    row: HashMap<String, SqlParam>,
+) -> Result<(String, Vec<SqlParam>), WorkerError> {
+    validate_identifier(table)?;
+    let (columns, params) = sorted_columns(row)?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let sql = format!(
+        "INSERT OR REPLACE INTO {table} ({}) VALUES ({placeholders})",
+        columns.join(", ")
+    );
+    Ok((sql, params))
+}
+
+fn check_conflict_columns(
+    row: &HashMap<String, SqlParam>,
+    conflict_cols: &[&str],
+) -> Result<(), WorkerError> {
+    if conflict_cols.is_empty() {
+        return Err(WorkerError::QueryFailed(
+            "Upsert requires at least one conflict column".to_string(),
+        ));
+    }
+    for column in conflict_cols {
+        validate_identifier(column)?;
+        if !row.contains_key(*column) {
+            return Err(WorkerError::QueryFailed(format!(
+                "Conflict column {column} is not in the row"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// `UPDATE` statement and parameters; the `SET` values bind before `where_params`
+pub fn update_sql(
+    table: &str,
+    set: HashMap<String, SqlParam>,
+    where_clause: Option<String>,
+    where_params: Vec<SqlParam>,
+) -> Result<(String, Vec<SqlParam>), WorkerError> {
+    validate_identifier(table)?;
+    if set.is_empty() {
+        return Err(WorkerError::QueryFailed(
+            "UPDATE requires at least one column".to_string(),
+        ));
+    }
+
+    let (columns, mut params) = sorted_columns(set)?;
+    let assignments: Vec<String> = columns
+        .iter()
+        .map(|column| format!("{column} = ?"))
+        .collect();
+    let mut sql = format!("UPDATE {table} SET {}", assignments.join(", "));
+    if let Some(where_clause) = where_clause {
+        sql.push_str(&format!(" WHERE {where_clause}"));
+    }
+    params.extend(where_params);
+    Ok((sql, params))
+}
+
+/// `DELETE` statement for the rows matching `where_clause`
+pub fn delete_sql(table: &str, where_clause: &str) -> Result<String, WorkerError> {
+    validate_identifier(table)?;
+    Ok(format!("DELETE FROM {table} WHERE {where_clause}"))
+}
+
+/// Outcome of `upsert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpsertResult {
+    // No row with the conflict columns' values existed beforehand
+    pub inserted: bool,
+    pub rowid: i64,
+    pub rows_affected: u64,
+}
+
+/// A data-modifying statement, with the same arguments as `execute_insert`,
+/// `execute_update` and `execute_delete`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DmlOperation {
+    Insert(HashMap<String, SqlParam>),
+    Update {
+        set: HashMap<String, SqlParam>,
+        where_clause: Option<String>,
+        where_params: Vec<SqlParam>,
+    },
+    Delete {
+        where_clause: String,
+        params: Vec<SqlParam>,
+    },
+}
+
+/// A DML statement and the columns its `RETURNING` clause reports, where no
+/// columns means `RETURNING *`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturningStatement {
+    pub operation: DmlOperation,
+    pub columns: Vec<String>,
+}
+
+/// Statement and parameters for `stmt` run against `table`
+pub fn returning_sql(
+    table: &str,
+    stmt: ReturningStatement,
+) -> Result<(String, Vec<SqlParam>), WorkerError> {
+    for column in &stmt.columns {
+        validate_identifier(column)?;
+    }
+    let (mut sql, params) = match stmt.operation {
+        DmlOperation::Insert(row) => insert_sql(table, row)?,
+        DmlOperation::Update {
+            set,
+            where_clause,
+            where_params,
+        } => update_sql(table, set, where_clause, where_params)?,
+        DmlOperation::Delete {
+            where_clause,
+            params,
+        } => (delete_sql(table, &where_clause)?, params),
+    };
+    if stmt.columns.is_empty() {
+        sql.push_str(" RETURNING *");
+    } else {
+        sql.push_str(&format!(" RETURNING {}", stmt.columns.join(", ")));
+    }
+    Ok((sql, params))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    Integer,
+    Real,
+    Blob,
+    Numeric,
+}
+
+impl ColumnType {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Blob => "BLOB",
+            ColumnType::Numeric => "NUMERIC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    NotNull,
+    PrimaryKey,
+    Unique,
+    Default(SqlParam),
+    /// Foreign key to `(table, column)`
+    References(String, String),
+}
+
+impl ColumnConstraint {
+    fn to_sql(&self) -> String {
+        match self {
+            ColumnConstraint::NotNull => "NOT NULL".to_string(),
+            ColumnConstraint::PrimaryKey => "PRIMARY KEY".to_string(),
+            ColumnConstraint::Unique => "UNIQUE".to_string(),
+            ColumnConstraint::Default(value) => format!("DEFAULT {}", sql_literal(value)),
+            ColumnConstraint::References(table, column) => {
+                format!("REFERENCES {table}({column})")
+            }
+        }
+    }
+}
+
+// Render a value as an SQL literal for use in a DEFAULT clause
+pub(crate) fn sql_literal(value: &SqlParam) -> String {
+    match value {
+        SqlParam::Null => "NULL".to_string(),
+        SqlParam::Integer(val) => val.to_string(),
+        SqlParam::Real(val) if val.is_finite() => format!("{val:?}"),
+        SqlParam::Real(_) => "NULL".to_string(),
+        SqlParam::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        SqlParam::Blob(bytes) => {
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            format!("X'{hex}'")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnDefinition {
+    name: String,
+    ty: ColumnType,
+    constraints: Vec<ColumnConstraint>,
+}
+
+/// Builds a `CREATE TABLE IF NOT EXISTS` statement from typed column definitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableBuilder {
+    name: String,
+    columns: Vec<ColumnDefinition>,
+}
+
+impl TableBuilder {
+    pub fn new(name: &str) -> Self {
+        TableBuilder {
+            name: name.to_string(),
+            columns: Vec::new(),
+        }
+    }
+
+    pub fn column(mut self, name: &str, ty: ColumnType, constraints: &[ColumnConstraint]) -> Self {
+        self.columns.push(ColumnDefinition {
+            name: name.to_string(),
+            ty,
+            constraints: constraints.to_vec(),
+        });
+        self
+    }
+
+    pub fn build_create_sql(&self) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut definition = format!("{} {}", column.name, column.ty.as_sql());
+                for constraint in &column.constraints {
+                    definition.push(' ');
+                    definition.push_str(&constraint.to_sql());
+                }
+                definition
+            })
+            .collect();
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            self.name,
+            columns.join(", ")
+        )
+    }
+
+    // Every name spliced into the statement must be a safe identifier
+    fn validate(&self) -> Result<(), WorkerError> {
+        validate_identifier(&self.name)?;
+        if self.columns.is_empty() {
+            return Err(WorkerError::QueryFailed(format!(
+                "Table {} has no columns",
+                self.name
+            )));
+        }
+        for column in &self.columns {
+            validate_identifier(&column.name)?;
+            for constraint in &column.constraints {
+                if let ColumnConstraint::References(table, column) = constraint {
+                    validate_identifier(table)?;
+                    validate_identifier(column)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WorkerState {
+    /// Create the table described by `builder` if it does not already exist,
+    /// as a `STRICT` table in strict mode
+    pub async fn create_table(&self, builder: TableBuilder) -> Result<(), WorkerError> {
+        builder.validate()?;
+        let mut sql = [builder.build_create_sql()];
+        self.apply_strict_mode(&mut sql).await?;
+        let [sql] = sql;
+        self.execute_parameterized(sql, Vec::new()).await?;
+        Ok(())
+    }
+
+    /// Insert one row from a column-value map and return its rowid
+    pub async fn execute_insert(
+        &self,
+        table: &str,
+        row: HashMap<String, SqlParam>,
+    ) -> Result<i64, WorkerError> {
+        let (sql, params) = insert_sql(table, row)?;
+        let result = self.execute_parameterized(sql, params).await?;
+        Ok(result.last_insert_rowid)
+    }
+
+    /// Insert `row`, or update the non-key columns of the row that already has
+    /// its `conflict_cols` values, with `ON CONFLICT DO UPDATE`. Needs SQLite
+    /// 3.24 or later.
+    pub async fn upsert(
+        &self,
+        table: &str,
+        row: HashMap<String, SqlParam>,
+        conflict_cols: &[&str],
+    ) -> Result<UpsertResult, WorkerError> {
+        self.upsert_with_mode(table, row, conflict_cols, false)
+            .await
+    }
+
+    /// `upsert`, falling back to `INSERT OR REPLACE` on SQLite older than 3.24
+    /// if `replace_mode` is set. A replaced row gets a new rowid, and the
+    /// columns missing from `row` take their defaults.
+    pub async fn upsert_with_mode(
+        &self,
+        table: &str,
+        row: HashMap<String, SqlParam>,
+        conflict_cols: &[&str],
+        replace_mode: bool,
+    ) -> Result<UpsertResult, WorkerError> {
+        validate_identifier(table)?;
+        check_conflict_columns(&row, conflict_cols)?;
+        let supported = match self
+            .require_sqlite_version("ON CONFLICT upserts", UPSERT_MIN_VERSION)
+            .await
+        {
+            Ok(()) => true,
+            Err(WorkerError::FeatureUnsupported { .. }) if replace_mode => false,
+            Err(err) => return Err(err),
+        };
+
+        // Tell inserts from updates by looking for the conflicting row first
+        let key_values: Vec<SqlParam> = conflict_cols
+            .iter()
+            .map(|column| row[*column].clone())
+            .collect();
+        let conditions: Vec<String> = conflict_cols
+            .iter()
+            .map(|column| format!("{column} = ?"))
+            .collect();
+        let existing = self
+            .execute_parameterized(
+                format!(
+                    "SELECT rowid AS rowid FROM {table} WHERE {}",
+                    conditions.join(" AND ")
+                ),
+                key_values,
+            )
+            .await?
+            .into_iter()
+            .next()
+            .and_then(|row| row.get_i64("rowid"));
+
+        let (sql, params) = if supported {
+            upsert_sql(table, row, conflict_cols)?
+        } else {
+            insert_or_replace_sql(table, row)?
+        };
+        let result = self.execute_parameterized(sql, params).await?;
+        let rowid = match existing {
+            Some(rowid) if supported => rowid,
+            _ => result.last_insert_rowid,
+        };
+        Ok(UpsertResult {
+            inserted: existing.is_none(),
+            rowid,
+            rows_affected: result.rows_affected,
+        })
+    }
+
+    /// Update the rows matching `where_clause` (every row if `None`) and return
+    /// the number of rows changed
+    pub async fn execute_update(
+        &self,
+        table: &str,
+        set: HashMap<String, SqlParam>,
+        where_clause: Option<String>,
+        where_params: Vec<SqlParam>,
+    ) -> Result<u64, WorkerError> {
+        let (sql, params) = update_sql(table, set, where_clause, where_params)?;
+        let result = self.execute_parameterized(sql, params).await?;
+        Ok(result.rows_affected)
+    }
+
+    /// Run `stmt` against `table` and return the rows of its `RETURNING`
+    /// clause, such as generated ids. Needs SQLite 3.35 or later.
+    pub async fn execute_returning(
+        &self,
+        table: &str,
+        stmt: ReturningStatement,
+    ) -> Result<QueryResult, WorkerError> {
+        let (sql, params) = returning_sql(table, stmt)?;
+        self.require_sqlite_version("RETURNING", RETURNING_MIN_VERSION)
+            .await?;
+        self.execute_parameterized(sql, params).await
+    }
+
+    /// Delete the rows matching `where_clause` and return the number removed
+    pub async fn execute_delete(
+        &self,
+        table: &str,
+        where_clause: String,
+        params: Vec<SqlParam>,
+    ) -> Result<u64, WorkerError> {
+        let sql = delete_sql(table, &where_clause)?;
+        let result = self.execute_parameterized(sql, params).await?;
+        Ok(result.rows_affected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_insert_sql() {
+        let row = HashMap::from([
+            ("name".to_string(), SqlParam::from("Alice")),
+            ("age".to_string(), SqlParam::Integer(30)),
+        ]);
+        let (sql, params) = insert_sql("users", row).unwrap();
+        assert_eq!(sql, "INSERT INTO users (age, name) VALUES (?, ?)");
+        assert_eq!(params, vec![SqlParam::Integer(30), SqlParam::from("Alice")]);
+
+        let (sql, params) = insert_sql("users", HashMap::new()).unwrap();
+        assert_eq!(sql, "INSERT INTO users DEFAULT VALUES");
+        assert!(params.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_update_sql() {
+        let set = HashMap::from([("name".to_string(), SqlParam::from("Bob"))]);
+        let (sql, params) = update_sql(
+            "users",
+            set,
+            Some("id = ?".to_string()),
+            vec![SqlParam::Integer(1)],
+        )
+        .unwrap();
+        assert_eq!(sql, "UPDATE users SET name = ? WHERE id = ?");
+        assert_eq!(params, vec![SqlParam::from("Bob"), SqlParam::Integer(1)]);
+
+        assert!(update_sql("users", HashMap::new(), None, vec![]).is_err());
+    }
+
+    async fn leader_with_table(table: &str) -> Option<WorkerState> {
+        let state = leader_with_db().await?;
+        state
+            .execute_many(vec![
+                format!("DROP TABLE IF EXISTS {table}"),
+                format!("CREATE TABLE {table} (id INTEGER PRIMARY KEY, name TEXT, age INTEGER)"),
+            ])
+            .await
+            .ok()?;
+        Some(state)
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_insert_update_delete() {
+        let Some(state) = leader_with_table("builder_users").await else {
+            return;
+        };
+
+        let first = state
+            .execute_insert(
+                "builder_users",
+                HashMap::from([
+                    ("name".to_string(), SqlParam::from("Alice")),
+                    ("age".to_string(), SqlParam::Integer(30)),
+                ]),
+            )
+            .await
+            .expect("Insert failed");
+        let second = state
+            .execute_insert(
+                "builder_users",
+                HashMap::from([("name".to_string(), SqlParam::from("Bob"))]),
+            )
+            .await
+            .expect("Insert failed");
+        assert_eq!(second, first + 1);
+
+        let updated = state
+            .execute_update(
+                "builder_users",
+                HashMap::from([("age".to_string(), SqlParam::Integer(31))]),
+                Some("id = ?".to_string()),
+                vec![SqlParam::Integer(first)],
+            )
+            .await
+            .expect("Update failed");
+        assert_eq!(updated, 1);
+
+        let deleted = state
+            .execute_delete(
+                "builder_users",
+                "name = ?".to_string(),
+                vec![SqlParam::from("Bob")],
+            )
+            .await
+            .expect("Delete failed");
+        assert_eq!(deleted, 1);
+
+        let remaining = state
+            .execute_parameterized("SELECT name, age FROM builder_users".to_string(), vec![])
+            .await
+            .expect("Select failed");
+        assert_eq!(
+            remaining.rows,
+            vec![vec![SqlParam::from("Alice"), SqlParam::Integer(31)]]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_returning_sql() {
+        let (sql, params) = returning_sql(
+            "users",
+            ReturningStatement {
+                operation: DmlOperation::Insert(HashMap::from([(
+                    "name".to_string(),
+                    SqlParam::from("Alice"),
+                )])),
+                columns: vec!["id".to_string(), "name".to_string()],
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "INSERT INTO users (name) VALUES (?) RETURNING id, name"
+        );
+        assert_eq!(params, vec![SqlParam::from("Alice")]);
+
+        let (sql, params) = returning_sql(
+            "users",
+            ReturningStatement {
+                operation: DmlOperation::Delete {
+                    where_clause: "id = ?".to_string(),
+                    params: vec![SqlParam::Integer(1)],
+                },
+                columns: vec![],
+            },
+        )
+        .unwrap();
+        assert_eq!(sql, "DELETE FROM users WHERE id = ? RETURNING *");
+        assert_eq!(params, vec![SqlParam::Integer(1)]);
+
+        let invalid = returning_sql(
+            "users",
+            ReturningStatement {
+                operation: DmlOperation::Insert(HashMap::new()),
+                columns: vec!["id; DROP TABLE users".to_string()],
+            },
+        );
+        assert!(matches!(invalid, Err(WorkerError::InvalidIdentifier(_))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_returning_generated_ids() {
+        let Some(state) = leader_with_table("returning_users").await else {
+            return;
+        };
+
+        let insert = |name: &str| ReturningStatement {
+            operation: DmlOperation::Insert(HashMap::from([(
+                "name".to_string(),
+                SqlParam::from(name),
+            )])),
+            columns: vec!["id".to_string()],
+        };
+        let (first,): (i64,) = state
+            .execute_returning("returning_users", insert("Alice"))
+            .await
+            .expect("Insert failed")
+            .get_single()
+            .unwrap();
+        let (second,): (i64,) = state
+            .execute_returning("returning_users", insert("Bob"))
+            .await
+            .expect("Insert failed")
+            .get_single()
+            .unwrap();
+        assert_eq!(second, first + 1);
+
+        let deleted = state
+            .execute_returning(
+                "returning_users",
+                ReturningStatement {
+                    operation: DmlOperation::Delete {
+                        where_clause: "id = ?".to_string(),
+                        params: vec![SqlParam::Integer(first)],
+                    },
+                    columns: vec![],
+                },
+            )
+            .await
+            .expect("Delete failed");
+        assert_eq!(
+            deleted.rows,
+            vec![vec![
+                SqlParam::Integer(first),
+                SqlParam::from("Alice"),
+                SqlParam::Null
+            ]]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_bulk_insert_rows_and_sql() {
+        let row = |name: &str, age: i64| {
+            HashMap::from([
+                ("name".to_string(), SqlParam::from(name)),
+                ("age".to_string(), SqlParam::Integer(age)),
+            ])
+        };
+        let (columns, values) = bulk_insert_rows(vec![row("Alice", 30), row("Bob", 25)]).unwrap();
+        assert_eq!(columns, vec!["age", "name"]);
+        assert_eq!(
+            values,
+            vec![
+                vec![SqlParam::Integer(30), SqlParam::from("Alice")],
+                vec![SqlParam::Integer(25), SqlParam::from("Bob")],
+            ]
+        );
+        assert_eq!(
+            bulk_insert_sql("users", &columns, 2),
+            "INSERT INTO users (age, name) VALUES (?, ?), (?, ?)"
+        );
+
+        let missing_age = HashMap::from([("name".to_string(), SqlParam::from("Carol"))]);
+        assert_eq!(
+            bulk_insert_rows(vec![row("Alice", 30), missing_age]),
+            Err(WorkerError::InconsistentColumns {
+                row: 1,
+                expected: vec!["age".to_string(), "name".to_string()],
+                found: vec!["name".to_string()],
+            })
+        );
+        assert!(bulk_insert_rows(vec![HashMap::new()]).is_err());
+
+        assert_eq!(bulk_insert_chunk_rows(2), BULK_INSERT_CHUNK_ROWS);
+        assert_eq!(bulk_insert_chunk_rows(100), 327);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_bulk_insert_thousand_rows() {
+        let Some(state) = leader_with_table("bulk_users").await else {
+            return;
+        };
+        let rows: Vec<_> = (0..1000)
+            .map(|i| {
+                HashMap::from([
+                    ("name".to_string(), SqlParam::Text(format!("user-{i}"))),
+                    ("age".to_string(), SqlParam::Integer(i)),
+                ])
+            })
+            .collect();
+
+        let started_at = js_sys::Date::now();
+        let inserted = state
+            .bulk_insert("bulk_users", rows)
+            .await
+            .expect("Bulk insert failed");
+        let duration_ms = js_sys::Date::now() - started_at;
+        assert_eq!(inserted, 1000);
+        // Two statements in one transaction, far below a per-row insert's cost
+        assert!(duration_ms < 2000.0, "Bulk insert took {duration_ms}ms");
+
+        let (count, total_age): (i64, i64) = state
+            .execute_parameterized(
+                "SELECT count(*), sum(age) FROM bulk_users".to_string(),
+                vec![],
+            )
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(count, 1000);
+        assert_eq!(total_age, 999 * 1000 / 2);
+        assert_eq!(state.bulk_insert("bulk_users", vec![]).await, Ok(0));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_upsert_sql() {
+        let row = HashMap::from([
+            ("id".to_string(), SqlParam::Integer(1)),
+            ("name".to_string(), SqlParam::from("Alice")),
+            ("age".to_string(), SqlParam::Integer(30)),
+        ]);
+        let (sql, params) = upsert_sql("users", row.clone(), &["id"]).unwrap();
+        assert_eq!(
+            sql,
+            "INSERT INTO users (age, id, name) VALUES (?, ?, ?) \
+             ON CONFLICT (id) DO UPDATE SET age = excluded.age, name = excluded.name"
+        );
+        assert_eq!(
+            params,
+            vec![
+                SqlParam::Integer(30),
+                SqlParam::Integer(1),
+                SqlParam::from("Alice")
+            ]
+        );
+
+        let (sql, _) = upsert_sql("users", row.clone(), &["id", "name", "age"]).unwrap();
+        assert!(sql.ends_with("ON CONFLICT (id, name, age) DO NOTHING"));
+
+        let (sql, _) = insert_or_replace_sql("users", row.clone()).unwrap();
+        assert_eq!(
+            sql,
+            "INSERT OR REPLACE INTO users (age, id, name) VALUES (?, ?, ?)"
+        );
+
+        assert!(upsert_sql("users", row.clone(), &[]).is_err());
+        assert!(upsert_sql("users", row.clone(), &["email"]).is_err());
+        assert!(matches!(
+            upsert_sql("users", row, &["id; DROP TABLE users"]),
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_upsert_inserts_then_updates() {
+        let Some(state) = leader_with_table("upsert_users").await else {
+            return;
+        };
+        let row = |age: i64| {
+            HashMap::from([
+                ("id".to_string(), SqlParam::Integer(7)),
+                ("name".to_string(), SqlParam::from("Alice")),
+                ("age".to_string(), SqlParam::Integer(age)),
+            ])
+        };
+
+        let first = state
+            .upsert("upsert_users", row(30), &["id"])
+            .await
+            .expect("Upsert failed");
+        assert_eq!(
+            first,
+            UpsertResult {
+                inserted: true,
+                rowid: 7,
+                rows_affected: 1
+            }
+        );
+
+        let second = state
+            .upsert("upsert_users", row(31), &["id"])
+            .await
+            .expect("Upsert failed");
+        assert_eq!(
+            second,
+            UpsertResult {
+                inserted: false,
+                rowid: 7,
+                rows_affected: 1
+            }
+        );
+
+        let (count, age): (i64, i64) = state
+            .execute_parameterized(
+                "SELECT count(*), max(age) FROM upsert_users".to_string(),
+                vec![],
+            )
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!((count, age), (1, 31));
+
+        // Replace mode takes the same path on an SQLite with `ON CONFLICT`
+        let replaced = state
+            .upsert_with_mode("upsert_users", row(32), &["id"], true)
+            .await
+            .expect("Upsert failed");
+        assert!(!replaced.inserted);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_helpers_reject_invalid_tables() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let invalid = || WorkerError::InvalidIdentifier("users; DROP TABLE x".to_string());
+
+        assert_eq!(
+            state
+                .execute_insert("users; DROP TABLE x", HashMap::new())
+                .await,
+            Err(invalid())
+        );
+        assert_eq!(
+            state
+                .execute_update(
+                    "users; DROP TABLE x",
+                    HashMap::from([("name".to_string(), SqlParam::Null)]),
+                    None,
+                    vec![],
+                )
+                .await,
+            Err(invalid())
+        );
+        assert_eq!(
+            state
+                .execute_delete("users; DROP TABLE x", "1 = 1".to_string(), vec![])
+                .await,
+            Err(invalid())
+        );
+    }
+
+    fn orders_table(name: &str) -> TableBuilder {
+        TableBuilder::new(name)
+            .column("id", ColumnType::Integer, &[ColumnConstraint::PrimaryKey])
+            .column(
+                "customer_id",
+                ColumnType::Integer,
+                &[
+                    ColumnConstraint::NotNull,
+                    ColumnConstraint::References("builder_customers".to_string(), "id".to_string()),
+                ],
+            )
+            .column(
+                "status",
+                ColumnType::Text,
+                &[ColumnConstraint::Default(SqlParam::from("it's new"))],
+            )
+            .column("total", ColumnType::Real, &[])
+    }
+
+    #[wasm_bindgen_test]
+    fn test_build_create_sql() {
+        assert_eq!(
+            orders_table("orders").build_create_sql(),
+            "CREATE TABLE IF NOT EXISTS orders (id INTEGER PRIMARY KEY, \
+             customer_id INTEGER NOT NULL REFERENCES builder_customers(id), \
+             status TEXT DEFAULT 'it''s new', total REAL)"
+        );
+
+        let defaults = TableBuilder::new("defaults")
+            .column(
+                "a",
+                ColumnType::Real,
+                &[ColumnConstraint::Default(SqlParam::Real(1.0))],
+            )
+            .column(
+                "b",
+                ColumnType::Blob,
+                &[ColumnConstraint::Default(SqlParam::Blob(vec![0xAB, 1]))],
+            )
+            .column(
+                "c",
+                ColumnType::Numeric,
+                &[
+                    ColumnConstraint::Unique,
+                    ColumnConstraint::Default(SqlParam::Null),
+                ],
+            );
+        assert_eq!(
+            defaults.build_create_sql(),
+            "CREATE TABLE IF NOT EXISTS defaults (a REAL DEFAULT 1.0, b BLOB DEFAULT X'AB01', \
+             c NUMERIC UNIQUE DEFAULT NULL)"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_create_table_with_constraints() {
+        let Some(state) = leader_with_table("builder_customers").await else {
+            return;
+        };
+        state
+            .execute_parameterized("DROP TABLE IF EXISTS builder_orders".to_string(), vec![])
+            .await
+            .expect("Drop failed");
+
+        state
+            .create_table(orders_table("builder_orders"))
+            .await
+            .expect("Create failed");
+
+        let info = state
+            .execute_parameterized("PRAGMA table_info(builder_orders)".to_string(), vec![])
+            .await
+            .expect("table_info failed");
+        let columns: Vec<_> = info.into_iter().collect();
+        assert_eq!(columns.len(), 4);
+
+        let customer_id = &columns[1];
+        assert_eq!(customer_id.get_str("name"), Some("customer_id"));
+        assert_eq!(customer_id.get_str("type"), Some("INTEGER"));
+        assert_eq!(customer_id.get_bool("notnull"), Some(true));
+
+        let status = &columns[2];
+        assert_eq!(status.get_str("dflt_value"), Some("'it''s new'"));
+        assert_eq!(columns[0].get_i64("pk"), Some(1));
+
+        let foreign_keys = state
+            .execute_parameterized(
+                "PRAGMA foreign_key_list(builder_orders)".to_string(),
+                vec![],
+            )
+            .await
+            .expect("foreign_key_list failed");
+        let foreign_key = foreign_keys
+            .into_iter()
+            .next()
+            .expect("Should have a foreign key");
+        assert_eq!(foreign_key.get_str("table"), Some("builder_customers"));
+        assert_eq!(foreign_key.get_str("from"), Some("customer_id"));
+        assert_eq!(foreign_key.get_str("to"), Some("id"));
+
+        // IF NOT EXISTS makes repeated creation a no-op
+        assert!(state
+            .create_table(orders_table("builder_orders"))
+            .await
+            .is_ok());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_create_table_rejects_unsafe_names() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let bad_reference = TableBuilder::new("orders").column(
+            "customer_id",
+            ColumnType::Integer,
+            &[ColumnConstraint::References(
+                "customers(id); --".to_string(),
+                "id".to_string(),
+            )],
+        );
+        assert_eq!(
+            state.create_table(bad_reference).await,
+            Err(WorkerError::InvalidIdentifier(
+                "customers(id); --".to_string()
+            ))
+        );
+        assert!(matches!(
+            state.create_table(TableBuilder::new("empty")).await,
+            Err(WorkerError::QueryFailed(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_builders_reject_unsafe_identifiers() {
+        let bad_column = HashMap::from([("name; --".to_string(), SqlParam::Null)]);
+        assert_eq!(
+            insert_sql("users", bad_column).unwrap_err(),
+            WorkerError::InvalidIdentifier("name; --".to_string())
+        );
+        assert_eq!(
+            insert_sql("users x", HashMap::new()).unwrap_err(),
+            WorkerError::InvalidIdentifier("users x".to_string())
+        );
+        assert_eq!(
+            delete_sql("1users", "id = 1").unwrap_err(),
+            WorkerError::InvalidIdentifier("1users".to_string())
+        );
+        assert_eq!(
+            delete_sql("users", "id = ?").unwrap(),
+            "DELETE FROM users WHERE id = ?"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::QueryResult;
+
+pub const DEFAULT_QUERY_CACHE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedEntry {
+    pub result: QueryResult,
+    pub expires_at_ms: f64,
+}
+
+/// Results of `execute_query_cached`, keyed on normalized SQL
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryCache {
+    pub entries: HashMap<String, CachedEntry>,
+    pub max_entries: usize,
+}
+
+/// Cache key for `sql`: surrounding whitespace and trailing semicolons
+/// removed and runs of whitespace collapsed to one space. String literals
+/// are not special-cased, so queries differing only in whitespace inside a
+/// literal share an entry.
+pub fn normalize_sql(sql: &str) -> String {
+    sql.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+        .to_string()
+}
+
+impl QueryCache {
+    pub fn new(max_entries: usize) -> Self {
+        QueryCache {
+            entries: HashMap::new(),
+            max_entries,
+        }
+    }
+
+    pub fn get(&self, sql: &str, now_ms: f64) -> Option<QueryResult> {
+        self.entries
+            .get(&normalize_sql(sql))
+            .filter(|entry| entry.expires_at_ms > now_ms)
+            .map(|entry| entry.result.clone())
+    }
+
+    /// Store `result` until `now_ms + ttl_ms`. When full, expired entries are
+    /// dropped first, then the one closest to expiring.
+    pub fn insert(&mut self, sql: &str, result: QueryResult, now_ms: f64, ttl_ms: u64) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let key = normalize_sql(sql);
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.entries.retain(|_, entry| entry.expires_at_ms > now_ms);
+            if self.entries.len() >= self.max_entries {
+                let soonest = self
+                    .entries
+                    .iter()
+                    .min_by(|a, b| a.1.expires_at_ms.total_cmp(&b.1.expires_at_ms))
+                    .map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    self.entries.remove(&soonest);
+                }
+            }
+        }
+        self.entries.insert(
+            key,
+            CachedEntry {
+                result,
+                expires_at_ms: now_ms + ttl_ms as f64,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, sql: &str) -> bool {
+        self.entries.remove(&normalize_sql(sql)).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        QueryCache::new(DEFAULT_QUERY_CACHE_SIZE)
+    }
+}
+
+impl WorkerState {
+    /// Like `execute_parameterized` with no parameters, but a result less
+    /// than `ttl_ms` old is returned from memory without contacting the
+    /// leader. Writes do not invalidate the cache; call `invalidate_cache`
+    /// after changing data a cached query reads.
+    pub async fn execute_query_cached(
+        &self,
+        sql: &str,
+        ttl_ms: u64,
+    ) -> Result<QueryResult, WorkerError> {
+        if let Some(result) = self.query_cache.borrow().get(sql, self.time.now_ms()) {
+            return Ok(result);
+        }
+        let result = self
+            .execute_parameterized(sql.to_string(), Vec::new())
+            .await?;
+        self.query_cache
+            .borrow_mut()
+            .insert(sql, result.clone(), self.time.now_ms(), ttl_ms);
+        Ok(result)
+    }
+
+    /// Drop the cached result for `sql`, or every cached result if `None`
+    pub fn invalidate_cache(&self, sql: Option<String>) {
+        let mut cache = self.query_cache.borrow_mut();
+        match sql {
+            Some(sql) => {
+                cache.remove(&sql);
+            }
+            None => cache.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, WorkerStateBuilder};
+    use crate::time::MockTimeProvider;
+    use std::rc::Rc;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn result_with(rows_affected: u64) -> QueryResult {
+        QueryResult {
+            rows_affected,
+            ..Default::default()
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_normalize_sql() {
+        assert_eq!(normalize_sql("  SELECT *\n  FROM t ;; "), "SELECT * FROM t");
+        assert_eq!(normalize_sql("SELECT 1"), normalize_sql("SELECT\t1;"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_cache_expiry_and_eviction() {
+        let mut cache = QueryCache::new(2);
+        cache.insert("SELECT 1", result_with(1), 0.0, 100);
+        assert_eq!(cache.get("SELECT  1;", 99.0), Some(result_with(1)));
+        assert_eq!(cache.get("SELECT 1", 100.0), None);
+
+        cache.insert("SELECT 2", result_with(2), 0.0, 500);
+        cache.insert("SELECT 3", result_with(3), 0.0, 1000);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(!cache.entries.contains_key("SELECT 1"));
+
+        // Full with live entries: the one expiring soonest goes
+        cache.insert("SELECT 4", result_with(4), 0.0, 1000);
+        assert_eq!(cache.get("SELECT 2", 0.0), None);
+        assert_eq!(cache.get("SELECT 3", 0.0), Some(result_with(3)));
+
+        assert!(cache.remove("SELECT 3"));
+        assert!(!cache.remove("SELECT 3"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_execute_query_cached() {
+        let time = MockTimeProvider::new();
+        let Ok(state) = WorkerStateBuilder::new()
+            .time_provider(Rc::new(time.clone()))
+            .build()
+        else {
+            return;
+        };
+        let Some(state) = lead_with_db(state).await else {
+            return;
+        };
+        state
+            .execute_query_simple("CREATE TABLE IF NOT EXISTS cached (v INTEGER)".to_string())
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("DELETE FROM cached".to_string())
+            .await
+            .expect("Delete failed");
+
+        let sql = "SELECT count(*) FROM cached";
+        let count = |result: QueryResult| result.get_single::<(i64,)>().unwrap().0;
+        let first = state
+            .execute_query_cached(sql, 1000)
+            .await
+            .expect("Query failed");
+        assert_eq!(count(first), 0);
+
+        state
+            .execute_query_simple("INSERT INTO cached VALUES (1)".to_string())
+            .await
+            .expect("Insert failed");
+        let cached = state
+            .execute_query_cached(sql, 1000)
+            .await
+            .expect("Query failed");
+        assert_eq!(count(cached), 0, "Served from the cache");
+
+        time.advance(1000.0);
+        let expired = state
+            .execute_query_cached(sql, 1000)
+            .await
+            .expect("Query failed");
+        assert_eq!(count(expired), 1);
+
+        state
+            .execute_query_simple("INSERT INTO cached VALUES (2)".to_string())
+            .await
+            .expect("Insert failed");
+        state.invalidate_cache(Some(format!("{sql};")));
+        let invalidated = state
+            .execute_query_cached(sql, 1000)
+            .await
+            .expect("Query failed");
+        assert_eq!(count(invalidated), 2);
+
+        state.invalidate_cache(None);
+        assert!(state.query_cache.borrow().entries.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use js_sys::Function;
+use std::collections::HashMap;
+
+use crate::priority::QueryPriority;
+use crate::read_concern::ReadConcern;
+
+// How long a non-leader waits for the leader's response by default
+pub const DEFAULT_QUERY_TIMEOUT_MS: u64 = 5000;
+// VM instructions between progress reports for a query with `with_progress`
+pub const PROGRESS_REPORT_INTERVAL: u32 = 100_000;
+
+/// Metadata and options that travel with a query without changing its SQL
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryContext {
+    // Sent to the leader with the request
+    pub metadata: HashMap<String, String>,
+    pub priority: QueryPriority,
+    // Overrides `DEFAULT_QUERY_TIMEOUT_MS` while waiting for the leader
+    pub timeout_ms: Option<u64>,
+    pub trace_id: Option<String>,
+    // Checkpoint the leader runs before the query; see `ReadConcern`
+    pub read_concern: Option<ReadConcern>,
+    // Called with the VM instructions run so far while the leader runs the query
+    pub progress: Option<Function>,
+}
+
+impl QueryContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: QueryPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn with_trace_id(mut self, trace_id: String) -> Self {
+        self.trace_id = Some(trace_id);
+        self
+    }
+
+    pub fn with_read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.read_concern = Some(read_concern);
+        self
+    }
+
+    /// Call `cb` with the number of VM instructions the leader has run so
+    /// far, about every `PROGRESS_REPORT_INTERVAL` of them
+    pub fn with_progress(mut self, cb: Function) -> Self {
+        self.progress = Some(cb);
+        self
+    }
+
+    pub fn timeout_ms_or_default(&self) -> u64 {
+        self.timeout_ms.unwrap_or(DEFAULT_QUERY_TIMEOUT_MS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_query_context_builder() {
+        let ctx = QueryContext::new();
+        assert!(ctx.metadata.is_empty());
+        assert_eq!(ctx.priority, QueryPriority::Normal);
+        assert_eq!(ctx.timeout_ms_or_default(), DEFAULT_QUERY_TIMEOUT_MS);
+        assert_eq!(ctx.read_concern, None);
+        assert!(ctx.progress.is_none());
+
+        let ctx = QueryContext::new()
+            .with_metadata("user", "alice")
+            .with_priority(QueryPriority::High)
+            .with_timeout_ms(250)
+            .with_trace_id("trace-1".to_string())
+            .with_read_concern(ReadConcern::Majority);
+        assert_eq!(ctx.metadata.get("user").map(String::as_str), Some("alice"));
+        assert_eq!(ctx.priority, QueryPriority::High);
+        assert_eq!(ctx.timeout_ms_or_default(), 250);
+        assert_eq!(ctx.trace_id.as_deref(), Some("trace-1"));
+        assert_eq!(ctx.read_concern, Some(ReadConcern::Majority));
+    }
+}
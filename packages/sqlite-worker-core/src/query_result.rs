@@ -0,0 +1,258 @@
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+/// A single SQLite value as returned by a query. Blobs serialize as bytes, so
+/// `serde_wasm_bindgen` sends them over the channel as a `Uint8Array` rather
+/// than an array of numbers; JSON still renders them as an array.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(#[serde(with = "serde_bytes")] Vec<u8>),
+}
+
+// Written by hand because an untagged derive cannot tell bytes from text:
+// `String` accepts valid UTF-8 bytes and `serde_bytes` accepts strings
+impl<'de> Deserialize<'de> for SqlValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SqlValueVisitor;
+
+        impl<'de> Visitor<'de> for SqlValueVisitor {
+            type Value = SqlValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("null, a number, a string or bytes")
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<SqlValue, E> {
+                Ok(SqlValue::Null)
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<SqlValue, E> {
+                Ok(SqlValue::Null)
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<SqlValue, E> {
+                Ok(SqlValue::Integer(value.into()))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<SqlValue, E> {
+                Ok(SqlValue::Integer(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<SqlValue, E> {
+                Ok(i64::try_from(value).map_or(SqlValue::Real(value as f64), SqlValue::Integer))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<SqlValue, E> {
+                Ok(SqlValue::Real(value))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<SqlValue, E> {
+                Ok(SqlValue::Text(value.to_string()))
+            }
+
+            fn visit_string<E: de::Error>(self, value: String) -> Result<SqlValue, E> {
+                Ok(SqlValue::Text(value))
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<SqlValue, E> {
+                Ok(SqlValue::Blob(value.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<SqlValue, E> {
+                Ok(SqlValue::Blob(value))
+            }
+
+            // Blobs in JSON, and from JS code that sends a plain array
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SqlValue, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                Ok(SqlValue::Blob(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(SqlValueVisitor)
+    }
+}
+
+impl SqlValue {
+    // JSON rendering used by `SQLiteDatabase::exec`
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            SqlValue::Null => serde_json::Value::Null,
+            SqlValue::Integer(val) => serde_json::Value::Number(serde_json::Number::from(*val)),
+            SqlValue::Real(val) => serde_json::Value::Number(
+                serde_json::Number::from_f64(*val).unwrap_or(serde_json::Number::from(0)),
+            ),
+            SqlValue::Text(text) => serde_json::Value::String(text.clone()),
+            SqlValue::Blob(bytes) => {
+                serde_json::Value::String(format!("<blob {} bytes>", bytes.len()))
+            }
+        }
+    }
+}
+
+/// Value bound to a `?` placeholder; shares the representation of result values
+pub type SqlParam = SqlValue;
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::Integer(value)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::Real(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<Vec<u8>> for SqlValue {
+    fn from(value: Vec<u8>) -> Self {
+        SqlValue::Blob(value)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// Structured result of a single statement
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<SqlValue>>,
+    #[serde(rename = "rowsAffected")]
+    pub rows_affected: u64,
+    // Rowid of the most recent insert on the connection, for statements without a result set
+    #[serde(rename = "lastInsertRowid", default)]
+    pub last_insert_rowid: i64,
+}
+
+impl QueryResult {
+    /// Rows as JSON objects keyed by column name
+    pub fn to_json_rows(&self) -> Vec<serde_json::Value> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let object = self
+                    .columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(SqlValue::to_json))
+                    .collect();
+                serde_json::Value::Object(object)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn sample_result() -> QueryResult {
+        QueryResult {
+            columns: vec!["id".to_string(), "name".to_string(), "data".to_string()],
+            rows: vec![
+                vec![
+                    SqlValue::Integer(1),
+                    SqlValue::Text("Alice".to_string()),
+                    SqlValue::Blob(vec![1, 2, 3]),
+                ],
+                vec![SqlValue::Integer(2), SqlValue::Null, SqlValue::Real(1.5)],
+            ],
+            rows_affected: 0,
+            last_insert_rowid: 0,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_query_result_serialization_roundtrip() {
+        let result = sample_result();
+        let json = serde_json::to_string(&result).expect("Should serialize");
+        assert!(json.contains("\"rowsAffected\":0"));
+        assert!(json.contains("[1,\"Alice\",[1,2,3]]"));
+
+        let deserialized: QueryResult = serde_json::from_str(&json).expect("Should deserialize");
+        assert_eq!(deserialized, result);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sql_param_from_json() {
+        let params: Vec<SqlParam> =
+            serde_json::from_str(r#"[null, 42, 2.5, "text", [1, 2]]"#).expect("Should parse");
+        assert_eq!(
+            params,
+            vec![
+                SqlParam::Null,
+                SqlParam::Integer(42),
+                SqlParam::Real(2.5),
+                SqlParam::Text("text".to_string()),
+                SqlParam::Blob(vec![1, 2]),
+            ]
+        );
+        assert_eq!(SqlParam::from(None::<i64>), SqlParam::Null);
+        assert_eq!(SqlParam::from("a"), SqlParam::Text("a".to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_blob_crosses_js_as_uint8array() {
+        // Valid UTF-8, which must still come back as a blob
+        let blob = SqlValue::Blob(b"text".to_vec());
+        let value = serde_wasm_bindgen::to_value(&blob).expect("Should serialize");
+        assert!(value.is_instance_of::<js_sys::Uint8Array>());
+        assert_eq!(
+            serde_wasm_bindgen::from_value::<SqlValue>(value).expect("Should deserialize"),
+            blob
+        );
+
+        let text = serde_wasm_bindgen::to_value(&SqlValue::from("text")).unwrap();
+        assert_eq!(
+            serde_wasm_bindgen::from_value::<SqlValue>(text).unwrap(),
+            SqlValue::Text("text".to_string())
+        );
+
+        let array = js_sys::Array::of2(&1.into(), &2.into());
+        assert_eq!(
+            serde_wasm_bindgen::from_value::<SqlValue>(array.into()).unwrap(),
+            SqlValue::Blob(vec![1, 2])
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_to_json_rows() {
+        let rows = sample_result().to_json_rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["id"], 1);
+        assert_eq!(rows[0]["name"], "Alice");
+        assert_eq!(rows[0]["data"], "<blob 3 bytes>");
+        assert!(rows[1]["name"].is_null());
+        assert_eq!(rows[1]["data"], 1.5);
+    }
+}
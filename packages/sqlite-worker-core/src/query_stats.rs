@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::WorkerState;
+use crate::read_concern::WalCheckpoint;
+
+/// SQLite memory and cache counters the leader read after running a query.
+/// The cache hit and miss counts cover the time since the leader's previous
+/// reading, so usually just that query.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueryStats {
+    // Page cache slots in use, and allocations that overflowed the cache
+    #[serde(rename = "pagecacheUsed")]
+    pub pagecache_used: i32,
+    #[serde(rename = "pagecacheOverflow")]
+    pub pagecache_overflow: i32,
+    // Bytes allocated by SQLite across all connections
+    #[serde(rename = "mallocUsed")]
+    pub malloc_used: i32,
+    #[serde(rename = "stmtCacheHits")]
+    pub stmt_cache_hits: i32,
+    #[serde(rename = "stmtCacheMisses")]
+    pub stmt_cache_misses: i32,
+    // WAL state after the checkpoint a query's `ReadConcern` asked for
+    #[serde(
+        rename = "walCheckpoint",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wal_checkpoint: Option<WalCheckpoint>,
+}
+
+impl WorkerState {
+    /// Stats from the leader's most recent `QueryResponse` to this worker.
+    /// Queries with no rows and no changes are answered without stats and
+    /// leave this as it was, unless they had a read concern. On the leader,
+    /// stats from its last query with a read concern.
+    pub fn last_query_stats(&self) -> Option<QueryStats> {
+        *self.last_query_stats.borrow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::lead_with_db;
+    use crate::query_context::QueryContext;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_query_stats_serialization() {
+        let stats = QueryStats {
+            pagecache_used: 4,
+            pagecache_overflow: 1,
+            malloc_used: 2048,
+            stmt_cache_hits: 3,
+            stmt_cache_misses: 2,
+            wal_checkpoint: None,
+        };
+        let json = serde_json::to_string(&stats).expect("Should serialize");
+        assert!(json.contains("\"pagecacheUsed\":4"));
+        assert!(json.contains("\"mallocUsed\":2048"));
+        assert!(json.contains("\"stmtCacheMisses\":2"));
+        assert_eq!(serde_json::from_str::<QueryStats>(&json).unwrap(), stats);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_stats_present_after_query() {
+        let (Ok(leader), Ok(follower)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        let Some(leader) = lead_with_db(leader).await else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        assert_eq!(follower.last_query_stats(), None);
+
+        follower
+            .execute_query("SELECT 1 AS n".to_string(), QueryContext::default())
+            .await
+            .expect("Query failed");
+        let stats = follower
+            .last_query_stats()
+            .expect("Response should carry stats");
+        assert!(stats.malloc_used > 0);
+        assert!(stats.stmt_cache_hits >= 0 && stats.stmt_cache_misses >= 0);
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::database::SQLiteDatabase;
+use crate::error::WorkerError;
+
+/// How settled the data a query reads must be, named after MongoDB's read
+/// concerns. The leader checkpoints the WAL as needed before running the
+/// query and reports the checkpoint in the query's stats. The OPFS sahpool
+/// VFS keeps the database in rollback journal mode, never WAL, so there the
+/// checkpoint does nothing and every concern reads what `Local` would.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadConcern {
+    // Read straight away, as a query without a read concern does
+    Local,
+    // Copy what the WAL holds into the database without waiting on others;
+    // a no-op without WAL
+    Majority,
+    // Wait for writers to finish, then copy the whole WAL into the database;
+    // a no-op without WAL
+    Linearizable,
+}
+
+impl ReadConcern {
+    /// The `PRAGMA wal_checkpoint` mode run before reading, if any
+    pub fn checkpoint_mode(&self) -> Option<&'static str> {
+        match self {
+            ReadConcern::Local => None,
+            ReadConcern::Majority => Some("PASSIVE"),
+            ReadConcern::Linearizable => Some("FULL"),
+        }
+    }
+}
+
+/// The row `PRAGMA wal_checkpoint` reports. Both frame counts are -1 when
+/// the database is not in WAL mode.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalCheckpoint {
+    // The checkpoint could not finish because another connection held a lock
+    pub busy: bool,
+    #[serde(rename = "logFrames")]
+    pub log_frames: i64,
+    #[serde(rename = "checkpointedFrames")]
+    pub checkpointed_frames: i64,
+}
+
+// Checkpoint `database` as `concern` asks, returning the WAL state or `None`
+// if it asks for no checkpoint
+pub(crate) async fn apply_read_concern(
+    database: &SQLiteDatabase,
+    concern: ReadConcern,
+) -> Result<Option<WalCheckpoint>, WorkerError> {
+    let Some(mode) = concern.checkpoint_mode() else {
+        return Ok(None);
+    };
+    let (busy, log_frames, checkpointed_frames): (i64, i64, i64) = database
+        .query(&format!("PRAGMA wal_checkpoint({mode})"))
+        .await?
+        .get_single()?;
+    Ok(Some(WalCheckpoint {
+        busy: busy != 0,
+        log_frames,
+        checkpointed_frames,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, WorkerState};
+    use crate::query_context::QueryContext;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_read_concern_checkpoint_modes() {
+        assert_eq!(ReadConcern::Local.checkpoint_mode(), None);
+        assert_eq!(ReadConcern::Majority.checkpoint_mode(), Some("PASSIVE"));
+        assert_eq!(ReadConcern::Linearizable.checkpoint_mode(), Some("FULL"));
+        assert_eq!(
+            serde_json::to_string(&ReadConcern::Linearizable).unwrap(),
+            "\"linearizable\""
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_concern_checkpoint_is_a_no_op_without_wal() {
+        let (Ok(leader), Ok(follower)) = (WorkerState::new(), WorkerState::new()) else {
+            return;
+        };
+        let Some(leader) = lead_with_db(leader).await else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+        leader
+            .execute_query_simple(
+                "CREATE TABLE IF NOT EXISTS read_concern_items (n INTEGER)".to_string(),
+            )
+            .await
+            .expect("Create failed");
+
+        for concern in [ReadConcern::Majority, ReadConcern::Linearizable] {
+            follower
+                .execute_query(
+                    "SELECT count(*) AS n FROM read_concern_items".to_string(),
+                    QueryContext::new().with_read_concern(concern),
+                )
+                .await
+                .expect("Read failed");
+            let checkpoint = follower
+                .last_query_stats()
+                .and_then(|stats| stats.wal_checkpoint)
+                .expect("The read reports the checkpoint");
+            assert_eq!(
+                checkpoint,
+                WalCheckpoint {
+                    busy: false,
+                    log_frames: -1,
+                    checkpointed_frames: -1,
+                },
+                "The sahpool VFS has no WAL to checkpoint"
+            );
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::{QueryResult, SqlParam};
+
+/// A read transaction opened with `WorkerState::begin_read_transaction`. Its
+/// queries all run against an in-memory copy of the database the leader took
+/// when it began, so they see the same data whatever is written meanwhile.
+/// Dropping it without calling `end` releases it without waiting for the
+/// leader.
+pub struct ReadTransaction<'a> {
+    state: &'a WorkerState,
+    tx_id: String,
+    ended: bool,
+}
+
+impl<'a> ReadTransaction<'a> {
+    pub(crate) fn new(state: &'a WorkerState, tx_id: String) -> Self {
+        ReadTransaction {
+            state,
+            tx_id,
+            ended: false,
+        }
+    }
+
+    /// The leader's id for the transaction
+    pub fn tx_id(&self) -> &str {
+        &self.tx_id
+    }
+
+    /// Run `sql` in the transaction with `params` bound to its placeholders.
+    /// The copy is read-only, so statements that write fail.
+    pub async fn query(&self, sql: &str, params: &[SqlParam]) -> Result<QueryResult, WorkerError> {
+        self.state
+            .query_read_transaction(&self.tx_id, sql.to_string(), params.to_vec())
+            .await
+    }
+
+    /// End the transaction and free the leader's copy of the database
+    pub async fn end(mut self) -> Result<(), WorkerError> {
+        self.ended = true;
+        self.state.end_read_transaction(&self.tx_id).await
+    }
+}
+
+impl Drop for ReadTransaction<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.state.rollback_read_transaction(&self.tx_id);
+        }
+    }
+}
+
+impl std::fmt::Debug for ReadTransaction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadTransaction")
+            .field("tx_id", &self.tx_id)
+            .field("ended", &self.ended)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, sleep};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    const COUNT: &str = "SELECT COUNT(*) FROM read_tx_items WHERE id > ?";
+
+    async fn count_in(tx: &ReadTransaction<'_>) -> i64 {
+        let (count,): (i64,) = tx
+            .query(COUNT, &[SqlParam::Integer(0)])
+            .await
+            .expect("Read failed")
+            .get_single()
+            .unwrap();
+        count
+    }
+
+    async fn insert(state: &WorkerState, id: i64) {
+        state
+            .execute_parameterized(
+                "INSERT INTO read_tx_items VALUES (?)".to_string(),
+                vec![SqlParam::Integer(id)],
+            )
+            .await
+            .expect("Insert failed");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_transactions_keep_their_snapshots() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS read_tx_items".to_string(),
+                "CREATE TABLE read_tx_items (id INTEGER)".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+        insert(&state, 1).await;
+
+        let first = state
+            .begin_read_transaction()
+            .await
+            .expect("First transaction failed");
+        assert_eq!(count_in(&first).await, 1);
+        insert(&state, 2).await;
+
+        let second = state
+            .begin_read_transaction()
+            .await
+            .expect("Second transaction failed");
+        assert_eq!(count_in(&second).await, 2);
+        insert(&state, 3).await;
+
+        assert_eq!(count_in(&first).await, 1);
+        assert_eq!(count_in(&second).await, 2);
+        let (count,): (i64,) = state
+            .execute_parameterized(COUNT.to_string(), vec![SqlParam::Integer(0)])
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(state.snapshots.borrow().len(), 2);
+
+        first.end().await.expect("End failed");
+        assert_eq!(state.snapshots.borrow().len(), 1);
+        // Dropping releases it in the background
+        drop(second);
+        sleep(50.0).await;
+        assert!(state.snapshots.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_transaction_is_read_only() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let tx = state
+            .begin_read_transaction()
+            .await
+            .expect("Transaction failed");
+        assert!(tx
+            .query("CREATE TABLE read_tx_denied (id INTEGER)", &[])
+            .await
+            .is_err());
+        let tx_id = tx.tx_id().to_string();
+        tx.end().await.expect("End failed");
+        assert_eq!(
+            state
+                .query_read_transaction(&tx_id, "SELECT 1".to_string(), Vec::new())
+                .await,
+            Err(WorkerError::SnapshotNotFound(tx_id))
+        );
+    }
+}
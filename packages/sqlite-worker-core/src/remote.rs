@@ -0,0 +1,118 @@
+use js_sys::{ArrayBuffer, Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::database::{has_sqlite_header, SQLiteDatabase};
+use crate::error::WorkerError;
+
+// Fetch `url` and return the response body
+async fn fetch_bytes(url: &str) -> Result<Vec<u8>, WorkerError> {
+    let failed = |reason: String| WorkerError::RemoteFetchFailed {
+        url: url.to_string(),
+        reason,
+    };
+    let global = js_sys::global();
+    let fetch = Reflect::get(&global, &JsValue::from_str("fetch"))
+        .ok()
+        .and_then(|fetch| fetch.dyn_into::<Function>().ok())
+        .ok_or_else(|| failed("fetch is not available".to_string()))?;
+    let promise: Promise = fetch
+        .call1(&global, &JsValue::from_str(url))
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| failed(format!("{e:?}")))?;
+    let response: web_sys::Response = JsFuture::from(promise)
+        .await
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| failed(format!("{e:?}")))?;
+    if !response.ok() {
+        return Err(failed(format!("HTTP {}", response.status())));
+    }
+
+    let body = response
+        .array_buffer()
+        .map_err(|e| failed(format!("{e:?}")))?;
+    let buffer: ArrayBuffer = JsFuture::from(body)
+        .await
+        .and_then(JsCast::dyn_into)
+        .map_err(|e| failed(format!("{e:?}")))?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+// Fetch the SQLite file at `url`, checking it looks like a database
+async fn fetch_database(url: &str) -> Result<Vec<u8>, WorkerError> {
+    let bytes = fetch_bytes(url).await?;
+    if !has_sqlite_header(&bytes) {
+        return Err(WorkerError::InvalidDatabaseFile(url.to_string()));
+    }
+    Ok(bytes)
+}
+
+// Open the OPFS database for a new leader, first replacing it with the SQLite
+// file at `url` if it has no pages yet, so later leaders keep the imported
+// data and any changes made to it. Returns the connection and the number of
+// bytes imported, if any were.
+pub(crate) async fn open_with_remote_import(
+    url: &str,
+) -> Result<(SQLiteDatabase, Option<u64>), WorkerError> {
+    let database = SQLiteDatabase::initialize_opfs()
+        .await
+        .map_err(|e| WorkerError::from_js_error(&e))?;
+    let (page_count,): (i64,) = database.query("PRAGMA page_count").await?.get_single()?;
+    if page_count > 0 {
+        return Ok((database, None));
+    }
+
+    // The file cannot be replaced while it is open
+    drop(database);
+    let bytes = fetch_database(url).await?;
+    let database = SQLiteDatabase::import_opfs(&bytes)
+        .await
+        .map_err(|e| WorkerError::from_js_error(&e))?;
+    Ok((database, Some(bytes.len() as u64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SQLITE_MAGIC;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_has_sqlite_header() {
+        let mut header = SQLITE_MAGIC.to_vec();
+        header.resize(100, 0);
+        assert!(has_sqlite_header(&header));
+        assert!(!has_sqlite_header(SQLITE_MAGIC));
+        assert!(!has_sqlite_header(&[b'x'; 100]));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_fetch_rejects_non_sqlite_body() {
+        let url = "data:text/plain,this is not a database";
+        assert_eq!(
+            fetch_database(url).await,
+            Err(WorkerError::InvalidDatabaseFile(url.to_string()))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_import_keeps_existing_database() {
+        let Ok(database) = SQLiteDatabase::initialize_opfs().await else {
+            return;
+        };
+        database
+            .exec("CREATE TABLE IF NOT EXISTS remote_items (id INTEGER)")
+            .await
+            .expect("Create failed");
+        drop(database);
+
+        // The file already has pages, so the URL is never fetched
+        let (database, imported) = open_with_remote_import("data:text/plain,unused")
+            .await
+            .expect("Opening failed");
+        assert_eq!(imported, None);
+        assert!(database.query("SELECT id FROM remote_items").await.is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use std::rc::Rc;
+
+use crate::coordination::{connection_pragmas, WorkerState};
+use crate::database::SQLiteDatabase;
+use crate::error::WorkerError;
+use crate::messages::ChannelMessage;
+
+impl WorkerState {
+    /// Replace the leader's database with an empty one: close the connection,
+    /// delete the OPFS file and open it afresh, applying the configured
+    /// pragmas and authorizer again. Unlike deleting every row this also drops
+    /// the schema, `user_version` and freed pages. Other workers are sent
+    /// `DatabaseReset` and drop their cached results.
+    ///
+    /// Fails with `ResetDisabled` unless `WorkerStateBuilder::allow_reset` was
+    /// set, and with `Busy` if a query still holds the connection. Open
+    /// snapshots and prepared statements are discarded. If deleting or
+    /// reopening the file fails, the leader is left without a database.
+    pub async fn reset_database(&self) -> Result<(), WorkerError> {
+        if !self.config.allow_reset {
+            return Err(WorkerError::ResetDisabled);
+        }
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        if self.active_transaction.borrow().is_some() {
+            return Err(WorkerError::TransactionActive);
+        }
+
+        let database = self
+            .db
+            .borrow_mut()
+            .take()
+            .ok_or(WorkerError::DatabaseNotInitialized)?;
+        match Rc::try_unwrap(database) {
+            // Closes the connection
+            Ok(database) => drop(database),
+            Err(database) => {
+                *self.db.borrow_mut() = Some(database);
+                return Err(WorkerError::Busy {
+                    message: "the database connection is still in use".to_string(),
+                });
+            }
+        }
+        self.snapshots.borrow_mut().clear();
+
+        // The sahpool VFS keeps its files in a pool of access handles
+        // rather than under their own names, so the pool removes it
+        SQLiteDatabase::delete_opfs()
+            .await
+            .map_err(|e| WorkerError::from_js_error(&e))?;
+        let database = SQLiteDatabase::initialize_opfs()
+            .await
+            .map_err(|e| WorkerError::from_js_error(&e))?;
+        for pragma in connection_pragmas(
+            self.config.busy_timeout_ms,
+            self.config.enforce_foreign_keys,
+        ) {
+            database.exec(&pragma).await?;
+        }
+        if let Some(authorizer) = self.authorizer.borrow().clone() {
+            database.set_authorizer(Some(authorizer));
+        }
+        *self.db.borrow_mut() = Some(Rc::new(database));
+
+        self.query_cache.borrow_mut().clear();
+        self.broadcast(ChannelMessage::DatabaseReset {
+            leader_id: self.worker_id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, sleep, WorkerStateBuilder};
+    use crate::query_result::QueryResult;
+    use crate::time::TimeProvider;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_reset_database_requires_opt_in() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+        assert_eq!(
+            state.reset_database().await,
+            Err(WorkerError::ResetDisabled)
+        );
+
+        let Ok(follower) = WorkerStateBuilder::new().allow_reset(true).build() else {
+            return;
+        };
+        assert_eq!(follower.reset_database().await, Err(WorkerError::NotLeader));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_reset_database_wipes_file() {
+        let (Ok(leader), Ok(follower)) = (
+            WorkerStateBuilder::new().allow_reset(true).build(),
+            WorkerState::new(),
+        ) else {
+            return;
+        };
+        let Some(leader) = lead_with_db(leader).await else {
+            return;
+        };
+        leader.setup_channel_listener();
+        follower.setup_channel_listener();
+
+        leader
+            .execute_many(vec![
+                "CREATE TABLE IF NOT EXISTS reset_items (n INTEGER)".to_string(),
+                "INSERT INTO reset_items VALUES (1)".to_string(),
+                "PRAGMA user_version = 7".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+        follower.query_cache.borrow_mut().insert(
+            "SELECT n FROM reset_items",
+            QueryResult::default(),
+            follower.time.now_ms(),
+            60_000,
+        );
+
+        leader.reset_database().await.expect("Reset failed");
+        let (tables,): (i64,) = leader
+            .execute_parameterized("SELECT count(*) FROM sqlite_master".to_string(), Vec::new())
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(tables, 0);
+        let (version,): (i64,) = leader
+            .execute_parameterized("PRAGMA user_version".to_string(), Vec::new())
+            .await
+            .unwrap()
+            .get_single()
+            .unwrap();
+        assert_eq!(version, 0);
+
+        sleep(50.0).await;
+        assert!(follower.query_cache.borrow().entries.is_empty());
+    }
+}
@@ -0,0 +1,423 @@
+use std::rc::Rc;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::{QueryResult, SqlParam, SqlValue};
+use crate::sql_classifier::SqlClassifier;
+
+/// A single result row whose values can be looked up by column name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Rc<[String]>,
+    values: Vec<SqlValue>,
+}
+
+impl Row {
+    pub fn new(columns: Rc<[String]>, values: Vec<SqlValue>) -> Self {
+        Row { columns, values }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[SqlValue] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<SqlValue> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Value at a column position
+    pub fn get_index(&self, index: usize) -> Option<&SqlValue> {
+        self.values.get(index)
+    }
+
+    /// Value of the first column named `col`
+    pub fn get(&self, col: &str) -> Option<&SqlValue> {
+        let index = self.columns.iter().position(|name| name == col)?;
+        self.values.get(index)
+    }
+
+    pub fn get_str(&self, col: &str) -> Option<&str> {
+        match self.get(col)? {
+            SqlValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn get_i64(&self, col: &str) -> Option<i64> {
+        match self.get(col)? {
+            SqlValue::Integer(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Real or integer columns as a float
+    pub fn get_f64(&self, col: &str) -> Option<f64> {
+        match self.get(col)? {
+            SqlValue::Real(val) => Some(*val),
+            SqlValue::Integer(val) => Some(*val as f64),
+            _ => None,
+        }
+    }
+
+    /// SQLite stores booleans as integers, so any non-zero integer is `true`
+    pub fn get_bool(&self, col: &str) -> Option<bool> {
+        self.get_i64(col).map(|val| val != 0)
+    }
+
+    pub fn get_bytes(&self, col: &str) -> Option<&[u8]> {
+        match self.get(col)? {
+            SqlValue::Blob(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+impl QueryResult {
+    /// Decode the only row of the result, failing with `NotFound` if there are
+    /// no rows and `UnexpectedRowCount` if there are several
+    pub fn get_single<T: FromRow>(self) -> Result<T, WorkerError> {
+        if self.rows.is_empty() {
+            return Err(WorkerError::NotFound);
+        }
+        if self.rows.len() != 1 {
+            return Err(WorkerError::UnexpectedRowCount(self.rows.len()));
+        }
+        let row = self.into_iter().next().expect("Checked row count");
+        T::from_row(&row)
+    }
+}
+
+impl WorkerState {
+    /// Run `sql` with `params` and decode its first row, or `None` if it
+    /// returns none. A query without a `LIMIT` of its own gets `LIMIT 1`.
+    pub async fn select_one<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[SqlParam],
+    ) -> Result<Option<T>, WorkerError> {
+        self.execute_parameterized(limit_one(sql), params.to_vec())
+            .await?
+            .into_iter()
+            .next()
+            .map(|row| T::from_row(&row))
+            .transpose()
+    }
+}
+
+// `sql` limited to one row, unless it has a limit already or is not a query
+fn limit_one(sql: &str) -> String {
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    if SqlClassifier::is_query(sql) && !SqlClassifier::has_limit(sql) {
+        format!("{sql} LIMIT 1")
+    } else {
+        sql.to_string()
+    }
+}
+
+impl IntoIterator for QueryResult {
+    type Item = Row;
+    type IntoIter = RowIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        RowIter {
+            columns: self.columns.into(),
+            rows: self.rows.into_iter(),
+        }
+    }
+}
+
+/// Iterator over the rows of a `QueryResult`
+pub struct RowIter {
+    columns: Rc<[String]>,
+    rows: std::vec::IntoIter<Vec<SqlValue>>,
+}
+
+impl Iterator for RowIter {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = self.rows.next()?;
+        Some(Row::new(Rc::clone(&self.columns), values))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl ExactSizeIterator for RowIter {}
+
+/// Conversion from a single SQLite value
+pub trait FromSqlValue: Sized {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError>;
+}
+
+fn decode_error(expected: &str, value: &SqlValue) -> WorkerError {
+    WorkerError::RowDecode(format!("expected {expected}, got {value:?}"))
+}
+
+impl FromSqlValue for SqlValue {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromSqlValue for i64 {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Integer(val) => Ok(*val),
+            other => Err(decode_error("integer", other)),
+        }
+    }
+}
+
+impl FromSqlValue for f64 {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Real(val) => Ok(*val),
+            SqlValue::Integer(val) => Ok(*val as f64),
+            other => Err(decode_error("real", other)),
+        }
+    }
+}
+
+impl FromSqlValue for bool {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Integer(val) => Ok(*val != 0),
+            other => Err(decode_error("integer", other)),
+        }
+    }
+}
+
+impl FromSqlValue for String {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Text(text) => Ok(text.clone()),
+            other => Err(decode_error("text", other)),
+        }
+    }
+}
+
+impl FromSqlValue for Vec<u8> {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Blob(bytes) => Ok(bytes.clone()),
+            other => Err(decode_error("blob", other)),
+        }
+    }
+}
+
+impl<T: FromSqlValue> FromSqlValue for Option<T> {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, WorkerError> {
+        match value {
+            SqlValue::Null => Ok(None),
+            other => T::from_sql_value(other).map(Some),
+        }
+    }
+}
+
+/// Conversion from a whole result row
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, WorkerError>;
+}
+
+impl FromRow for Row {
+    fn from_row(row: &Row) -> Result<Self, WorkerError> {
+        Ok(row.clone())
+    }
+}
+
+// Tuples decode their elements from the row's columns by position
+macro_rules! impl_from_row_for_tuple {
+    ($len:expr => $($index:tt $name:ident),+) => {
+        impl<$($name: FromSqlValue),+> FromRow for ($($name,)+) {
+            fn from_row(row: &Row) -> Result<Self, WorkerError> {
+                if row.len() < $len {
+                    return Err(WorkerError::RowDecode(format!(
+                        "expected {} columns, got {}",
+                        $len,
+                        row.len()
+                    )));
+                }
+                Ok(($($name::from_sql_value(&row.values[$index])?,)+))
+            }
+        }
+    };
+}
+
+impl_from_row_for_tuple!(1 => 0 A);
+impl_from_row_for_tuple!(2 => 0 A, 1 B);
+impl_from_row_for_tuple!(3 => 0 A, 1 B, 2 C);
+impl_from_row_for_tuple!(4 => 0 A, 1 B, 2 C, 3 D);
+impl_from_row_for_tuple!(5 => 0 A, 1 B, 2 C, 3 D, 4 E);
+impl_from_row_for_tuple!(6 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+impl_from_row_for_tuple!(7 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+impl_from_row_for_tuple!(8 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn sample_result() -> QueryResult {
+        QueryResult {
+            columns: vec![
+                "id".to_string(),
+                "name".to_string(),
+                "score".to_string(),
+                "active".to_string(),
+                "data".to_string(),
+            ],
+            rows: vec![
+                vec![
+                    SqlValue::Integer(1),
+                    SqlValue::Text("Alice".to_string()),
+                    SqlValue::Real(9.5),
+                    SqlValue::Integer(1),
+                    SqlValue::Blob(vec![1, 2]),
+                ],
+                vec![
+                    SqlValue::Integer(2),
+                    SqlValue::Null,
+                    SqlValue::Integer(7),
+                    SqlValue::Integer(0),
+                    SqlValue::Null,
+                ],
+            ],
+            rows_affected: 0,
+            last_insert_rowid: 0,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_row_getters() {
+        let rows: Vec<Row> = sample_result().into_iter().collect();
+        assert_eq!(rows.len(), 2);
+
+        let first = &rows[0];
+        assert_eq!(first.get_i64("id"), Some(1));
+        assert_eq!(first.get_str("name"), Some("Alice"));
+        assert_eq!(first.get_f64("score"), Some(9.5));
+        assert_eq!(first.get_bool("active"), Some(true));
+        assert_eq!(first.get_bytes("data"), Some(&[1u8, 2][..]));
+        assert_eq!(first.get_str("missing"), None);
+        assert_eq!(first.get_i64("name"), None, "Type mismatch yields None");
+
+        let second = &rows[1];
+        assert_eq!(second.get_str("name"), None);
+        assert_eq!(second.get_f64("score"), Some(7.0));
+        assert_eq!(second.get_bool("active"), Some(false));
+        assert_eq!(second.get_bytes("data"), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_from_row_tuples() {
+        let rows: Vec<Row> = sample_result().into_iter().collect();
+
+        let (id,): (i64,) = FromRow::from_row(&rows[0]).unwrap();
+        assert_eq!(id, 1);
+
+        let (id, name, score, active, data): (i64, Option<String>, f64, bool, Option<Vec<u8>>) =
+            FromRow::from_row(&rows[1]).unwrap();
+        assert_eq!((id, name, score, active, data), (2, None, 7.0, false, None));
+
+        let err = <(String,)>::from_row(&rows[0]).unwrap_err();
+        assert!(matches!(err, WorkerError::RowDecode(_)));
+
+        let err = <(i64, String, f64, bool, Vec<u8>, i64)>::from_row(&rows[0]).unwrap_err();
+        assert_eq!(
+            err,
+            WorkerError::RowDecode("expected 6 columns, got 5".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_get_single() {
+        let mut single = sample_result();
+        single.rows.truncate(1);
+        let (id, name): (i64, String) = single.get_single().unwrap();
+        assert_eq!((id, name.as_str()), (1, "Alice"));
+
+        let err = sample_result().get_single::<(i64,)>().unwrap_err();
+        assert_eq!(err, WorkerError::UnexpectedRowCount(2));
+
+        let empty = QueryResult::default();
+        assert_eq!(
+            empty.get_single::<Row>().unwrap_err(),
+            WorkerError::NotFound
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_limit_one() {
+        assert_eq!(limit_one("SELECT * FROM t;"), "SELECT * FROM t LIMIT 1");
+        assert_eq!(
+            limit_one("SELECT * FROM t LIMIT 5"),
+            "SELECT * FROM t LIMIT 5"
+        );
+        assert_eq!(limit_one("PRAGMA user_version"), "PRAGMA user_version");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_select_one_arities() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let values = "VALUES ('a', 1, 1.5, 'x'), ('b', 2, 2.5, 'y')";
+        let select = |columns: &str| format!("SELECT {columns} FROM ({values}) ORDER BY column1");
+
+        let text: Option<(String,)> = state.select_one(&select("column1"), &[]).await.unwrap();
+        assert_eq!(text, Some(("a".to_string(),)));
+        let integer: Option<(i64,)> = state.select_one(&select("column2"), &[]).await.unwrap();
+        assert_eq!(integer, Some((1,)));
+        let real: Option<(f64,)> = state.select_one(&select("column3"), &[]).await.unwrap();
+        assert_eq!(real, Some((1.5,)));
+        let pair: Option<(String, String)> = state
+            .select_one(&select("column1, column4"), &[])
+            .await
+            .unwrap();
+        assert_eq!(pair, Some(("a".to_string(), "x".to_string())));
+        let triple: Option<(String, i64, f64)> = state
+            .select_one(&select("column1, column2, column3"), &[])
+            .await
+            .unwrap();
+        assert_eq!(triple, Some(("a".to_string(), 1, 1.5)));
+
+        let bound: Option<(i64,)> = state
+            .select_one(
+                &format!("SELECT column2 FROM ({values}) WHERE column1 = ?"),
+                &[SqlParam::from("b")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(bound, Some((2,)));
+        let none: Option<(i64,)> = state
+            .select_one(
+                &format!("SELECT column2 FROM ({values}) WHERE column1 = ?"),
+                &[SqlParam::from("z")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(none, None);
+
+        let mismatched = state.select_one::<(i64,)>(&select("column1"), &[]).await;
+        assert!(matches!(mismatched, Err(WorkerError::RowDecode(_))));
+    }
+}
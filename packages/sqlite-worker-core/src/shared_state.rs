@@ -0,0 +1,121 @@
+use js_sys::{Function, Reflect, SharedArrayBuffer, Uint8Array};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+
+pub const SHARED_STATE_BYTES: u32 = 16;
+// Offset of the leadership flag within the shared state buffer
+pub const SHARED_STATE_LEADER_BYTE: u32 = 0;
+
+// Buffer handed out by `shared_state_buffer`, once created
+pub type SharedStateBuffer = Rc<RefCell<Option<SharedArrayBuffer>>>;
+
+fn atomics_function(name: &str) -> Option<(JsValue, Function)> {
+    let atomics = Reflect::get(&js_sys::global(), &JsValue::from_str("Atomics")).ok()?;
+    let function = Reflect::get(&atomics, &JsValue::from_str(name))
+        .ok()?
+        .dyn_into::<Function>()
+        .ok()?;
+    Some((atomics, function))
+}
+
+/// Write the leadership flag into `buffer` with `Atomics.store`, if one has
+/// been created
+pub(crate) fn store_leader_flag(buffer: &SharedStateBuffer, is_leader: bool) {
+    let Some(buffer) = buffer.borrow().clone() else {
+        return;
+    };
+    let Some((atomics, store)) = atomics_function("store") else {
+        return;
+    };
+    let _ = store.call3(
+        &atomics,
+        &Uint8Array::new(&buffer),
+        &JsValue::from(SHARED_STATE_LEADER_BYTE),
+        &JsValue::from(u8::from(is_leader)),
+    );
+}
+
+/// Read the leadership flag from a buffer returned by `shared_state_buffer`
+/// with `Atomics.load`, as the main thread would
+pub fn load_leader_flag(buffer: &SharedArrayBuffer) -> Option<bool> {
+    let (atomics, load) = atomics_function("load")?;
+    let value = load
+        .call2(
+            &atomics,
+            &Uint8Array::new(buffer),
+            &JsValue::from(SHARED_STATE_LEADER_BYTE),
+        )
+        .ok()?;
+    Some(value.as_f64()? != 0.0)
+}
+
+impl WorkerState {
+    /// A `SHARED_STATE_BYTES`-byte `SharedArrayBuffer` whose first byte is 1
+    /// while this worker leads and 0 otherwise, kept up to date with
+    /// `Atomics.store` so another thread can read it without a message round
+    /// trip. Every call returns the same buffer. Fails unless the page is
+    /// cross-origin isolated.
+    pub fn shared_state_buffer(&self) -> Result<SharedArrayBuffer, WorkerError> {
+        if let Some(buffer) = self.shared_state.borrow().clone() {
+            return Ok(buffer);
+        }
+        let constructor = Reflect::get(&js_sys::global(), &JsValue::from_str("SharedArrayBuffer"));
+        if !constructor.is_ok_and(|constructor| constructor.is_function()) {
+            return Err(WorkerError::SharedArrayBufferUnsupported);
+        }
+        let buffer = SharedArrayBuffer::new(SHARED_STATE_BYTES);
+        *self.shared_state.borrow_mut() = Some(buffer.clone());
+        store_leader_flag(&self.shared_state, *self.is_leader.borrow());
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_shared_state_buffer_tracks_leadership() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let buffer = match state.shared_state_buffer() {
+            Ok(buffer) => buffer,
+            Err(error) => {
+                assert_eq!(error, WorkerError::SharedArrayBufferUnsupported);
+                return;
+            }
+        };
+        assert_eq!(buffer.byte_length(), SHARED_STATE_BYTES);
+        assert_eq!(load_leader_flag(&buffer), Some(false));
+
+        *state.is_leader.borrow_mut() = true;
+        store_leader_flag(&state.shared_state, true);
+        assert_eq!(load_leader_flag(&buffer), Some(true));
+
+        state.resign_leadership();
+        assert_eq!(load_leader_flag(&buffer), Some(false));
+
+        let again = state.shared_state_buffer().expect("Buffer already exists");
+        assert!(JsValue::from(again) == JsValue::from(buffer));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_new_buffer_reflects_current_leadership() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+        let Ok(buffer) = state.shared_state_buffer() else {
+            return;
+        };
+        assert_eq!(load_leader_flag(&buffer), Some(true));
+    }
+}
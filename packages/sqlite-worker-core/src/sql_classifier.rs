@@ -0,0 +1,272 @@
+use sqlite_wasm_rs::export::sqlite3_complete;
+use std::ffi::CString;
+
+/// Rough classification of SQL statements by their leading keyword
+pub struct SqlClassifier;
+
+// Statements that change the schema rather than the data
+const DDL_KEYWORDS: [&str; 3] = ["CREATE", "DROP", "ALTER"];
+// Statements that return rows a `LIMIT` clause can cut down
+const QUERY_KEYWORDS: [&str; 3] = ["SELECT", "VALUES", "WITH"];
+// Statements that change rows
+const WRITE_KEYWORDS: [&str; 4] = ["INSERT", "UPDATE", "DELETE", "REPLACE"];
+
+impl SqlClassifier {
+    /// The first keyword of `sql` in upper case, after any whitespace and
+    /// `--` or `/* */` comments
+    pub fn leading_keyword(sql: &str) -> Option<String> {
+        let mut rest = sql;
+        loop {
+            rest = rest.trim_start();
+            if let Some(comment) = rest.strip_prefix("--") {
+                rest = comment.split_once('\n').map_or("", |(_, after)| after);
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                rest = comment.split_once("*/").map_or("", |(_, after)| after);
+            } else {
+                break;
+            }
+        }
+        let keyword: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        (!keyword.is_empty()).then(|| keyword.to_ascii_uppercase())
+    }
+
+    /// Whether `sql` is a `CREATE`, `DROP` or `ALTER` statement
+    pub fn is_ddl(sql: &str) -> bool {
+        Self::leading_keyword(sql).is_some_and(|keyword| DDL_KEYWORDS.contains(&keyword.as_str()))
+    }
+
+    /// Whether `sql` is a query whose rows can be limited: a `SELECT`,
+    /// `VALUES` or `WITH` statement
+    pub fn is_query(sql: &str) -> bool {
+        Self::leading_keyword(sql).is_some_and(|keyword| QUERY_KEYWORDS.contains(&keyword.as_str()))
+    }
+
+    /// Whether `sql` has a `LIMIT` clause of its own, outside any
+    /// parentheses, string literal, quoted name or comment
+    pub fn has_limit(sql: &str) -> bool {
+        top_level_words(sql)
+            .iter()
+            .any(|word| word.eq_ignore_ascii_case("LIMIT"))
+    }
+
+    /// Whether `sql` starts with `INSERT`, `UPDATE`, `DELETE` or `REPLACE`.
+    /// Writes behind a `WITH` clause are not recognised.
+    pub fn is_write_statement(sql: &str) -> bool {
+        Self::leading_keyword(sql).is_some_and(|keyword| WRITE_KEYWORDS.contains(&keyword.as_str()))
+    }
+
+    /// Whether `sql` only reads: a `SELECT` or `VALUES` statement, or a
+    /// `WITH` statement with no `INSERT`, `UPDATE`, `DELETE` or `REPLACE`
+    /// outside its parentheses. Errs on the side of rejecting, so a `WITH`
+    /// query calling `replace()` in its result columns counts as a write.
+    pub fn is_read_only(sql: &str) -> bool {
+        match Self::leading_keyword(sql).as_deref() {
+            Some("SELECT" | "VALUES") => true,
+            Some("WITH") => !top_level_words(sql).iter().any(|word| {
+                WRITE_KEYWORDS
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword))
+            }),
+            _ => false,
+        }
+    }
+
+    /// Whether `sql` ends outside any string literal, quoted name, comment or
+    /// `CREATE TRIGGER` body, using `sqlite3_complete`. Unlike SQLite, a
+    /// missing final semicolon is allowed.
+    pub fn is_complete(sql: &str) -> bool {
+        let Ok(sql) = CString::new(format!("{sql};")) else {
+            return false;
+        };
+        unsafe { sqlite3_complete(sql.as_ptr()) != 0 }
+    }
+}
+
+// The words of `sql` outside any parentheses, string literal, quoted name or
+// comment
+fn top_level_words(sql: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut depth = 0usize;
+    let mut word = String::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        let finished = std::mem::take(&mut word);
+        if depth == 0 && !finished.is_empty() {
+            words.push(finished);
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            // A doubled quote ends one quoted run and starts the next
+            '\'' | '"' | '`' => {
+                chars.find(|&inner| inner == c);
+            }
+            '[' => {
+                chars.find(|&inner| inner == ']');
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                chars.find(|&inner| inner == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                chars.find(|&inner| {
+                    let closes = previous == '*' && inner == '/';
+                    previous = inner;
+                    closes
+                });
+            }
+            _ => {}
+        }
+    }
+    if depth == 0 && !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_leading_keyword() {
+        assert_eq!(
+            SqlClassifier::leading_keyword("  select 1"),
+            Some("SELECT".to_string())
+        );
+        assert_eq!(
+            SqlClassifier::leading_keyword("-- note\n/* block */ Create TABLE t (x)"),
+            Some("CREATE".to_string())
+        );
+        assert_eq!(SqlClassifier::leading_keyword("-- only a comment"), None);
+        assert_eq!(SqlClassifier::leading_keyword(""), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_is_ddl() {
+        for sql in [
+            "CREATE TABLE t (x)",
+            "create unique index t_x on t (x)",
+            "DROP VIEW IF EXISTS v",
+            "/* migrate */ ALTER TABLE t ADD COLUMN y",
+        ] {
+            assert!(SqlClassifier::is_ddl(sql), "{sql} should be DDL");
+        }
+        for sql in [
+            "INSERT INTO t VALUES (1)",
+            "SELECT * FROM t",
+            "WITH x AS (SELECT 1) DELETE FROM t",
+            "PRAGMA user_version = 2",
+            "BEGIN",
+        ] {
+            assert!(!SqlClassifier::is_ddl(sql), "{sql} should not be DDL");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_has_limit() {
+        for sql in [
+            "SELECT * FROM t LIMIT 5",
+            "select * from t order by x limit 1 offset 2",
+            "SELECT (SELECT max(x) FROM t) AS m FROM u LIMIT 1;",
+        ] {
+            assert!(SqlClassifier::has_limit(sql), "{sql} should have a limit");
+        }
+        for sql in [
+            "SELECT * FROM t",
+            "SELECT * FROM t WHERE x IN (SELECT x FROM u LIMIT 3)",
+            "SELECT 'no LIMIT here' AS note",
+            "SELECT \"limit\" FROM t",
+            "SELECT * FROM t -- LIMIT 1",
+            "SELECT * FROM t /* LIMIT 1 */",
+            "SELECT unlimited FROM t",
+        ] {
+            assert!(!SqlClassifier::has_limit(sql), "{sql} should have no limit");
+        }
+        assert!(SqlClassifier::is_query(
+            "WITH x AS (SELECT 1) SELECT * FROM x"
+        ));
+        assert!(!SqlClassifier::is_query("PRAGMA user_version"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_is_write_statement() {
+        for sql in [
+            "INSERT INTO t VALUES (1)",
+            "  update t SET x = 2",
+            "-- cleanup\nDELETE FROM t",
+            "REPLACE INTO t VALUES (1)",
+        ] {
+            assert!(SqlClassifier::is_write_statement(sql), "{sql} should write");
+        }
+        for sql in ["SELECT * FROM t", "CREATE TABLE t (x)", "BEGIN", ""] {
+            assert!(
+                !SqlClassifier::is_write_statement(sql),
+                "{sql} should not write"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_is_read_only() {
+        for sql in [
+            "SELECT * FROM t",
+            "/* report */ select count(*) from t",
+            "VALUES (1), (2)",
+            "WITH x AS (SELECT 1) SELECT * FROM x",
+            "WITH x AS (SELECT 'DELETE') SELECT * FROM x WHERE note = 'INSERT'",
+        ] {
+            assert!(SqlClassifier::is_read_only(sql), "{sql} should only read");
+        }
+        for sql in [
+            "INSERT INTO t VALUES (1)",
+            "WITH x AS (SELECT 1) DELETE FROM t",
+            "with x as (select 1) insert into t select * from x",
+            "CREATE TABLE t (x)",
+            "PRAGMA user_version = 2",
+            "ATTACH 'other.db' AS other",
+            "BEGIN",
+            "",
+        ] {
+            assert!(
+                !SqlClassifier::is_read_only(sql),
+                "{sql} should not be read-only"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_is_complete() {
+        for sql in [
+            "SELECT 1",
+            "SELECT 1;",
+            "SELECT 'a;b' FROM t",
+            "CREATE TRIGGER t AFTER INSERT ON items BEGIN SELECT 1; END",
+        ] {
+            assert!(SqlClassifier::is_complete(sql), "{sql} should be complete");
+        }
+        for sql in [
+            "SELECT 'unterminated",
+            "SELECT \"name",
+            "SELECT 1 /* open comment",
+            "CREATE TRIGGER t AFTER INSERT ON items BEGIN SELECT 1;",
+            "SELECT '\0'",
+        ] {
+            assert!(
+                !SqlClassifier::is_complete(sql),
+                "{sql} should be incomplete"
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use js_sys::{Function, Promise, Reflect};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::coordination::{Interval, WorkerState};
+use crate::error::WorkerError;
+
+/// How often `start_storage_monitor` checks the storage estimate
+pub const STORAGE_MONITOR_INTERVAL_MS: u64 = 60_000;
+
+/// The origin's storage use and limit from `navigator.storage.estimate()`.
+/// Browsers round both figures, so treat them as approximate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct StorageQuota {
+    #[serde(rename = "usageBytes")]
+    pub usage_bytes: u64,
+    #[serde(rename = "quotaBytes")]
+    pub quota_bytes: u64,
+}
+
+impl StorageQuota {
+    /// The fraction of the quota in use, or `None` if the browser reported no
+    /// quota
+    pub fn usage_fraction(&self) -> Option<f64> {
+        (self.quota_bytes > 0).then(|| self.usage_bytes as f64 / self.quota_bytes as f64)
+    }
+
+    /// Whether more than `threshold_fraction` of the quota is in use
+    pub fn exceeds(&self, threshold_fraction: f64) -> bool {
+        self.usage_fraction()
+            .is_some_and(|fraction| fraction > threshold_fraction)
+    }
+}
+
+impl WorkerState {
+    /// Estimate how much storage the origin uses and may use. OPFS shares the
+    /// origin's quota, so any worker can ask without going through the leader.
+    pub async fn estimated_storage_quota(&self) -> Result<StorageQuota, WorkerError> {
+        estimate_storage().await
+    }
+
+    /// Check the storage estimate every `STORAGE_MONITOR_INTERVAL_MS` and
+    /// call `cb` with it each time more than `threshold_fraction` of the
+    /// quota is in use. Estimates that fail are skipped. Starting again
+    /// replaces the monitor already running.
+    pub fn start_storage_monitor(
+        &self,
+        threshold_fraction: f64,
+        cb: impl Fn(StorageQuota) + 'static,
+    ) -> Result<(), WorkerError> {
+        self.stop_storage_monitor();
+
+        let cb: Rc<dyn Fn(StorageQuota)> = Rc::new(cb);
+        let tick = Closure::wrap(Box::new(move || {
+            let cb = Rc::clone(&cb);
+            spawn_local(async move {
+                if let Ok(quota) = estimate_storage().await {
+                    if quota.exceeds(threshold_fraction) {
+                        cb(quota);
+                    }
+                }
+            });
+        }) as Box<dyn FnMut()>);
+
+        *self.storage_monitor.borrow_mut() =
+            Some(Interval::start(tick, STORAGE_MONITOR_INTERVAL_MS)?);
+        Ok(())
+    }
+
+    /// Stop the monitor started by `start_storage_monitor`, if any
+    pub fn stop_storage_monitor(&self) {
+        self.storage_monitor.borrow_mut().take();
+    }
+
+    /// Ask the browser to keep the origin's storage, OPFS included, when
+    /// space runs low, returning whether it agreed. Fails with
+    /// `StoragePersistenceUnavailable` where `navigator.storage.persist` is
+    /// missing, as it is in workers in some browsers.
+    pub async fn request_storage_persistence(&self) -> Result<bool, WorkerError> {
+        request_persistence().await
+    }
+}
+
+// `navigator.storage.estimate()`, with missing figures as zero
+async fn estimate_storage() -> Result<StorageQuota, WorkerError> {
+    let unavailable = || WorkerError::StorageEstimateUnavailable;
+    let navigator = Reflect::get(&js_sys::global(), &JsValue::from_str("navigator"))
+        .map_err(|_| unavailable())?;
+    let storage =
+        Reflect::get(&navigator, &JsValue::from_str("storage")).map_err(|_| unavailable())?;
+    let estimate = Reflect::get(&storage, &JsValue::from_str("estimate"))
+        .ok()
+        .and_then(|estimate| estimate.dyn_into::<Function>().ok())
+        .ok_or_else(unavailable)?;
+    let promise: Promise = estimate
+        .call0(&storage)
+        .map_err(|e| WorkerError::from_js_error(&e))?
+        .dyn_into()
+        .map_err(|_| unavailable())?;
+    let estimate = wasm_bindgen_futures::JsFuture::from(promise)
+        .await
+        .map_err(|e| WorkerError::from_js_error(&e))?;
+
+    // Either field may be missing when the browser cannot tell
+    let bytes = |field: &str| {
+        Reflect::get(&estimate, &JsValue::from_str(field))
+            .ok()
+            .and_then(|value| value.as_f64())
+            .map_or(0, |value| value as u64)
+    };
+    Ok(StorageQuota {
+        usage_bytes: bytes("usage"),
+        quota_bytes: bytes("quota"),
+    })
+}
+
+// Ask the browser to exempt the origin's storage from eviction, returning
+// whether it agreed. Browsers decide by their own heuristics and may grant it
+// without asking the user.
+pub(crate) async fn request_persistence() -> Result<bool, WorkerError> {
+    let unavailable = || WorkerError::StoragePersistenceUnavailable;
+    let navigator = Reflect::get(&js_sys::global(), &JsValue::from_str("navigator"))
+        .map_err(|_| unavailable())?;
+    let storage =
+        Reflect::get(&navigator, &JsValue::from_str("storage")).map_err(|_| unavailable())?;
+    let persist = Reflect::get(&storage, &JsValue::from_str("persist"))
+        .ok()
+        .and_then(|persist| persist.dyn_into::<Function>().ok())
+        .ok_or_else(unavailable)?;
+    let promise: Promise = persist
+        .call0(&storage)
+        .map_err(|e| WorkerError::from_js_error(&e))?
+        .dyn_into()
+        .map_err(|_| unavailable())?;
+    let granted = wasm_bindgen_futures::JsFuture::from(promise)
+        .await
+        .map_err(|e| WorkerError::from_js_error(&e))?;
+    Ok(granted.as_bool() == Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    async fn test_estimated_storage_quota() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let Ok(quota) = state.estimated_storage_quota().await else {
+            return;
+        };
+        assert!(quota.quota_bytes > 0);
+        assert!(quota.usage_bytes <= quota.quota_bytes);
+
+        let json = serde_json::to_string(&quota).expect("Should serialize");
+        assert!(json.contains("\"usageBytes\""));
+        assert!(json.contains("\"quotaBytes\""));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_storage_quota_threshold() {
+        let quota = |usage_bytes, quota_bytes| StorageQuota {
+            usage_bytes,
+            quota_bytes,
+        };
+        assert_eq!(quota(250, 1000).usage_fraction(), Some(0.25));
+        assert!(quota(900, 1000).exceeds(0.8));
+        assert!(!quota(800, 1000).exceeds(0.8));
+        assert!(!quota(100, 1000).exceeds(0.8));
+        // Without a quota there is nothing to compare against
+        assert_eq!(quota(100, 0).usage_fraction(), None);
+        assert!(!quota(100, 0).exceeds(0.0));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_start_and_stop_storage_monitor() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        state
+            .start_storage_monitor(0.9, |_| {})
+            .expect("Monitor should start");
+        assert!(state.storage_monitor.borrow().is_some());
+        state.stop_storage_monitor();
+        assert!(state.storage_monitor.borrow().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_request_storage_persistence() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        // Whether it is granted is up to the browser
+        match state.request_storage_persistence().await {
+            Ok(_) | Err(WorkerError::StoragePersistenceUnavailable) => {}
+            Err(error) => panic!("Unexpected error: {error:?}"),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+
+// First SQLite release with `STRICT` tables
+const STRICT_MIN_VERSION: (u32, u32, u32) = (3, 37, 0);
+
+impl WorkerState {
+    /// Declare every table created through `create_table` or
+    /// `execute_ddl_transaction` from now on as `STRICT`, so SQLite rejects
+    /// values that do not match a column's type instead of storing them
+    /// under another affinity. Creating a table then needs SQLite 3.37 or
+    /// later.
+    pub fn enable_strict_mode(&self) {
+        self.strict_tables.set(true);
+    }
+
+    pub fn strict_mode_enabled(&self) -> bool {
+        self.strict_tables.get()
+    }
+
+    // Append `STRICT` to each `CREATE TABLE` in `statements` while strict mode
+    // is on, failing with `FeatureUnsupported` if any needed it and SQLite is
+    // too old to accept it
+    pub(crate) async fn apply_strict_mode(
+        &self,
+        statements: &mut [String],
+    ) -> Result<(), WorkerError> {
+        if !self.strict_tables.get() {
+            return Ok(());
+        }
+        let mut rewritten = false;
+        for sql in statements.iter_mut() {
+            if let Some(strict) = strict_create_table(sql) {
+                *sql = strict;
+                rewritten = true;
+            }
+        }
+        if rewritten {
+            self.require_sqlite_version("STRICT tables", STRICT_MIN_VERSION)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+// `sql` with `STRICT` added to its table options, or `None` if it is not a
+// `CREATE [TEMP] TABLE` with a column list or is strict already
+fn strict_create_table(sql: &str) -> Option<String> {
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    let mut words = sql.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("CREATE") {
+        return None;
+    }
+    let mut word = words.next()?;
+    if word.eq_ignore_ascii_case("TEMP") || word.eq_ignore_ascii_case("TEMPORARY") {
+        word = words.next()?;
+    }
+    if !word.eq_ignore_ascii_case("TABLE") {
+        return None;
+    }
+
+    // `CREATE TABLE ... AS SELECT` takes no table options
+    let (head, _) = sql.split_once('(')?;
+    if head
+        .split_whitespace()
+        .any(|word| word.eq_ignore_ascii_case("AS"))
+    {
+        return None;
+    }
+
+    let (_, options) = sql.rsplit_once(')')?;
+    if options
+        .split(',')
+        .any(|option| option.trim().eq_ignore_ascii_case("STRICT"))
+    {
+        return None;
+    }
+    if options.trim().is_empty() {
+        Some(format!("{sql} STRICT"))
+    } else {
+        Some(format!("{sql}, STRICT"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{lead_with_db, WorkerStateBuilder};
+    use crate::query_builder::{ColumnType, TableBuilder};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_strict_create_table() {
+        assert_eq!(
+            strict_create_table("CREATE TABLE t (x INTEGER)").as_deref(),
+            Some("CREATE TABLE t (x INTEGER) STRICT")
+        );
+        assert_eq!(
+            strict_create_table("create temp table t(x INT, y TEXT) WITHOUT ROWID;").as_deref(),
+            Some("create temp table t(x INT, y TEXT) WITHOUT ROWID, STRICT")
+        );
+        for sql in [
+            "CREATE TABLE t (x INTEGER) STRICT",
+            "CREATE TABLE t (x INTEGER) WITHOUT ROWID, strict",
+            "CREATE TABLE t AS SELECT count(*) AS n FROM u",
+            "CREATE INDEX t_x ON t (x)",
+            "CREATE VIRTUAL TABLE v USING fts5(body)",
+            "DROP TABLE t",
+        ] {
+            assert_eq!(strict_create_table(sql), None, "{sql} should be left alone");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_strict_mode_flag() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        assert!(!state.strict_mode_enabled());
+        state.enable_strict_mode();
+        assert!(state.strict_mode_enabled());
+
+        if let Ok(state) = WorkerStateBuilder::new().strict_tables(true).build() {
+            assert!(state.strict_mode_enabled());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_strict_table_rejects_mistyped_value() {
+        let Ok(state) = WorkerStateBuilder::new().strict_tables(true).build() else {
+            return;
+        };
+        let Some(state) = lead_with_db(state).await else {
+            return;
+        };
+        let _ = state.drop_table("strict_counts", true).await;
+        let _ = state.drop_table("strict_ddl", true).await;
+
+        state
+            .create_table(TableBuilder::new("strict_counts").column("n", ColumnType::Integer, &[]))
+            .await
+            .expect("Create failed");
+        state
+            .execute_query_simple("INSERT INTO strict_counts (n) VALUES ('12')".to_string())
+            .await
+            .expect("A text value with an integer form is converted");
+        assert!(state
+            .execute_query_simple("INSERT INTO strict_counts (n) VALUES ('twelve')".to_string())
+            .await
+            .is_err());
+
+        state
+            .execute_ddl_transaction(vec!["CREATE TABLE strict_ddl (n INTEGER)".to_string()])
+            .await
+            .expect("DDL failed");
+        let ddl = state.get_table_ddl("strict_ddl").await.unwrap();
+        assert!(ddl.ends_with("STRICT"), "{ddl}");
+    }
+}
@@ -0,0 +1,262 @@
+// sw.rs - This module runs in the service worker context
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{FetchEvent, Headers, Request, Response, ResponseInit, ServiceWorkerGlobalScope};
+
+use crate::coordination::WorkerState;
+use crate::query_result::SqlParam;
+use crate::sql_classifier::SqlClassifier;
+
+pub const DEFAULT_URL_PREFIX: &str = "/api/db/";
+
+// Global state
+thread_local! {
+    static SW_STATE: RefCell<Option<Rc<WorkerState>>> = const { RefCell::new(None) };
+    static URL_PREFIX: RefCell<String> = RefCell::new(DEFAULT_URL_PREFIX.to_string());
+}
+
+// JSON body of an intercepted request
+#[derive(Deserialize, Debug, PartialEq)]
+struct SqlRequestBody {
+    sql: String,
+    #[serde(default)]
+    params: Vec<SqlParam>,
+}
+
+/// Set the URL path prefix whose fetches are answered from the database.
+/// Call this from the service worker's `install` event.
+#[wasm_bindgen]
+pub fn configure_service_worker(prefix: String) {
+    URL_PREFIX.with(|p| *p.borrow_mut() = prefix);
+}
+
+fn url_prefix() -> String {
+    URL_PREFIX.with(|p| p.borrow().clone())
+}
+
+// Whether the path of `url` falls under `prefix`
+fn matches_prefix(url: &str, prefix: &str) -> bool {
+    web_sys::Url::new(url)
+        .map(|url| url.pathname().starts_with(prefix))
+        .unwrap_or(false)
+}
+
+/// Entry point for the service worker. It only ever follows: service workers
+/// have no OPFS sync access handles, so it cannot open the database and
+/// forwards every query to a dedicated worker that leads. It only answers
+/// read queries.
+pub fn main() -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+
+    let state = Rc::new(WorkerState::new()?);
+
+    // Never bids for leadership, which it would win and then fail to use
+    state.setup_channel_listener();
+
+    SW_STATE.with(|s| {
+        *s.borrow_mut() = Some(Rc::clone(&state));
+    });
+
+    let global = js_sys::global();
+    let sw_scope: ServiceWorkerGlobalScope = global.unchecked_into();
+
+    let onfetch = Closure::wrap(Box::new(move |event: FetchEvent| {
+        let request = event.request();
+        // Anything outside the prefix falls through to the network
+        if !matches_prefix(&request.url(), &url_prefix()) {
+            return;
+        }
+
+        let state = Rc::clone(&state);
+        let promise =
+            future_to_promise(async move { handle_fetch(&state, request).await.map(Into::into) });
+        let _ = event.respond_with(&promise);
+    }) as Box<dyn FnMut(FetchEvent)>);
+
+    sw_scope.set_onfetch(Some(onfetch.as_ref().unchecked_ref()));
+    onfetch.forget();
+
+    Ok(())
+}
+
+/// Run the read-only SQL carried by `request` and wrap the outcome in a JSON
+/// response, as the service worker's fetch handler does. Statements that
+/// could write are refused with status 403.
+pub async fn handle_fetch(state: &WorkerState, request: Request) -> Result<Response, JsValue> {
+    let text = JsFuture::from(request.text()?)
+        .await?
+        .as_string()
+        .unwrap_or_default();
+
+    let (status, body) = match serde_json::from_str::<SqlRequestBody>(&text) {
+        Ok(body) if !SqlClassifier::is_read_only(&body.sql) => (
+            403,
+            serde_json::json!({ "error": "Only read-only statements can be run from the service worker" }),
+        ),
+        Ok(body) => match state.execute_parameterized(body.sql, body.params).await {
+            Ok(result) => (
+                200,
+                serde_json::to_value(result)
+                    .map_err(|e| JsValue::from_str(&format!("JSON serialization error: {e}")))?,
+            ),
+            Err(err) => (500, serde_json::json!({ "error": err.to_string() })),
+        },
+        Err(err) => (
+            400,
+            serde_json::json!({ "error": format!("Invalid request body: {err}") }),
+        ),
+    };
+
+    json_response(status, &body)
+}
+
+fn json_response(status: u16, body: &serde_json::Value) -> Result<Response, JsValue> {
+    let headers = Headers::new()?;
+    headers.set("Content-Type", "application/json")?;
+
+    let init = ResponseInit::new();
+    init.set_status(status);
+    init.set_headers(&headers);
+
+    Response::new_with_opt_str_and_init(Some(&body.to_string()), &init)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+    use web_sys::RequestInit;
+
+    // All tests in the crate share one runner, so the fetch handler is
+    // exercised directly instead of through a registered service worker
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn post_request(body: &str) -> Request {
+        let init = RequestInit::new();
+        init.set_method("POST");
+        init.set_body(&JsValue::from_str(body));
+        Request::new_with_str_and_init("http://localhost/api/db/query", &init).unwrap()
+    }
+
+    async fn response_json(response: Response) -> serde_json::Value {
+        let text = JsFuture::from(response.text().unwrap())
+            .await
+            .unwrap()
+            .as_string()
+            .unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_matches_prefix() {
+        assert!(matches_prefix("http://localhost/api/db/query", "/api/db/"));
+        assert!(matches_prefix(
+            "https://example.com/api/db/users?id=1",
+            "/api/db/"
+        ));
+        assert!(!matches_prefix("http://localhost/api/other", "/api/db/"));
+        assert!(!matches_prefix(
+            "http://localhost/static/api/db/",
+            "/api/db/"
+        ));
+        assert!(!matches_prefix("not a url", "/api/db/"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_configure_service_worker_sets_prefix() {
+        configure_service_worker("/sql/".to_string());
+        assert_eq!(url_prefix(), "/sql/");
+        configure_service_worker(DEFAULT_URL_PREFIX.to_string());
+        assert_eq!(url_prefix(), DEFAULT_URL_PREFIX);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_request_body_parsing() {
+        let body: SqlRequestBody =
+            serde_json::from_str(r#"{"sql": "SELECT ?", "params": [1]}"#).unwrap();
+        assert_eq!(body.sql, "SELECT ?");
+        assert_eq!(body.params, vec![SqlParam::Integer(1)]);
+
+        let body: SqlRequestBody = serde_json::from_str(r#"{"sql": "SELECT 1"}"#).unwrap();
+        assert!(body.params.is_empty());
+
+        assert!(serde_json::from_str::<SqlRequestBody>(r#"{"params": []}"#).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_handle_fetch_rejects_invalid_body() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        let response = handle_fetch(&state, post_request("not json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap().as_deref(),
+            Some("application/json")
+        );
+        let body = response_json(response).await;
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request body"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_handle_fetch_rejects_writes() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+
+        for sql in [
+            "INSERT INTO items VALUES (1)",
+            "WITH x AS (SELECT 1) DELETE FROM items",
+            "DROP TABLE items",
+        ] {
+            let body = serde_json::json!({ "sql": sql }).to_string();
+            let response = handle_fetch(&state, post_request(&body)).await.unwrap();
+            assert_eq!(response.status(), 403, "{sql} should be refused");
+            let body = response_json(response).await;
+            assert!(body["error"].as_str().unwrap().contains("read-only"));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_handle_fetch_reports_query_errors() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+
+        let response = handle_fetch(&state, post_request(r#"{"sql": "SELECT 1"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 500);
+        let body = response_json(response).await;
+        assert_eq!(body["error"], "Database not initialized");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_handle_fetch_returns_query_result() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        let response = handle_fetch(
+            &state,
+            post_request(r#"{"sql": "SELECT ? AS value", "params": ["hello"]}"#),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response_json(response).await;
+        assert_eq!(body["columns"][0], "value");
+        assert_eq!(body["rows"][0][0], "hello");
+    }
+}
@@ -0,0 +1,140 @@
+use js_sys::{Function, Reflect};
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+/// Source of the current time and timers, so tests can control the clock
+pub trait TimeProvider {
+    fn now_ms(&self) -> f64;
+
+    /// Call `cb` once, `ms` from now
+    fn set_timeout(&self, ms: f64, cb: Box<dyn FnOnce()>);
+}
+
+/// `Date.now()` and the global `setTimeout`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealTimeProvider;
+
+impl TimeProvider for RealTimeProvider {
+    fn now_ms(&self) -> f64 {
+        js_sys::Date::now()
+    }
+
+    fn set_timeout(&self, ms: f64, cb: Box<dyn FnOnce()>) {
+        let global = js_sys::global();
+        let Some(set_timeout) = Reflect::get(&global, &JsValue::from_str("setTimeout"))
+            .ok()
+            .and_then(|set_timeout| set_timeout.dyn_into::<Function>().ok())
+        else {
+            return;
+        };
+        let callback = Closure::once_into_js(cb);
+        let _ = set_timeout.call2(&JsValue::NULL, &callback, &JsValue::from_f64(ms));
+    }
+}
+
+#[cfg(test)]
+type MockTimer = (f64, Box<dyn FnOnce()>);
+
+/// A clock that only moves when `advance` is called, firing due timers then
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockTimeProvider {
+    now_ms: Rc<RefCell<f64>>,
+    timers: Rc<RefCell<Vec<MockTimer>>>,
+}
+
+#[cfg(test)]
+impl MockTimeProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pending_timers(&self) -> usize {
+        self.timers.borrow().len()
+    }
+
+    /// Move the clock forward by `ms` and fire, earliest first, every timer
+    /// whose deadline has passed
+    pub fn advance(&self, ms: f64) {
+        let now = {
+            let mut now_ms = self.now_ms.borrow_mut();
+            *now_ms += ms;
+            *now_ms
+        };
+        loop {
+            // Taken out before calling so callbacks can schedule more timers
+            let due = {
+                let mut timers = self.timers.borrow_mut();
+                let next = timers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (deadline, _))| *deadline <= now)
+                    .min_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))
+                    .map(|(index, _)| index);
+                next.map(|index| timers.remove(index))
+            };
+            match due {
+                Some((_, cb)) => cb(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl TimeProvider for MockTimeProvider {
+    fn now_ms(&self) -> f64 {
+        *self.now_ms.borrow()
+    }
+
+    fn set_timeout(&self, ms: f64, cb: Box<dyn FnOnce()>) {
+        let deadline = self.now_ms() + ms;
+        self.timers.borrow_mut().push((deadline, cb));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_mock_fires_due_timers_in_order() {
+        let time = MockTimeProvider::new();
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        for (ms, label) in [(30.0, "late"), (10.0, "early"), (100.0, "never")] {
+            let fired = Rc::clone(&fired);
+            time.set_timeout(ms, Box::new(move || fired.borrow_mut().push(label)));
+        }
+
+        time.advance(5.0);
+        assert!(fired.borrow().is_empty());
+        time.advance(25.0);
+        assert_eq!(*fired.borrow(), ["early", "late"]);
+        assert_eq!(time.now_ms(), 30.0);
+        assert_eq!(time.pending_timers(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mock_timer_can_schedule_another() {
+        let time = MockTimeProvider::new();
+        let fired = Rc::new(RefCell::new(0));
+        let (inner_time, inner_fired) = (time.clone(), Rc::clone(&fired));
+        time.set_timeout(
+            10.0,
+            Box::new(move || {
+                *inner_fired.borrow_mut() += 1;
+                let fired = Rc::clone(&inner_fired);
+                inner_time.set_timeout(0.0, Box::new(move || *fired.borrow_mut() += 1));
+            }),
+        );
+
+        time.advance(10.0);
+        assert_eq!(*fired.borrow(), 2);
+    }
+}
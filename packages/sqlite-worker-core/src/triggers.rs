@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+use std::str::SplitWhitespace;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_result::SqlParam;
+use crate::row::FromRow;
+
+/// The statement that fires a trigger
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// When a trigger runs relative to the statement that fired it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TriggerTiming {
+    Before,
+    After,
+    // Only allowed on views, in place of the statement
+    InsteadOf,
+}
+
+/// A trigger as stored in `sqlite_master`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TriggerInfo {
+    pub name: String,
+    // The table or view the trigger is attached to
+    pub table: String,
+    pub event: TriggerEvent,
+    pub timing: TriggerTiming,
+    // The `CREATE TRIGGER` statement
+    pub sql: String,
+}
+
+impl WorkerState {
+    /// The triggers in the main database ordered by name, only those on
+    /// `table` if given
+    pub async fn list_triggers(
+        &self,
+        table: Option<&str>,
+    ) -> Result<Vec<TriggerInfo>, WorkerError> {
+        let triggers: Vec<(String, String, String)> = self
+            .execute_parameterized(
+                "SELECT name, tbl_name, sql FROM sqlite_master \
+                 WHERE type = 'trigger' AND (?1 IS NULL OR tbl_name = ?1) ORDER BY name"
+                    .to_string(),
+                vec![table.map_or(SqlParam::Null, SqlParam::from)],
+            )
+            .await?
+            .into_iter()
+            .map(|row| FromRow::from_row(&row))
+            .collect::<Result<_, _>>()?;
+
+        triggers
+            .into_iter()
+            .map(|(name, table, sql)| {
+                let (timing, event) = parse_trigger_header(&sql).ok_or_else(|| {
+                    WorkerError::RowDecode(format!("unrecognised trigger statement: {sql}"))
+                })?;
+                Ok(TriggerInfo {
+                    name,
+                    table,
+                    event,
+                    timing,
+                    sql,
+                })
+            })
+            .collect()
+    }
+}
+
+// Consume the next word if it is `keyword`, ignoring case
+fn take_keyword(words: &mut Peekable<SplitWhitespace>, keyword: &str) -> bool {
+    let matched = words
+        .peek()
+        .is_some_and(|word| word.eq_ignore_ascii_case(keyword));
+    if matched {
+        words.next();
+    }
+    matched
+}
+
+// The timing and event of a `CREATE TRIGGER` statement. A trigger declared
+// without a timing runs before the statement.
+fn parse_trigger_header(sql: &str) -> Option<(TriggerTiming, TriggerEvent)> {
+    let words = &mut sql.split_whitespace().peekable();
+    if !take_keyword(words, "CREATE") {
+        return None;
+    }
+    if !take_keyword(words, "TEMP") {
+        take_keyword(words, "TEMPORARY");
+    }
+    if !take_keyword(words, "TRIGGER") {
+        return None;
+    }
+    if take_keyword(words, "IF") && !(take_keyword(words, "NOT") && take_keyword(words, "EXISTS")) {
+        return None;
+    }
+    // The trigger's name
+    words.next()?;
+
+    let timing = if take_keyword(words, "BEFORE") {
+        TriggerTiming::Before
+    } else if take_keyword(words, "AFTER") {
+        TriggerTiming::After
+    } else if take_keyword(words, "INSTEAD") {
+        if !take_keyword(words, "OF") {
+            return None;
+        }
+        TriggerTiming::InsteadOf
+    } else {
+        TriggerTiming::Before
+    };
+    let event = if take_keyword(words, "INSERT") {
+        TriggerEvent::Insert
+    } else if take_keyword(words, "UPDATE") {
+        TriggerEvent::Update
+    } else if take_keyword(words, "DELETE") {
+        TriggerEvent::Delete
+    } else {
+        return None;
+    };
+    Some((timing, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_parse_trigger_header() {
+        assert_eq!(
+            parse_trigger_header("CREATE TRIGGER t AFTER INSERT ON items BEGIN SELECT 1; END"),
+            Some((TriggerTiming::After, TriggerEvent::Insert))
+        );
+        assert_eq!(
+            parse_trigger_header(
+                "create temp trigger if not exists after_log before update of name on items begin select 1; end"
+            ),
+            Some((TriggerTiming::Before, TriggerEvent::Update))
+        );
+        assert_eq!(
+            parse_trigger_header("CREATE TRIGGER v_del INSTEAD OF DELETE ON v BEGIN SELECT 1; END"),
+            Some((TriggerTiming::InsteadOf, TriggerEvent::Delete))
+        );
+        assert_eq!(
+            parse_trigger_header("CREATE TRIGGER t DELETE ON items BEGIN SELECT 1; END"),
+            Some((TriggerTiming::Before, TriggerEvent::Delete))
+        );
+        assert_eq!(parse_trigger_header("CREATE TABLE t (x)"), None);
+        assert_eq!(
+            serde_json::to_string(&TriggerTiming::InsteadOf).unwrap(),
+            "\"instead-of\""
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_list_triggers() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS trigger_items".to_string(),
+                "DROP TABLE IF EXISTS trigger_log".to_string(),
+                "CREATE TABLE trigger_items (id INTEGER PRIMARY KEY, name TEXT)".to_string(),
+                "CREATE TABLE trigger_log (item_id INTEGER)".to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+        let sql = "CREATE TRIGGER trigger_items_log AFTER INSERT ON trigger_items \
+                   BEGIN INSERT INTO trigger_log VALUES (new.id); END";
+        state
+            .execute_parameterized(sql.to_string(), Vec::new())
+            .await
+            .expect("Create trigger failed");
+
+        let triggers = state
+            .list_triggers(Some("trigger_items"))
+            .await
+            .expect("Listing triggers failed");
+        assert_eq!(
+            triggers,
+            vec![TriggerInfo {
+                name: "trigger_items_log".to_string(),
+                table: "trigger_items".to_string(),
+                event: TriggerEvent::Insert,
+                timing: TriggerTiming::After,
+                sql: sql.to_string(),
+            }]
+        );
+
+        assert!(state
+            .list_triggers(Some("trigger_log"))
+            .await
+            .unwrap()
+            .is_empty());
+        let all = state.list_triggers(None).await.unwrap();
+        assert!(all
+            .iter()
+            .any(|trigger| trigger.name == "trigger_items_log"));
+    }
+}
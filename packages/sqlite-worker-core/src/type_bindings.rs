@@ -0,0 +1,311 @@
+use std::fmt::Write as _;
+
+use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::SqlParam;
+use crate::row::FromRow;
+
+// Rust keywords a column may be named after, written as raw identifiers
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+    "while",
+];
+
+// How a column's values decode, from its declared type
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Integer,
+    Real,
+    Boolean,
+    Text,
+    Blob,
+    // No declared type, so any value is stored as given
+    Any,
+}
+
+impl ColumnKind {
+    // SQLite's column affinity rules, with BOOLEAN singled out from NUMERIC
+    fn from_declared_type(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        if declared.contains("INT") {
+            ColumnKind::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|name| declared.contains(name))
+        {
+            ColumnKind::Text
+        } else if declared.contains("BLOB") {
+            ColumnKind::Blob
+        } else if declared.trim().is_empty() {
+            ColumnKind::Any
+        } else if declared.contains("BOOL") {
+            ColumnKind::Boolean
+        } else {
+            ColumnKind::Real
+        }
+    }
+
+    fn rust_type(self) -> &'static str {
+        match self {
+            ColumnKind::Integer => "i64",
+            ColumnKind::Real => "f64",
+            ColumnKind::Boolean => "bool",
+            ColumnKind::Text => "String",
+            ColumnKind::Blob => "Vec<u8>",
+            ColumnKind::Any => "SqlValue",
+        }
+    }
+
+    fn typescript_type(self) -> &'static str {
+        match self {
+            ColumnKind::Integer | ColumnKind::Real => "number",
+            ColumnKind::Boolean => "boolean",
+            ColumnKind::Text => "string",
+            ColumnKind::Blob => "Uint8Array",
+            ColumnKind::Any => "number | string | Uint8Array | null",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TableColumn {
+    name: String,
+    kind: ColumnKind,
+    nullable: bool,
+}
+
+impl WorkerState {
+    /// Rust source for a struct with a field per column of `table` and a
+    /// `FromRow` implementation decoding it by column name, as a starting
+    /// point for hand-written bindings. Column types follow SQLite's affinity
+    /// rules and columns without `NOT NULL` become `Option`s.
+    pub async fn generate_type_bindings(&self, table: &str) -> Result<String, WorkerError> {
+        let columns = self.table_columns(table).await?;
+        Ok(rust_bindings(table, &columns))
+    }
+
+    /// A TypeScript interface with a property per column of `table`, typed as
+    /// the values arrive in JavaScript
+    pub async fn generate_typescript_interface(&self, table: &str) -> Result<String, WorkerError> {
+        let columns = self.table_columns(table).await?;
+        Ok(typescript_interface(table, &columns))
+    }
+
+    async fn table_columns(&self, table: &str) -> Result<Vec<TableColumn>, WorkerError> {
+        validate_identifier(table)?;
+        let columns: Vec<(String, String, bool, i64)> = self
+            .execute_parameterized(
+                "SELECT name, type, \"notnull\", pk FROM pragma_table_info(?) ORDER BY cid"
+                    .to_string(),
+                vec![SqlParam::from(table)],
+            )
+            .await?
+            .into_iter()
+            .map(|row| FromRow::from_row(&row))
+            .collect::<Result<_, _>>()?;
+        if columns.is_empty() {
+            return Err(WorkerError::TableNotFound(table.to_string()));
+        }
+        let single_key = columns.iter().filter(|(.., pk)| *pk > 0).count() == 1;
+
+        Ok(columns
+            .into_iter()
+            .map(|(name, declared, not_null, pk)| {
+                let kind = ColumnKind::from_declared_type(&declared);
+                // An INTEGER PRIMARY KEY is the rowid and is never NULL
+                let rowid = single_key && pk > 0 && declared.eq_ignore_ascii_case("INTEGER");
+                TableColumn {
+                    name,
+                    kind,
+                    nullable: !not_null && !rowid && kind != ColumnKind::Any,
+                }
+            })
+            .collect())
+    }
+}
+
+// `order_items` as `OrderItems`
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// A column name as a snake_case Rust field name
+fn field_name(column: &str) -> String {
+    let mut name: String = column
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if RUST_KEYWORDS.contains(&name.as_str()) {
+        name.insert_str(0, "r#");
+    }
+    name
+}
+
+fn rust_bindings(table: &str, columns: &[TableColumn]) -> String {
+    let struct_name = pascal_case(table);
+    let imports = if columns.iter().any(|column| column.kind == ColumnKind::Any) {
+        "FromRow, FromSqlValue, Row, SqlValue, WorkerError"
+    } else {
+        "FromRow, FromSqlValue, Row, WorkerError"
+    };
+    let mut source = format!("use sqlite_worker_core::{{{imports}}};\n\n");
+
+    source.push_str("#[derive(Debug, Clone, PartialEq)]\n");
+    let _ = writeln!(source, "pub struct {struct_name} {{");
+    for column in columns {
+        let rust_type = column.kind.rust_type();
+        let rust_type = if column.nullable {
+            format!("Option<{rust_type}>")
+        } else {
+            rust_type.to_string()
+        };
+        let _ = writeln!(source, "    pub {}: {rust_type},", field_name(&column.name));
+    }
+    source.push_str("}\n\n");
+
+    let _ = writeln!(source, "impl FromRow for {struct_name} {{");
+    source.push_str("    fn from_row(row: &Row) -> Result<Self, WorkerError> {\n");
+    source.push_str("        let column = |name: &str| {\n");
+    source.push_str("            row.get(name)\n");
+    source.push_str(
+        "                .ok_or_else(|| WorkerError::RowDecode(format!(\"missing column {name}\")))\n",
+    );
+    source.push_str("        };\n");
+    let _ = writeln!(source, "        Ok({struct_name} {{");
+    for column in columns {
+        let _ = writeln!(
+            source,
+            "            {}: FromSqlValue::from_sql_value(column({:?})?)?,",
+            field_name(&column.name),
+            column.name
+        );
+    }
+    source.push_str("        })\n    }\n}\n");
+    source
+}
+
+fn typescript_interface(table: &str, columns: &[TableColumn]) -> String {
+    let mut source = format!("export interface {} {{\n", pascal_case(table));
+    for column in columns {
+        let valid_name = !column.name.starts_with(|c: char| c.is_ascii_digit())
+            && column
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+        let name = if valid_name {
+            column.name.clone()
+        } else {
+            format!("{:?}", column.name)
+        };
+        let null = if column.nullable { " | null" } else { "" };
+        let _ = writeln!(source, "  {name}: {}{null};", column.kind.typescript_type());
+    }
+    source.push_str("}\n");
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_column_names_and_kinds() {
+        assert_eq!(pascal_case("order_items"), "OrderItems");
+        assert_eq!(pascal_case("users"), "Users");
+        assert_eq!(field_name("createdAt"), "createdat");
+        assert_eq!(field_name("first name"), "first_name");
+        assert_eq!(field_name("type"), "r#type");
+        assert_eq!(field_name("2fa"), "_2fa");
+
+        assert_eq!(
+            ColumnKind::from_declared_type("BIGINT"),
+            ColumnKind::Integer
+        );
+        assert_eq!(
+            ColumnKind::from_declared_type("VARCHAR(255)"),
+            ColumnKind::Text
+        );
+        assert_eq!(ColumnKind::from_declared_type("DOUBLE"), ColumnKind::Real);
+        assert_eq!(ColumnKind::from_declared_type("DECIMAL"), ColumnKind::Real);
+        assert_eq!(
+            ColumnKind::from_declared_type("BOOLEAN"),
+            ColumnKind::Boolean
+        );
+        assert_eq!(ColumnKind::from_declared_type("blob"), ColumnKind::Blob);
+        assert_eq!(ColumnKind::from_declared_type(""), ColumnKind::Any);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_generate_bindings() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        state
+            .execute_many(vec![
+                "DROP TABLE IF EXISTS binding_users".to_string(),
+                "CREATE TABLE binding_users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+                 score REAL, avatar BLOB, active BOOLEAN NOT NULL)"
+                    .to_string(),
+            ])
+            .await
+            .expect("Setup failed");
+
+        let rust = state
+            .generate_type_bindings("binding_users")
+            .await
+            .expect("Rust bindings failed");
+        assert!(rust.contains(
+            "pub struct BindingUsers {\n    pub id: i64,\n    pub name: String,\n    \
+             pub score: Option<f64>,\n    pub avatar: Option<Vec<u8>>,\n    pub active: bool,\n}"
+        ));
+        assert!(
+            rust.starts_with("use sqlite_worker_core::{FromRow, FromSqlValue, Row, WorkerError};")
+        );
+        assert!(rust.contains("impl FromRow for BindingUsers {"));
+        assert!(rust.contains("name: FromSqlValue::from_sql_value(column(\"name\")?)?,"));
+
+        let typescript = state
+            .generate_typescript_interface("binding_users")
+            .await
+            .expect("TypeScript interface failed");
+        assert_eq!(
+            typescript,
+            "export interface BindingUsers {\n  id: number;\n  name: string;\n  \
+             score: number | null;\n  avatar: Uint8Array | null;\n  active: boolean;\n}\n"
+        );
+
+        assert_eq!(
+            state.generate_type_bindings("binding_missing").await,
+            Err(WorkerError::TableNotFound("binding_missing".to_string()))
+        );
+        assert!(matches!(
+            state.generate_typescript_interface("users; --").await,
+            Err(WorkerError::InvalidIdentifier(_))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+use js_sys::Reflect;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::coordination::{EventListener, WorkerState};
+use crate::error::WorkerError;
+
+impl WorkerState {
+    /// With `resign_on_hide` set, resign leadership whenever the document is
+    /// hidden and request it again once visible, so a suspended background tab
+    /// does not block the others. Does nothing if the option is off, and fails
+    /// where there is no `document`, such as inside a worker.
+    pub fn setup_visibility_listener(self: &Rc<Self>) -> Result<(), WorkerError> {
+        if !self.config.resign_on_hide {
+            return Ok(());
+        }
+        let global = js_sys::global();
+        let document = Reflect::get(&global, &JsValue::from_str("document"))
+            .ok()
+            .filter(JsValue::is_object)
+            .ok_or_else(|| WorkerError::QueryFailed("document is unavailable".to_string()))?;
+
+        let state = Rc::downgrade(self);
+        let target = document.clone();
+        let on_change = Closure::wrap(Box::new(move || {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let visibility = Reflect::get(&target, &JsValue::from_str("visibilityState"))
+                .ok()
+                .and_then(|visibility| visibility.as_string())
+                .unwrap_or_default();
+            spawn_local(async move {
+                state.handle_visibility_change(&visibility).await;
+            });
+        }) as Box<dyn FnMut()>);
+
+        // Replacing an earlier listener removes it
+        let listener = EventListener::add(&document, "visibilitychange", on_change)?;
+        *self.visibility_listener.borrow_mut() = Some(listener);
+        Ok(())
+    }
+
+    // React to `document.visibilityState` becoming `visibility`
+    pub(crate) async fn handle_visibility_change(&self, visibility: &str) {
+        let is_leader = *self.is_leader.borrow();
+        match visibility {
+            "hidden" => self.resign_leadership(),
+            "visible" if !is_leader => self.attempt_leadership().await,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::WorkerStateBuilder;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    fn resigning_state() -> Option<Rc<WorkerState>> {
+        WorkerStateBuilder::new()
+            .resign_on_hide(true)
+            .build()
+            .ok()
+            .map(Rc::new)
+    }
+
+    #[wasm_bindgen_test]
+    fn test_setup_visibility_listener() {
+        if let Ok(state) = WorkerState::new() {
+            assert!(!state.config.resign_on_hide);
+            assert_eq!(Rc::new(state).setup_visibility_listener(), Ok(()));
+        }
+        // The tests run in a dedicated worker, which has no document
+        if let Some(state) = resigning_state() {
+            assert_eq!(
+                state.setup_visibility_listener(),
+                Err(WorkerError::QueryFailed(
+                    "document is unavailable".to_string()
+                ))
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_hidden_leader_resigns() {
+        let Some(state) = resigning_state() else {
+            return;
+        };
+        *state.is_leader.borrow_mut() = true;
+        *state.known_leader_id.borrow_mut() = Some(state.worker_id.clone());
+        state.leadership_requested.set(true);
+
+        state.handle_visibility_change("hidden").await;
+        assert!(!*state.is_leader.borrow());
+        assert!(state.db.borrow().is_none());
+        assert_eq!(*state.known_leader_id.borrow(), None);
+        assert!(!state.leadership_requested.get());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_visible_follower_requests_leadership() {
+        let Some(state) = resigning_state() else {
+            return;
+        };
+        // Hiding a follower changes nothing
+        state.handle_visibility_change("hidden").await;
+        assert!(!state.leadership_requested.get());
+
+        state.handle_visibility_change("visible").await;
+        assert!(state.leadership_requested.get());
+    }
+}
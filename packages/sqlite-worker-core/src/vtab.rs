@@ -0,0 +1,542 @@
+use sqlite_wasm_rs::export::*;
+use std::ffi::{c_int, c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::rc::Rc;
+
+use crate::coordination::WorkerState;
+use crate::database_functions::{argument_value, result_error, transient};
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::SqlValue;
+
+/// Columns of a virtual table, declared to SQLite when the table is created
+#[derive(Debug, Clone, PartialEq)]
+pub struct VTabSchema {
+    pub columns: Vec<String>,
+}
+
+impl VTabSchema {
+    pub fn new(columns: Vec<String>) -> Self {
+        VTabSchema { columns }
+    }
+
+    // The statement `sqlite3_declare_vtab` expects; SQLite ignores the name
+    fn declaration(&self) -> Result<String, WorkerError> {
+        if self.columns.is_empty() {
+            return Err(WorkerError::QueryFailed(
+                "A virtual table needs at least one column".to_string(),
+            ));
+        }
+        for column in &self.columns {
+            validate_identifier(column)?;
+        }
+        Ok(format!("CREATE TABLE x({})", self.columns.join(", ")))
+    }
+}
+
+/// A `WHERE` term SQLite could pass to the table, such as `column = ?`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexConstraint {
+    pub column: i32,
+    // One of the `SQLITE_INDEX_CONSTRAINT_*` operators
+    pub op: u8,
+    // Unusable terms must not be used in this plan
+    pub usable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexOrderBy {
+    pub column: i32,
+    pub desc: bool,
+}
+
+/// What SQLite asks of the table while planning a query
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VTabIndexInfo {
+    pub constraints: Vec<IndexConstraint>,
+    pub order_by: Vec<IndexOrderBy>,
+}
+
+/// How the table will answer a query, returned from `VTabImpl::best_index`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestIndex {
+    // Passed back to `VTabCursor::filter`
+    pub idx_num: i32,
+    pub estimated_cost: f64,
+    pub estimated_rows: i64,
+    // Positions in `VTabIndexInfo::constraints` whose values `filter` receives as
+    // its arguments, in this order. SQLite still checks the terms itself.
+    pub used_constraints: Vec<usize>,
+    // Whether rows come out in the order of `VTabIndexInfo::order_by`
+    pub order_by_consumed: bool,
+}
+
+impl BestIndex {
+    /// A plan that reads all `rows` rows and uses no constraints
+    pub fn full_scan(rows: i64) -> Self {
+        BestIndex {
+            idx_num: 0,
+            estimated_cost: rows as f64,
+            estimated_rows: rows,
+            used_constraints: Vec::new(),
+            order_by_consumed: false,
+        }
+    }
+}
+
+/// A scan over a virtual table's rows
+pub trait VTabCursor {
+    /// Start a scan from the first row, with `args` holding the values of the
+    /// plan's `BestIndex::used_constraints`
+    fn filter(&mut self, idx_num: i32, args: &[SqlValue]) -> Result<(), WorkerError>;
+    fn next(&mut self) -> Result<(), WorkerError>;
+    fn eof(&self) -> bool;
+    fn column(&self, index: usize) -> Result<SqlValue, WorkerError>;
+    fn rowid(&self) -> Result<i64, WorkerError>;
+}
+
+/// A read-only virtual table module, used through
+/// `CREATE VIRTUAL TABLE name USING module(args)`
+pub trait VTabImpl {
+    /// Columns of a new table, given the arguments after the module name
+    fn create(&self, args: &[&str]) -> Result<VTabSchema, WorkerError>;
+    fn best_index(&self, info: &VTabIndexInfo) -> BestIndex;
+    fn open(&self) -> Result<Box<dyn VTabCursor>, WorkerError>;
+}
+
+// SQLite hands these back as the `sqlite3_vtab` and `sqlite3_vtab_cursor`
+// pointers, so the SQLite struct must come first
+#[repr(C)]
+struct VTab {
+    base: sqlite3_vtab,
+    module: Rc<dyn VTabImpl>,
+}
+
+#[repr(C)]
+struct Cursor {
+    base: sqlite3_vtab_cursor,
+    cursor: Box<dyn VTabCursor>,
+}
+
+// Copy `message` into memory SQLite can free
+unsafe fn sqlite_string(message: &str) -> *mut c_char {
+    let message = message.replace('\0', "");
+    let ptr = sqlite3_malloc(message.len() as c_int + 1) as *mut u8;
+    if !ptr.is_null() {
+        std::ptr::copy_nonoverlapping(message.as_ptr(), ptr, message.len());
+        *ptr.add(message.len()) = 0;
+    }
+    ptr as *mut c_char
+}
+
+// Report `err` as the table's error message
+unsafe fn vtab_error(vtab: *mut sqlite3_vtab, err: &WorkerError) -> c_int {
+    sqlite3_free((*vtab).zErrMsg as *mut c_void);
+    (*vtab).zErrMsg = sqlite_string(&err.to_string());
+    SQLITE_ERROR
+}
+
+unsafe fn cursor_result(
+    cursor: *mut sqlite3_vtab_cursor,
+    result: Result<(), WorkerError>,
+) -> c_int {
+    match result {
+        Ok(()) => SQLITE_OK,
+        Err(err) => vtab_error((*cursor).pVtab, &err),
+    }
+}
+
+unsafe fn set_sql_result(context: *mut sqlite3_context, value: &SqlValue) {
+    match value {
+        SqlValue::Null => sqlite3_result_null(context),
+        SqlValue::Integer(val) => sqlite3_result_int64(context, *val),
+        SqlValue::Real(val) => sqlite3_result_double(context, *val),
+        SqlValue::Text(text) => sqlite3_result_text(
+            context,
+            text.as_ptr() as *const c_char,
+            text.len() as c_int,
+            transient(),
+        ),
+        SqlValue::Blob(bytes) => sqlite3_result_blob(
+            context,
+            bytes.as_ptr() as *const c_void,
+            bytes.len() as c_int,
+            transient(),
+        ),
+    }
+}
+
+// Used for both xCreate and xConnect, as tables keep no state of their own
+unsafe extern "C" fn x_create(
+    db: *mut sqlite3,
+    aux: *mut c_void,
+    argc: c_int,
+    argv: *const *const c_char,
+    pp_vtab: *mut *mut sqlite3_vtab,
+    pz_err: *mut *mut c_char,
+) -> c_int {
+    let module = &*(aux as *const Rc<dyn VTabImpl>);
+    // The first three arguments are the module, database and table names
+    let args: Vec<String> = (3..argc as isize)
+        .map(|i| {
+            CStr::from_ptr(*argv.offset(i))
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let declaration = match module.create(&args).and_then(|schema| schema.declaration()) {
+        Ok(declaration) => declaration,
+        Err(err) => {
+            *pz_err = sqlite_string(&err.to_string());
+            return SQLITE_ERROR;
+        }
+    };
+    // Column names are plain identifiers, so there is no NUL to reject
+    let declaration = CString::new(declaration).unwrap();
+    let ret = sqlite3_declare_vtab(db, declaration.as_ptr());
+    if ret != SQLITE_OK {
+        return ret;
+    }
+
+    let vtab = Box::new(VTab {
+        base: std::mem::zeroed(),
+        module: Rc::clone(module),
+    });
+    *pp_vtab = Box::into_raw(vtab) as *mut sqlite3_vtab;
+    SQLITE_OK
+}
+
+unsafe extern "C" fn x_disconnect(vtab: *mut sqlite3_vtab) -> c_int {
+    sqlite3_free((*vtab).zErrMsg as *mut c_void);
+    drop(Box::from_raw(vtab as *mut VTab));
+    SQLITE_OK
+}
+
+unsafe extern "C" fn x_best_index(vtab: *mut sqlite3_vtab, info: *mut sqlite3_index_info) -> c_int {
+    let info = &mut *info;
+    let constraints: Vec<IndexConstraint> = (0..info.nConstraint as usize)
+        .map(|i| {
+            let constraint = &*info.aConstraint.add(i);
+            IndexConstraint {
+                column: constraint.iColumn,
+                op: constraint.op,
+                usable: constraint.usable != 0,
+            }
+        })
+        .collect();
+    let order_by = (0..info.nOrderBy as usize)
+        .map(|i| {
+            let order_by = &*info.aOrderBy.add(i);
+            IndexOrderBy {
+                column: order_by.iColumn,
+                desc: order_by.desc != 0,
+            }
+        })
+        .collect();
+    let index_info = VTabIndexInfo {
+        constraints,
+        order_by,
+    };
+
+    let best = (*(vtab as *mut VTab)).module.best_index(&index_info);
+    // Arguments are numbered from 1 in the order `filter` receives them
+    let mut next_arg = 1;
+    for &position in &best.used_constraints {
+        if index_info
+            .constraints
+            .get(position)
+            .is_some_and(|constraint| constraint.usable)
+        {
+            (*info.aConstraintUsage.add(position)).argvIndex = next_arg;
+            next_arg += 1;
+        }
+    }
+    info.idxNum = best.idx_num;
+    info.estimatedCost = best.estimated_cost;
+    info.estimatedRows = best.estimated_rows;
+    info.orderByConsumed = best.order_by_consumed as c_int;
+    SQLITE_OK
+}
+
+unsafe extern "C" fn x_open(
+    vtab: *mut sqlite3_vtab,
+    pp_cursor: *mut *mut sqlite3_vtab_cursor,
+) -> c_int {
+    match (*(vtab as *mut VTab)).module.open() {
+        Ok(cursor) => {
+            let cursor = Box::new(Cursor {
+                base: std::mem::zeroed(),
+                cursor,
+            });
+            *pp_cursor = Box::into_raw(cursor) as *mut sqlite3_vtab_cursor;
+            SQLITE_OK
+        }
+        Err(err) => vtab_error(vtab, &err),
+    }
+}
+
+unsafe extern "C" fn x_close(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    drop(Box::from_raw(cursor as *mut Cursor));
+    SQLITE_OK
+}
+
+unsafe extern "C" fn x_filter(
+    cursor: *mut sqlite3_vtab_cursor,
+    idx_num: c_int,
+    _idx_str: *const c_char,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) -> c_int {
+    let args: Vec<SqlValue> = (0..argc as isize)
+        .map(|i| argument_value(*argv.offset(i)))
+        .collect();
+    let result = (*(cursor as *mut Cursor)).cursor.filter(idx_num, &args);
+    cursor_result(cursor, result)
+}
+
+unsafe extern "C" fn x_next(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    let result = (*(cursor as *mut Cursor)).cursor.next();
+    cursor_result(cursor, result)
+}
+
+unsafe extern "C" fn x_eof(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    (*(cursor as *mut Cursor)).cursor.eof() as c_int
+}
+
+unsafe extern "C" fn x_column(
+    cursor: *mut sqlite3_vtab_cursor,
+    context: *mut sqlite3_context,
+    index: c_int,
+) -> c_int {
+    match (*(cursor as *mut Cursor)).cursor.column(index as usize) {
+        Ok(value) => {
+            set_sql_result(context, &value);
+            SQLITE_OK
+        }
+        Err(err) => {
+            result_error(context, &err.to_string());
+            SQLITE_ERROR
+        }
+    }
+}
+
+unsafe extern "C" fn x_rowid(cursor: *mut sqlite3_vtab_cursor, rowid: *mut sqlite3_int64) -> c_int {
+    match (*(cursor as *mut Cursor)).cursor.rowid() {
+        Ok(id) => {
+            *rowid = id;
+            SQLITE_OK
+        }
+        Err(err) => cursor_result(cursor, Err(err)),
+    }
+}
+
+unsafe extern "C" fn drop_module(aux: *mut c_void) {
+    drop(Box::from_raw(aux as *mut Rc<dyn VTabImpl>));
+}
+
+// Read-only tables, so no xUpdate or transaction callbacks
+static MODULE: sqlite3_module = sqlite3_module {
+    iVersion: 0,
+    xCreate: Some(x_create),
+    xConnect: Some(x_create),
+    xBestIndex: Some(x_best_index),
+    xDisconnect: Some(x_disconnect),
+    xDestroy: Some(x_disconnect),
+    xOpen: Some(x_open),
+    xClose: Some(x_close),
+    xFilter: Some(x_filter),
+    xNext: Some(x_next),
+    xEof: Some(x_eof),
+    xColumn: Some(x_column),
+    xRowid: Some(x_rowid),
+    ..unsafe { std::mem::zeroed() }
+};
+
+/// Register `module` as the virtual table module `name` on `db`
+pub(crate) fn register_module(
+    db: *mut sqlite3,
+    name: &str,
+    module: Rc<dyn VTabImpl>,
+) -> Result<(), String> {
+    let module_name = CString::new(name).map_err(|e| e.to_string())?;
+    let aux = Box::into_raw(Box::new(module)) as *mut c_void;
+    // SQLite calls the destructor itself if registration fails
+    let ret = unsafe {
+        sqlite3_create_module_v2(db, module_name.as_ptr(), &MODULE, aux, Some(drop_module))
+    };
+    if ret != SQLITE_OK {
+        return Err(format!("Failed to register {name} module"));
+    }
+    Ok(())
+}
+
+impl WorkerState {
+    /// Register `vtab_impl` as the virtual table module `module_name` on the
+    /// leader's connection, so data such as JSON or CSV can be queried without
+    /// importing it. Modules are Rust values and cannot be sent to the leader,
+    /// so this fails with `NotLeader` elsewhere, and the module has to be
+    /// registered again if leadership moves. Create its tables in the `temp`
+    /// schema, as other connections cannot open them.
+    pub fn register_virtual_table(
+        &self,
+        module_name: &str,
+        vtab_impl: Box<dyn VTabImpl>,
+    ) -> Result<(), WorkerError> {
+        validate_identifier(module_name)?;
+        if !*self.is_leader.borrow() {
+            return Err(WorkerError::NotLeader);
+        }
+        let database = self
+            .db
+            .borrow()
+            .clone()
+            .ok_or(WorkerError::DatabaseNotInitialized)?;
+        database
+            .register_virtual_table(module_name, Rc::from(vtab_impl))
+            .map_err(WorkerError::sqlite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::leader_with_db;
+    use serde_json::{json, Value};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    // Exposes a JSON array of objects as a table whose columns are named in
+    // `CREATE VIRTUAL TABLE ... USING json_rows(column, ...)`
+    struct JsonVTab {
+        rows: Rc<Vec<Value>>,
+        columns: std::cell::RefCell<Vec<String>>,
+    }
+
+    struct JsonCursor {
+        rows: Rc<Vec<Value>>,
+        columns: Vec<String>,
+        position: usize,
+    }
+
+    impl VTabImpl for JsonVTab {
+        fn create(&self, args: &[&str]) -> Result<VTabSchema, WorkerError> {
+            let columns: Vec<String> = args.iter().map(|arg| arg.trim().to_string()).collect();
+            *self.columns.borrow_mut() = columns.clone();
+            Ok(VTabSchema::new(columns))
+        }
+
+        fn best_index(&self, _info: &VTabIndexInfo) -> BestIndex {
+            BestIndex::full_scan(self.rows.len() as i64)
+        }
+
+        fn open(&self) -> Result<Box<dyn VTabCursor>, WorkerError> {
+            Ok(Box::new(JsonCursor {
+                rows: Rc::clone(&self.rows),
+                columns: self.columns.borrow().clone(),
+                position: 0,
+            }))
+        }
+    }
+
+    impl VTabCursor for JsonCursor {
+        fn filter(&mut self, _idx_num: i32, _args: &[SqlValue]) -> Result<(), WorkerError> {
+            self.position = 0;
+            Ok(())
+        }
+
+        fn next(&mut self) -> Result<(), WorkerError> {
+            self.position += 1;
+            Ok(())
+        }
+
+        fn eof(&self) -> bool {
+            self.position >= self.rows.len()
+        }
+
+        fn column(&self, index: usize) -> Result<SqlValue, WorkerError> {
+            let name = &self.columns[index];
+            Ok(match &self.rows[self.position][name] {
+                Value::Number(n) if n.is_i64() => SqlValue::Integer(n.as_i64().unwrap()),
+                Value::Number(n) => SqlValue::Real(n.as_f64().unwrap_or_default()),
+                Value::String(text) => SqlValue::Text(text.clone()),
+                Value::Bool(flag) => SqlValue::Integer(*flag as i64),
+                Value::Null => SqlValue::Null,
+                other => SqlValue::Text(other.to_string()),
+            })
+        }
+
+        fn rowid(&self) -> Result<i64, WorkerError> {
+            Ok(self.position as i64)
+        }
+    }
+
+    fn json_vtab() -> Box<JsonVTab> {
+        Box::new(JsonVTab {
+            rows: Rc::new(vec![
+                json!({"name": "alice", "age": 34}),
+                json!({"name": "bob", "age": 27}),
+                json!({"name": "carol", "age": 41}),
+            ]),
+            columns: std::cell::RefCell::new(Vec::new()),
+        })
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_query_json_virtual_table() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+
+        state
+            .register_virtual_table("json_rows", json_vtab())
+            .expect("Registration failed");
+        state
+            .execute_query_simple(
+                "CREATE VIRTUAL TABLE temp.people USING json_rows(name, age)".to_string(),
+            )
+            .await
+            .expect("Create failed");
+
+        let result = state
+            .execute_query_simple(
+                "SELECT name FROM temp.people WHERE age > 30 ORDER BY name".to_string(),
+            )
+            .await
+            .expect("Select failed");
+        assert_eq!(
+            result.to_json_rows(),
+            vec![json!({"name": "alice"}), json!({"name": "carol"})]
+        );
+
+        let _ = state
+            .execute_query_simple("DROP TABLE temp.people".to_string())
+            .await;
+    }
+
+    #[wasm_bindgen_test]
+    fn test_register_virtual_table_requires_leader() {
+        let Ok(state) = WorkerState::new() else {
+            return;
+        };
+        assert_eq!(
+            state.register_virtual_table("json_rows", json_vtab()),
+            Err(WorkerError::NotLeader)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_schema_declaration() {
+        let schema = VTabSchema::new(vec!["name".to_string(), "age".to_string()]);
+        assert_eq!(
+            schema.declaration(),
+            Ok("CREATE TABLE x(name, age)".to_string())
+        );
+        assert!(VTabSchema::new(Vec::new()).declaration().is_err());
+        assert!(VTabSchema::new(vec!["a; DROP".to_string()])
+            .declaration()
+            .is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::coordination::{Interval, WorkerState};
+use crate::error::WorkerError;
+use crate::identifier::validate_identifier;
+use crate::query_result::QueryResult;
+
+/// A `watch_table` watcher, stopped by `stop` or when dropped
+#[wasm_bindgen]
+pub struct WatchHandle {
+    interval: RefCell<Option<Interval>>,
+}
+
+#[wasm_bindgen]
+impl WatchHandle {
+    pub fn stop(&self) {
+        self.interval.borrow_mut().take();
+    }
+
+    #[wasm_bindgen(js_name = "isActive")]
+    pub fn is_active(&self) -> bool {
+        self.interval.borrow().is_some()
+    }
+}
+
+// Results are compared by a hash of their JSON rather than kept whole
+fn result_hash(result: &QueryResult) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(result)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+impl WorkerState {
+    /// Run `sql` now and then every `interval_ms`, calling `on_change` with the
+    /// first result and with each one that differs from the last. Errors go to
+    /// the `on_error` handlers. Returns an id for `unwatch_query`.
+    pub fn watch_query(
+        self: &Rc<Self>,
+        sql: String,
+        interval_ms: u64,
+        on_change: impl Fn(QueryResult) + 'static,
+    ) -> Result<String, WorkerError> {
+        let watcher_id = Uuid::new_v4().to_string();
+        // Held weakly so a forgotten watcher does not keep the state alive
+        let state = Rc::downgrade(self);
+        let on_change = Rc::new(on_change);
+        let last_hash = Rc::new(Cell::new(None));
+        // Skip ticks while the previous poll is still waiting on the leader
+        let in_flight = Rc::new(Cell::new(false));
+
+        let poll = move || {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            if in_flight.replace(true) {
+                return;
+            }
+            let sql = sql.clone();
+            let on_change = Rc::clone(&on_change);
+            let last_hash = Rc::clone(&last_hash);
+            let in_flight = Rc::clone(&in_flight);
+            spawn_local(async move {
+                match state.execute_parameterized(sql, Vec::new()).await {
+                    Ok(result) => {
+                        let hash = result_hash(&result);
+                        if last_hash.replace(Some(hash)) != Some(hash) {
+                            on_change(result);
+                        }
+                    }
+                    Err(error) => state.report_error(error),
+                }
+                in_flight.set(false);
+            });
+        };
+        poll();
+
+        let tick = Closure::wrap(Box::new(poll) as Box<dyn FnMut()>);
+        let interval = Interval::start(tick, interval_ms)?;
+        self.watchers
+            .borrow_mut()
+            .insert(watcher_id.clone(), interval);
+        Ok(watcher_id)
+    }
+
+    /// Watch `table` for inserted rows: every `poll_ms` check its largest
+    /// rowid and, once that has grown, call `on_rows` with the rows above the
+    /// previous largest in rowid order. Rows present when watching starts are
+    /// not reported, nor are rows that reuse the rowids of deleted ones.
+    /// Errors go to the `on_error` handlers.
+    pub fn watch_table(
+        self: &Rc<Self>,
+        table: &str,
+        poll_ms: u64,
+        on_rows: impl Fn(QueryResult) + 'static,
+    ) -> Result<WatchHandle, WorkerError> {
+        validate_identifier(table)?;
+        let max_rowid_sql = format!("SELECT max(rowid) FROM {table}");
+        let table = table.to_string();
+        let state = Rc::downgrade(self);
+        let on_rows = Rc::new(on_rows);
+        // None until the first poll has read the starting watermark
+        let last_max_rowid: Rc<Cell<Option<i64>>> = Rc::new(Cell::new(None));
+        let in_flight = Rc::new(Cell::new(false));
+
+        let poll = move || {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            if in_flight.replace(true) {
+                return;
+            }
+            let max_rowid_sql = max_rowid_sql.clone();
+            let table = table.clone();
+            let on_rows = Rc::clone(&on_rows);
+            let last_max_rowid = Rc::clone(&last_max_rowid);
+            let in_flight = Rc::clone(&in_flight);
+            spawn_local(async move {
+                let result = poll_new_rows(&state, &max_rowid_sql, &table, &last_max_rowid).await;
+                match result {
+                    Ok(Some(rows)) => on_rows(rows),
+                    Ok(None) => {}
+                    Err(error) => state.report_error(error),
+                }
+                in_flight.set(false);
+            });
+        };
+        poll();
+
+        let tick = Closure::wrap(Box::new(poll) as Box<dyn FnMut()>);
+        Ok(WatchHandle {
+            interval: RefCell::new(Some(Interval::start(tick, poll_ms)?)),
+        })
+    }
+
+    /// Stop a watcher started by `watch_query`, returning whether it was running
+    pub fn unwatch_query(&self, watcher_id: &str) -> bool {
+        self.watchers.borrow_mut().remove(watcher_id).is_some()
+    }
+}
+
+// Rows of `table` above the watermark, moving it up to the current largest
+// rowid. The first call only records the watermark.
+async fn poll_new_rows(
+    state: &WorkerState,
+    max_rowid_sql: &str,
+    table: &str,
+    last_max_rowid: &Cell<Option<i64>>,
+) -> Result<Option<QueryResult>, WorkerError> {
+    // An empty table has no largest rowid
+    let (max_rowid,): (Option<i64>,) = state
+        .execute_parameterized(max_rowid_sql.to_string(), Vec::new())
+        .await?
+        .get_single()?;
+    let max_rowid = max_rowid.unwrap_or(0);
+    let Some(last) = last_max_rowid.get() else {
+        last_max_rowid.set(Some(max_rowid));
+        return Ok(None);
+    };
+    if max_rowid <= last {
+        return Ok(None);
+    }
+
+    let rows = state
+        .execute_parameterized(
+            format!("SELECT * FROM {table} WHERE rowid > {last} ORDER BY rowid"),
+            Vec::new(),
+        )
+        .await?;
+    last_max_rowid.set(Some(max_rowid));
+    Ok(Some(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordination::{leader_with_db, sleep};
+    use crate::query_result::SqlValue;
+    use std::cell::RefCell;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn test_result_hash_tracks_content() {
+        let mut result = QueryResult {
+            columns: vec!["id".to_string()],
+            rows: vec![vec![SqlValue::Integer(1)]],
+            ..QueryResult::default()
+        };
+        let first = result_hash(&result);
+        assert_eq!(first, result_hash(&result.clone()));
+
+        result.rows.push(vec![SqlValue::Integer(2)]);
+        assert_ne!(first, result_hash(&result));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_watch_query_reports_changes_only() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let state = Rc::new(state);
+        for sql in [
+            "DROP TABLE IF EXISTS watched_items",
+            "CREATE TABLE watched_items (id INTEGER PRIMARY KEY)",
+        ] {
+            state
+                .execute_parameterized(sql.to_string(), Vec::new())
+                .await
+                .expect("Setup failed");
+        }
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_clone = Rc::clone(&seen);
+        let watcher_id = state
+            .watch_query(
+                "SELECT COUNT(*) AS count FROM watched_items".to_string(),
+                20,
+                move |result| seen_clone.borrow_mut().push(result.rows),
+            )
+            .expect("Should start watching");
+        sleep(80.0).await;
+        assert_eq!(
+            *seen.borrow(),
+            vec![vec![vec![SqlValue::Integer(0)]]],
+            "Unchanged results are reported once"
+        );
+
+        state
+            .execute_parameterized(
+                "INSERT INTO watched_items (id) VALUES (1)".to_string(),
+                Vec::new(),
+            )
+            .await
+            .expect("Insert failed");
+        sleep(80.0).await;
+        assert_eq!(seen.borrow().len(), 2);
+        assert_eq!(seen.borrow()[1], vec![vec![SqlValue::Integer(1)]]);
+
+        assert!(state.unwatch_query(&watcher_id));
+        assert!(!state.unwatch_query(&watcher_id));
+        state
+            .execute_parameterized(
+                "INSERT INTO watched_items (id) VALUES (2)".to_string(),
+                Vec::new(),
+            )
+            .await
+            .expect("Insert failed");
+        sleep(80.0).await;
+        assert_eq!(seen.borrow().len(), 2, "No callbacks after unwatching");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_watch_table_reports_new_rows() {
+        let Some(state) = leader_with_db().await else {
+            return;
+        };
+        let state = Rc::new(state);
+        for sql in [
+            "DROP TABLE IF EXISTS watched_table",
+            "CREATE TABLE watched_table (name TEXT)",
+            "INSERT INTO watched_table VALUES ('existing')",
+        ] {
+            state
+                .execute_parameterized(sql.to_string(), Vec::new())
+                .await
+                .expect("Setup failed");
+        }
+
+        let poll_ms = 40;
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_clone = Rc::clone(&seen);
+        let handle = state
+            .watch_table("watched_table", poll_ms, move |result| {
+                seen_clone.borrow_mut().push(result.rows)
+            })
+            .expect("Should start watching");
+        // Let the first poll record the starting watermark
+        sleep(10.0).await;
+
+        state
+            .execute_parameterized(
+                "INSERT INTO watched_table VALUES ('new')".to_string(),
+                Vec::new(),
+            )
+            .await
+            .expect("Insert failed");
+        sleep(2.0 * poll_ms as f64).await;
+        assert_eq!(
+            *seen.borrow(),
+            vec![vec![vec![SqlValue::Text("new".to_string())]]]
+        );
+
+        handle.stop();
+        assert!(!handle.is_active());
+        state
+            .execute_parameterized(
+                "INSERT INTO watched_table VALUES ('after')".to_string(),
+                Vec::new(),
+            )
+            .await
+            .expect("Insert failed");
+        sleep(2.0 * poll_ms as f64).await;
+        assert_eq!(seen.borrow().len(), 1, "No callbacks after stopping");
+    }
+}
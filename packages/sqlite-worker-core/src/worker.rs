@@ -6,23 +6,30 @@ use wasm_bindgen_futures::spawn_local;
 use web_sys::{DedicatedWorkerGlobalScope, MessageEvent};
 
 use crate::coordination::WorkerState;
+use crate::error::WorkerError;
+use crate::query_context::QueryContext;
 
 // Global state
 thread_local! {
     static WORKER_STATE: RefCell<Option<Rc<WorkerState>>> = const { RefCell::new(None) };
 }
 
+/// State of the running worker, if `main` has been called
+pub(crate) fn current_state() -> Option<Rc<WorkerState>> {
+    WORKER_STATE.with(|s| s.borrow().clone())
+}
+
 /// Entry point for the worker - called from the blob
 pub fn main() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
 
     let state = Rc::new(WorkerState::new()?);
 
-    state.setup_channel_listener();
-
     let state_clone = Rc::clone(&state);
     spawn_local(async move {
-        state_clone.attempt_leadership().await;
+        if let Err(error) = state_clone.start().await {
+            state_clone.report_error(error);
+        }
     });
 
     WORKER_STATE.with(|s| {
@@ -46,7 +53,8 @@ pub fn main() -> Result<(), JsValue> {
                                 if let Some(state) = s.borrow().as_ref() {
                                     let state = Rc::clone(state);
                                     spawn_local(async move {
-                                        let result = state.execute_query(sql).await;
+                                        let result =
+                                            state.execute_query(sql, QueryContext::default()).await;
 
                                         // Send response as plain JavaScript object
                                         let response = js_sys::Object::new();
@@ -91,7 +99,11 @@ pub fn main() -> Result<(), JsValue> {
                                         let global = js_sys::global();
                                         let worker_scope: DedicatedWorkerGlobalScope =
                                             global.unchecked_into();
-                                        let _ = worker_scope.post_message(&response);
+                                        if let Err(err) = worker_scope.post_message(&response) {
+                                            state.report_error(WorkerError::ChannelError(format!(
+                                                "{err:?}"
+                                            )));
+                                        }
                                     });
                                 }
                             });
@@ -116,7 +128,7 @@ mod tests {
     use wasm_bindgen_test::*;
     use web_sys::MessageEvent;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
 
     #[wasm_bindgen_test]
     fn test_worker_state_creation() {
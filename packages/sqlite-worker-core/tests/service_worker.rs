@@ -0,0 +1,63 @@
+// The unit tests run in a dedicated worker, so the service worker's
+// behaviour is checked here, in a real service worker
+use sqlite_worker_core::{handle_fetch, SQLiteDatabase, WorkerState};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::*;
+use web_sys::{Request, RequestInit, Response};
+
+wasm_bindgen_test_configure!(run_in_service_worker);
+
+fn post_request(sql: &str) -> Request {
+    let init = RequestInit::new();
+    init.set_method("POST");
+    let body = serde_json::json!({ "sql": sql }).to_string();
+    init.set_body(&JsValue::from_str(&body));
+    Request::new_with_str_and_init("http://localhost/api/db/query", &init).unwrap()
+}
+
+async fn response_json(response: Response) -> serde_json::Value {
+    let text = JsFuture::from(response.text().unwrap())
+        .await
+        .unwrap()
+        .as_string()
+        .unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn test_service_worker_cannot_open_the_database() {
+    // OPFS sync access handles only exist in dedicated workers, which is why
+    // the service worker never leads
+    assert!(SQLiteDatabase::initialize_opfs().await.is_err());
+}
+
+#[wasm_bindgen_test]
+async fn test_service_worker_refuses_writes() {
+    let state = WorkerState::new().expect("Service workers have a BroadcastChannel");
+    for sql in [
+        "INSERT INTO items VALUES (1)",
+        "UPDATE items SET name = 'pear'",
+        "WITH x AS (SELECT 1) DELETE FROM items",
+        "CREATE TABLE items (id INTEGER)",
+    ] {
+        let response = handle_fetch(&state, post_request(sql)).await.unwrap();
+        assert_eq!(response.status(), 403, "{sql} should be refused");
+        let body = response_json(response).await;
+        assert!(body["error"].as_str().unwrap().contains("read-only"));
+    }
+}
+
+#[wasm_bindgen_test]
+async fn test_service_worker_forwards_reads() {
+    let state = WorkerState::new().expect("Service workers have a BroadcastChannel");
+    state.setup_channel_listener();
+    // With no dedicated worker leading the read is let through, and fails
+    // only for want of a leader
+    let response = handle_fetch(&state, post_request("SELECT 1"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 500);
+    let body = response_json(response).await;
+    assert!(!body["error"].as_str().unwrap().contains("read-only"));
+}